@@ -0,0 +1,1116 @@
+use crate::client::Client;
+use crate::frame::Frame;
+use crate::shared;
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Error, ErrorKind, Result};
+
+pub const ACL_CATEGORY_KEYSPACE: u64 = 1 << 0;
+pub const ACL_CATEGORY_READ: u64 = 1 << 1;
+pub const ACL_CATEGORY_WRITE: u64 = 1 << 2;
+pub const ACL_CATEGORY_SET: u64 = 1 << 3;
+pub const ACL_CATEGORY_SORTEDSET: u64 = 1 << 4;
+pub const ACL_CATEGORY_LIST: u64 = 1 << 5;
+pub const ACL_CATEGORY_HASH: u64 = 1 << 6;
+pub const ACL_CATEGORY_STRING: u64 = 1 << 7;
+pub const ACL_CATEGORY_BITMAP: u64 = 1 << 8;
+pub const ACL_CATEGORY_HYPERLOGLOG: u64 = 1 << 9;
+pub const ACL_CATEGORY_GEO: u64 = 1 << 10;
+pub const ACL_CATEGORY_STREAM: u64 = 1 << 11;
+pub const ACL_CATEGORY_PUBSUB: u64 = 1 << 12;
+pub const ACL_CATEGORY_ADMIN: u64 = 1 << 13;
+pub const ACL_CATEGORY_FAST: u64 = 1 << 14;
+pub const ACL_CATEGORY_SLOW: u64 = 1 << 15;
+pub const ACL_CATEGORY_BLOCKING: u64 = 1 << 16;
+pub const ACL_CATEGORY_DANGEROUS: u64 = 1 << 17;
+pub const ACL_CATEGORY_CONNECTION: u64 = 1 << 18;
+pub const ACL_CATEGORY_TRANSACTION: u64 = 1 << 19;
+pub const ACL_CATEGORY_SCRIPTING: u64 = 1 << 20;
+
+/// Names of the categories used by the +@<category> and -@<category> rules
+pub const ACL_CATEGORY_NAMES: &[(&str, u64)] = &[
+    ("keyspace", ACL_CATEGORY_KEYSPACE),
+    ("read", ACL_CATEGORY_READ),
+    ("write", ACL_CATEGORY_WRITE),
+    ("set", ACL_CATEGORY_SET),
+    ("sortedset", ACL_CATEGORY_SORTEDSET),
+    ("list", ACL_CATEGORY_LIST),
+    ("hash", ACL_CATEGORY_HASH),
+    ("string", ACL_CATEGORY_STRING),
+    ("bitmap", ACL_CATEGORY_BITMAP),
+    ("hyperloglog", ACL_CATEGORY_HYPERLOGLOG),
+    ("geo", ACL_CATEGORY_GEO),
+    ("stream", ACL_CATEGORY_STREAM),
+    ("pubsub", ACL_CATEGORY_PUBSUB),
+    ("admin", ACL_CATEGORY_ADMIN),
+    ("fast", ACL_CATEGORY_FAST),
+    ("slow", ACL_CATEGORY_SLOW),
+    ("blocking", ACL_CATEGORY_BLOCKING),
+    ("dangerous", ACL_CATEGORY_DANGEROUS),
+    ("connection", ACL_CATEGORY_CONNECTION),
+    ("transaction", ACL_CATEGORY_TRANSACTION),
+    ("scripting", ACL_CATEGORY_SCRIPTING),
+];
+
+const ACL_READ_PERMISSION: u8 = 1 << 0;
+const ACL_WRITE_PERMISSION: u8 = 1 << 1;
+const ACL_ALL_PERMISSION: u8 = ACL_READ_PERMISSION | ACL_WRITE_PERMISSION;
+
+/// Position of the arguments checked against the key and channel patterns,
+/// `last` is relative to the end of the arguments when negative
+#[derive(Debug, Clone, Copy)]
+pub enum AclArgs {
+    None,
+    /// first, last and step of the key arguments
+    Keys(usize, isize, usize),
+    /// the keys from `first` up to the number of keys at `numkeys`, then as
+    /// many keys as that number, e.g. `ZUNIONSTORE dst numkeys key [key ...]`
+    NumKeys(usize, usize),
+    /// the first half of the arguments after STREAMS, e.g. `XREAD COUNT 1
+    /// STREAMS key [key ...] id [id ...]`
+    Streams,
+    /// first and last of the channel arguments
+    Channels(usize, isize),
+    /// first and last of the channel pattern arguments
+    Patterns(usize, isize),
+}
+
+/// The command table used to check the permissions, the flags are the
+/// categories of the command, "no-auth" for the commands that can be run
+/// before authenticating and "deny-oom" for the commands that may use more
+/// memory. Subcommands are named "<command>|<subcommand>".
+#[rustfmt::skip]
+const COMMAND_TABLE: &[(&str, &str, AclArgs)] = &[
+    ("ping", "@fast @connection", AclArgs::None),
+    ("echo", "@fast @connection", AclArgs::None),
+    ("quit", "@fast @connection no-auth", AclArgs::None),
+    ("auth", "@fast @connection no-auth", AclArgs::None),
+    ("hello", "@fast @connection no-auth", AclArgs::None),
+    ("select", "@fast @connection", AclArgs::None),
+    ("get", "@read @string @fast", AclArgs::Keys(1, 1, 1)),
+    ("set", "@write @string @slow deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("setnx", "@write @string @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("setex", "@write @string @slow deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("psetex", "@write @string @slow deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("mget", "@read @string @fast", AclArgs::Keys(1, -1, 1)),
+    ("mset", "@write @string @slow deny-oom", AclArgs::Keys(1, -1, 2)),
+    ("msetnx", "@write @string @slow deny-oom", AclArgs::Keys(1, -1, 2)),
+    ("getset", "@write @string @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("getdel", "@write @string @fast", AclArgs::Keys(1, 1, 1)),
+    ("getex", "@write @string @fast", AclArgs::Keys(1, 1, 1)),
+    ("getrange", "@read @string @slow", AclArgs::Keys(1, 1, 1)),
+    ("setrange", "@write @string @slow deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("incrbyfloat", "@write @string @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("lcs", "@read @string @slow", AclArgs::Keys(1, 2, 1)),
+    ("setbit", "@write @bitmap @slow deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("getbit", "@read @bitmap @fast", AclArgs::Keys(1, 1, 1)),
+    ("bitcount", "@read @bitmap @slow", AclArgs::Keys(1, 1, 1)),
+    ("bitpos", "@read @bitmap @slow", AclArgs::Keys(1, 1, 1)),
+    ("bitop", "@write @bitmap @slow deny-oom", AclArgs::Keys(2, -1, 1)),
+    ("bitfield", "@write @bitmap @slow deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("bitfield_ro", "@read @bitmap @fast", AclArgs::Keys(1, 1, 1)),
+    ("pfadd", "@write @hyperloglog @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("pfcount", "@read @hyperloglog @slow", AclArgs::Keys(1, -1, 1)),
+    ("pfmerge", "@write @hyperloglog @slow deny-oom", AclArgs::Keys(1, -1, 1)),
+    ("append", "@write @string @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("strlen", "@read @string @fast", AclArgs::Keys(1, 1, 1)),
+    ("incr", "@write @string @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("incrby", "@write @string @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("decr", "@write @string @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("decrby", "@write @string @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("del", "@keyspace @write @slow", AclArgs::Keys(1, -1, 1)),
+    ("exists", "@keyspace @read @fast", AclArgs::Keys(1, -1, 1)),
+    ("keys", "@keyspace @read @slow @dangerous", AclArgs::None),
+    ("scan", "@keyspace @read @slow", AclArgs::None),
+    ("dbsize", "@keyspace @read @fast", AclArgs::None),
+    ("shutdown", "@admin @slow @dangerous", AclArgs::None),
+    ("rename", "@keyspace @write @slow", AclArgs::Keys(1, 2, 1)),
+    ("expire", "@keyspace @write @fast", AclArgs::Keys(1, 1, 1)),
+    ("expireat", "@keyspace @write @fast", AclArgs::Keys(1, 1, 1)),
+    ("pexpire", "@keyspace @write @fast", AclArgs::Keys(1, 1, 1)),
+    ("pexpireat", "@keyspace @write @fast", AclArgs::Keys(1, 1, 1)),
+    ("type", "@keyspace @read @fast", AclArgs::Keys(1, 1, 1)),
+    ("move", "@keyspace @write @fast", AclArgs::Keys(1, 1, 1)),
+    ("swapdb", "@keyspace @write @fast @dangerous", AclArgs::None),
+    ("flushdb", "@keyspace @write @slow @dangerous", AclArgs::None),
+    ("flushall", "@keyspace @write @slow @dangerous", AclArgs::None),
+    ("lpush", "@write @list @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("rpush", "@write @list @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("lpop", "@write @list @fast", AclArgs::Keys(1, 1, 1)),
+    ("rpop", "@write @list @fast", AclArgs::Keys(1, 1, 1)),
+    ("lpushx", "@write @list @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("rpushx", "@write @list @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("llen", "@read @list @fast", AclArgs::Keys(1, 1, 1)),
+    ("lrange", "@read @list @slow", AclArgs::Keys(1, 1, 1)),
+    ("lindex", "@read @list @slow", AclArgs::Keys(1, 1, 1)),
+    ("lset", "@write @list @slow deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("linsert", "@write @list @slow deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("lrem", "@write @list @slow", AclArgs::Keys(1, 1, 1)),
+    ("ltrim", "@write @list @slow", AclArgs::Keys(1, 1, 1)),
+    ("lpos", "@read @list @slow", AclArgs::Keys(1, 1, 1)),
+    ("lmove", "@write @list @slow deny-oom", AclArgs::Keys(1, 2, 1)),
+    ("rpoplpush", "@write @list @slow deny-oom", AclArgs::Keys(1, 2, 1)),
+    ("lmpop", "@write @list @slow", AclArgs::NumKeys(1, 1)),
+    ("blpop", "@write @list @slow @blocking", AclArgs::Keys(1, -2, 1)),
+    ("brpop", "@write @list @slow @blocking", AclArgs::Keys(1, -2, 1)),
+    ("blmpop", "@write @list @slow @blocking", AclArgs::NumKeys(2, 2)),
+    ("blmove", "@write @list @slow @blocking deny-oom", AclArgs::Keys(1, 2, 1)),
+    ("hset", "@write @hash @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("hget", "@read @hash @fast", AclArgs::Keys(1, 1, 1)),
+    ("hmset", "@write @hash @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("hsetnx", "@write @hash @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("hmget", "@read @hash @fast", AclArgs::Keys(1, 1, 1)),
+    ("hdel", "@write @hash @fast", AclArgs::Keys(1, 1, 1)),
+    ("hexists", "@read @hash @fast", AclArgs::Keys(1, 1, 1)),
+    ("hlen", "@read @hash @fast", AclArgs::Keys(1, 1, 1)),
+    ("hstrlen", "@read @hash @fast", AclArgs::Keys(1, 1, 1)),
+    ("hkeys", "@read @hash @slow", AclArgs::Keys(1, 1, 1)),
+    ("hvals", "@read @hash @slow", AclArgs::Keys(1, 1, 1)),
+    ("hgetall", "@read @hash @slow", AclArgs::Keys(1, 1, 1)),
+    ("hscan", "@read @hash @slow", AclArgs::Keys(1, 1, 1)),
+    ("hincrby", "@write @hash @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("hincrbyfloat", "@write @hash @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("hrandfield", "@read @hash @slow", AclArgs::Keys(1, 1, 1)),
+    ("sadd", "@write @set @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("srem", "@write @set @fast", AclArgs::Keys(1, 1, 1)),
+    ("smembers", "@read @set @slow", AclArgs::Keys(1, 1, 1)),
+    ("sscan", "@read @set @slow", AclArgs::Keys(1, 1, 1)),
+    ("sismember", "@read @set @fast", AclArgs::Keys(1, 1, 1)),
+    ("smismember", "@read @set @fast", AclArgs::Keys(1, 1, 1)),
+    ("scard", "@read @set @fast", AclArgs::Keys(1, 1, 1)),
+    ("spop", "@write @set @fast", AclArgs::Keys(1, 1, 1)),
+    ("srandmember", "@read @set @slow", AclArgs::Keys(1, 1, 1)),
+    ("smove", "@write @set @fast", AclArgs::Keys(1, 2, 1)),
+    ("sinter", "@read @set @slow", AclArgs::Keys(1, -1, 1)),
+    ("sunion", "@read @set @slow", AclArgs::Keys(1, -1, 1)),
+    ("sdiff", "@read @set @slow", AclArgs::Keys(1, -1, 1)),
+    ("sinterstore", "@write @set @slow deny-oom", AclArgs::Keys(1, -1, 1)),
+    ("sunionstore", "@write @set @slow deny-oom", AclArgs::Keys(1, -1, 1)),
+    ("sdiffstore", "@write @set @slow deny-oom", AclArgs::Keys(1, -1, 1)),
+    ("sintercard", "@read @set @slow", AclArgs::NumKeys(1, 1)),
+    ("zadd", "@write @sortedset @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("zincrby", "@write @sortedset @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("zrem", "@write @sortedset @fast", AclArgs::Keys(1, 1, 1)),
+    ("zscore", "@read @sortedset @fast", AclArgs::Keys(1, 1, 1)),
+    ("zscan", "@read @sortedset @slow", AclArgs::Keys(1, 1, 1)),
+    ("zmscore", "@read @sortedset @fast", AclArgs::Keys(1, 1, 1)),
+    ("zcard", "@read @sortedset @fast", AclArgs::Keys(1, 1, 1)),
+    ("zcount", "@read @sortedset @fast", AclArgs::Keys(1, 1, 1)),
+    ("zrank", "@read @sortedset @fast", AclArgs::Keys(1, 1, 1)),
+    ("zrevrank", "@read @sortedset @fast", AclArgs::Keys(1, 1, 1)),
+    ("zrange", "@read @sortedset @slow", AclArgs::Keys(1, 1, 1)),
+    ("zrangestore", "@write @sortedset @slow deny-oom", AclArgs::Keys(1, 2, 1)),
+    ("zpopmin", "@write @sortedset @fast", AclArgs::Keys(1, 1, 1)),
+    ("zpopmax", "@write @sortedset @fast", AclArgs::Keys(1, 1, 1)),
+    ("zremrangebyrank", "@write @sortedset @slow", AclArgs::Keys(1, 1, 1)),
+    ("zremrangebyscore", "@write @sortedset @slow", AclArgs::Keys(1, 1, 1)),
+    ("zremrangebylex", "@write @sortedset @slow", AclArgs::Keys(1, 1, 1)),
+    ("zunionstore", "@write @sortedset @slow deny-oom", AclArgs::NumKeys(1, 2)),
+    ("zinterstore", "@write @sortedset @slow deny-oom", AclArgs::NumKeys(1, 2)),
+    ("zdiffstore", "@write @sortedset @slow deny-oom", AclArgs::NumKeys(1, 2)),
+    ("xadd", "@write @stream @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("xrange", "@read @stream @slow", AclArgs::Keys(1, 1, 1)),
+    ("xrevrange", "@read @stream @slow", AclArgs::Keys(1, 1, 1)),
+    ("xlen", "@read @stream @fast", AclArgs::Keys(1, 1, 1)),
+    ("xdel", "@write @stream @fast", AclArgs::Keys(1, 1, 1)),
+    ("xtrim", "@write @stream @slow", AclArgs::Keys(1, 1, 1)),
+    ("xread", "@read @stream @slow @blocking", AclArgs::Streams),
+    ("xreadgroup", "@write @stream @slow @blocking", AclArgs::Streams),
+    ("xgroup|create", "@write @stream @slow deny-oom", AclArgs::Keys(2, 2, 1)),
+    ("xgroup|setid", "@write @stream @slow", AclArgs::Keys(2, 2, 1)),
+    ("xgroup|destroy", "@write @stream @slow", AclArgs::Keys(2, 2, 1)),
+    ("xgroup|createconsumer", "@write @stream @slow deny-oom", AclArgs::Keys(2, 2, 1)),
+    ("xgroup|delconsumer", "@write @stream @slow", AclArgs::Keys(2, 2, 1)),
+    ("xack", "@write @stream @fast", AclArgs::Keys(1, 1, 1)),
+    ("xpending", "@read @stream @slow", AclArgs::Keys(1, 1, 1)),
+    ("xclaim", "@write @stream @fast", AclArgs::Keys(1, 1, 1)),
+    ("xautoclaim", "@write @stream @fast", AclArgs::Keys(1, 1, 1)),
+    ("xinfo|stream", "@read @stream @slow", AclArgs::Keys(2, 2, 1)),
+    ("xinfo|groups", "@read @stream @slow", AclArgs::Keys(2, 2, 1)),
+    ("xinfo|consumers", "@read @stream @slow", AclArgs::Keys(2, 2, 1)),
+    ("xsetid", "@write @stream @fast deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("geoadd", "@write @geo @slow deny-oom", AclArgs::Keys(1, 1, 1)),
+    ("geopos", "@read @geo @slow", AclArgs::Keys(1, 1, 1)),
+    ("geodist", "@read @geo @slow", AclArgs::Keys(1, 1, 1)),
+    ("geohash", "@read @geo @slow", AclArgs::Keys(1, 1, 1)),
+    ("geosearch", "@read @geo @slow", AclArgs::Keys(1, 1, 1)),
+    ("geosearchstore", "@write @geo @slow deny-oom", AclArgs::Keys(1, 2, 1)),
+    ("save", "@admin @slow @dangerous", AclArgs::None),
+    ("bgsave", "@admin @slow @dangerous", AclArgs::None),
+    ("bgrewriteaof", "@admin @slow @dangerous", AclArgs::None),
+    ("info", "@slow @dangerous", AclArgs::None),
+    ("config|get", "@admin @slow @dangerous", AclArgs::None),
+    ("config|set", "@admin @slow @dangerous", AclArgs::None),
+    ("config|resetstat", "@admin @slow @dangerous", AclArgs::None),
+    ("config|rewrite", "@admin @slow @dangerous", AclArgs::None),
+    ("multi", "@fast @transaction", AclArgs::None),
+    ("exec", "@slow @transaction", AclArgs::None),
+    ("discard", "@fast @transaction", AclArgs::None),
+    ("watch", "@fast @transaction", AclArgs::Keys(1, -1, 1)),
+    ("unwatch", "@fast @transaction", AclArgs::None),
+    ("subscribe", "@pubsub @slow", AclArgs::Channels(1, -1)),
+    ("unsubscribe", "@pubsub @slow", AclArgs::None),
+    ("psubscribe", "@pubsub @slow", AclArgs::Patterns(1, -1)),
+    ("punsubscribe", "@pubsub @slow", AclArgs::None),
+    ("publish", "@pubsub @fast", AclArgs::Channels(1, 1)),
+    ("pubsub|channels", "@pubsub @slow", AclArgs::None),
+    ("pubsub|numsub", "@pubsub @slow", AclArgs::None),
+    ("pubsub|numpat", "@pubsub @slow", AclArgs::None),
+    ("acl|setuser", "@admin @slow @dangerous", AclArgs::None),
+    ("acl|getuser", "@admin @slow @dangerous", AclArgs::None),
+    ("acl|deluser", "@admin @slow @dangerous", AclArgs::None),
+    ("acl|list", "@admin @slow @dangerous", AclArgs::None),
+    ("acl|users", "@admin @slow @dangerous", AclArgs::None),
+    ("acl|load", "@admin @slow @dangerous", AclArgs::None),
+    ("acl|save", "@admin @slow @dangerous", AclArgs::None),
+    ("acl|whoami", "@slow", AclArgs::None),
+    ("acl|cat", "@slow", AclArgs::None),
+];
+
+#[derive(Debug)]
+pub struct CommandPerm {
+    pub name: &'static str,
+    pub categories: u64,
+    pub no_auth: bool,
+    pub deny_oom: bool,
+    pub args: AclArgs,
+}
+
+lazy_static::lazy_static! {
+    static ref COMMANDS: HashMap<&'static str, CommandPerm> = COMMAND_TABLE
+        .iter()
+        .map(|&(name, flags, args)| {
+            let mut perm = CommandPerm {
+                name,
+                categories: 0,
+                no_auth: false,
+                deny_oom: false,
+                args,
+            };
+            for flag in flags.split_whitespace() {
+                match flag.strip_prefix('@') {
+                    Some(category) => perm.categories |= category_flag(category).unwrap(),
+                    None if flag == "no-auth" => perm.no_auth = true,
+                    None if flag == "deny-oom" => perm.deny_oom = true,
+                    None => panic!("unknown command flag {}", flag),
+                }
+            }
+            (name, perm)
+        })
+        .collect();
+
+    /// Commands that are only run through their subcommands
+    static ref CONTAINER_COMMANDS: HashSet<&'static str> = COMMAND_TABLE
+        .iter()
+        .filter_map(|(name, _, _)| name.split_once('|').map(|(container, _)| container))
+        .collect();
+}
+
+pub fn category_flag(name: &str) -> Option<u64> {
+    ACL_CATEGORY_NAMES
+        .iter()
+        .find(|(category, _)| name.eq_ignore_ascii_case(category))
+        .map(|(_, flag)| *flag)
+}
+
+/// Names of the commands in the category
+pub fn category_commands(flag: u64) -> Vec<&'static str> {
+    COMMAND_TABLE
+        .iter()
+        .map(|(name, _, _)| *name)
+        .filter(|name| COMMANDS[name].categories & flag != 0)
+        .collect()
+}
+
+/// The arguments of a command frame
+pub fn frame_argv(frame: &Frame) -> Vec<Bytes> {
+    match frame {
+        Frame::Array(parts) => parts
+            .iter()
+            .map(|part| match part {
+                Frame::Simple(s) | Frame::Bulk(s) => s.clone(),
+                _ => Bytes::new(),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Find the permissions of the command called with `argv`
+pub fn lookup_command(argv: &[Bytes]) -> Option<&'static CommandPerm> {
+    let name = String::from_utf8_lossy(argv.first()?).to_ascii_lowercase();
+    if CONTAINER_COMMANDS.contains(name.as_str()) {
+        let subcommand = String::from_utf8_lossy(argv.get(1)?).to_ascii_lowercase();
+        return COMMANDS.get(format!("{}|{}", name, subcommand).as_str());
+    }
+    COMMANDS.get(name.as_str())
+}
+
+pub fn hash_password(password: &[u8]) -> String {
+    Sha256::digest(password)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Why a command was refused by the ACL
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AclDenied {
+    Command,
+    Key,
+    Channel,
+}
+
+impl AclDenied {
+    pub fn to_frame(self, command: &str) -> Frame {
+        let msg = match self {
+            AclDenied::Command => format!(
+                "NOPERM this user has no permissions to run the '{}' command",
+                command
+            ),
+            AclDenied::Key => {
+                "NOPERM this user has no permissions to access one of the keys used as arguments"
+                    .to_owned()
+            }
+            AclDenied::Channel => {
+                "NOPERM this user has no permissions to access one of the channels used as arguments"
+                    .to_owned()
+            }
+        };
+        Frame::Error(Bytes::from(msg))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct User {
+    pub name: Bytes,
+    pub enabled: bool,
+    /// any password is accepted
+    pub nopass: bool,
+    /// hex encoded SHA256 of the passwords
+    pub passwords: Vec<String>,
+    /// the user may run any command, including the ones not in the table
+    pub all_commands: bool,
+    pub allowed_commands: HashSet<&'static str>,
+    /// the command rules applied since the last +@all or -@all, used to
+    /// describe the user
+    pub command_rules: String,
+    pub all_keys: bool,
+    pub key_patterns: Vec<(Bytes, u8)>,
+    pub all_channels: bool,
+    pub channel_patterns: Vec<Bytes>,
+}
+
+impl User {
+    /// Create a user with no permissions at all
+    pub fn new(name: Bytes) -> User {
+        User {
+            name,
+            enabled: false,
+            nopass: false,
+            passwords: Vec::new(),
+            all_commands: false,
+            allowed_commands: HashSet::new(),
+            command_rules: "-@all".to_owned(),
+            all_keys: false,
+            key_patterns: Vec::new(),
+            all_channels: false,
+            channel_patterns: Vec::new(),
+        }
+    }
+
+    /// The default user, used by the connections that did not authenticate
+    pub fn new_default() -> User {
+        let mut user = User::new(Bytes::from_static(b"default"));
+        for op in ["on", "nopass", "~*", "&*", "+@all"] {
+            user.set(op.as_bytes()).unwrap();
+        }
+        user
+    }
+
+    /// Apply an ACL rule to the user, see ACL SETUSER for the syntax
+    pub fn set(&mut self, op: &[u8]) -> Result<()> {
+        let syntax_err = || Error::new(ErrorKind::InvalidInput, "Syntax error");
+
+        match &op.to_ascii_lowercase()[..] {
+            b"on" => self.enabled = true,
+            b"off" => self.enabled = false,
+            b"nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            b"resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            b"allkeys" | b"~*" => {
+                self.all_keys = true;
+                self.key_patterns.clear();
+            }
+            b"resetkeys" => {
+                self.all_keys = false;
+                self.key_patterns.clear();
+            }
+            b"allchannels" | b"&*" => {
+                self.all_channels = true;
+                self.channel_patterns.clear();
+            }
+            b"resetchannels" => {
+                self.all_channels = false;
+                self.channel_patterns.clear();
+            }
+            b"allcommands" | b"+@all" => self.set_all_commands(true),
+            b"nocommands" | b"-@all" => self.set_all_commands(false),
+            b"reset" => {
+                for op in ["resetpass", "resetkeys", "resetchannels", "off", "-@all"] {
+                    self.set(op.as_bytes())?;
+                }
+            }
+            _ => match op.first() {
+                Some(b'>') => {
+                    self.add_password(hash_password(&op[1..]));
+                }
+                Some(b'#') => {
+                    let hash = std::str::from_utf8(&op[1..]).map_err(|_| syntax_err())?;
+                    if hash.len() != 64 || !hash.bytes().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            "The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters",
+                        ));
+                    }
+                    self.add_password(hash.to_ascii_lowercase());
+                }
+                Some(b'<') => self.remove_password(&hash_password(&op[1..]))?,
+                Some(b'!') => {
+                    let hash = String::from_utf8_lossy(&op[1..]).to_ascii_lowercase();
+                    self.remove_password(&hash)?;
+                }
+                Some(b'~') => {
+                    self.add_key_pattern(Bytes::copy_from_slice(&op[1..]), ACL_ALL_PERMISSION)?
+                }
+                Some(b'%') => {
+                    // %R~<pattern>, %W~<pattern> or %RW~<pattern>
+                    let offset = op.iter().position(|&c| c == b'~').ok_or_else(syntax_err)?;
+                    let mut flags = 0;
+                    for c in &op[1..offset] {
+                        flags |= match c.to_ascii_uppercase() {
+                            b'R' => ACL_READ_PERMISSION,
+                            b'W' => ACL_WRITE_PERMISSION,
+                            _ => return Err(syntax_err()),
+                        };
+                    }
+                    if flags == 0 {
+                        return Err(syntax_err());
+                    }
+                    self.add_key_pattern(Bytes::copy_from_slice(&op[offset + 1..]), flags)?;
+                }
+                Some(b'&') => {
+                    if self.all_channels {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            "Adding a pattern after the * pattern (or the 'allchannels' flag) is not valid and does not have any effect. Try 'resetchannels' to start with an empty list of channels",
+                        ));
+                    }
+                    let pattern = Bytes::copy_from_slice(&op[1..]);
+                    if !self.channel_patterns.contains(&pattern) {
+                        self.channel_patterns.push(pattern);
+                    }
+                }
+                Some(&c @ (b'+' | b'-')) => {
+                    let allow = c == b'+';
+                    let name = String::from_utf8_lossy(&op[1..]).to_ascii_lowercase();
+                    let commands = match name.strip_prefix('@') {
+                        Some(category) => category_flag(category)
+                            .map(category_commands)
+                            .ok_or_else(|| {
+                                Error::new(
+                                    ErrorKind::InvalidInput,
+                                    "Unknown command or category name in ACL",
+                                )
+                            })?,
+                        None => self.rule_commands(&name)?,
+                    };
+                    for command in commands {
+                        if allow {
+                            self.allowed_commands.insert(command);
+                        } else {
+                            self.allowed_commands.remove(command);
+                        }
+                    }
+                    if !allow {
+                        self.all_commands = false;
+                    }
+                    self.command_rules.push(' ');
+                    self.command_rules.push(c as char);
+                    self.command_rules.push_str(&name);
+                }
+                _ => return Err(syntax_err()),
+            },
+        }
+        Ok(())
+    }
+
+    fn set_all_commands(&mut self, allow: bool) {
+        self.all_commands = allow;
+        if allow {
+            self.allowed_commands = COMMANDS.keys().copied().collect();
+            self.command_rules = "+@all".to_owned();
+        } else {
+            self.allowed_commands.clear();
+            self.command_rules = "-@all".to_owned();
+        }
+    }
+
+    /// Commands affected by a +<command> or -<command> rule, a container
+    /// command affects all its subcommands
+    fn rule_commands(&self, name: &str) -> Result<Vec<&'static str>> {
+        if let Some((name, _)) = COMMANDS.get_key_value(name) {
+            return Ok(vec![name]);
+        }
+        if CONTAINER_COMMANDS.contains(name) {
+            return Ok(COMMAND_TABLE
+                .iter()
+                .map(|(command, _, _)| *command)
+                .filter(|command| command.split_once('|').map(|(c, _)| c) == Some(name))
+                .collect());
+        }
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Unknown command or category name in ACL",
+        ))
+    }
+
+    fn add_password(&mut self, hash: String) {
+        self.nopass = false;
+        if !self.passwords.contains(&hash) {
+            self.passwords.push(hash);
+        }
+    }
+
+    fn remove_password(&mut self, hash: &str) -> Result<()> {
+        match self.passwords.iter().position(|p| p == hash) {
+            Some(index) => {
+                self.passwords.remove(index);
+                Ok(())
+            }
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                "The password you are trying to remove from the user does not exist",
+            )),
+        }
+    }
+
+    fn add_key_pattern(&mut self, pattern: Bytes, flags: u8) -> Result<()> {
+        if self.all_keys {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Adding a pattern after the * pattern (or the 'allkeys' flag) is not valid and does not have any effect. Try 'resetkeys' to start with an empty list of patterns",
+            ));
+        }
+        match self.key_patterns.iter_mut().find(|(p, _)| *p == pattern) {
+            Some((_, f)) => *f |= flags,
+            None => self.key_patterns.push((pattern, flags)),
+        }
+        Ok(())
+    }
+
+    pub fn check_password(&self, password: &[u8]) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        self.nopass || self.passwords.contains(&hash_password(password))
+    }
+
+    /// Check whether the user can run the command called with `argv`
+    pub fn check_command_permission(
+        &self,
+        perm: Option<&CommandPerm>,
+        argv: &[Bytes],
+    ) -> std::result::Result<(), AclDenied> {
+        let perm = match perm {
+            Some(perm) => perm,
+            // not in the table, only users allowed to run anything can
+            None if self.all_commands => return Ok(()),
+            None => return Err(AclDenied::Command),
+        };
+
+        if !self.allowed_commands.contains(perm.name) {
+            return Err(AclDenied::Command);
+        }
+
+        let mut needed = 0;
+        if perm.categories & ACL_CATEGORY_WRITE != 0 {
+            needed |= ACL_WRITE_PERMISSION;
+        }
+        if perm.categories & ACL_CATEGORY_READ != 0 || needed == 0 {
+            needed |= ACL_READ_PERMISSION;
+        }
+        let key_allowed = |key: &Bytes| {
+            self.key_patterns.iter().any(|(pattern, flags)| {
+                flags & needed == needed && shared::string_match(pattern, key, false)
+            })
+        };
+
+        match perm.args {
+            AclArgs::None => {}
+            AclArgs::Keys(first, last, step) if !self.all_keys => {
+                for i in (first..=last_index(argv, last)).step_by(step) {
+                    let key = match argv.get(i) {
+                        Some(key) => key,
+                        None => break,
+                    };
+                    if !key_allowed(key) {
+                        return Err(AclDenied::Key);
+                    }
+                }
+            }
+            AclArgs::NumKeys(first, numkeys) if !self.all_keys => {
+                let count = argv
+                    .get(numkeys)
+                    .and_then(|n| std::str::from_utf8(n).ok())
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .unwrap_or(0);
+                let keys = argv.iter().take(numkeys).skip(first);
+                if !keys
+                    .chain(argv.iter().skip(numkeys + 1).take(count))
+                    .all(key_allowed)
+                {
+                    return Err(AclDenied::Key);
+                }
+            }
+            AclArgs::Streams if !self.all_keys => {
+                // skip the options and their values, a group or consumer
+                // may be named STREAMS
+                let mut i = 1;
+                while i < argv.len() && !argv[i].eq_ignore_ascii_case(b"streams") {
+                    i += match &argv[i].to_ascii_lowercase()[..] {
+                        b"group" => 3,
+                        b"count" | b"block" => 2,
+                        _ => 1,
+                    };
+                }
+                let args = argv.get(i + 1..).unwrap_or_default();
+                if !args[..args.len() / 2].iter().all(key_allowed) {
+                    return Err(AclDenied::Key);
+                }
+            }
+            AclArgs::Channels(first, last) if !self.all_channels => {
+                for channel in argv.iter().take(last_index(argv, last) + 1).skip(first) {
+                    if !self
+                        .channel_patterns
+                        .iter()
+                        .any(|pattern| shared::string_match(pattern, channel, false))
+                    {
+                        return Err(AclDenied::Channel);
+                    }
+                }
+            }
+            AclArgs::Patterns(first, last) if !self.all_channels => {
+                // a pattern subscription must be allowed literally
+                for pattern in argv.iter().take(last_index(argv, last) + 1).skip(first) {
+                    if !self.channel_patterns.contains(pattern) {
+                        return Err(AclDenied::Channel);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// The flags reported by ACL GETUSER
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    pub fn describe_keys(&self) -> String {
+        if self.all_keys {
+            return "~*".to_owned();
+        }
+        self.key_patterns
+            .iter()
+            .map(|(pattern, flags)| {
+                let prefix = match *flags {
+                    ACL_READ_PERMISSION => "%R~",
+                    ACL_WRITE_PERMISSION => "%W~",
+                    _ => "~",
+                };
+                format!("{}{}", prefix, String::from_utf8_lossy(pattern))
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn describe_channels(&self) -> String {
+        if self.all_channels {
+            return "&*".to_owned();
+        }
+        if self.channel_patterns.is_empty() {
+            return "resetchannels".to_owned();
+        }
+        self.channel_patterns
+            .iter()
+            .map(|pattern| format!("&{}", String::from_utf8_lossy(pattern)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The rules that recreate the user, as shown by ACL LIST and stored in
+    /// the ACL file
+    pub fn describe(&self) -> String {
+        let mut rules: Vec<String> = self.flags().into_iter().map(String::from).collect();
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        let keys = self.describe_keys();
+        if !keys.is_empty() {
+            rules.push(keys);
+        }
+        rules.push(self.describe_channels());
+        rules.push(self.command_rules.clone());
+        rules.join(" ")
+    }
+}
+
+fn last_index(argv: &[Bytes], last: isize) -> usize {
+    if last < 0 {
+        (argv.len() as isize + last).max(0) as usize
+    } else {
+        last as usize
+    }
+}
+
+/// Server-wide table of the ACL users
+#[derive(Debug)]
+pub struct Acl {
+    pub users: BTreeMap<Bytes, User>,
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Acl {
+    pub fn new() -> Acl {
+        let default = User::new_default();
+        Acl {
+            users: BTreeMap::from([(default.name.clone(), default)]),
+        }
+    }
+
+    pub fn default_user(&self) -> &User {
+        &self.users[b"default" as &[u8]]
+    }
+
+    /// Connections must authenticate if the default user requires a password
+    pub fn auth_required(&self) -> bool {
+        let default = self.default_user();
+        !default.nopass || !default.enabled
+    }
+
+    /// Update the default user password as set by the requirepass option
+    pub fn set_requirepass(&mut self, password: Option<&str>) {
+        let default = self
+            .users
+            .get_mut(b"default" as &[u8])
+            .expect("the default user always exists");
+        default.set(b"resetpass").unwrap();
+        match password {
+            Some(password) if !password.is_empty() => {
+                default.set(format!(">{}", password).as_bytes()).unwrap()
+            }
+            _ => default.set(b"nopass").unwrap(),
+        }
+    }
+
+    /// Check the credentials of AUTH
+    pub fn authenticate(&self, username: &[u8], password: &[u8]) -> bool {
+        self.users
+            .get(username)
+            .is_some_and(|user| user.check_password(password))
+    }
+
+    /// Parse the users of an ACL file, one "user <name> <rules>..." per line.
+    /// The default user keeps its current rules if the file doesn't define it.
+    pub fn parse_users(&self, content: &str) -> Result<BTreeMap<Bytes, User>> {
+        let mut users = BTreeMap::new();
+        for (lineno, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let name = match (parts.next(), parts.next()) {
+                (Some("user"), Some(name)) => Bytes::copy_from_slice(name.as_bytes()),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("/{}: line should start with user keyword", lineno + 1),
+                    ))
+                }
+            };
+            if users.contains_key(&name) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "/{}: Duplicate user '{}' found",
+                        lineno + 1,
+                        String::from_utf8_lossy(&name)
+                    ),
+                ));
+            }
+            let mut user = User::new(name.clone());
+            for op in parts {
+                user.set(op.as_bytes()).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("/{}: {}. Error in user declaration '{}'", lineno + 1, e, op),
+                    )
+                })?;
+            }
+            users.insert(name, user);
+        }
+
+        let default = self.default_user();
+        users
+            .entry(default.name.clone())
+            .or_insert_with(|| default.clone());
+        Ok(users)
+    }
+
+    pub fn load_file(&mut self, filename: &str) -> Result<()> {
+        let content = std::fs::read_to_string(filename)?;
+        self.users = self.parse_users(&content)?;
+        Ok(())
+    }
+
+    pub fn save_file(&self, filename: &str) -> Result<()> {
+        let mut content = String::new();
+        for user in self.users.values() {
+            content.push_str(&format!(
+                "user {} {}\n",
+                String::from_utf8_lossy(&user.name),
+                user.describe()
+            ));
+        }
+
+        // write to a temporary file first, so that the old file is kept on
+        // failures
+        let tmpfile = format!("{}.tmp-{}", filename, shared::get_pid());
+        std::fs::write(&tmpfile, content)?;
+        std::fs::rename(&tmpfile, filename)
+    }
+}
+
+impl Client {
+    /// Check the authentication and the ACL permissions of the command
+    /// called with `argv` before it is parsed, return the error to reply with
+    /// if it is refused. Fails if the user of the client was deleted.
+    pub async fn acl_check_command(
+        &self,
+        argv: &[Bytes],
+        perm: Option<&CommandPerm>,
+    ) -> Result<Option<Frame>> {
+        if argv.is_empty() {
+            return Ok(None);
+        }
+
+        let acl = self.server.acl.read().await;
+
+        if acl.auth_required() && !self.authenticated && !perm.is_some_and(|p| p.no_auth) {
+            return Ok(Some(Frame::Error(Bytes::from_static(
+                b"NOAUTH Authentication required.",
+            ))));
+        }
+
+        let user = acl.users.get(&self.user).ok_or_else(|| {
+            Error::new(
+                ErrorKind::PermissionDenied,
+                "the user of the client was deleted",
+            )
+        })?;
+
+        match user.check_command_permission(perm, argv) {
+            Ok(()) => Ok(None),
+            Err(denied) => {
+                let name = match perm {
+                    Some(perm) => perm.name.to_owned(),
+                    None => argv
+                        .first()
+                        .map(|name| String::from_utf8_lossy(name).to_ascii_lowercase())
+                        .unwrap_or_default(),
+                };
+                log::debug!(
+                    "ACL denied {:?} of {} to user {}",
+                    denied,
+                    name,
+                    String::from_utf8_lossy(&user.name)
+                );
+                Ok(Some(denied.to_frame(&name)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(rules: &str) -> User {
+        let mut user = User::new(Bytes::from_static(b"alice"));
+        for op in rules.split_whitespace() {
+            user.set(op.as_bytes()).unwrap();
+        }
+        user
+    }
+
+    fn check(user: &User, command: &str) -> std::result::Result<(), AclDenied> {
+        let argv: Vec<Bytes> = command
+            .split_whitespace()
+            .map(|arg| Bytes::copy_from_slice(arg.as_bytes()))
+            .collect();
+        user.check_command_permission(lookup_command(&argv), &argv)
+    }
+
+    #[test]
+    fn command_rules() {
+        let alice = user("on nopass ~* +@read -hgetall +set");
+        assert_eq!(check(&alice, "GET k"), Ok(()));
+        assert_eq!(check(&alice, "HGETALL k"), Err(AclDenied::Command));
+        assert_eq!(check(&alice, "SET k v"), Ok(()));
+        assert_eq!(check(&alice, "DEL k"), Err(AclDenied::Command));
+        // not in the command table
+        assert_eq!(check(&alice, "NOSUCHCOMMAND"), Err(AclDenied::Command));
+        assert_eq!(check(&user("+@all"), "NOSUCHCOMMAND"), Ok(()));
+        // a -<command> after +@all drops the commands outside the table
+        assert_eq!(
+            check(&user("+@all -get"), "NOSUCHCOMMAND"),
+            Err(AclDenied::Command)
+        );
+
+        // a container command covers its subcommands
+        let admin = user("~* +config -config|set");
+        assert_eq!(check(&admin, "CONFIG GET maxmemory"), Ok(()));
+        assert_eq!(
+            check(&admin, "config set maxmemory 1"),
+            Err(AclDenied::Command)
+        );
+
+        assert_eq!(
+            alice.describe(),
+            "on nopass ~* resetchannels -@all +@read -hgetall +set"
+        );
+        assert!(User::new(Bytes::new()).set(b"+@nosuchcategory").is_err());
+        assert!(User::new(Bytes::new()).set(b"+nosuchcommand").is_err());
+        assert!(User::new(Bytes::new()).set(b"bogus").is_err());
+    }
+
+    #[test]
+    fn key_patterns() {
+        let alice = user("+@all ~cache:* %R~ro:* %W~wo:*");
+        assert_eq!(check(&alice, "GET cache:1"), Ok(()));
+        assert_eq!(check(&alice, "GET other"), Err(AclDenied::Key));
+        assert_eq!(check(&alice, "GET ro:1"), Ok(()));
+        assert_eq!(check(&alice, "SET ro:1 v"), Err(AclDenied::Key));
+        assert_eq!(check(&alice, "SET wo:1 v"), Ok(()));
+        assert_eq!(check(&alice, "GET wo:1"), Err(AclDenied::Key));
+        // every key of the command is checked
+        assert_eq!(check(&alice, "MSET cache:1 v cache:2 v"), Ok(()));
+        assert_eq!(check(&alice, "MSET cache:1 v other v"), Err(AclDenied::Key));
+        assert_eq!(check(&alice, "MGET cache:1 ro:1"), Ok(()));
+        // the values aren't keys
+        assert_eq!(check(&alice, "MSET cache:1 other"), Ok(()));
+        // the timeout of BLPOP isn't a key
+        assert_eq!(check(&alice, "BLPOP cache:1 cache:2 0"), Ok(()));
+        assert_eq!(check(&alice, "BLPOP cache:1 other 0"), Err(AclDenied::Key));
+        // the keys counted by numkeys
+        assert_eq!(check(&alice, "LMPOP 2 cache:1 cache:2 LEFT"), Ok(()));
+        assert_eq!(
+            check(&alice, "LMPOP 2 cache:1 other LEFT"),
+            Err(AclDenied::Key)
+        );
+        // the streams, not the IDs
+        assert_eq!(check(&alice, "XREAD COUNT 1 STREAMS cache:1 $"), Ok(()));
+        assert_eq!(
+            check(&alice, "XREAD STREAMS cache:1 other 0 0"),
+            Err(AclDenied::Key)
+        );
+        assert_eq!(alice.describe_keys(), "~cache:* %R~ro:* %W~wo:*");
+
+        assert!(user("~*").set(b"~foo").is_err());
+        assert!(user("").set(b"%X~foo").is_err());
+    }
+
+    #[test]
+    fn channel_patterns() {
+        let alice = user("+@all ~* &news.*");
+        assert_eq!(check(&alice, "SUBSCRIBE news.tech news.art"), Ok(()));
+        assert_eq!(
+            check(&alice, "SUBSCRIBE news.tech sport"),
+            Err(AclDenied::Channel)
+        );
+        assert_eq!(check(&alice, "PUBLISH news.tech hello"), Ok(()));
+        assert_eq!(
+            check(&alice, "PUBLISH sport hello"),
+            Err(AclDenied::Channel)
+        );
+        // a pattern subscription must match a pattern literally
+        assert_eq!(check(&alice, "PSUBSCRIBE news.*"), Ok(()));
+        assert_eq!(check(&alice, "PSUBSCRIBE news.t*"), Err(AclDenied::Channel));
+        assert_eq!(check(&user("+@all ~* &*"), "PSUBSCRIBE *"), Ok(()));
+        assert!(user("&*").set(b"&foo").is_err());
+    }
+
+    #[test]
+    fn passwords() {
+        let mut alice = user("on >secret >other");
+        assert!(alice.check_password(b"secret"));
+        assert!(alice.check_password(b"other"));
+        assert!(!alice.check_password(b"wrong"));
+        alice.set(b"<other").unwrap();
+        assert!(!alice.check_password(b"other"));
+        assert!(alice.set(b"<other").is_err());
+        alice.set(b"off").unwrap();
+        assert!(!alice.check_password(b"secret"));
+
+        let hash = hash_password(b"secret");
+        let bob = user(&format!("on #{}", hash));
+        assert!(bob.check_password(b"secret"));
+        assert!(User::new(Bytes::new()).set(b"#abc").is_err());
+
+        let mut acl = Acl::new();
+        assert!(!acl.auth_required());
+        acl.set_requirepass(Some("foobared"));
+        assert!(acl.auth_required());
+        assert!(acl.authenticate(b"default", b"foobared"));
+        assert!(!acl.authenticate(b"default", b"wrong"));
+        assert!(!acl.authenticate(b"nobody", b"foobared"));
+    }
+
+    #[test]
+    fn parse_acl_file() {
+        let acl = Acl::new();
+        let users = acl
+            .parse_users("# comment\nuser alice on nopass ~k* +get\n\nuser bob off\n")
+            .unwrap();
+        assert_eq!(users.len(), 3);
+        let alice = &users[b"alice" as &[u8]];
+        assert_eq!(check(alice, "GET k1"), Ok(()));
+        assert_eq!(check(alice, "GET x"), Err(AclDenied::Key));
+        // the default user is kept
+        assert!(users[b"default" as &[u8]].all_commands);
+
+        let err = acl
+            .parse_users("user alice on\nuser alice off")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "/2: Duplicate user 'alice' found");
+        assert!(acl.parse_users("alice on").is_err());
+        assert!(acl.parse_users("user alice bogus").is_err());
+    }
+}
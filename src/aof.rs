@@ -1,22 +1,23 @@
-use crate::client::{Client, ClientInner};
+use crate::client::{Client, REDIS_MULTI};
 use crate::command::Command;
 use crate::config::ConfigRef;
 use crate::frame::Frame;
-use crate::object::{RudisHash, RudisList, RudisObject, RudisSet, RudisString, RudisZSet};
+use crate::object::{
+    RudisHash, RudisList, RudisObject, RudisSet, RudisStream, RudisString, RudisZSet,
+};
 use crate::server::Server;
 use crate::shared;
 use bytes::{Buf, Bytes, BytesMut};
 use libc::pid_t;
 use std::fmt::Display;
+use std::io::Result;
 use std::io::Write;
-use std::io::{Cursor, Result};
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::ops::{Deref, DerefMut};
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::RwLock;
 use tokio::sync::RwLockWriteGuard;
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -156,6 +157,13 @@ impl AofState {
                 self.aof_buf.advance(n_written);
                 self.aof_last_write_status = true;
 
+                // release the written buffer unless it is small enough to be
+                // reused, it may have grown large and it is not counted
+                // against maxmemory
+                if self.aof_buf.is_empty() && n_written + self.aof_buf.capacity() > 4000 {
+                    self.aof_buf.buffer = BytesMut::new();
+                }
+
                 match aof_fsync {
                     AofFsync::Always => {
                         aof_file.sync_data().await?;
@@ -257,6 +265,83 @@ impl AofWriter {
             self.extend_bulk_string(&member[..]);
         }
     }
+
+    fn extend_command<'a>(&mut self, args: impl IntoIterator<Item = &'a [u8]>) {
+        self.buffer.extend_from_slice(&shared::rewrite_args(args));
+    }
+
+    /// Rewrite the entries, then the ID counters and the consumer groups,
+    /// see rewriteStreamObject in redis
+    fn rewrite_stream(&mut self, key: &Bytes, stream: &RudisStream) {
+        for (id, fields) in stream.entries.iter() {
+            // "XADD key id field value [field value ...]"
+            let id = id.to_string();
+            self.extend_command(
+                [b"XADD" as &[u8], key, id.as_bytes()]
+                    .into_iter()
+                    .chain(fields.iter().flat_map(|(f, v)| [&f[..], &v[..]])),
+            );
+        }
+        if stream.is_empty() {
+            // an empty stream is created by trimming the one entry added
+            self.extend_command([b"XADD" as &[u8], key, b"MAXLEN", b"0", b"0-1", b"x", b"y"]);
+        }
+        let last_id = stream.last_id.to_string();
+        let entries_added = stream.entries_added.to_string();
+        let max_deleted_id = stream.max_deleted_id.to_string();
+        self.extend_command([
+            b"XSETID" as &[u8],
+            key,
+            last_id.as_bytes(),
+            b"ENTRIESADDED",
+            entries_added.as_bytes(),
+            b"MAXDELETEDID",
+            max_deleted_id.as_bytes(),
+        ]);
+
+        for (name, group) in stream.groups.iter() {
+            let last_id = group.last_id.to_string();
+            let entries_read = group.entries_read.map(|n| n.to_string());
+            let mut args = vec![b"XGROUP" as &[u8], b"CREATE", key, name, last_id.as_bytes()];
+            if let Some(entries_read) = &entries_read {
+                args.extend([b"ENTRIESREAD" as &[u8], entries_read.as_bytes()]);
+            }
+            self.extend_command(args);
+
+            for (id, pending) in group.pel.iter() {
+                // "XCLAIM key group consumer 0 id TIME ms RETRYCOUNT count
+                // JUSTID FORCE"
+                let id = id.to_string();
+                let time = pending.delivery_time.to_string();
+                let count = pending.delivery_count.to_string();
+                self.extend_command([
+                    b"XCLAIM" as &[u8],
+                    key,
+                    name,
+                    &pending.consumer,
+                    b"0",
+                    id.as_bytes(),
+                    b"TIME",
+                    time.as_bytes(),
+                    b"RETRYCOUNT",
+                    count.as_bytes(),
+                    b"JUSTID",
+                    b"FORCE",
+                ]);
+            }
+            for (consumer_name, consumer) in group.consumers.iter() {
+                if consumer.pending.is_empty() {
+                    self.extend_command([
+                        b"XGROUP" as &[u8],
+                        b"CREATECONSUMER",
+                        key,
+                        name,
+                        consumer_name,
+                    ]);
+                }
+            }
+        }
+    }
 }
 
 impl Deref for AofWriter {
@@ -284,35 +369,39 @@ impl Server {
 
             let mut aof = AofWriter::default();
 
-            // for db in self.snapshot().iter() {
-            let db = self.get(0);
-            // "SELECT index"
-            aof.extend_array(2);
-            aof.extend_bulk_string(b"SELECT" as &[u8]);
-            aof.extend_bulk_string(db.index.to_string().as_bytes());
-
-            for it in db.iter() {
-                if it.is_expired() {
+            for db in self.databases() {
+                if db.is_empty() {
                     continue;
                 }
 
-                match &it.value {
-                    RudisObject::String(s) => aof.rewrite_string(&it.key(), &s),
-                    RudisObject::List(l) => aof.rewrite_list(&it.key(), &l),
-                    RudisObject::Set(s) => aof.rewrite_set(&it.key(), &s),
-                    RudisObject::Hash(h) => aof.rewrite_hash(&it.key(), &h),
-                    RudisObject::ZSet(z) => aof.rewrite_zset(&it.key(), &z),
-                }
+                // "SELECT index"
+                aof.extend_array(2);
+                aof.extend_bulk_string(b"SELECT" as &[u8]);
+                aof.extend_bulk_string(db.index.to_string().as_bytes());
 
-                if let Some(expire) = &it.expire_at {
-                    // "PEXPIREAT key timestamp"
-                    aof.extend_array(3);
-                    aof.extend_bulk_string(b"PEXPIREAT" as &[u8]);
-                    aof.extend_bulk_string(&it.key()[..]);
-                    aof.extend_bulk_string(expire.to_string().as_bytes());
+                for it in db.iter() {
+                    if it.is_expired() {
+                        continue;
+                    }
+
+                    match &it.value {
+                        RudisObject::String(s) => aof.rewrite_string(it.key(), s),
+                        RudisObject::List(l) => aof.rewrite_list(it.key(), l),
+                        RudisObject::Set(s) => aof.rewrite_set(it.key(), s),
+                        RudisObject::Hash(h) => aof.rewrite_hash(it.key(), h),
+                        RudisObject::ZSet(z) => aof.rewrite_zset(it.key(), z),
+                        RudisObject::Stream(s) => aof.rewrite_stream(it.key(), s),
+                    }
+
+                    if let Some(expire) = &it.expire_at {
+                        // "PEXPIREAT key timestamp"
+                        aof.extend_array(3);
+                        aof.extend_bulk_string(b"PEXPIREAT" as &[u8]);
+                        aof.extend_bulk_string(&it.key()[..]);
+                        aof.extend_bulk_string(expire.to_string().as_bytes());
+                    }
                 }
             }
-            // }
 
             file.write_all(&aof);
             file.flush();
@@ -379,24 +468,105 @@ impl Server {
     }
 
     pub async fn feed_append_only_file(&self, cmd: Command, db_index: u32) -> Result<()> {
+        self.feed_append_only_file_commands(vec![(cmd, db_index)], false)
+            .await
+    }
+
+    /// Feed the commands executed by EXEC wrapped in MULTI ... EXEC, so that
+    /// the transaction is replayed atomically
+    pub async fn feed_append_only_file_transaction(&self, cmds: Vec<(Command, u32)>) -> Result<()> {
+        self.feed_append_only_file_commands(cmds, true).await
+    }
+
+    async fn feed_append_only_file_commands(
+        &self,
+        cmds: Vec<(Command, u32)>,
+        transaction: bool,
+    ) -> Result<()> {
         let mut buf = BytesMut::new();
         let mut aof_state = self.aof_state.write().await;
 
-        if aof_state.aof_selected_db != Some(db_index) {
-            // emit "SELECT index"
-            shared::extend_array(&mut buf, 2);
-            shared::extend_bulk_string(&mut buf, b"SELECT" as &[u8]);
-            shared::extend_bulk_string(&mut buf, db_index.to_string().as_bytes());
-            aof_state.aof_selected_db = Some(db_index);
+        for (cmd, db_index) in cmds {
+            let mut cmd_buf = BytesMut::new();
+            Self::cat_append_only_command(&mut cmd_buf, cmd);
+            if cmd_buf.is_empty() {
+                // nothing to persist
+                continue;
+            }
+
+            if transaction && buf.is_empty() {
+                // "MULTI"
+                shared::extend_array(&mut buf, 1);
+                shared::extend_bulk_string(&mut buf, b"MULTI" as &[u8]);
+            }
+
+            if aof_state.aof_selected_db != Some(db_index) {
+                // emit "SELECT index"
+                shared::extend_array(&mut buf, 2);
+                shared::extend_bulk_string(&mut buf, b"SELECT" as &[u8]);
+                shared::extend_bulk_string(&mut buf, db_index.to_string().as_bytes());
+                aof_state.aof_selected_db = Some(db_index);
+            }
+
+            buf.extend_from_slice(&cmd_buf);
         }
 
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        if transaction {
+            // "EXEC"
+            shared::extend_array(&mut buf, 1);
+            shared::extend_bulk_string(&mut buf, b"EXEC" as &[u8]);
+        }
+
+        if self.config.read().await.aof_state == AofOption::On {
+            aof_state.aof_buf.extend_from_slice(&buf);
+        }
+
+        if aof_state.aof_child_pid.is_some() {
+            // aof full rewrite in progress
+            aof_state.aof_rewrite_buffer_append(&buf);
+        }
+
+        Ok(())
+    }
+
+    /// Append the AOF representation of a write command to the buffer
+    fn cat_append_only_command(buf: &mut BytesMut, cmd: Command) {
         match cmd {
             Command::Ping(_cmd) => {}
             Command::Echo(_cmd) => {}
             Command::Quit(_cmd) => {}
+            Command::Hello(_cmd) => {}
             Command::Get(_cmd) => {}
             Command::Set(cmd) => buf.extend_from_slice(&cmd.rewrite()),
             Command::SetNx(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::SetEx(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::PSetEx(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::MGet(_cmd) => {}
+            Command::MSet(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::MSetNx(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::GetSet(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::GetDel(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::GetEx(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::GetRange(_cmd) => {}
+            Command::SetRange(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            // the resulting value is propagated as SET instead
+            Command::IncrByFloat(_cmd) => {}
+            Command::Lcs(_cmd) => {}
+            Command::SetBit(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::GetBit(_cmd) => {}
+            Command::BitCount(_cmd) => {}
+            Command::BitPos(_cmd) => {}
+            Command::BitOp(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::BitField(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::BitFieldRo(_cmd) => {}
+            Command::PfAdd(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            // only the cached cardinality may change
+            Command::PfCount(_cmd) => {}
+            Command::PfMerge(cmd) => buf.extend_from_slice(&cmd.rewrite()),
             Command::Append(cmd) => buf.extend_from_slice(&cmd.rewrite()),
             Command::Strlen(_cmd) => {}
             Command::Incr(cmd) => buf.extend_from_slice(&cmd.rewrite()),
@@ -407,6 +577,7 @@ impl Server {
             Command::Exists(_cmd) => {}
             Command::Select(_cmd) => {}
             Command::Keys(_cmd) => {}
+            Command::Scan(_cmd) => {}
             Command::DbSize(_cmd) => {}
             Command::Shutdown(_cmd) => {}
             Command::Rename(cmd) => buf.extend_from_slice(&cmd.rewrite()),
@@ -418,31 +589,138 @@ impl Server {
             Command::RPush(cmd) => buf.extend_from_slice(&cmd.rewrite()),
             Command::LPop(cmd) => buf.extend_from_slice(&cmd.rewrite()),
             Command::RPop(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::LPushX(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::RPushX(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::LLen(_cmd) => {}
+            Command::LRange(_cmd) => {}
+            Command::LIndex(_cmd) => {}
+            Command::LSet(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::LInsert(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::LRem(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::LTrim(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::LPos(_cmd) => {}
+            Command::LMove(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::RPopLPush(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::LMPop(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            // the pop actually done is propagated instead
+            Command::BLPop(_cmd) => {}
+            Command::BRPop(_cmd) => {}
+            Command::BLMPop(_cmd) => {}
+            Command::BLMove(_cmd) => {}
             Command::HSet(cmd) => buf.extend_from_slice(&cmd.rewrite()),
             Command::HGet(_cmd) => {}
+            Command::HMSet(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::HSetNx(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::HMGet(_cmd) => {}
+            Command::HDel(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::HExists(_cmd) => {}
+            Command::HLen(_cmd) => {}
+            Command::HStrLen(_cmd) => {}
+            Command::HKeys(_cmd) => {}
+            Command::HVals(_cmd) => {}
+            Command::HGetAll(_cmd) => {}
+            Command::HScan(_cmd) => {}
+            Command::HIncrBy(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::HIncrByFloat(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::HRandField(_cmd) => {}
             Command::SAdd(cmd) => buf.extend_from_slice(&cmd.rewrite()),
             Command::SRem(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::SMembers(_cmd) => {}
+            Command::SScan(_cmd) => {}
+            Command::SIsMember(_cmd) => {}
+            Command::SMIsMember(_cmd) => {}
+            Command::SCard(_cmd) => {}
+            // the members actually popped are propagated as SREM instead
+            Command::SPop(_cmd) => {}
+            Command::SRandMember(_cmd) => {}
+            Command::SMove(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::SInter(_cmd) => {}
+            Command::SUnion(_cmd) => {}
+            Command::SDiff(_cmd) => {}
+            Command::SInterStore(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::SUnionStore(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::SDiffStore(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::SInterCard(_cmd) => {}
+            Command::ZAdd(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::ZIncrBy(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::ZRem(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::ZScore(_cmd) => {}
+            Command::ZScan(_cmd) => {}
+            Command::ZMScore(_cmd) => {}
+            Command::ZCard(_cmd) => {}
+            Command::ZCount(_cmd) => {}
+            Command::ZRank(_cmd) => {}
+            Command::ZRevRank(_cmd) => {}
+            Command::ZRange(_cmd) => {}
+            Command::ZRangeStore(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::ZPopMin(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::ZPopMax(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::ZRemRangeByRank(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::ZRemRangeByScore(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::ZRemRangeByLex(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::ZUnionStore(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::ZInterStore(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::ZDiffStore(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::XAdd(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::XRange(_cmd) => {}
+            Command::XRevRange(_cmd) => {}
+            Command::XLen(_cmd) => {}
+            Command::XDel(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::XTrim(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::XRead(_cmd) => {}
+            Command::XReadGroup(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::XGroup(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::XAck(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::XPending(_cmd) => {}
+            Command::XClaim(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            // the entries actually claimed are propagated as XCLAIM instead
+            Command::XAutoClaim(_cmd) => {}
+            Command::XInfo(_cmd) => {}
+            Command::XSetId(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::GeoAdd(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::GeoPos(_cmd) => {}
+            Command::GeoDist(_cmd) => {}
+            Command::GeoHash(_cmd) => {}
+            Command::GeoSearch(_cmd) => {}
+            Command::GeoSearchStore(cmd) => buf.extend_from_slice(&cmd.rewrite()),
             Command::Type(_cmd) => {}
+            Command::Multi(_cmd) => {}
+            Command::Exec(_cmd) => {}
+            Command::Discard(_cmd) => {}
+            Command::Watch(_cmd) => {}
+            Command::Unwatch(_cmd) => {}
+            Command::Auth(_cmd) => {}
+            Command::AclSetUser(_cmd) => {}
+            Command::AclGetUser(_cmd) => {}
+            Command::AclDelUser(_cmd) => {}
+            Command::AclList(_cmd) => {}
+            Command::AclUsers(_cmd) => {}
+            Command::AclWhoAmI(_cmd) => {}
+            Command::AclCat(_cmd) => {}
+            Command::AclLoad(_cmd) => {}
+            Command::AclSave(_cmd) => {}
+            Command::Subscribe(_cmd) => {}
+            Command::Unsubscribe(_cmd) => {}
+            Command::PSubscribe(_cmd) => {}
+            Command::PUnsubscribe(_cmd) => {}
+            Command::Publish(_cmd) => {}
+            Command::PubSubChannels(_cmd) => {}
+            Command::PubSubNumSub(_cmd) => {}
+            Command::PubSubNumPat(_cmd) => {}
+            Command::Move(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::SwapDb(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::FlushDb(cmd) => buf.extend_from_slice(&cmd.rewrite()),
+            Command::FlushAll(cmd) => buf.extend_from_slice(&cmd.rewrite()),
             Command::Save(_cmd) => {}
             Command::BgSave(_cmd) => {}
             Command::BgRewriteAof(_cmd) => {}
+            Command::Info(_cmd) => {}
             Command::ConfigGet(_cmd) => {}
             Command::ConfigSet(_cmd) => {}
             Command::ConfigResetStat(_cmd) => {}
             Command::ConfigRewrite(_cmd) => {}
             Command::Unknown(_cmd) => {}
         }
-
-        if self.config.read().await.aof_state == AofOption::On {
-            aof_state.aof_buf.extend_from_slice(&buf);
-        }
-
-        if aof_state.aof_child_pid.is_some() {
-            // aof full rewrite in progress
-            aof_state.aof_rewrite_buffer_append(&buf);
-        }
-
-        Ok(())
     }
 
     /// Trigger by config set
@@ -543,19 +821,11 @@ impl Server {
         };
 
         {
-            let mut fake_client = Client {
-                config: self.config.clone(),
-                server: self.clone(),
-                db: self.get(0),
-                connection: None,
-                address: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0)),
-                inner: RwLock::new(ClientInner {
-                    name: String::new(),
-                    last_interaction: 0,
-                    flags: Default::default(),
-                }),
-                quit_ch: self.quit_ch.subscribe(),
-            };
+            let mut fake_client = Client::new(
+                self.clone(),
+                None,
+                SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0)),
+            );
 
             let mut reader = tokio::io::BufReader::new(
                 OpenOptions::new()
@@ -566,14 +836,11 @@ impl Server {
             let mut buffer = BytesMut::new();
 
             loop {
-                let mut cur = Cursor::new(&buffer);
-                if let Some(frame) = Frame::parse(&mut cur)? {
-                    // if a frame is parsed successfully, advance the buffer
-                    buffer.advance(cur.position() as usize);
-
-                    // handle the command
+                if let Some(frame) = Frame::parse(&mut buffer)? {
+                    // handle the command, MULTI ... EXEC blocks are queued
+                    // and executed as in a real client
                     let cmd = Command::from(frame)?;
-                    fake_client.handle_command(cmd).await?;
+                    fake_client.process_command(cmd).await?;
                 } else {
                     // no enough data, need to read more
                     let n_read = reader.read_buf(&mut buffer).await?;
@@ -582,6 +849,10 @@ impl Server {
                     }
                 }
             }
+
+            if fake_client.flags().await & REDIS_MULTI != 0 {
+                log::warn!("Revert incomplete MULTI/EXEC transaction in AOF file");
+            }
         }
 
         self.config.write().await.aof_state = old_aof_state;
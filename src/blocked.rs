@@ -0,0 +1,260 @@
+use crate::client::{Client, REDIS_BLOCKED};
+use crate::command::{BlockingOp, BlockingPop, XRead};
+use crate::frame::Frame;
+use bytes::Bytes;
+use std::io::Result;
+use std::sync::atomic::Ordering;
+use tokio::time::Instant;
+
+/// A command that blocks until one of its keys is pushed to
+#[derive(Debug, Clone)]
+pub enum BlockingCommand {
+    /// BLPOP, BRPOP, BLMPOP and BLMOVE
+    Pop(BlockingPop),
+    /// XREAD and XREADGROUP
+    Read(XRead),
+}
+
+impl BlockingCommand {
+    pub fn keys(&self) -> &[Bytes] {
+        match self {
+            BlockingCommand::Pop(cmd) => cmd.keys(),
+            BlockingCommand::Read(cmd) => cmd.keys(),
+        }
+    }
+
+    /// Whether serving the command must not interleave with the commands of
+    /// other clients, see Command::is_exclusive
+    pub fn is_exclusive(&self) -> bool {
+        matches!(self, BlockingCommand::Pop(cmd) if matches!(cmd.op, BlockingOp::Move(_)))
+    }
+
+    /// The reply once the timeout is reached
+    pub fn timeout_reply(&self) -> Frame {
+        match self {
+            BlockingCommand::Pop(cmd) => cmd.null_reply(),
+            BlockingCommand::Read(_) => Frame::Null,
+        }
+    }
+
+    /// Reply if the command can be served, None to keep blocking
+    pub fn serve(&self, client: &mut Client) -> Option<Frame> {
+        match self {
+            BlockingCommand::Pop(cmd) => cmd.serve(client),
+            BlockingCommand::Read(cmd) => cmd.serve(client),
+        }
+    }
+}
+
+/// A blocking command waiting for one of its keys to be pushed to, see
+/// blocked.c in redis
+#[derive(Debug)]
+pub struct BlockedState {
+    pub cmd: BlockingCommand,
+    /// None to block forever
+    pub deadline: Option<Instant>,
+}
+
+impl Client {
+    /// Park the client until one of the keys of the command is pushed to or
+    /// the deadline is reached, the clients blocked on a key are served in
+    /// the order they blocked
+    pub fn block_for_keys(&mut self, cmd: BlockingCommand, deadline: Option<Instant>) {
+        for key in cmd.keys() {
+            self.db
+                .block_key(key.clone(), self.id, self.ready_keys.clone());
+        }
+        self.blocked = Some(BlockedState { cmd, deadline });
+        self.handle.flags.fetch_or(REDIS_BLOCKED, Ordering::Relaxed);
+    }
+
+    /// Leave the blocked state, waking the next client blocked on the keys
+    /// that may still have elements
+    pub fn unblock(&mut self) {
+        if let Some(state) = self.blocked.take() {
+            self.handle
+                .flags
+                .fetch_and(!REDIS_BLOCKED, Ordering::Relaxed);
+            for key in state.cmd.keys() {
+                self.db.unblock_key(key, self.id);
+                if self.db.contains_key(key) {
+                    self.db.signal_key_as_ready(key);
+                }
+            }
+        }
+    }
+
+    /// Retry the blocked command once one of its keys was pushed to, the
+    /// client keeps blocking if another one took the elements first
+    pub async fn serve_blocked(&mut self) -> Result<()> {
+        let cmd = match &self.blocked {
+            Some(state) => state.cmd.clone(),
+            None => return Ok(()),
+        };
+        if let Some(reply) = cmd.serve(self) {
+            self.unblock();
+            self.write_frame(&reply).await?;
+            if let Some(cmd) = self.propagate_as.take() {
+                self.propagate(cmd).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reply the null reply of a blocking command that timed out
+    pub async fn timeout_blocked(&mut self) -> Result<()> {
+        let reply = match &self.blocked {
+            Some(state) => state.cmd.timeout_reply(),
+            None => return Ok(()),
+        };
+        self.unblock();
+        self.write_frame(&reply).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{Client, REDIS_BLOCKED};
+    use crate::config::{Config, ConfigRef};
+    use crate::connection::Connection;
+    use crate::frame::Frame;
+    use crate::server::Server;
+    use bytes::BytesMut;
+    use std::net::SocketAddr;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::{sleep, timeout, Duration};
+
+    async fn server() -> (Arc<Server>, SocketAddr) {
+        let server = Server::from_config(ConfigRef::new(Config {
+            rdb_filename: "/nonexistent/dump.rdb".to_owned(),
+            ..Default::default()
+        }))
+        .await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = server.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, address) = listener.accept().await.unwrap();
+                let mut client =
+                    Client::new(accept.clone(), Some(Connection::from(stream)), address);
+                tokio::spawn(async move { client.serve().await });
+            }
+        });
+        (server, addr)
+    }
+
+    struct Conn {
+        stream: TcpStream,
+        buf: BytesMut,
+    }
+
+    impl Conn {
+        async fn connect(addr: SocketAddr) -> Conn {
+            Conn {
+                stream: TcpStream::connect(addr).await.unwrap(),
+                buf: BytesMut::new(),
+            }
+        }
+
+        async fn send(&mut self, args: &[&str]) {
+            let frame = Frame::Array(
+                args.iter()
+                    .map(|a| Frame::new_bulk_from(a.to_string()))
+                    .collect(),
+            );
+            self.stream.write_all(&frame.serialize(2)).await.unwrap();
+        }
+
+        /// The raw bytes of the next reply
+        async fn reply(&mut self) -> Vec<u8> {
+            loop {
+                let len = self.buf.len();
+                let mut buf = self.buf.clone();
+                if Frame::parse(&mut buf).unwrap().is_some() {
+                    return self.buf.split_to(len - buf.len()).to_vec();
+                }
+                let read = timeout(Duration::from_secs(5), self.stream.read_buf(&mut self.buf));
+                assert!(read.await.unwrap().unwrap() > 0, "connection closed");
+            }
+        }
+
+        async fn call(&mut self, args: &[&str]) -> Vec<u8> {
+            self.send(args).await;
+            self.reply().await
+        }
+    }
+
+    /// Wait until `n` clients are blocked
+    async fn blocked(server: &Server, n: usize) {
+        for _ in 0..500 {
+            let clients = server.clients.read();
+            let count = clients
+                .values()
+                .filter(|c| c.flags.load(Ordering::Relaxed) & REDIS_BLOCKED != 0)
+                .count();
+            drop(clients);
+            if count == n {
+                return;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{} clients never blocked", n);
+    }
+
+    #[tokio::test]
+    async fn clients_are_served_in_the_order_they_blocked() {
+        let (server, addr) = server().await;
+        let mut waiting = Vec::new();
+        for i in 0..3 {
+            let mut conn = Conn::connect(addr).await;
+            conn.send(&["BLPOP", "other", "list", "0"]).await;
+            blocked(&server, i + 1).await;
+            waiting.push(conn);
+        }
+
+        let mut pusher = Conn::connect(addr).await;
+        assert_eq!(pusher.call(&["RPUSH", "list", "a", "b"]).await, b":2\r\n");
+        assert_eq!(waiting[0].reply().await, b"*2\r\n$4\r\nlist\r\n$1\r\na\r\n");
+        assert_eq!(waiting[1].reply().await, b"*2\r\n$4\r\nlist\r\n$1\r\nb\r\n");
+        blocked(&server, 1).await;
+
+        assert_eq!(pusher.call(&["LPUSH", "other", "c"]).await, b":1\r\n");
+        assert_eq!(
+            waiting[2].reply().await,
+            b"*2\r\n$5\r\nother\r\n$1\r\nc\r\n"
+        );
+        blocked(&server, 0).await;
+        assert_eq!(pusher.call(&["EXISTS", "list"]).await, b":0\r\n");
+        assert_eq!(pusher.call(&["EXISTS", "other"]).await, b":0\r\n");
+    }
+
+    #[tokio::test]
+    async fn timeouts_reply_null() {
+        let (_server, addr) = server().await;
+        let mut conn = Conn::connect(addr).await;
+        assert_eq!(conn.call(&["BLPOP", "list", "0.01"]).await, b"*-1\r\n");
+        assert_eq!(conn.call(&["BRPOP", "list", "0.01"]).await, b"*-1\r\n");
+        assert_eq!(
+            conn.call(&["BLMPOP", "0.01", "1", "list", "LEFT"]).await,
+            b"*-1\r\n"
+        );
+        assert_eq!(
+            conn.call(&["BLMOVE", "list", "dst", "LEFT", "RIGHT", "0.01"])
+                .await,
+            b"$-1\r\n"
+        );
+
+        // not blocking inside a transaction
+        assert_eq!(conn.call(&["MULTI"]).await, b"+OK\r\n");
+        assert_eq!(conn.call(&["BLPOP", "list", "0"]).await, b"+QUEUED\r\n");
+        assert_eq!(conn.call(&["EXEC"]).await, b"*1\r\n*-1\r\n");
+
+        conn.call(&["HELLO", "3"]).await;
+        assert_eq!(conn.call(&["BLPOP", "list", "0.01"]).await, b"_\r\n");
+    }
+}
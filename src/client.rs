@@ -1,127 +1,436 @@
-use crate::command::Command;
+use crate::acl;
+use crate::blocked::BlockedState;
+use crate::command::{Command, ReplyError};
 use crate::config::ConfigRef;
-use crate::connection::Connection;
+use crate::connection::{Connection, REPLY_FLUSH_SIZE};
 use crate::dbms::DatabaseRef;
+use crate::pubsub::PubSubQueue;
 use crate::server::Server;
 use crate::shared;
 use crate::{aof::AofOption, frame::Frame};
-use std::io::Result;
+use bytes::Bytes;
+use std::collections::HashSet;
+use std::io::{ErrorKind, Result};
 use std::net::SocketAddr;
-use std::sync::atomic::AtomicU32;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::{broadcast, mpsc, Notify, RwLock};
+use tokio::time::sleep_until;
 
-const REDIS_MULTI: u32 = 1 << 3;
-const REDIS_CLOSE_AFTER_REPLY: u32 = 1 << 6;
-const REDIS_DIRTY_EXEC: u32 = 1 << 12;
+pub const REDIS_MULTI: u32 = 1 << 3; /* This client is in a MULTI context */
+pub const REDIS_BLOCKED: u32 = 1 << 4; /* The client is waiting in a blocking operation */
+pub const REDIS_DIRTY_CAS: u32 = 1 << 5; /* Watched keys modified. EXEC will fail. */
+pub const REDIS_CLOSE_AFTER_REPLY: u32 = 1 << 6; /* Close after writing entire reply. */
+pub const REDIS_DIRTY_EXEC: u32 = 1 << 12; /* EXEC will fail for errors while queueing */
+pub const REDIS_PUBSUB: u32 = 1 << 18; /* Client is in Pub/Sub mode */
 
 pub struct ClientInner {
     pub name: String,
-    pub last_interaction: u64,
-    pub flags: AtomicU32,
+    /// shared with the databases so that touching a watched key can flag the
+    /// watching clients with REDIS_DIRTY_CAS
+    pub flags: Arc<AtomicU32>,
+}
+
+/// The part of a connected client shared with the server, registered in
+/// `Server::clients` while the connection is served
+#[derive(Debug)]
+pub struct ClientHandle {
+    pub id: u64,
+    pub address: SocketAddr,
+    /// millisecond timestamp of the last command received
+    pub last_interaction: AtomicU64,
+    pub flags: Arc<AtomicU32>,
+    /// notified to close the connection
+    pub kill: Notify,
+}
+
+impl ClientHandle {
+    /// Seconds since the last command received
+    pub fn idle_time(&self) -> u64 {
+        shared::now_ms().saturating_sub(self.last_interaction.load(Ordering::Relaxed)) / 1000
+    }
+
+    /// Close the connection once the client is done with its current
+    /// command
+    pub fn close(&self) {
+        self.kill.notify_one();
+    }
 }
 
 pub struct Client {
+    pub id: u64,
     pub config: ConfigRef,
     pub server: Arc<Server>,
     pub db: DatabaseRef,
     pub connection: Option<Connection>,
     pub address: SocketAddr,
     pub inner: RwLock<ClientInner>,
+    pub handle: Arc<ClientHandle>,
     pub quit_ch: broadcast::Receiver<()>,
+    pub pubsub_channels: HashSet<Bytes>,
+    pub pubsub_patterns: HashSet<Bytes>,
+    pub pubsub_tx: mpsc::UnboundedSender<Frame>,
+    pub pubsub_rx: mpsc::UnboundedReceiver<Frame>,
+    /// the size of the messages in `pubsub_rx`
+    pub pubsub_queue: Arc<PubSubQueue>,
+    pub mstate: Vec<Command>,
+    pub watched_keys: Vec<(DatabaseRef, Bytes)>,
+    /// collect the replies instead of writing them out, used by EXEC
+    pub reply_capture: Option<Vec<Frame>>,
+    /// name of the ACL user the client runs the commands as
+    pub user: Bytes,
+    pub authenticated: bool,
+    /// protocol version negotiated with HELLO, 2 or 3
+    pub resp: u8,
+    /// the blocking command the client waits on
+    pub blocked: Option<BlockedState>,
+    /// notified when a key the client is blocked on is pushed to
+    pub ready_keys: Arc<Notify>,
+    /// the command to propagate instead of the executed one, e.g. the pop
+    /// actually done by a blocking command
+    pub propagate_as: Option<Command>,
 }
 
 impl Client {
+    /// Create a client bound to database 0, `connection` is None for the
+    /// fake client used to replay the AOF
+    pub fn new(server: Arc<Server>, connection: Option<Connection>, address: SocketAddr) -> Client {
+        let (pubsub_tx, pubsub_rx) = mpsc::unbounded_channel();
+        let id = server.next_client_id.fetch_add(1, Ordering::Relaxed);
+        let flags: Arc<AtomicU32> = Default::default();
+        Client {
+            id,
+            config: server.config.clone(),
+            db: server.get(0),
+            connection,
+            address,
+            inner: RwLock::new(ClientInner {
+                name: String::new(),
+                flags: flags.clone(),
+            }),
+            handle: Arc::new(ClientHandle {
+                id,
+                address,
+                last_interaction: AtomicU64::new(shared::now_ms()),
+                flags,
+                kill: Notify::new(),
+            }),
+            quit_ch: server.quit_ch.subscribe(),
+            pubsub_channels: HashSet::new(),
+            pubsub_patterns: HashSet::new(),
+            pubsub_tx,
+            pubsub_rx,
+            pubsub_queue: Default::default(),
+            mstate: Vec::new(),
+            watched_keys: Vec::new(),
+            reply_capture: None,
+            user: Bytes::from_static(b"default"),
+            authenticated: false,
+            resp: 2,
+            blocked: None,
+            ready_keys: Arc::new(Notify::new()),
+            propagate_as: None,
+            server,
+        }
+    }
+
+    pub async fn flags(&self) -> u32 {
+        self.inner.read().await.flags.load(Ordering::Relaxed)
+    }
+
+    pub async fn add_flags(&self, flags: u32) {
+        self.inner
+            .read()
+            .await
+            .flags
+            .fetch_or(flags, Ordering::Relaxed);
+    }
+
+    pub async fn remove_flags(&self, flags: u32) {
+        self.inner
+            .read()
+            .await
+            .flags
+            .fetch_and(!flags, Ordering::Relaxed);
+    }
+
     pub async fn serve(&mut self) {
-        // set the stream to non-blocking mode
-        // stream.set_nonblocking(true).unwrap();
+        let tcp_keepalive = self.config.read().await.tcp_keepalive;
+        if let Some(connection) = &self.connection {
+            if let Err(e) = connection.stream.set_nodelay(true) {
+                log::warn!("Error setting TCP_NODELAY for {}: {}", self.address, e);
+            }
+            if tcp_keepalive > 0 {
+                if let Err(e) = connection.set_keepalive(tcp_keepalive) {
+                    log::warn!("Error setting keepalive for {}: {}", self.address, e);
+                }
+            }
+        }
 
-        // enable TCP_NODELAY
-        // self.stream.set_nodelay(true).unwrap();
+        // the connections accepted while no password is required are
+        // authenticated as the default user
+        self.authenticated = !self.server.acl.read().await.auth_required();
 
-        // set the keepalive timeout to 5 seconds
-        // stream.set_keepalive(Some(Duration::from_secs(5))).unwrap();
+        self.server.link_client(self.handle.clone());
 
         let _ = self.handle_client().await;
+
+        // the connection is gone, drop all the subscriptions and watches
+        self.server.unlink_client(self.id);
+        self.unblock();
+        let _ = self.pubsub_unsubscribe_all_channels(false).await;
+        let _ = self.pubsub_unsubscribe_all_patterns(false).await;
+        self.unwatch_all_keys();
     }
 
     pub fn select(&mut self, index: usize) -> Result<()> {
-        if index >= 1 {
+        if index >= self.server.db_num() {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "invalid db index",
             ));
         }
-        self.db = self.server.get(0);
+        self.db = self.server.get(index);
         Ok(())
     }
 
     pub async fn write_frame(&mut self, frame: &Frame) -> Result<usize> {
+        if let Some(replies) = self.reply_capture.as_mut() {
+            replies.push(frame.clone());
+            return Ok(0);
+        }
         match self.connection {
             None => Ok(0), // fake client
-            Some(ref mut connection) => connection.write_frame(frame).await,
+            Some(ref mut connection) => Ok(connection.write_frame(frame, self.resp)),
+        }
+    }
+
+    /// Send the buffered replies
+    pub async fn flush(&mut self) -> Result<()> {
+        match self.connection {
+            None => Ok(()), // fake client
+            Some(ref mut connection) => connection.flush().await,
         }
     }
 
     pub async fn handle_client(&mut self) -> Result<()> {
         loop {
+            let (limits, query_buffer_limit) = {
+                let config = self.config.read().await;
+                (config.proto_limits(), config.client_query_buffer_limit)
+            };
             let connection = self.connection.as_mut().unwrap();
+            connection.limits = limits;
+            connection.query_buffer_limit = query_buffer_limit as usize;
 
-            let maybe_frame = tokio::select! {
-                _ = self.quit_ch.recv() => {
-                    log::debug!("server quit");
-                    return Ok(());
+            // a blocked client waits for its keys before reading further
+            // requests
+            if let Some(state) = &self.blocked {
+                connection.flush().await?;
+                let deadline = state.deadline;
+                let timeout = async {
+                    match deadline {
+                        Some(deadline) => sleep_until(deadline).await,
+                        None => std::future::pending().await,
+                    }
+                };
+                tokio::select! {
+                    _ = self.quit_ch.recv() => {
+                        log::debug!("server quit");
+                        return Ok(());
+                    }
+                    _ = self.handle.kill.notified() => {
+                        log::debug!("client {} closed by the server", self.address);
+                        return Ok(());
+                    }
+                    _ = self.ready_keys.notified() => {
+                        let server = self.server.clone();
+                        let exclusive = self
+                            .blocked
+                            .as_ref()
+                            .is_some_and(|state| state.cmd.is_exclusive());
+                        if exclusive {
+                            let _guard = server.keyspace_lock.write().await;
+                            self.serve_blocked().await?;
+                        } else {
+                            let _guard = server.keyspace_lock.read().await;
+                            self.serve_blocked().await?;
+                        }
+                    }
+                    _ = timeout => self.timeout_blocked().await?,
+                    closed = connection.wait_closed() => return closed,
+                }
+                continue;
+            }
+
+            // process all the pipelined requests already read before sending
+            // their replies at once
+            let buffered = match connection.parse_frame() {
+                Ok(None) => None,
+                maybe_err_frame => {
+                    if connection.wbuffer.len() >= REPLY_FLUSH_SIZE {
+                        connection.flush().await?;
+                    }
+                    Some(maybe_err_frame)
                 }
-                maybe_err_frame = connection.read_frame() => {
-                    // illegal frame
-                    match maybe_err_frame {
-                        Ok(f) => f,
-                        Err(e) => {
-                            self.write_frame(&shared::protocol_err).await?;
-                            log::error!("read frame error: {:?}", e);
+            };
+            let maybe_err_frame = match buffered {
+                Some(maybe_err_frame) => maybe_err_frame,
+                None => {
+                    // a subscriber which doesn't read its messages is closed
+                    // by the publishers while the replies wait to be sent
+                    tokio::select! {
+                        _ = self.handle.kill.notified() => {
+                            log::debug!("client {} closed by the server", self.address);
                             return Ok(());
                         }
+                        flushed = connection.flush() => flushed?,
+                    }
+                    tokio::select! {
+                        _ = self.quit_ch.recv() => {
+                            log::debug!("server quit");
+                            return Ok(());
+                        }
+                        _ = self.handle.kill.notified() => {
+                            log::debug!("client {} closed by the server", self.address);
+                            return Ok(());
+                        }
+                        Some(message) = self.pubsub_rx.recv() => {
+                            // published message for a subscribed channel or pattern
+                            self.pubsub_queue.dequeued(&message);
+                            self.write_frame(&message).await?;
+                            continue;
+                        }
+                        maybe_err_frame = connection.read_frame() => maybe_err_frame,
                     }
                 }
             };
+            // illegal frame
+            let maybe_frame = match maybe_err_frame {
+                Ok(f) => f,
+                Err(e) => {
+                    let reply = if e.kind() == ErrorKind::InvalidData {
+                        Frame::Error(Bytes::from(format!("ERR Protocol error: {}", e)))
+                    } else {
+                        shared::protocol_err
+                    };
+                    self.write_frame(&reply).await?;
+                    self.flush().await?;
+                    log::error!("read frame error: {:?}", e);
+                    return Ok(());
+                }
+            };
             let frame = match maybe_frame {
                 Some(frame) => frame,
                 // connection closed
                 None => return Ok(()),
             };
+            self.handle
+                .last_interaction
+                .store(shared::now_ms(), Ordering::Relaxed);
+
+            let argv = acl::frame_argv(&frame);
+            let perm = acl::lookup_command(&argv);
+
+            // check auth and permissions before the command is parsed
+            if let Some(err) = self.acl_check_command(&argv, perm).await? {
+                self.flag_transaction().await;
+                self.write_frame(&err).await?;
+                continue;
+            }
 
             let cmd = {
                 let maybe_cmd = Command::from(frame);
                 match maybe_cmd {
                     Ok(cmd) => cmd,
                     Err(e) => {
-                        self.write_frame(&shared::syntax_err).await?;
+                        self.flag_transaction().await;
+                        let reply = match e.get_ref().and_then(|e| e.downcast_ref::<ReplyError>()) {
+                            Some(ReplyError(msg)) => Frame::Error(Bytes::from(msg.clone())),
+                            None => shared::syntax_err,
+                        };
+                        self.write_frame(&reply).await?;
                         log::error!("parse command error: {:?}", e);
                         continue;
                     }
                 }
             };
 
-            // TODO: check auth
+            // only the subscribe family is allowed in the RESP2 pubsub
+            // context, RESP3 tells the messages apart from the replies
+            if self.resp < 3 && self.pubsub_count() > 0 && !cmd.is_pubsub_context_allowed() {
+                self.write_frame(&Frame::Error(Bytes::from_static(
+                    b"ERR only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT allowed in this context",
+                )))
+                .await?;
+                continue;
+            }
 
             log::debug!("client command: {:?}", cmd);
 
-            // TODO: check memory
+            // free some memory before running the commands that may use more,
+            // refuse them if the limit is reached and nothing can be evicted
+            if perm.is_some_and(|p| p.deny_oom) && self.server.perform_evictions().await.is_err() {
+                self.flag_transaction().await;
+                self.write_frame(&shared::oom_err).await?;
+                continue;
+            }
 
             // TODO: check last write disk status
 
             // TODO: check if the server is loading
 
-            self.handle_command(cmd.clone()).await;
+            // EXEC must not interleave with other clients' commands
+            let server = self.server.clone();
+            if cmd.is_exclusive() {
+                let _guard = server.keyspace_lock.write().await;
+                self.process_command(cmd).await?;
+            } else {
+                let _guard = server.keyspace_lock.read().await;
+                self.process_command(cmd).await?;
+            }
+
+            if self.flags().await & REDIS_CLOSE_AFTER_REPLY != 0 {
+                self.flush().await?;
+                return Ok(());
+            }
+        }
+    }
+
+    /// Queue the command if the client is in a MULTI context, otherwise
+    /// execute and propagate it
+    pub async fn process_command(&mut self, cmd: Command) -> Result<()> {
+        if self.flags().await & REDIS_MULTI != 0 && !cmd.is_transaction_control() {
+            if let Command::Unknown(_) = cmd {
+                self.flag_transaction().await;
+                let _ = self.handle_command(cmd).await;
+                return Ok(());
+            }
+            self.mstate.push(cmd);
+            self.write_frame(&Frame::Simple(Bytes::from_static(b"QUEUED")))
+                .await?;
+            return Ok(());
+        }
+
+        let _ = self.handle_command(cmd.clone()).await;
 
-            // propagate
-            self.propagate(cmd).await;
+        // propagate
+        let cmd = self.propagate_as.take().unwrap_or(cmd);
+        self.propagate(cmd).await;
+
+        Ok(())
+    }
+
+    pub async fn propagate(&mut self, cmd: Command) {
+        if self.config.read().await.aof_state != AofOption::Off {
+            let _ = self.server.feed_append_only_file(cmd, self.db.index).await;
         }
     }
 
-    async fn propagate(&mut self, cmd: Command) {
+    /// Propagate the commands executed by EXEC as a single MULTI/EXEC block
+    pub async fn propagate_transaction(&mut self, cmds: Vec<(Command, u32)>) {
         if self.config.read().await.aof_state != AofOption::Off {
-            self.server.feed_append_only_file(cmd, self.db.index).await;
+            let _ = self.server.feed_append_only_file_transaction(cmds).await;
         }
     }
 }
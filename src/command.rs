@@ -1,36 +1,83 @@
+mod acl;
 mod aof;
+mod bitops;
 mod config;
 mod db;
+mod geo;
 mod hash;
+mod hello;
+mod hyperloglog;
+mod info;
 mod list;
+mod multi;
 mod ping;
+mod pubsub;
 mod rdb;
 mod set;
+mod stream;
 mod string;
 mod unknown;
+mod zset;
 use crate::aof::{AofFsync, AofOption};
-use crate::client::Client;
-use crate::config::Verbosity;
+use crate::client::{Client, REDIS_CLOSE_AFTER_REPLY};
+use crate::config::{OutputBufferLimit, Verbosity};
+use crate::evict::MaxmemoryPolicy;
 use crate::frame::Frame;
 use crate::rdb::AutoSave;
 use crate::shared;
+use acl::{
+    AclCat, AclDelUser, AclGetUser, AclList, AclLoad, AclSave, AclSetUser, AclUsers, AclWhoAmI,
+    Auth,
+};
 use aof::BgRewriteAof;
+use bitops::{BitCount, BitField, BitOp, BitPos, GetBit, SetBit};
 use bytes::Bytes;
 use config::{ConfigGet, ConfigResetStat, ConfigRewrite, ConfigSet};
 use db::{
-    DbSize, Del, Exists, Expire, ExpireAt, Keys, PExpire, PExpireAt, Rename, Select, Shutdown, Type,
+    DbSize, Del, Exists, Expire, ExpireAt, FlushAll, FlushDb, Keys, Move, PExpire, PExpireAt,
+    Rename, Scan, Select, Shutdown, SwapDb, Type,
+};
+use geo::{GeoAdd, GeoDist, GeoHash, GeoPos, GeoSearch, GeoSearchStore};
+use hash::{
+    HDel, HExists, HGet, HGetAll, HIncrBy, HIncrByFloat, HLen, HMGet, HRandField, HScan, HSet,
+    HSetNx, HStrLen,
 };
-use hash::{HGet, HSet};
-use list::{ListPop, ListPush};
+use hello::Hello;
+use hyperloglog::{PfAdd, PfCount, PfMerge};
+use info::Info;
+pub use list::{BlockingOp, BlockingPop};
+use list::{
+    LIndex, LInsert, LLen, LMPop, LMove, LPos, LRange, LRem, LSet, LTrim, ListPop, ListPush,
+};
+use multi::{Discard, Exec, Multi, Unwatch, Watch};
 use ping::{Echo, Ping, Quit};
+use pubsub::{
+    PSubscribe, PUnsubscribe, PubSubChannels, PubSubNumPat, PubSubNumSub, Publish, Subscribe,
+    Unsubscribe,
+};
 use rdb::{BgSave, Save};
-use set::{SAdd, SRem};
+use set::{
+    SAdd, SCard, SInterCard, SIsMember, SMembers, SMove, SPop, SRandMember, SRem, SScan, SetOp,
+    SetOpQuery, SetOpStore,
+};
 use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::Ordering;
 use std::vec;
-use string::{Append, Get, Set, Strlen};
+pub use stream::XRead;
+use stream::{
+    XAck, XAdd, XAutoClaim, XClaim, XDel, XGroup, XInfo, XLen, XPending, XRange, XSetId, XTrim,
+};
+use string::{
+    Append, Get, GetDel, GetEx, GetRange, GetSet, IncrByFloat, Lcs, MGet, MSet, Set, SetRange,
+    Strlen,
+};
 use string::{Decr, DecrBy, Incr, IncrBy};
 use tokio::fs::File;
 use unknown::Unknown;
+use zset::{
+    ZAdd, ZCard, ZCount, ZIncrBy, ZMScore, ZPop, ZRange, ZRangeStore, ZRank, ZRem, ZRemRangeBy,
+    ZRemRangeByRank, ZScan, ZScore, ZSetOp, ZSetOpStore,
+};
 
 use self::string::SetNx;
 
@@ -38,13 +85,31 @@ pub struct CommandParser {
     parts: vec::IntoIter<Frame>,
 }
 
+/// A parse error replied to the client as is, e.g. `ERR invalid expire time
+/// in 'set' command`, the other parse errors are replied as a syntax error
+#[derive(Debug)]
+pub struct ReplyError(pub String);
+
+impl std::fmt::Display for ReplyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ReplyError {}
+
 impl CommandParser {
-    pub fn from(frame: Frame) -> CommandParser {
+    pub fn from(frame: Frame) -> Result<CommandParser> {
         let parts = match frame {
             Frame::Array(parts) => parts.into_iter(),
-            _ => panic!("not an array frame"),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "command must be an array frame",
+                ))
+            }
         };
-        CommandParser { parts }
+        Ok(CommandParser { parts })
     }
 
     pub fn next(&mut self) -> Option<Frame> {
@@ -71,6 +136,22 @@ impl CommandParser {
         }
     }
 
+    /// The key argument of `cmd`, an error if there are no arguments left
+    pub fn next_key(&mut self, cmd: &str) -> Result<Bytes> {
+        self.next_string()?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("{} requires a key", cmd)))
+    }
+
+    /// The error of arguments which don't follow the syntax of the command
+    pub fn syntax_error() -> Error {
+        Error::new(ErrorKind::InvalidInput, "ERR syntax error")
+    }
+
+    /// The error replied to the client with the message `msg`
+    pub fn reply_error(msg: impl Into<String>) -> Error {
+        Error::new(ErrorKind::InvalidInput, ReplyError(msg.into()))
+    }
+
     pub fn next_integer(&mut self) -> Result<Option<i64>> {
         if let Some(frame) = self.next() {
             match frame {
@@ -90,15 +171,100 @@ impl CommandParser {
     }
 }
 
+/// The `cursor [MATCH pattern] [COUNT count] [TYPE type]` arguments of the
+/// SCAN family
+#[derive(Debug, Clone)]
+pub struct ScanArgs {
+    pub cursor: u64,
+    pub pattern: Option<Bytes>,
+    pub count: usize,
+    /// TYPE, only for SCAN
+    pub object_type: Option<Bytes>,
+}
+
+impl ScanArgs {
+    pub fn from(frame: &mut CommandParser, type_allowed: bool) -> Result<Self> {
+        let syntax_error = CommandParser::syntax_error;
+        let cursor = frame.next_string()?.ok_or_else(syntax_error)?;
+        let cursor = std::str::from_utf8(&cursor)
+            .ok()
+            .and_then(|cursor| cursor.parse::<u64>().ok())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "invalid cursor"))?;
+        let mut args = Self {
+            cursor,
+            pattern: None,
+            count: 10,
+            object_type: None,
+        };
+        while let Some(arg) = frame.next_string()? {
+            match &arg.to_ascii_lowercase()[..] {
+                b"match" => args.pattern = Some(frame.next_string()?.ok_or_else(syntax_error)?),
+                b"count" => {
+                    args.count = frame
+                        .next_integer()?
+                        .and_then(|count| usize::try_from(count).ok())
+                        .filter(|count| *count > 0)
+                        .ok_or_else(syntax_error)?;
+                }
+                b"type" if type_allowed => {
+                    args.object_type = Some(frame.next_string()?.ok_or_else(syntax_error)?)
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+        Ok(args)
+    }
+
+    /// Whether the element matches MATCH, if given
+    pub fn matches(&self, element: &[u8]) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| shared::string_match(pattern, element, false))
+    }
+
+    /// The reply of a page, the cursor of the next call and the elements
+    pub fn reply(cursor: u64, elements: Vec<Frame>) -> Frame {
+        Frame::Array(vec![
+            Frame::Bulk(Bytes::from(cursor.to_string())),
+            Frame::Array(elements),
+        ])
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Command {
     Ping(Ping),
     Echo(Echo),
     Quit(Quit),
+    Hello(Hello),
 
     Get(Get),
     Set(Set),
     SetNx(SetNx),
+    SetEx(Set),
+    PSetEx(Set),
+    MGet(MGet),
+    MSet(MSet),
+    MSetNx(MSet),
+    GetSet(GetSet),
+    GetDel(GetDel),
+    GetEx(GetEx),
+    GetRange(GetRange),
+    SetRange(SetRange),
+    IncrByFloat(IncrByFloat),
+    Lcs(Lcs),
+
+    SetBit(SetBit),
+    GetBit(GetBit),
+    BitCount(BitCount),
+    BitPos(BitPos),
+    BitOp(BitOp),
+    BitField(BitField),
+    BitFieldRo(BitField),
+
+    PfAdd(PfAdd),
+    PfCount(PfCount),
+    PfMerge(PfMerge),
     Append(Append),
     Strlen(Strlen),
     Incr(Incr),
@@ -110,6 +276,7 @@ pub enum Command {
     Exists(Exists),
     Select(Select),
     Keys(Keys),
+    Scan(Scan),
     DbSize(DbSize),
     Shutdown(Shutdown),
     Rename(Rename),
@@ -118,37 +285,155 @@ pub enum Command {
     PExpire(PExpire),
     PExpireAt(PExpireAt),
     Type(Type),
+    Move(Move),
+    SwapDb(SwapDb),
+    FlushDb(FlushDb),
+    FlushAll(FlushAll),
 
     LPush(ListPush),
     RPush(ListPush),
     LPop(ListPop),
     RPop(ListPop),
+    LPushX(ListPush),
+    RPushX(ListPush),
+    LLen(LLen),
+    LRange(LRange),
+    LIndex(LIndex),
+    LSet(LSet),
+    LInsert(LInsert),
+    LRem(LRem),
+    LTrim(LTrim),
+    LPos(LPos),
+    LMove(LMove),
+    RPopLPush(LMove),
+    LMPop(LMPop),
+    BLPop(BlockingPop),
+    BRPop(BlockingPop),
+    BLMPop(BlockingPop),
+    BLMove(BlockingPop),
 
     HSet(HSet),
     HGet(HGet),
+    HMSet(HSet),
+    HSetNx(HSetNx),
+    HMGet(HMGet),
+    HDel(HDel),
+    HExists(HExists),
+    HLen(HLen),
+    HStrLen(HStrLen),
+    HKeys(HGetAll),
+    HVals(HGetAll),
+    HGetAll(HGetAll),
+    HScan(HScan),
+    HIncrBy(HIncrBy),
+    HIncrByFloat(HIncrByFloat),
+    HRandField(HRandField),
 
     SAdd(SAdd),
     SRem(SRem),
+    SMembers(SMembers),
+    SScan(SScan),
+    SIsMember(SIsMember),
+    SMIsMember(SIsMember),
+    SCard(SCard),
+    SPop(SPop),
+    SRandMember(SRandMember),
+    SMove(SMove),
+    SInter(SetOpQuery),
+    SUnion(SetOpQuery),
+    SDiff(SetOpQuery),
+    SInterStore(SetOpStore),
+    SUnionStore(SetOpStore),
+    SDiffStore(SetOpStore),
+    SInterCard(SInterCard),
+
+    ZAdd(ZAdd),
+    ZIncrBy(ZIncrBy),
+    ZRem(ZRem),
+    ZScore(ZScore),
+    ZScan(ZScan),
+    ZMScore(ZMScore),
+    ZCard(ZCard),
+    ZCount(ZCount),
+    ZRank(ZRank),
+    ZRevRank(ZRank),
+    ZRange(ZRange),
+    ZRangeStore(ZRangeStore),
+    ZPopMin(ZPop),
+    ZPopMax(ZPop),
+    ZRemRangeByRank(ZRemRangeByRank),
+    ZRemRangeByScore(ZRemRangeBy),
+    ZRemRangeByLex(ZRemRangeBy),
+    ZUnionStore(ZSetOpStore),
+    ZInterStore(ZSetOpStore),
+    ZDiffStore(ZSetOpStore),
+
+    XAdd(XAdd),
+    XRange(XRange),
+    XRevRange(XRange),
+    XLen(XLen),
+    XDel(XDel),
+    XTrim(XTrim),
+    XRead(XRead),
+    XReadGroup(XRead),
+    XGroup(XGroup),
+    XAck(XAck),
+    XPending(XPending),
+    XClaim(XClaim),
+    XAutoClaim(XAutoClaim),
+    XInfo(XInfo),
+    XSetId(XSetId),
+    GeoAdd(GeoAdd),
+    GeoPos(GeoPos),
+    GeoDist(GeoDist),
+    GeoHash(GeoHash),
+    GeoSearch(GeoSearch),
+    GeoSearchStore(GeoSearchStore),
 
     Save(Save),
     BgSave(BgSave),
 
     BgRewriteAof(BgRewriteAof),
 
+    Info(Info),
+
     ConfigGet(ConfigGet),
     ConfigSet(ConfigSet),
     ConfigResetStat(ConfigResetStat),
     ConfigRewrite(ConfigRewrite),
 
-    // Publish(Publish),
-    // Subscribe(Subscribe),
-    // Unsubscribe(Unsubscribe),
+    Multi(Multi),
+    Exec(Exec),
+    Discard(Discard),
+    Watch(Watch),
+    Unwatch(Unwatch),
+
+    Subscribe(Subscribe),
+    Unsubscribe(Unsubscribe),
+    PSubscribe(PSubscribe),
+    PUnsubscribe(PUnsubscribe),
+    Publish(Publish),
+    PubSubChannels(PubSubChannels),
+    PubSubNumSub(PubSubNumSub),
+    PubSubNumPat(PubSubNumPat),
+
+    Auth(Auth),
+    AclSetUser(AclSetUser),
+    AclGetUser(AclGetUser),
+    AclDelUser(AclDelUser),
+    AclList(AclList),
+    AclUsers(AclUsers),
+    AclWhoAmI(AclWhoAmI),
+    AclCat(AclCat),
+    AclLoad(AclLoad),
+    AclSave(AclSave),
+
     Unknown(Unknown),
 }
 
 impl Command {
     pub fn from(frame: Frame) -> Result<Command> {
-        let mut parser = CommandParser::from(frame);
+        let mut parser = CommandParser::from(frame)?;
 
         let cmd = {
             let maybe_cmd = parser.next_string()?;
@@ -162,10 +447,33 @@ impl Command {
         let command = match &cmd.to_ascii_lowercase()[..] {
             b"ping" => Command::Ping(Ping::from(&mut parser)?),
             b"quit" => Command::Quit(Quit::from(&mut parser)?),
+            b"hello" => Command::Hello(Hello::from(&mut parser)?),
 
             b"get" => Command::Get(Get::from(&mut parser)?),
             b"set" => Command::Set(Set::from(&mut parser)?),
             b"setnx" => Command::SetNx(SetNx::from(&mut parser)?),
+            b"setex" => Command::SetEx(Set::from_setex(&mut parser, 1000)?),
+            b"psetex" => Command::PSetEx(Set::from_setex(&mut parser, 1)?),
+            b"mget" => Command::MGet(MGet::from(&mut parser)?),
+            b"mset" => Command::MSet(MSet::from(&mut parser, false)?),
+            b"msetnx" => Command::MSetNx(MSet::from(&mut parser, true)?),
+            b"getset" => Command::GetSet(GetSet::from(&mut parser)?),
+            b"getdel" => Command::GetDel(GetDel::from(&mut parser)?),
+            b"getex" => Command::GetEx(GetEx::from(&mut parser)?),
+            b"getrange" => Command::GetRange(GetRange::from(&mut parser)?),
+            b"setrange" => Command::SetRange(SetRange::from(&mut parser)?),
+            b"incrbyfloat" => Command::IncrByFloat(IncrByFloat::from(&mut parser)?),
+            b"lcs" => Command::Lcs(Lcs::from(&mut parser)?),
+            b"setbit" => Command::SetBit(SetBit::from(&mut parser)?),
+            b"getbit" => Command::GetBit(GetBit::from(&mut parser)?),
+            b"bitcount" => Command::BitCount(BitCount::from(&mut parser)?),
+            b"bitpos" => Command::BitPos(BitPos::from(&mut parser)?),
+            b"bitop" => Command::BitOp(BitOp::from(&mut parser)?),
+            b"bitfield" => Command::BitField(BitField::from(&mut parser, false)?),
+            b"bitfield_ro" => Command::BitFieldRo(BitField::from(&mut parser, true)?),
+            b"pfadd" => Command::PfAdd(PfAdd::from(&mut parser)?),
+            b"pfcount" => Command::PfCount(PfCount::from(&mut parser)?),
+            b"pfmerge" => Command::PfMerge(PfMerge::from(&mut parser)?),
             b"append" => Command::Append(Append::from(&mut parser)?),
             b"strlen" => Command::Strlen(Strlen::from(&mut parser)?),
             b"incr" => Command::Incr(Incr::from(&mut parser)?),
@@ -177,6 +485,7 @@ impl Command {
             b"exists" => Command::Exists(Exists::from(&mut parser)?),
             b"select" => Command::Select(Select::from(&mut parser)?),
             b"keys" => Command::Keys(Keys::from(&mut parser)?),
+            b"scan" => Command::Scan(Scan::from(&mut parser)?),
             b"dbsize" => Command::DbSize(DbSize::from(&mut parser)?),
             b"shutdown" => Command::Shutdown(Shutdown::from(&mut parser)?),
             b"rename" => Command::Rename(Rename::from(&mut parser)?),
@@ -185,23 +494,120 @@ impl Command {
             b"pexpire" => Command::PExpire(PExpire::from(&mut parser)?),
             b"pexpireat" => Command::PExpireAt(PExpireAt::from(&mut parser)?),
             b"type" => Command::Type(Type::from(&mut parser)?),
+            b"move" => Command::Move(Move::from(&mut parser)?),
+            b"swapdb" => Command::SwapDb(SwapDb::from(&mut parser)?),
+            b"flushdb" => Command::FlushDb(FlushDb::from(&mut parser)?),
+            b"flushall" => Command::FlushAll(FlushAll::from(&mut parser)?),
 
-            b"lpush" => Command::LPush(ListPush::from(&mut parser, true)?),
-            b"rpush" => Command::RPush(ListPush::from(&mut parser, true)?),
+            b"lpush" => Command::LPush(ListPush::from(&mut parser, true, false)?),
+            b"rpush" => Command::RPush(ListPush::from(&mut parser, false, false)?),
             b"lpop" => Command::LPop(ListPop::from(&mut parser, true)?),
             b"rpop" => Command::RPop(ListPop::from(&mut parser, false)?),
+            b"lpushx" => Command::LPushX(ListPush::from(&mut parser, true, true)?),
+            b"rpushx" => Command::RPushX(ListPush::from(&mut parser, false, true)?),
+            b"llen" => Command::LLen(LLen::from(&mut parser)?),
+            b"lrange" => Command::LRange(LRange::from(&mut parser)?),
+            b"lindex" => Command::LIndex(LIndex::from(&mut parser)?),
+            b"lset" => Command::LSet(LSet::from(&mut parser)?),
+            b"linsert" => Command::LInsert(LInsert::from(&mut parser)?),
+            b"lrem" => Command::LRem(LRem::from(&mut parser)?),
+            b"ltrim" => Command::LTrim(LTrim::from(&mut parser)?),
+            b"lpos" => Command::LPos(LPos::from(&mut parser)?),
+            b"lmove" => Command::LMove(LMove::from(&mut parser)?),
+            b"rpoplpush" => Command::RPopLPush(LMove::from_rpoplpush(&mut parser)?),
+            b"lmpop" => Command::LMPop(LMPop::from(&mut parser)?),
+            b"blpop" => Command::BLPop(BlockingPop::from_pop(&mut parser, true)?),
+            b"brpop" => Command::BRPop(BlockingPop::from_pop(&mut parser, false)?),
+            b"blmpop" => Command::BLMPop(BlockingPop::from_mpop(&mut parser)?),
+            b"blmove" => Command::BLMove(BlockingPop::from_move(&mut parser)?),
 
-            b"hset" => Command::HSet(HSet::from(&mut parser)?),
+            b"hset" => Command::HSet(HSet::from(&mut parser, false)?),
             b"hget" => Command::HGet(HGet::from(&mut parser)?),
+            b"hmset" => Command::HMSet(HSet::from(&mut parser, true)?),
+            b"hsetnx" => Command::HSetNx(HSetNx::from(&mut parser)?),
+            b"hmget" => Command::HMGet(HMGet::from(&mut parser)?),
+            b"hdel" => Command::HDel(HDel::from(&mut parser)?),
+            b"hexists" => Command::HExists(HExists::from(&mut parser)?),
+            b"hlen" => Command::HLen(HLen::from(&mut parser)?),
+            b"hstrlen" => Command::HStrLen(HStrLen::from(&mut parser)?),
+            b"hkeys" => Command::HKeys(HGetAll::from(&mut parser, true, false)?),
+            b"hvals" => Command::HVals(HGetAll::from(&mut parser, false, true)?),
+            b"hgetall" => Command::HGetAll(HGetAll::from(&mut parser, true, true)?),
+            b"hscan" => Command::HScan(HScan::from(&mut parser)?),
+            b"hincrby" => Command::HIncrBy(HIncrBy::from(&mut parser)?),
+            b"hincrbyfloat" => Command::HIncrByFloat(HIncrByFloat::from(&mut parser)?),
+            b"hrandfield" => Command::HRandField(HRandField::from(&mut parser)?),
 
             b"sadd" => Command::SAdd(SAdd::from(&mut parser)?),
             b"srem" => Command::SRem(SRem::from(&mut parser)?),
+            b"smembers" => Command::SMembers(SMembers::from(&mut parser)?),
+            b"sscan" => Command::SScan(SScan::from(&mut parser)?),
+            b"sismember" => Command::SIsMember(SIsMember::from(&mut parser, false)?),
+            b"smismember" => Command::SMIsMember(SIsMember::from(&mut parser, true)?),
+            b"scard" => Command::SCard(SCard::from(&mut parser)?),
+            b"spop" => Command::SPop(SPop::from(&mut parser)?),
+            b"srandmember" => Command::SRandMember(SRandMember::from(&mut parser)?),
+            b"smove" => Command::SMove(SMove::from(&mut parser)?),
+            b"sinter" => Command::SInter(SetOpQuery::from(&mut parser, SetOp::Inter)?),
+            b"sunion" => Command::SUnion(SetOpQuery::from(&mut parser, SetOp::Union)?),
+            b"sdiff" => Command::SDiff(SetOpQuery::from(&mut parser, SetOp::Diff)?),
+            b"sinterstore" => Command::SInterStore(SetOpStore::from(&mut parser, SetOp::Inter)?),
+            b"sunionstore" => Command::SUnionStore(SetOpStore::from(&mut parser, SetOp::Union)?),
+            b"sdiffstore" => Command::SDiffStore(SetOpStore::from(&mut parser, SetOp::Diff)?),
+            b"sintercard" => Command::SInterCard(SInterCard::from(&mut parser)?),
+
+            b"zadd" => Command::ZAdd(ZAdd::from(&mut parser)?),
+            b"zincrby" => Command::ZIncrBy(ZIncrBy::from(&mut parser)?),
+            b"zrem" => Command::ZRem(ZRem::from(&mut parser)?),
+            b"zscore" => Command::ZScore(ZScore::from(&mut parser)?),
+            b"zscan" => Command::ZScan(ZScan::from(&mut parser)?),
+            b"zmscore" => Command::ZMScore(ZMScore::from(&mut parser)?),
+            b"zcard" => Command::ZCard(ZCard::from(&mut parser)?),
+            b"zcount" => Command::ZCount(ZCount::from(&mut parser)?),
+            b"zrank" => Command::ZRank(ZRank::from(&mut parser, false)?),
+            b"zrevrank" => Command::ZRevRank(ZRank::from(&mut parser, true)?),
+            b"zrange" => Command::ZRange(ZRange::from(&mut parser)?),
+            b"zrangestore" => Command::ZRangeStore(ZRangeStore::from(&mut parser)?),
+            b"zpopmin" => Command::ZPopMin(ZPop::from(&mut parser, false)?),
+            b"zpopmax" => Command::ZPopMax(ZPop::from(&mut parser, true)?),
+            b"zremrangebyrank" => Command::ZRemRangeByRank(ZRemRangeByRank::from(&mut parser)?),
+            b"zremrangebyscore" => {
+                Command::ZRemRangeByScore(ZRemRangeBy::from(&mut parser, false)?)
+            }
+            b"zremrangebylex" => Command::ZRemRangeByLex(ZRemRangeBy::from(&mut parser, true)?),
+            b"zunionstore" => Command::ZUnionStore(ZSetOpStore::from(&mut parser, ZSetOp::Union)?),
+            b"zinterstore" => Command::ZInterStore(ZSetOpStore::from(&mut parser, ZSetOp::Inter)?),
+            b"zdiffstore" => Command::ZDiffStore(ZSetOpStore::from(&mut parser, ZSetOp::Diff)?),
+
+            b"xadd" => Command::XAdd(XAdd::from(&mut parser)?),
+            b"xrange" => Command::XRange(XRange::from(&mut parser, false)?),
+            b"xrevrange" => Command::XRevRange(XRange::from(&mut parser, true)?),
+            b"xlen" => Command::XLen(XLen::from(&mut parser)?),
+            b"xdel" => Command::XDel(XDel::from(&mut parser)?),
+            b"xtrim" => Command::XTrim(XTrim::from(&mut parser)?),
+            b"xread" => Command::XRead(XRead::from(&mut parser, false)?),
+            b"xreadgroup" => Command::XReadGroup(XRead::from(&mut parser, true)?),
+            b"xgroup" => Command::XGroup(XGroup::from(&mut parser)?),
+            b"xack" => Command::XAck(XAck::from(&mut parser)?),
+            b"xpending" => Command::XPending(XPending::from(&mut parser)?),
+            b"xclaim" => Command::XClaim(XClaim::from(&mut parser)?),
+            b"xautoclaim" => Command::XAutoClaim(XAutoClaim::from(&mut parser)?),
+            b"xinfo" => Command::XInfo(XInfo::from(&mut parser)?),
+            b"xsetid" => Command::XSetId(XSetId::from(&mut parser)?),
+            b"geoadd" => Command::GeoAdd(GeoAdd::from(&mut parser)?),
+            b"geopos" => Command::GeoPos(GeoPos::from(&mut parser)?),
+            b"geodist" => Command::GeoDist(GeoDist::from(&mut parser)?),
+            b"geohash" => Command::GeoHash(GeoHash::from(&mut parser)?),
+            b"geosearch" => Command::GeoSearch(GeoSearch::from(&mut parser)?),
+            b"geosearchstore" => Command::GeoSearchStore(GeoSearchStore::from(&mut parser)?),
 
             b"save" => Command::Save(Save::from(&mut parser)?),
             b"bgsave" => Command::BgSave(BgSave::from(&mut parser)?),
 
             b"bgrewriteaof" => Command::BgRewriteAof(BgRewriteAof::from(&mut parser)?),
 
+            b"info" => Command::Info(Info::from(&mut parser)?),
+
             b"config" => match parser.next_string()? {
                 Some(subcmd) => match &subcmd.to_ascii_lowercase()[..] {
                     b"get" => Command::ConfigGet(ConfigGet::from(&mut parser)?),
@@ -218,9 +624,54 @@ impl Command {
                 }
             },
 
-            // b"publish" => Ok(Command::Publish(Publish {})),
-            // b"subscribe" => Ok(Command::Subscribe(Subscribe {})),
-            // b"unsubscribe" => Ok(Command::Unsubscribe(Unsubscribe {})),
+            b"multi" => Command::Multi(Multi::from(&mut parser)?),
+            b"exec" => Command::Exec(Exec::from(&mut parser)?),
+            b"discard" => Command::Discard(Discard::from(&mut parser)?),
+            b"watch" => Command::Watch(Watch::from(&mut parser)?),
+            b"unwatch" => Command::Unwatch(Unwatch::from(&mut parser)?),
+
+            b"subscribe" => Command::Subscribe(Subscribe::from(&mut parser)?),
+            b"unsubscribe" => Command::Unsubscribe(Unsubscribe::from(&mut parser)?),
+            b"psubscribe" => Command::PSubscribe(PSubscribe::from(&mut parser)?),
+            b"punsubscribe" => Command::PUnsubscribe(PUnsubscribe::from(&mut parser)?),
+            b"publish" => Command::Publish(Publish::from(&mut parser)?),
+            b"pubsub" => match parser.next_string()? {
+                Some(subcmd) => match &subcmd.to_ascii_lowercase()[..] {
+                    b"channels" => Command::PubSubChannels(PubSubChannels::from(&mut parser)?),
+                    b"numsub" => Command::PubSubNumSub(PubSubNumSub::from(&mut parser)?),
+                    b"numpat" => Command::PubSubNumPat(PubSubNumPat::from(&mut parser)?),
+                    _ => Command::Unknown(Unknown::new(subcmd)),
+                },
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "PUBSUB subcommand not provided",
+                    ))
+                }
+            },
+
+            b"auth" => Command::Auth(Auth::from(&mut parser)?),
+            b"acl" => match parser.next_string()? {
+                Some(subcmd) => match &subcmd.to_ascii_lowercase()[..] {
+                    b"setuser" => Command::AclSetUser(AclSetUser::from(&mut parser)?),
+                    b"getuser" => Command::AclGetUser(AclGetUser::from(&mut parser)?),
+                    b"deluser" => Command::AclDelUser(AclDelUser::from(&mut parser)?),
+                    b"list" => Command::AclList(AclList::from(&mut parser)?),
+                    b"users" => Command::AclUsers(AclUsers::from(&mut parser)?),
+                    b"whoami" => Command::AclWhoAmI(AclWhoAmI::from(&mut parser)?),
+                    b"cat" => Command::AclCat(AclCat::from(&mut parser)?),
+                    b"load" => Command::AclLoad(AclLoad::from(&mut parser)?),
+                    b"save" => Command::AclSave(AclSave::from(&mut parser)?),
+                    _ => Command::Unknown(Unknown::new(subcmd)),
+                },
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "ACL subcommand not provided",
+                    ))
+                }
+            },
+
             _ => Command::Unknown(Unknown::new(cmd)),
         };
 
@@ -230,10 +681,62 @@ impl Command {
 
         Ok(command)
     }
+
+    /// Commands executed immediately instead of being queued in a MULTI context
+    pub fn is_transaction_control(&self) -> bool {
+        matches!(
+            self,
+            Command::Multi(_)
+                | Command::Exec(_)
+                | Command::Discard(_)
+                | Command::Watch(_)
+                | Command::Quit(_)
+        )
+    }
+
+    /// Commands that must not interleave with the commands of other clients
+    pub fn is_exclusive(&self) -> bool {
+        matches!(
+            self,
+            Command::Exec(_)
+                | Command::MSet(_)
+                | Command::MSetNx(_)
+                | Command::Move(_)
+                | Command::LMove(_)
+                | Command::BLMove(_)
+                | Command::BitOp(_)
+                | Command::PfCount(_)
+                | Command::PfMerge(_)
+                | Command::SMove(_)
+                | Command::SInter(_)
+                | Command::SUnion(_)
+                | Command::SDiff(_)
+                | Command::SInterStore(_)
+                | Command::SUnionStore(_)
+                | Command::SDiffStore(_)
+                | Command::SInterCard(_)
+        )
+    }
+
+    /// Commands a client subscribed to channels or patterns is allowed to run
+    pub fn is_pubsub_context_allowed(&self) -> bool {
+        matches!(
+            self,
+            Command::Subscribe(_)
+                | Command::Unsubscribe(_)
+                | Command::PSubscribe(_)
+                | Command::PUnsubscribe(_)
+                | Command::Ping(_)
+                | Command::Quit(_)
+        )
+    }
 }
 
 impl Client {
     pub async fn handle_command(&mut self, cmd: Command) -> Result<()> {
+        // the selected database may have been swapped by SWAPDB
+        self.db = self.server.get(self.db.index as usize);
+
         match cmd {
             Command::Select(cmd) => {
                 if let Ok(()) = self.select(cmd.index as usize) {
@@ -244,10 +747,8 @@ impl Client {
                 }
             }
             Command::DbSize(_) => {
-                // let len = self.dbs.len();
-                let len = 1; // only one database for now
+                let len = self.db.len() as i64;
                 self.write_frame(&Frame::Integer(len)).await?;
-                // continue;
             }
             Command::Save(_) => {
                 if self.server.rdb_state.read().await.rdb_child_pid.is_some() {
@@ -305,16 +806,42 @@ impl Client {
                 }
             }
 
+            Command::Info(cmd) => cmd.apply(self).await?,
+
             Command::Ping(cmd) => cmd.apply(self).await?,
             Command::Echo(cmd) => cmd.apply(self).await?,
             Command::Quit(_) => {
-                self.server.quit_ch.send(());
+                // only this connection is closed, once the reply is sent
+                self.add_flags(REDIS_CLOSE_AFTER_REPLY).await;
                 self.write_frame(&shared::ok).await?;
             }
+            Command::Hello(cmd) => cmd.apply(self).await?,
 
             Command::Get(cmd) => cmd.apply(self).await?,
             Command::Set(cmd) => cmd.apply(self).await?,
             Command::SetNx(cmd) => cmd.apply(self).await?,
+            Command::SetEx(cmd) => cmd.apply(self).await?,
+            Command::PSetEx(cmd) => cmd.apply(self).await?,
+            Command::MGet(cmd) => cmd.apply(self).await?,
+            Command::MSet(cmd) => cmd.apply(self).await?,
+            Command::MSetNx(cmd) => cmd.apply(self).await?,
+            Command::GetSet(cmd) => cmd.apply(self).await?,
+            Command::GetDel(cmd) => cmd.apply(self).await?,
+            Command::GetEx(cmd) => cmd.apply(self).await?,
+            Command::GetRange(cmd) => cmd.apply(self).await?,
+            Command::SetRange(cmd) => cmd.apply(self).await?,
+            Command::IncrByFloat(cmd) => cmd.apply(self).await?,
+            Command::Lcs(cmd) => cmd.apply(self).await?,
+            Command::SetBit(cmd) => cmd.apply(self).await?,
+            Command::GetBit(cmd) => cmd.apply(self).await?,
+            Command::BitCount(cmd) => cmd.apply(self).await?,
+            Command::BitPos(cmd) => cmd.apply(self).await?,
+            Command::BitOp(cmd) => cmd.apply(self).await?,
+            Command::BitField(cmd) => cmd.apply(self).await?,
+            Command::BitFieldRo(cmd) => cmd.apply(self).await?,
+            Command::PfAdd(cmd) => cmd.apply(self).await?,
+            Command::PfCount(cmd) => cmd.apply(self).await?,
+            Command::PfMerge(cmd) => cmd.apply(self).await?,
             Command::Append(cmd) => cmd.apply(self).await?,
             Command::Strlen(cmd) => cmd.apply(self).await?,
             Command::Incr(cmd) => cmd.apply(self).await?,
@@ -325,6 +852,7 @@ impl Client {
             Command::Del(cmd) => cmd.apply(self).await?,
             Command::Exists(cmd) => cmd.apply(self).await?,
             Command::Keys(cmd) => cmd.apply(self).await?,
+            Command::Scan(cmd) => cmd.apply(self).await?,
             Command::Shutdown(cmd) => cmd.apply(self).await?,
             Command::Rename(cmd) => cmd.apply(self).await?,
             Command::Expire(cmd) => cmd.apply(self).await?,
@@ -332,70 +860,163 @@ impl Client {
             Command::PExpire(cmd) => cmd.apply(self).await?,
             Command::PExpireAt(cmd) => cmd.apply(self).await?,
             Command::Type(cmd) => cmd.apply(self).await?,
+            Command::Move(cmd) => cmd.apply(self).await?,
+            Command::SwapDb(cmd) => cmd.apply(self).await?,
+            Command::FlushDb(cmd) => cmd.apply(self).await?,
+            Command::FlushAll(cmd) => cmd.apply(self).await?,
 
             Command::LPush(cmd) => cmd.apply(self).await?,
             Command::RPush(cmd) => cmd.apply(self).await?,
             Command::LPop(cmd) => cmd.apply(self).await?,
             Command::RPop(cmd) => cmd.apply(self).await?,
+            Command::LPushX(cmd) => cmd.apply(self).await?,
+            Command::RPushX(cmd) => cmd.apply(self).await?,
+            Command::LLen(cmd) => cmd.apply(self).await?,
+            Command::LRange(cmd) => cmd.apply(self).await?,
+            Command::LIndex(cmd) => cmd.apply(self).await?,
+            Command::LSet(cmd) => cmd.apply(self).await?,
+            Command::LInsert(cmd) => cmd.apply(self).await?,
+            Command::LRem(cmd) => cmd.apply(self).await?,
+            Command::LTrim(cmd) => cmd.apply(self).await?,
+            Command::LPos(cmd) => cmd.apply(self).await?,
+            Command::LMove(cmd) => cmd.apply(self).await?,
+            Command::RPopLPush(cmd) => cmd.apply(self).await?,
+            Command::LMPop(cmd) => cmd.apply(self).await?,
+            Command::BLPop(cmd) => cmd.apply(self).await?,
+            Command::BRPop(cmd) => cmd.apply(self).await?,
+            Command::BLMPop(cmd) => cmd.apply(self).await?,
+            Command::BLMove(cmd) => cmd.apply(self).await?,
 
             Command::HSet(cmd) => cmd.apply(self).await?,
             Command::HGet(cmd) => cmd.apply(self).await?,
+            Command::HMSet(cmd) => cmd.apply(self).await?,
+            Command::HSetNx(cmd) => cmd.apply(self).await?,
+            Command::HMGet(cmd) => cmd.apply(self).await?,
+            Command::HDel(cmd) => cmd.apply(self).await?,
+            Command::HExists(cmd) => cmd.apply(self).await?,
+            Command::HLen(cmd) => cmd.apply(self).await?,
+            Command::HStrLen(cmd) => cmd.apply(self).await?,
+            Command::HKeys(cmd) => cmd.apply(self).await?,
+            Command::HVals(cmd) => cmd.apply(self).await?,
+            Command::HGetAll(cmd) => cmd.apply(self).await?,
+            Command::HScan(cmd) => cmd.apply(self).await?,
+            Command::HIncrBy(cmd) => cmd.apply(self).await?,
+            Command::HIncrByFloat(cmd) => cmd.apply(self).await?,
+            Command::HRandField(cmd) => cmd.apply(self).await?,
 
             Command::SAdd(cmd) => cmd.apply(self).await?,
             Command::SRem(cmd) => cmd.apply(self).await?,
+            Command::SMembers(cmd) => cmd.apply(self).await?,
+            Command::SScan(cmd) => cmd.apply(self).await?,
+            Command::SIsMember(cmd) => cmd.apply(self).await?,
+            Command::SMIsMember(cmd) => cmd.apply(self).await?,
+            Command::SCard(cmd) => cmd.apply(self).await?,
+            Command::SPop(cmd) => cmd.apply(self).await?,
+            Command::SRandMember(cmd) => cmd.apply(self).await?,
+            Command::SMove(cmd) => cmd.apply(self).await?,
+            Command::SInter(cmd) => cmd.apply(self).await?,
+            Command::SUnion(cmd) => cmd.apply(self).await?,
+            Command::SDiff(cmd) => cmd.apply(self).await?,
+            Command::SInterStore(cmd) => cmd.apply(self).await?,
+            Command::SUnionStore(cmd) => cmd.apply(self).await?,
+            Command::SDiffStore(cmd) => cmd.apply(self).await?,
+            Command::SInterCard(cmd) => cmd.apply(self).await?,
+
+            Command::ZAdd(cmd) => cmd.apply(self).await?,
+            Command::ZIncrBy(cmd) => cmd.apply(self).await?,
+            Command::ZRem(cmd) => cmd.apply(self).await?,
+            Command::ZScore(cmd) => cmd.apply(self).await?,
+            Command::ZScan(cmd) => cmd.apply(self).await?,
+            Command::ZMScore(cmd) => cmd.apply(self).await?,
+            Command::ZCard(cmd) => cmd.apply(self).await?,
+            Command::ZCount(cmd) => cmd.apply(self).await?,
+            Command::ZRank(cmd) => cmd.apply(self).await?,
+            Command::ZRevRank(cmd) => cmd.apply(self).await?,
+            Command::ZRange(cmd) => cmd.apply(self).await?,
+            Command::ZRangeStore(cmd) => cmd.apply(self).await?,
+            Command::ZPopMin(cmd) => cmd.apply(self).await?,
+            Command::ZPopMax(cmd) => cmd.apply(self).await?,
+            Command::ZRemRangeByRank(cmd) => cmd.apply(self).await?,
+            Command::ZRemRangeByScore(cmd) => cmd.apply(self).await?,
+            Command::ZRemRangeByLex(cmd) => cmd.apply(self).await?,
+            Command::ZUnionStore(cmd) => cmd.apply(self).await?,
+            Command::ZInterStore(cmd) => cmd.apply(self).await?,
+            Command::ZDiffStore(cmd) => cmd.apply(self).await?,
+
+            Command::XAdd(cmd) => cmd.apply(self).await?,
+            Command::XRange(cmd) => cmd.apply(self).await?,
+            Command::XRevRange(cmd) => cmd.apply(self).await?,
+            Command::XLen(cmd) => cmd.apply(self).await?,
+            Command::XDel(cmd) => cmd.apply(self).await?,
+            Command::XTrim(cmd) => cmd.apply(self).await?,
+            Command::XRead(cmd) => cmd.apply(self).await?,
+            Command::XReadGroup(cmd) => cmd.apply(self).await?,
+            Command::XGroup(cmd) => cmd.apply(self).await?,
+            Command::XAck(cmd) => cmd.apply(self).await?,
+            Command::XPending(cmd) => cmd.apply(self).await?,
+            Command::XClaim(cmd) => cmd.apply(self).await?,
+            Command::XAutoClaim(cmd) => cmd.apply(self).await?,
+            Command::XInfo(cmd) => cmd.apply(self).await?,
+            Command::XSetId(cmd) => cmd.apply(self).await?,
+            Command::GeoAdd(cmd) => cmd.apply(self).await?,
+            Command::GeoPos(cmd) => cmd.apply(self).await?,
+            Command::GeoDist(cmd) => cmd.apply(self).await?,
+            Command::GeoHash(cmd) => cmd.apply(self).await?,
+            Command::GeoSearch(cmd) => cmd.apply(self).await?,
+            Command::GeoSearchStore(cmd) => cmd.apply(self).await?,
 
             Command::ConfigGet(cmd) => {
                 let config = self.config.clone();
                 match &cmd.key[..] {
                     b"dbfilename" => {
-                        self.write_frame(&Frame::Array(vec![
+                        self.write_frame(&Frame::Map(vec![(
                             Frame::Bulk(cmd.key),
                             Frame::new_bulk_from(config.read().await.rdb_filename.clone()),
-                        ]))
+                        )]))
                         .await?;
                     }
                     b"port" => {
-                        self.write_frame(&Frame::Array(vec![
+                        self.write_frame(&Frame::Map(vec![(
                             Frame::Bulk(cmd.key),
                             Frame::new_bulk_from(config.read().await.port.to_string()),
-                        ]))
+                        )]))
                         .await?;
                     }
                     b"databases" => {
-                        self.write_frame(&Frame::Array(vec![
+                        self.write_frame(&Frame::Map(vec![(
                             Frame::Bulk(cmd.key),
                             Frame::new_bulk_from(config.read().await.db_num.to_string()),
-                        ]))
+                        )]))
                         .await?;
                     }
                     b"hz" => {
-                        self.write_frame(&Frame::Array(vec![
+                        self.write_frame(&Frame::Map(vec![(
                             Frame::Bulk(cmd.key),
                             Frame::new_bulk_from(config.read().await.hz.to_string()),
-                        ]))
+                        )]))
                         .await?;
                     }
                     b"appendonly" => {
-                        self.write_frame(&Frame::Array(vec![
+                        self.write_frame(&Frame::Map(vec![(
                             Frame::Bulk(cmd.key),
                             Frame::new_bulk_from(config.read().await.aof_state.to_string()),
-                        ]))
+                        )]))
                         .await?;
                     }
                     b"dir" => {
                         // cwd
                         let cwd = std::env::current_dir().unwrap();
-                        self.write_frame(&Frame::Array(vec![
+                        self.write_frame(&Frame::Map(vec![(
                             Frame::Bulk(cmd.key),
                             Frame::new_bulk_from_slice(cwd.to_str().unwrap().as_bytes()),
-                        ]))
+                        )]))
                         .await?;
                     }
                     b"appendfsync" => {
-                        self.write_frame(&Frame::Array(vec![
+                        self.write_frame(&Frame::Map(vec![(
                             Frame::Bulk(cmd.key),
                             Frame::new_bulk_from(config.read().await.aof_fsync.to_string()),
-                        ]))
+                        )]))
                         .await?;
                     }
                     b"save" => {
@@ -409,24 +1030,117 @@ impl Client {
                             .collect::<Vec<String>>()
                             .join(" ");
 
-                        self.write_frame(&Frame::Array(vec![
+                        self.write_frame(&Frame::Map(vec![(
                             Frame::Bulk(cmd.key),
                             Frame::new_bulk_from(save_params),
-                        ]))
+                        )]))
                         .await?;
                     }
                     b"loglevel" => {
-                        self.write_frame(&Frame::Array(vec![
+                        self.write_frame(&Frame::Map(vec![(
                             Frame::Bulk(cmd.key),
                             Frame::new_bulk_from(config.read().await.verbosity.to_string()),
-                        ]))
+                        )]))
                         .await?;
                     }
                     b"bind" => {
-                        self.write_frame(&Frame::Array(vec![
+                        self.write_frame(&Frame::Map(vec![(
                             Frame::Bulk(cmd.key),
                             Frame::new_bulk_from_slice(config.read().await.bindaddr.as_bytes()),
-                        ]))
+                        )]))
+                        .await?;
+                    }
+                    b"requirepass" => {
+                        let requirepass = config.read().await.requirepass.clone();
+                        self.write_frame(&Frame::Map(vec![(
+                            Frame::Bulk(cmd.key),
+                            Frame::new_bulk_from(requirepass.unwrap_or_default()),
+                        )]))
+                        .await?;
+                    }
+                    b"maxmemory" => {
+                        self.write_frame(&Frame::Map(vec![(
+                            Frame::Bulk(cmd.key),
+                            Frame::new_bulk_from(config.read().await.maxmemory.to_string()),
+                        )]))
+                        .await?;
+                    }
+                    b"proto-max-bulk-len" => {
+                        self.write_frame(&Frame::Map(vec![(
+                            Frame::Bulk(cmd.key),
+                            Frame::new_bulk_from(
+                                config.read().await.proto_max_bulk_len.to_string(),
+                            ),
+                        )]))
+                        .await?;
+                    }
+                    b"proto-max-multibulk-len" => {
+                        self.write_frame(&Frame::Map(vec![(
+                            Frame::Bulk(cmd.key),
+                            Frame::new_bulk_from(
+                                config.read().await.proto_max_multibulk_len.to_string(),
+                            ),
+                        )]))
+                        .await?;
+                    }
+                    b"proto-max-depth" => {
+                        self.write_frame(&Frame::Map(vec![(
+                            Frame::Bulk(cmd.key),
+                            Frame::new_bulk_from(config.read().await.proto_max_depth.to_string()),
+                        )]))
+                        .await?;
+                    }
+                    b"client-query-buffer-limit" => {
+                        self.write_frame(&Frame::Map(vec![(
+                            Frame::Bulk(cmd.key),
+                            Frame::new_bulk_from(
+                                config.read().await.client_query_buffer_limit.to_string(),
+                            ),
+                        )]))
+                        .await?;
+                    }
+                    b"client-output-buffer-limit" => {
+                        let limit = config.read().await.client_output_buffer_limit_pubsub;
+                        self.write_frame(&Frame::Map(vec![(
+                            Frame::Bulk(cmd.key),
+                            Frame::new_bulk_from(format!("pubsub {}", limit)),
+                        )]))
+                        .await?;
+                    }
+                    b"maxmemory-policy" => {
+                        self.write_frame(&Frame::Map(vec![(
+                            Frame::Bulk(cmd.key),
+                            Frame::new_bulk_from(config.read().await.maxmemory_policy.to_string()),
+                        )]))
+                        .await?;
+                    }
+                    b"maxmemory-samples" => {
+                        self.write_frame(&Frame::Map(vec![(
+                            Frame::Bulk(cmd.key),
+                            Frame::new_bulk_from(config.read().await.maxmemory_samples.to_string()),
+                        )]))
+                        .await?;
+                    }
+                    b"timeout" => {
+                        self.write_frame(&Frame::Map(vec![(
+                            Frame::Bulk(cmd.key),
+                            Frame::new_bulk_from(config.read().await.timeout.to_string()),
+                        )]))
+                        .await?;
+                    }
+                    b"tcp-keepalive" => {
+                        self.write_frame(&Frame::Map(vec![(
+                            Frame::Bulk(cmd.key),
+                            Frame::new_bulk_from(config.read().await.tcp_keepalive.to_string()),
+                        )]))
+                        .await?;
+                    }
+                    b"aclfile" => {
+                        let aclfile = config.read().await.aclfile.clone();
+                        self.write_frame(&Frame::Map(vec![(
+                            Frame::Bulk(cmd.key),
+                            Frame::new_bulk_from(aclfile.unwrap_or_default()),
+                        )]))
                         .await?;
                     }
                     _ => {
@@ -461,13 +1175,11 @@ impl Client {
                     }
                 }
                 b"databases" => {
-                    if let Ok(db_num) = std::str::from_utf8(&cmd.value).unwrap().parse::<usize>() {
-                        self.config.write().await.db_num = db_num;
-                        self.write_frame(&shared::ok).await?;
-                    } else {
-                        self.write_frame(&Frame::Error(Bytes::from_static(b"ERR invalid db_num")))
-                            .await?;
-                    }
+                    // the databases are allocated once at startup
+                    self.write_frame(&Frame::Error(Bytes::from_static(
+                        b"ERR can't set immutable config databases",
+                    )))
+                    .await?;
                 }
                 b"hz" => {
                     if let Ok(hz) = std::str::from_utf8(&cmd.value).unwrap().parse::<usize>() {
@@ -550,6 +1262,204 @@ impl Client {
                     self.config.write().await.verbosity = verbosity;
                     self.write_frame(&shared::ok).await?;
                 }
+                b"maxmemory" => {
+                    match std::str::from_utf8(&cmd.value)
+                        .ok()
+                        .and_then(shared::parse_memory)
+                    {
+                        Some(maxmemory) => {
+                            self.config.write().await.maxmemory = maxmemory;
+                            // evict right away if the limit was lowered
+                            let _ = self.server.perform_evictions().await;
+                            self.write_frame(&shared::ok).await?;
+                        }
+                        None => {
+                            self.write_frame(&Frame::Error(Bytes::from_static(
+                                b"ERR invalid maxmemory",
+                            )))
+                            .await?;
+                        }
+                    }
+                }
+                b"proto-max-bulk-len" => {
+                    match std::str::from_utf8(&cmd.value)
+                        .ok()
+                        .and_then(shared::parse_memory)
+                        .filter(|&n| n >= 1024 * 1024)
+                    {
+                        Some(len) => {
+                            self.config.write().await.proto_max_bulk_len = len;
+                            self.write_frame(&shared::ok).await?;
+                        }
+                        None => {
+                            self.write_frame(&Frame::Error(Bytes::from_static(
+                                b"ERR invalid proto-max-bulk-len",
+                            )))
+                            .await?;
+                        }
+                    }
+                }
+                b"proto-max-multibulk-len" => {
+                    match std::str::from_utf8(&cmd.value)
+                        .ok()
+                        .and_then(|s| s.parse::<u64>().ok())
+                        .filter(|&n| n > 0)
+                    {
+                        Some(len) => {
+                            self.config.write().await.proto_max_multibulk_len = len;
+                            self.write_frame(&shared::ok).await?;
+                        }
+                        None => {
+                            self.write_frame(&Frame::Error(Bytes::from_static(
+                                b"ERR invalid proto-max-multibulk-len",
+                            )))
+                            .await?;
+                        }
+                    }
+                }
+                b"proto-max-depth" => {
+                    match std::str::from_utf8(&cmd.value)
+                        .ok()
+                        .and_then(|s| s.parse::<u64>().ok())
+                        .filter(|&n| n > 0)
+                    {
+                        Some(depth) => {
+                            self.config.write().await.proto_max_depth = depth;
+                            self.write_frame(&shared::ok).await?;
+                        }
+                        None => {
+                            self.write_frame(&Frame::Error(Bytes::from_static(
+                                b"ERR invalid proto-max-depth",
+                            )))
+                            .await?;
+                        }
+                    }
+                }
+                b"client-query-buffer-limit" => {
+                    match std::str::from_utf8(&cmd.value)
+                        .ok()
+                        .and_then(shared::parse_memory)
+                        .filter(|&n| n >= 1024 * 1024)
+                    {
+                        Some(limit) => {
+                            self.config.write().await.client_query_buffer_limit = limit;
+                            self.write_frame(&shared::ok).await?;
+                        }
+                        None => {
+                            self.write_frame(&Frame::Error(Bytes::from_static(
+                                b"ERR invalid client-query-buffer-limit",
+                            )))
+                            .await?;
+                        }
+                    }
+                }
+                b"client-output-buffer-limit" => {
+                    // only the limits of the pubsub clients are enforced
+                    match std::str::from_utf8(&cmd.value)
+                        .ok()
+                        .and_then(|value| value.trim().strip_prefix("pubsub "))
+                        .and_then(OutputBufferLimit::parse)
+                    {
+                        Some(limit) => {
+                            self.config.write().await.client_output_buffer_limit_pubsub = limit;
+                            self.write_frame(&shared::ok).await?;
+                        }
+                        None => {
+                            self.write_frame(&Frame::Error(Bytes::from_static(
+                                b"ERR invalid client-output-buffer-limit",
+                            )))
+                            .await?;
+                        }
+                    }
+                }
+                b"maxmemory-policy" => {
+                    match std::str::from_utf8(&cmd.value)
+                        .ok()
+                        .and_then(MaxmemoryPolicy::parse)
+                    {
+                        Some(policy) => {
+                            self.config.write().await.maxmemory_policy = policy;
+                            self.write_frame(&shared::ok).await?;
+                        }
+                        None => {
+                            self.write_frame(&Frame::Error(Bytes::from_static(
+                                b"ERR invalid maxmemory-policy",
+                            )))
+                            .await?;
+                        }
+                    }
+                }
+                b"maxmemory-samples" => {
+                    match std::str::from_utf8(&cmd.value)
+                        .ok()
+                        .and_then(|s| s.parse::<usize>().ok())
+                        .filter(|&n| n > 0)
+                    {
+                        Some(samples) => {
+                            self.config.write().await.maxmemory_samples = samples;
+                            self.write_frame(&shared::ok).await?;
+                        }
+                        None => {
+                            self.write_frame(&Frame::Error(Bytes::from_static(
+                                b"ERR invalid maxmemory-samples",
+                            )))
+                            .await?;
+                        }
+                    }
+                }
+                b"timeout" => {
+                    match std::str::from_utf8(&cmd.value)
+                        .ok()
+                        .and_then(|s| s.parse::<u64>().ok())
+                    {
+                        Some(timeout) => {
+                            self.config.write().await.timeout = timeout;
+                            self.write_frame(&shared::ok).await?;
+                        }
+                        None => {
+                            self.write_frame(&Frame::Error(Bytes::from_static(
+                                b"ERR invalid timeout",
+                            )))
+                            .await?;
+                        }
+                    }
+                }
+                b"tcp-keepalive" => {
+                    // only applied to the new connections
+                    match std::str::from_utf8(&cmd.value)
+                        .ok()
+                        .and_then(|s| s.parse::<u64>().ok())
+                    {
+                        Some(interval) => {
+                            self.config.write().await.tcp_keepalive = interval;
+                            self.write_frame(&shared::ok).await?;
+                        }
+                        None => {
+                            self.write_frame(&Frame::Error(Bytes::from_static(
+                                b"ERR invalid tcp-keepalive",
+                            )))
+                            .await?;
+                        }
+                    }
+                }
+                b"requirepass" => match String::from_utf8(cmd.value.to_vec()) {
+                    Ok(requirepass) => {
+                        self.server
+                            .acl
+                            .write()
+                            .await
+                            .set_requirepass(Some(&requirepass));
+                        self.config.write().await.requirepass =
+                            Some(requirepass).filter(|p| !p.is_empty());
+                        self.write_frame(&shared::ok).await?;
+                    }
+                    Err(_) => {
+                        self.write_frame(&Frame::Error(Bytes::from_static(
+                            b"ERR invalid requirepass",
+                        )))
+                        .await?;
+                    }
+                },
                 _ => {
                     self.write_frame(&Frame::Error(Bytes::from_static(
                         b"ERR no such configuration",
@@ -557,14 +1467,41 @@ impl Client {
                     .await?;
                 }
             },
-            Command::ConfigResetStat(_) => todo!(),
+            Command::ConfigResetStat(_) => {
+                self.server.stats.reset();
+                for db in self.server.databases() {
+                    db.expired_keys.store(0, Ordering::Relaxed);
+                }
+                self.write_frame(&shared::ok).await?;
+            }
             Command::ConfigRewrite(_) => todo!(),
 
-            // Publish(cmd) => cmd.apply(self).await?,
-            // Subscribe(cmd) => cmd.apply(db, dst, shutdown).await,
-            // `Unsubscribe` cannot be applied. It may only be received from the
-            // context of a `Subscribe` command.
-            // Unsubscribe(_) => Err("`Unsubscribe` is unsupported in this context".into()),
+            Command::Multi(cmd) => cmd.apply(self).await?,
+            Command::Exec(cmd) => cmd.apply(self).await?,
+            Command::Discard(cmd) => cmd.apply(self).await?,
+            Command::Watch(cmd) => cmd.apply(self).await?,
+            Command::Unwatch(cmd) => cmd.apply(self).await?,
+
+            Command::Subscribe(cmd) => cmd.apply(self).await?,
+            Command::Unsubscribe(cmd) => cmd.apply(self).await?,
+            Command::PSubscribe(cmd) => cmd.apply(self).await?,
+            Command::PUnsubscribe(cmd) => cmd.apply(self).await?,
+            Command::Publish(cmd) => cmd.apply(self).await?,
+            Command::PubSubChannels(cmd) => cmd.apply(self).await?,
+            Command::PubSubNumSub(cmd) => cmd.apply(self).await?,
+            Command::PubSubNumPat(cmd) => cmd.apply(self).await?,
+
+            Command::Auth(cmd) => cmd.apply(self).await?,
+            Command::AclSetUser(cmd) => cmd.apply(self).await?,
+            Command::AclGetUser(cmd) => cmd.apply(self).await?,
+            Command::AclDelUser(cmd) => cmd.apply(self).await?,
+            Command::AclList(cmd) => cmd.apply(self).await?,
+            Command::AclUsers(cmd) => cmd.apply(self).await?,
+            Command::AclWhoAmI(cmd) => cmd.apply(self).await?,
+            Command::AclCat(cmd) => cmd.apply(self).await?,
+            Command::AclLoad(cmd) => cmd.apply(self).await?,
+            Command::AclSave(cmd) => cmd.apply(self).await?,
+
             Command::Unknown(cmd) => cmd.apply(self).await?,
         };
 
@@ -0,0 +1,389 @@
+use super::CommandParser;
+use crate::acl::{self, User};
+use crate::client::Client;
+use crate::frame::Frame;
+use crate::shared;
+use bytes::Bytes;
+use std::io::{Error, ErrorKind, Result};
+
+const NO_ACLFILE_ERR: &[u8] = b"ERR This Redis instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file set) in order to store users in the Redis configuration.";
+
+#[derive(Debug, Clone)]
+pub struct Auth {
+    /// the default user if not provided
+    pub username: Option<Bytes>,
+    pub password: Bytes,
+}
+
+impl Auth {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let first = frame
+            .next_string()?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "AUTH requires a password"))?;
+        match frame.next_string()? {
+            Some(password) => Ok(Self {
+                username: Some(first),
+                password,
+            }),
+            None => Ok(Self {
+                username: None,
+                password: first,
+            }),
+        }
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let username = {
+            let acl = client.server.acl.read().await;
+            if self.username.is_none() && !acl.auth_required() {
+                None
+            } else {
+                let username = self
+                    .username
+                    .unwrap_or_else(|| Bytes::from_static(b"default"));
+                if acl.authenticate(&username, &self.password) {
+                    Some(Ok(username))
+                } else {
+                    Some(Err(username))
+                }
+            }
+        };
+
+        match username {
+            None => {
+                client
+                    .write_frame(&Frame::Error(Bytes::from_static(
+                        b"ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?",
+                    )))
+                    .await?;
+            }
+            Some(Ok(username)) => {
+                client.user = username;
+                client.authenticated = true;
+                client.write_frame(&shared::ok).await?;
+            }
+            Some(Err(username)) => {
+                log::warn!(
+                    "Failed authentication of user {} from {}",
+                    String::from_utf8_lossy(&username),
+                    client.address
+                );
+                client
+                    .write_frame(&Frame::Error(Bytes::from_static(
+                        b"WRONGPASS invalid username-password pair or user is disabled.",
+                    )))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AclSetUser {
+    pub username: Bytes,
+    pub rules: Vec<Bytes>,
+}
+
+impl AclSetUser {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let username = frame.next_string()?.ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, "ACL SETUSER requires a username")
+        })?;
+        let mut rules = Vec::new();
+        while let Some(rule) = frame.next_string()? {
+            rules.push(rule);
+        }
+        Ok(Self { username, rules })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = {
+            let mut acl = client.server.acl.write().await;
+            let mut user = acl
+                .users
+                .get(&self.username)
+                .cloned()
+                .unwrap_or_else(|| User::new(self.username.clone()));
+
+            // the rules are applied on a copy, so that nothing changes if one
+            // of them is invalid
+            let mut result = Ok(());
+            for rule in &self.rules {
+                if let Err(e) = user.set(rule) {
+                    result = Err(format!(
+                        "ERR Error in ACL SETUSER modifier '{}': {}",
+                        String::from_utf8_lossy(rule),
+                        e
+                    ));
+                    break;
+                }
+            }
+
+            match result {
+                Ok(()) => {
+                    acl.users.insert(self.username, user);
+                    shared::ok
+                }
+                Err(msg) => Frame::Error(Bytes::from(msg)),
+            }
+        };
+
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AclGetUser {
+    pub username: Bytes,
+}
+
+impl AclGetUser {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let username = frame.next_string()?.ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, "ACL GETUSER requires a username")
+        })?;
+        Ok(Self { username })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = match client.server.acl.read().await.users.get(&self.username) {
+            None => Frame::Null,
+            Some(user) => Frame::Array(vec![
+                Frame::Bulk(Bytes::from_static(b"flags")),
+                Frame::Array(
+                    user.flags()
+                        .into_iter()
+                        .map(|flag| Frame::Bulk(Bytes::from_static(flag.as_bytes())))
+                        .collect(),
+                ),
+                Frame::Bulk(Bytes::from_static(b"passwords")),
+                Frame::Array(
+                    user.passwords
+                        .iter()
+                        .map(|hash| Frame::new_bulk_from(hash.clone()))
+                        .collect(),
+                ),
+                Frame::Bulk(Bytes::from_static(b"commands")),
+                Frame::new_bulk_from(user.command_rules.clone()),
+                Frame::Bulk(Bytes::from_static(b"keys")),
+                Frame::new_bulk_from(user.describe_keys()),
+                Frame::Bulk(Bytes::from_static(b"channels")),
+                Frame::new_bulk_from(user.describe_channels()),
+            ]),
+        };
+
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AclDelUser {
+    pub usernames: Vec<Bytes>,
+}
+
+impl AclDelUser {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let mut usernames = Vec::new();
+        while let Some(username) = frame.next_string()? {
+            usernames.push(username);
+        }
+        if usernames.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "ACL DELUSER requires at least one username",
+            ));
+        }
+        Ok(Self { usernames })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        if self.usernames.iter().any(|name| name == "default") {
+            client
+                .write_frame(&Frame::Error(Bytes::from_static(
+                    b"ERR The 'default' user cannot be removed",
+                )))
+                .await?;
+            return Ok(());
+        }
+
+        // the clients authenticated as a deleted user are disconnected on
+        // their next command
+        let deleted = {
+            let mut acl = client.server.acl.write().await;
+            self.usernames
+                .iter()
+                .filter(|name| acl.users.remove(*name).is_some())
+                .count()
+        };
+
+        client.write_frame(&Frame::Integer(deleted as i64)).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AclList {}
+
+impl AclList {
+    pub fn from(_frame: &mut CommandParser) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = Frame::Array(
+            client
+                .server
+                .acl
+                .read()
+                .await
+                .users
+                .values()
+                .map(|user| {
+                    Frame::new_bulk_from(format!(
+                        "user {} {}",
+                        String::from_utf8_lossy(&user.name),
+                        user.describe()
+                    ))
+                })
+                .collect(),
+        );
+
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AclUsers {}
+
+impl AclUsers {
+    pub fn from(_frame: &mut CommandParser) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = Frame::Array(
+            client
+                .server
+                .acl
+                .read()
+                .await
+                .users
+                .keys()
+                .cloned()
+                .map(Frame::Bulk)
+                .collect(),
+        );
+
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AclWhoAmI {}
+
+impl AclWhoAmI {
+    pub fn from(_frame: &mut CommandParser) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = Frame::Bulk(client.user.clone());
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AclCat {
+    /// list the categories if not provided
+    pub category: Option<Bytes>,
+}
+
+impl AclCat {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let category = frame.next_string()?;
+        Ok(Self { category })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = match self.category {
+            None => Frame::Array(
+                acl::ACL_CATEGORY_NAMES
+                    .iter()
+                    .map(|(name, _)| Frame::Bulk(Bytes::from_static(name.as_bytes())))
+                    .collect(),
+            ),
+            Some(category) => match acl::category_flag(&String::from_utf8_lossy(&category)) {
+                Some(flag) => Frame::Array(
+                    acl::category_commands(flag)
+                        .into_iter()
+                        .map(|name| Frame::Bulk(Bytes::from_static(name.as_bytes())))
+                        .collect(),
+                ),
+                None => Frame::Error(Bytes::from(format!(
+                    "ERR Unknown category '{}'",
+                    String::from_utf8_lossy(&category)
+                ))),
+            },
+        };
+
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AclLoad {}
+
+impl AclLoad {
+    pub fn from(_frame: &mut CommandParser) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let aclfile = client.config.read().await.aclfile.clone();
+        let response = match aclfile {
+            None => Frame::Error(Bytes::from_static(NO_ACLFILE_ERR)),
+            Some(aclfile) => match client.server.acl.write().await.load_file(&aclfile) {
+                Ok(()) => shared::ok,
+                Err(e) => Frame::Error(Bytes::from(format!("ERR {}", e))),
+            },
+        };
+
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AclSave {}
+
+impl AclSave {
+    pub fn from(_frame: &mut CommandParser) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let aclfile = client.config.read().await.aclfile.clone();
+        let response = match aclfile {
+            None => Frame::Error(Bytes::from_static(NO_ACLFILE_ERR)),
+            Some(aclfile) => match client.server.acl.read().await.save_file(&aclfile) {
+                Ok(()) => shared::ok,
+                Err(e) => {
+                    log::error!("Error saving the ACL file {}: {}", aclfile, e);
+                    Frame::Error(Bytes::from_static(
+                        b"ERR There was an error trying to save the ACLs. Please check the server logs for more information",
+                    ))
+                }
+            },
+        };
+
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
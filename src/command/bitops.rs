@@ -0,0 +1,813 @@
+use super::CommandParser;
+use crate::client::Client;
+use crate::dbms::{DatabaseRef, DictEntry, DictValue};
+use crate::frame::Frame;
+use crate::object::RudisObject;
+use crate::shared;
+use bytes::{Bytes, BytesMut};
+use std::io::Result;
+
+/// Bits of the largest string, 512MB
+const MAX_BITS: u64 = 512 * 1024 * 1024 * 8;
+
+fn next_bit_offset(frame: &mut CommandParser) -> Result<u64> {
+    match frame.next_integer()? {
+        Some(offset) if offset >= 0 && (offset as u64) < MAX_BITS => Ok(offset as u64),
+        _ => Err(CommandParser::reply_error(
+            "ERR bit offset is not an integer or out of range",
+        )),
+    }
+}
+
+/// Parse the optional `start end [BYTE|BIT]` range of BITCOUNT and BITPOS,
+/// the end defaults to the last byte or bit
+fn next_bit_range(frame: &mut CommandParser) -> Result<Option<BitRange>> {
+    let start = match frame.next_integer()? {
+        Some(start) => start,
+        None => return Ok(None),
+    };
+    let end = frame.next_integer()?;
+    let bit = match frame.next_string()? {
+        Some(unit) if unit.eq_ignore_ascii_case(b"bit") => true,
+        Some(unit) if unit.eq_ignore_ascii_case(b"byte") => false,
+        None => false,
+        Some(_) => return Err(CommandParser::syntax_error()),
+    };
+    if frame.has_next() {
+        return Err(CommandParser::syntax_error());
+    }
+    Ok(Some(BitRange { start, end, bit }))
+}
+
+/// Reply `f` applied to the string at `key`, `missing` if there is no such
+/// key
+fn read_string(
+    db: &DatabaseRef,
+    key: &Bytes,
+    missing: Frame,
+    f: impl FnOnce(&[u8]) -> Frame,
+) -> Frame {
+    match db.get(key) {
+        Some(entry) => match &entry.value {
+            RudisObject::String(s) => f(s),
+            _ => shared::wrong_type_err,
+        },
+        None => missing,
+    }
+}
+
+/// Reply `f` applied to the string at `key`, modified in place unless its
+/// memory is shared. The key is created if `f` grows the string
+fn write_string(db: &DatabaseRef, key: Bytes, f: impl FnOnce(&mut BytesMut) -> Frame) -> Frame {
+    match db.entry(key) {
+        DictEntry::Occupied(mut oe) => match &mut oe.get_mut().value {
+            RudisObject::String(s) => s.modify(f),
+            _ => shared::wrong_type_err,
+        },
+        DictEntry::Vacant(ve) => {
+            let mut value = BytesMut::new();
+            let reply = f(&mut value);
+            if !value.is_empty() {
+                ve.insert(DictValue::new(
+                    RudisObject::new_string_from(value.freeze()),
+                    None,
+                ));
+            }
+            reply
+        }
+    }
+}
+
+/// Zero-fill `buf` up to the byte holding `bit`
+fn grow(buf: &mut BytesMut, bit: u64) {
+    let len = (bit / 8) as usize + 1;
+    if buf.len() < len {
+        buf.resize(len, 0);
+    }
+}
+
+fn get_bit(buf: &[u8], bit: u64) -> u8 {
+    buf.get((bit / 8) as usize)
+        .map_or(0, |byte| (byte >> (7 - bit % 8)) & 1)
+}
+
+fn set_bit(buf: &mut [u8], bit: u64, value: u8) {
+    let byte = &mut buf[(bit / 8) as usize];
+    let mask = 1 << (7 - bit % 8);
+    if value == 0 {
+        *byte &= !mask;
+    } else {
+        *byte |= mask;
+    }
+}
+
+/// Mask of the bits `first` to `last` of a byte, the bit 0 being the most
+/// significant one
+fn byte_mask(first: u64, last: u64) -> u8 {
+    (0xffu8 >> first) & (0xffu8 << (7 - last))
+}
+
+/// An inclusive range of bytes, or of bits if `bit`, the negative indexes
+/// counting from the end
+#[derive(Debug, Clone, Copy)]
+pub struct BitRange {
+    pub start: i64,
+    pub end: Option<i64>,
+    pub bit: bool,
+}
+
+impl BitRange {
+    /// Resolve the range into the first and last bits of `buf`, None if it
+    /// is empty
+    fn resolve(range: Option<BitRange>, buf: &[u8]) -> Option<(u64, u64)> {
+        let len = buf.len();
+        match range {
+            None if len == 0 => None,
+            None => Some((0, len as u64 * 8 - 1)),
+            Some(range) if range.bit => {
+                shared::index_range(range.start, range.end.unwrap_or(-1), len * 8)
+                    .map(|(first, last)| (first as u64, last as u64))
+            }
+            Some(range) => shared::index_range(range.start, range.end.unwrap_or(-1), len)
+                .map(|(first, last)| (first as u64 * 8, last as u64 * 8 + 7)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SetBit {
+    pub key: Bytes,
+    pub offset: u64,
+    pub value: u8,
+}
+
+impl SetBit {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("SETBIT")?;
+        let offset = next_bit_offset(frame)?;
+        let value = match frame.next_integer()? {
+            Some(value @ (0 | 1)) => value as u8,
+            _ => {
+                return Err(CommandParser::reply_error(
+                    "ERR bit is not an integer or out of range",
+                ))
+            }
+        };
+        Ok(Self { key, offset, value })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_string(&db, self.key, |buf| {
+            grow(buf, self.offset);
+            let old = get_bit(buf, self.offset);
+            set_bit(buf, self.offset, self.value);
+            Frame::Integer(old as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([
+            b"SETBIT" as &[u8],
+            &self.key,
+            self.offset.to_string().as_bytes(),
+            self.value.to_string().as_bytes(),
+        ])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GetBit {
+    pub key: Bytes,
+    pub offset: u64,
+}
+
+impl GetBit {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("GETBIT")?;
+        let offset = next_bit_offset(frame)?;
+        Ok(Self { key, offset })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_string(&client.db, &self.key, Frame::Integer(0), |buf| {
+            Frame::Integer(get_bit(buf, self.offset) as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BitCount {
+    pub key: Bytes,
+    pub range: Option<BitRange>,
+}
+
+impl BitCount {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("BITCOUNT")?;
+        let range = next_bit_range(frame)?;
+        // BITCOUNT takes both the start and the end, or none of them
+        if range.is_some_and(|range| range.end.is_none()) {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(Self { key, range })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_string(&client.db, &self.key, Frame::Integer(0), |buf| {
+            let (first, last) = match BitRange::resolve(self.range, buf) {
+                Some(range) => range,
+                None => return Frame::Integer(0),
+            };
+            let (first_byte, last_byte) = ((first / 8) as usize, (last / 8) as usize);
+            let count = if first_byte == last_byte {
+                (buf[first_byte] & byte_mask(first % 8, last % 8)).count_ones()
+            } else {
+                (buf[first_byte] & byte_mask(first % 8, 7)).count_ones()
+                    + buf[first_byte + 1..last_byte]
+                        .iter()
+                        .map(|byte| byte.count_ones())
+                        .sum::<u32>()
+                    + (buf[last_byte] & byte_mask(0, last % 8)).count_ones()
+            };
+            Frame::Integer(count as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BitPos {
+    pub key: Bytes,
+    pub bit: u8,
+    pub range: Option<BitRange>,
+}
+
+impl BitPos {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("BITPOS")?;
+        let bit = match frame.next_integer()? {
+            Some(bit @ (0 | 1)) => bit as u8,
+            _ => {
+                return Err(CommandParser::reply_error(
+                    "ERR The bit argument must be 1 or 0.",
+                ))
+            }
+        };
+        let range = next_bit_range(frame)?;
+        Ok(Self { key, bit, range })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        // a missing key is a string of clear bits
+        let missing = Frame::Integer(if self.bit == 0 { 0 } else { -1 });
+        let response = read_string(&client.db, &self.key, missing, |buf| {
+            let (first, last) = match BitRange::resolve(self.range, buf) {
+                Some(range) => range,
+                None => return Frame::Integer(-1),
+            };
+            // skip the bytes holding none of the bits looked for
+            let skipped = if self.bit == 0 { 0xff } else { 0 };
+            let mut bit = first;
+            while bit <= last {
+                if bit % 8 == 0 && bit + 7 <= last && buf[(bit / 8) as usize] == skipped {
+                    bit += 8;
+                    continue;
+                }
+                if get_bit(buf, bit) == self.bit {
+                    return Frame::Integer(bit as i64);
+                }
+                bit += 1;
+            }
+            // looking for a clear bit up to the end of the string, the first
+            // bit past it is clear
+            if self.bit == 0 && self.range.is_none_or(|range| range.end.is_none()) {
+                Frame::Integer(last as i64 + 1)
+            } else {
+                Frame::Integer(-1)
+            }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitOpKind {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+#[derive(Debug, Clone)]
+pub struct BitOp {
+    pub op: BitOpKind,
+    pub dst: Bytes,
+    pub keys: Vec<Bytes>,
+}
+
+impl BitOp {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let op = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let op = match &op.to_ascii_lowercase()[..] {
+            b"and" => BitOpKind::And,
+            b"or" => BitOpKind::Or,
+            b"xor" => BitOpKind::Xor,
+            b"not" => BitOpKind::Not,
+            _ => return Err(CommandParser::syntax_error()),
+        };
+        let dst = frame.next_key("BITOP")?;
+        let mut keys = vec![frame.next_key("BITOP")?];
+        while let Some(key) = frame.next_string()? {
+            keys.push(key);
+        }
+        if op == BitOpKind::Not && keys.len() > 1 {
+            return Err(CommandParser::reply_error(
+                "ERR BITOP NOT must be called with a single source key.",
+            ));
+        }
+        Ok(Self { op, dst, keys })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let mut sources = Vec::with_capacity(self.keys.len());
+        for key in &self.keys {
+            match db.get(key) {
+                Some(entry) => match &entry.value {
+                    RudisObject::String(s) => sources.push(s.value.clone()),
+                    _ => {
+                        client.write_frame(&shared::wrong_type_err).await?;
+                        return Ok(());
+                    }
+                },
+                None => sources.push(Bytes::new()),
+            }
+        }
+
+        // the shorter strings are zero-padded to the longest one
+        let len = sources.iter().map(|s| s.len()).max().unwrap_or(0);
+        let mut result = BytesMut::zeroed(len);
+        for (i, byte) in result.iter_mut().enumerate() {
+            let mut bytes = sources.iter().map(|s| s.get(i).copied().unwrap_or(0));
+            let first = bytes.next().unwrap_or(0);
+            *byte = match self.op {
+                BitOpKind::And => bytes.fold(first, |acc, b| acc & b),
+                BitOpKind::Or => bytes.fold(first, |acc, b| acc | b),
+                BitOpKind::Xor => bytes.fold(first, |acc, b| acc ^ b),
+                BitOpKind::Not => !first,
+            };
+        }
+
+        if result.is_empty() {
+            db.remove(&self.dst);
+        } else {
+            db.insert(
+                self.dst,
+                RudisObject::new_string_from(result.freeze()),
+                None,
+            );
+        }
+        client.write_frame(&Frame::Integer(len as i64)).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let op = match self.op {
+            BitOpKind::And => b"AND" as &[u8],
+            BitOpKind::Or => b"OR",
+            BitOpKind::Xor => b"XOR",
+            BitOpKind::Not => b"NOT",
+        };
+        shared::rewrite_args(
+            [b"BITOP" as &[u8], op, &self.dst]
+                .into_iter()
+                .chain(self.keys.iter().map(|k| &k[..])),
+        )
+    }
+}
+
+/// Signed or unsigned integer of `bits` bits, e.g. i5 or u8
+#[derive(Debug, Clone, Copy)]
+pub struct BitFieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+impl BitFieldType {
+    fn parse(s: &[u8]) -> Result<Self> {
+        let invalid = || {
+            CommandParser::reply_error("ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.")
+        };
+        let signed = match s.first() {
+            Some(b'i' | b'I') => true,
+            Some(b'u' | b'U') => false,
+            _ => return Err(invalid()),
+        };
+        let bits = std::str::from_utf8(&s[1..])
+            .ok()
+            .and_then(|bits| bits.parse::<u32>().ok())
+            .filter(|&bits| bits >= 1 && bits <= if signed { 64 } else { 63 })
+            .ok_or_else(invalid)?;
+        Ok(Self { signed, bits })
+    }
+
+    /// Parse the offset of a field of this type, `#N` being the Nth field
+    fn parse_offset(&self, s: &[u8]) -> Result<u64> {
+        let (multiplier, s) = match s.strip_prefix(b"#") {
+            Some(s) => (self.bits as u64, s),
+            None => (1, s),
+        };
+        std::str::from_utf8(s)
+            .ok()
+            .and_then(|offset| offset.parse::<u64>().ok())
+            .and_then(|offset| offset.checked_mul(multiplier))
+            .filter(|offset| {
+                offset
+                    .checked_add(self.bits as u64)
+                    .is_some_and(|end| end <= MAX_BITS)
+            })
+            .ok_or_else(|| {
+                CommandParser::reply_error("ERR bit offset is not an integer or out of range")
+            })
+    }
+
+    fn min(&self) -> i128 {
+        if self.signed {
+            -(1i128 << (self.bits - 1))
+        } else {
+            0
+        }
+    }
+
+    fn max(&self) -> i128 {
+        if self.signed {
+            (1i128 << (self.bits - 1)) - 1
+        } else {
+            (1i128 << self.bits) - 1
+        }
+    }
+
+    fn get(&self, buf: &[u8], offset: u64) -> i64 {
+        let mut value = 0u64;
+        for i in 0..self.bits as u64 {
+            value = (value << 1) | get_bit(buf, offset + i) as u64;
+        }
+        // sign-extend the negative values
+        if self.signed && self.bits < 64 && value >> (self.bits - 1) == 1 {
+            value |= u64::MAX << self.bits;
+        }
+        value as i64
+    }
+
+    fn set(&self, buf: &mut BytesMut, offset: u64, value: i64) {
+        grow(buf, offset + self.bits as u64 - 1);
+        for i in 0..self.bits as u64 {
+            let bit = ((value as u64) >> (self.bits as u64 - 1 - i)) & 1;
+            set_bit(buf, offset + i, bit as u8);
+        }
+    }
+
+    /// Fit `value` into this type according to `overflow`, None if it
+    /// doesn't fit and the overflow fails
+    fn fit(&self, value: i128, overflow: BitFieldOverflow) -> Option<i64> {
+        if value >= self.min() && value <= self.max() {
+            return Some(value as i64);
+        }
+        match overflow {
+            BitFieldOverflow::Wrap => {
+                let modulus = 1i128 << self.bits;
+                let mut wrapped = value.rem_euclid(modulus);
+                if self.signed && wrapped > self.max() {
+                    wrapped -= modulus;
+                }
+                Some(wrapped as i64)
+            }
+            BitFieldOverflow::Sat if value > self.max() => Some(self.max() as i64),
+            BitFieldOverflow::Sat => Some(self.min() as i64),
+            BitFieldOverflow::Fail => None,
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("{}{}", if self.signed { 'i' } else { 'u' }, self.bits)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitFieldOverflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+#[derive(Debug, Clone)]
+pub enum BitFieldOp {
+    Get(BitFieldType, u64),
+    Set(BitFieldType, u64, i64),
+    IncrBy(BitFieldType, u64, i64),
+    /// the overflow behavior of the following SET and INCRBY
+    Overflow(BitFieldOverflow),
+}
+
+/// BITFIELD and BITFIELD_RO
+#[derive(Debug, Clone)]
+pub struct BitField {
+    pub key: Bytes,
+    pub ops: Vec<BitFieldOp>,
+}
+
+impl BitField {
+    pub fn from(frame: &mut CommandParser, readonly: bool) -> Result<Self> {
+        let key = frame.next_key("BITFIELD")?;
+        let mut ops = Vec::new();
+        while let Some(op) = frame.next_string()? {
+            let op = op.to_ascii_lowercase();
+            if &op == b"overflow" && !readonly {
+                let overflow = frame
+                    .next_string()?
+                    .ok_or_else(CommandParser::syntax_error)?;
+                let overflow = match &overflow.to_ascii_lowercase()[..] {
+                    b"wrap" => BitFieldOverflow::Wrap,
+                    b"sat" => BitFieldOverflow::Sat,
+                    b"fail" => BitFieldOverflow::Fail,
+                    _ => {
+                        return Err(CommandParser::reply_error(
+                            "ERR Invalid OVERFLOW type specified",
+                        ))
+                    }
+                };
+                ops.push(BitFieldOp::Overflow(overflow));
+                continue;
+            }
+            let ty = BitFieldType::parse(
+                &frame
+                    .next_string()?
+                    .ok_or_else(CommandParser::syntax_error)?,
+            )?;
+            let offset = ty.parse_offset(
+                &frame
+                    .next_string()?
+                    .ok_or_else(CommandParser::syntax_error)?,
+            )?;
+            match &op[..] {
+                b"get" => ops.push(BitFieldOp::Get(ty, offset)),
+                b"set" if !readonly => {
+                    let value = frame
+                        .next_integer()?
+                        .ok_or_else(CommandParser::syntax_error)?;
+                    ops.push(BitFieldOp::Set(ty, offset, value));
+                }
+                b"incrby" if !readonly => {
+                    let increment = frame
+                        .next_integer()?
+                        .ok_or_else(CommandParser::syntax_error)?;
+                    ops.push(BitFieldOp::IncrBy(ty, offset, increment));
+                }
+                _ => return Err(CommandParser::syntax_error()),
+            }
+        }
+        Ok(Self { key, ops })
+    }
+
+    /// Reply the value of each GET of a BITFIELD without SET nor INCRBY
+    fn get(&self, buf: &[u8]) -> Frame {
+        let replies = self
+            .ops
+            .iter()
+            .filter_map(|op| match *op {
+                BitFieldOp::Get(ty, offset) => Some(Frame::Integer(ty.get(buf, offset))),
+                _ => None,
+            })
+            .collect();
+        Frame::Array(replies)
+    }
+
+    /// Run the operations against `buf`, replying the value of each GET,
+    /// SET and INCRBY
+    fn run(&self, buf: &mut BytesMut) -> Frame {
+        let mut overflow = BitFieldOverflow::Wrap;
+        let mut replies = Vec::new();
+        for op in &self.ops {
+            match *op {
+                BitFieldOp::Get(ty, offset) => {
+                    replies.push(Frame::Integer(ty.get(buf, offset)));
+                }
+                BitFieldOp::Set(ty, offset, value) => {
+                    // the unsigned fields take the bits of the value
+                    let value = if ty.signed {
+                        value as i128
+                    } else {
+                        value as u64 as i128
+                    };
+                    match ty.fit(value, overflow) {
+                        Some(value) => {
+                            replies.push(Frame::Integer(ty.get(buf, offset)));
+                            ty.set(buf, offset, value);
+                        }
+                        None => replies.push(Frame::Null),
+                    }
+                }
+                BitFieldOp::IncrBy(ty, offset, increment) => {
+                    let value = ty.get(buf, offset) as i128 + increment as i128;
+                    match ty.fit(value, overflow) {
+                        Some(value) => {
+                            ty.set(buf, offset, value);
+                            replies.push(Frame::Integer(value));
+                        }
+                        None => replies.push(Frame::Null),
+                    }
+                }
+                BitFieldOp::Overflow(o) => overflow = o,
+            }
+        }
+        Frame::Array(replies)
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let readonly = self
+            .ops
+            .iter()
+            .all(|op| matches!(op, BitFieldOp::Get(..) | BitFieldOp::Overflow(_)));
+        let response = if readonly {
+            read_string(&db, &self.key, self.get(&[]), |buf| self.get(buf))
+        } else {
+            write_string(&db, self.key.clone(), |buf| self.run(buf))
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let mut args = vec![b"BITFIELD".to_vec(), self.key.to_vec()];
+        for op in &self.ops {
+            match op {
+                BitFieldOp::Get(ty, offset) => {
+                    args.push(b"GET".to_vec());
+                    args.push(ty.name().into_bytes());
+                    args.push(offset.to_string().into_bytes());
+                }
+                BitFieldOp::Set(ty, offset, value) | BitFieldOp::IncrBy(ty, offset, value) => {
+                    let name = if matches!(op, BitFieldOp::Set(..)) {
+                        b"SET" as &[u8]
+                    } else {
+                        b"INCRBY"
+                    };
+                    args.push(name.to_vec());
+                    args.push(ty.name().into_bytes());
+                    args.push(offset.to_string().into_bytes());
+                    args.push(value.to_string().into_bytes());
+                }
+                BitFieldOp::Overflow(overflow) => {
+                    args.push(b"OVERFLOW".to_vec());
+                    args.push(match overflow {
+                        BitFieldOverflow::Wrap => b"WRAP".to_vec(),
+                        BitFieldOverflow::Sat => b"SAT".to_vec(),
+                        BitFieldOverflow::Fail => b"FAIL".to_vec(),
+                    });
+                }
+            }
+        }
+        shared::rewrite_args(args.iter().map(|arg| &arg[..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bitfield(args: &[&str]) -> BitField {
+        let frame = Frame::Array(
+            args.iter()
+                .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+                .collect(),
+        );
+        BitField::from(&mut CommandParser::from(frame).unwrap(), false).unwrap()
+    }
+
+    fn run(buf: &mut BytesMut, args: &[&str]) -> Vec<Option<i64>> {
+        let Frame::Array(replies) = bitfield(args).run(buf) else {
+            panic!("not an array");
+        };
+        replies
+            .into_iter()
+            .map(|reply| match reply {
+                Frame::Integer(n) => Some(n),
+                Frame::Null => None,
+                _ => panic!("unexpected reply {:?}", reply),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bitfield_overflow_modes() {
+        // the INCRBY example of the redis documentation
+        let mut buf = BytesMut::new();
+        let incr = [
+            "k", "INCRBY", "u2", "100", "1", "OVERFLOW", "SAT", "INCRBY", "u2", "102", "1",
+        ];
+        assert_eq!(run(&mut buf, &incr), [Some(1), Some(1)]);
+        assert_eq!(run(&mut buf, &incr), [Some(2), Some(2)]);
+        assert_eq!(run(&mut buf, &incr), [Some(3), Some(3)]);
+        assert_eq!(run(&mut buf, &incr), [Some(0), Some(3)]);
+        assert_eq!(
+            run(
+                &mut buf,
+                &["k", "OVERFLOW", "FAIL", "INCRBY", "u2", "102", "1"]
+            ),
+            [None]
+        );
+
+        let mut buf = BytesMut::new();
+        assert_eq!(
+            run(
+                &mut buf,
+                &["k", "SET", "i8", "#0", "100", "SET", "i8", "#1", "200"]
+            ),
+            [Some(0), Some(0)]
+        );
+        assert_eq!(&buf[..], [100, 200]);
+        assert_eq!(
+            run(&mut buf, &["k", "GET", "i8", "#1", "GET", "u8", "#1"]),
+            [Some(-56), Some(200)]
+        );
+
+        // i8 at 127 incremented by one under each mode
+        for (overflow, expected) in [("WRAP", Some(-128)), ("SAT", Some(127)), ("FAIL", None)] {
+            let mut buf = BytesMut::from(&[127u8][..]);
+            assert_eq!(
+                run(
+                    &mut buf,
+                    &["k", "OVERFLOW", overflow, "INCRBY", "i8", "0", "1"]
+                ),
+                [expected]
+            );
+            assert_eq!(buf[0] as i8, expected.unwrap_or(127) as i8);
+        }
+        // and decremented below the minimum
+        let mut buf = BytesMut::from(&[0x80u8][..]);
+        assert_eq!(
+            run(
+                &mut buf,
+                &["k", "OVERFLOW", "SAT", "INCRBY", "i8", "0", "-1"]
+            ),
+            [Some(-128)]
+        );
+        assert_eq!(
+            run(&mut buf, &["k", "INCRBY", "i8", "0", "-1"]),
+            [Some(127)]
+        );
+
+        // a failed SET leaves the field unchanged
+        let mut buf = BytesMut::from(&[0x50u8][..]);
+        assert_eq!(
+            run(
+                &mut buf,
+                &["k", "OVERFLOW", "FAIL", "SET", "u4", "0", "16", "GET", "u4", "0"]
+            ),
+            [None, Some(5)]
+        );
+        assert_eq!(
+            run(
+                &mut buf,
+                &["k", "OVERFLOW", "SAT", "SET", "u4", "0", "16", "GET", "u4", "0"]
+            ),
+            [Some(5), Some(15)]
+        );
+        // the unsigned fields take the bits of the negative values
+        assert_eq!(
+            run(&mut buf, &["k", "SET", "u4", "0", "-1", "GET", "u63", "0"]),
+            [Some(15), Some(0x7800_0000_0000_0000)]
+        );
+        assert_eq!(
+            run(
+                &mut buf,
+                &["k", "SET", "i64", "0", "-1", "GET", "i64", "0", "GET", "i3", "61"]
+            ),
+            [Some(0xf000_0000_0000_0000u64 as i64), Some(-1), Some(-1)]
+        );
+    }
+
+    #[test]
+    fn bitfield_offsets() {
+        let ty = BitFieldType::parse(b"u8").unwrap();
+        assert_eq!(ty.parse_offset(b"#3").unwrap(), 24);
+        assert_eq!(ty.parse_offset(b"4294967288").unwrap(), MAX_BITS - 8);
+        assert!(ty.parse_offset(b"4294967289").is_err());
+        assert!(ty.parse_offset(b"#18446744073709551615").is_err());
+        assert!(ty.parse_offset(b"-1").is_err());
+        assert!(BitFieldType::parse(b"u64").is_err());
+        assert!(BitFieldType::parse(b"i65").is_err());
+        assert!(BitFieldType::parse(b"i0").is_err());
+        assert!(BitFieldType::parse(b"i64").is_ok());
+    }
+}
@@ -1,5 +1,6 @@
-use super::CommandParser;
+use super::{CommandParser, ScanArgs};
 use crate::client::Client;
+use crate::dbms::DictEntry;
 use crate::frame::Frame;
 use crate::object::RudisObject;
 use crate::shared;
@@ -126,6 +127,35 @@ impl Keys {
     }
 }
 
+/// SCAN, walks the keyspace a page at a time instead of at once like KEYS
+#[derive(Debug, Clone)]
+pub struct Scan {
+    pub args: ScanArgs,
+}
+
+impl Scan {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let args = ScanArgs::from(frame, true)?;
+        Ok(Self { args })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let (cursor, keys) = client
+            .db
+            .scan(self.args.cursor, self.args.count, |key, value| {
+                let matches =
+                    !value.is_expired()
+                        && self.args.matches(key)
+                        && self.args.object_type.as_ref().is_none_or(|t| {
+                            t.eq_ignore_ascii_case(value.value.get_type().as_bytes())
+                        });
+                matches.then(|| Frame::Bulk(key.clone()))
+            });
+        client.write_frame(&ScanArgs::reply(cursor, keys)).await?;
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DbSize {}
 
@@ -157,6 +187,7 @@ impl Type {
                     RudisObject::Set(_) => Frame::Simple(Bytes::from_static(b"set")),
                     RudisObject::ZSet(_) => Frame::Simple(Bytes::from_static(b"zset")),
                     RudisObject::Hash(_) => Frame::Simple(Bytes::from_static(b"hash")),
+                    RudisObject::Stream(_) => Frame::Simple(Bytes::from_static(b"stream")),
                 }
             } else {
                 Frame::Null
@@ -424,3 +455,178 @@ impl PExpireAt {
         out
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct Move {
+    pub key: Bytes,
+    pub db: u64,
+}
+
+impl Move {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame
+            .next_string()?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "MOVE requires a key"))?;
+        let db = frame
+            .next_integer()?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "MOVE requires a db"))?;
+        if db < 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid db index"));
+        }
+        Ok(Self { key, db: db as u64 })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        if self.db as usize >= client.server.db_num() {
+            client
+                .write_frame(&Frame::Error(Bytes::from_static(
+                    b"ERR DB index is out of range",
+                )))
+                .await?;
+            return Ok(());
+        }
+        if self.db == client.db.index as u64 {
+            client
+                .write_frame(&Frame::Error(Bytes::from_static(
+                    b"ERR source and destination objects are the same",
+                )))
+                .await?;
+            return Ok(());
+        }
+
+        // MOVE is exclusive, so neither database changes in between
+        let dst = client.server.get(self.db as usize);
+        let response = if dst.contains_key(&self.key) {
+            Frame::Integer(0)
+        } else if let Some((key, value)) = client.db.remove(&self.key) {
+            // added the way SET and the pushes add keys, so that the WATCHes
+            // of the key in the destination fail and the clients blocked on
+            // it there wake up
+            let list = matches!(value.value, RudisObject::List(_));
+            let stream = matches!(value.value, RudisObject::Stream(_));
+            if let DictEntry::Vacant(ve) = dst.entry(key.clone()) {
+                ve.insert(value);
+            }
+            if list {
+                dst.signal_key_as_ready(&key);
+            } else if stream {
+                dst.signal_key_as_ready_to_all(&key);
+            }
+            Frame::Integer(1)
+        } else {
+            Frame::Integer(0)
+        };
+
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let mut out = BytesMut::new();
+        shared::extend_array(&mut out, 3);
+        shared::extend_bulk_string(&mut out, b"MOVE" as &[u8]);
+        shared::extend_bulk_string(&mut out, &self.key[..]);
+        shared::extend_bulk_string(&mut out, self.db.to_string().as_bytes());
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SwapDb {
+    pub index1: u64,
+    pub index2: u64,
+}
+
+impl SwapDb {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let index1 = frame
+            .next_integer()?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "SWAPDB requires an index1"))?;
+        let index2 = frame
+            .next_integer()?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "SWAPDB requires an index2"))?;
+        if index1 < 0 || index2 < 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid db index"));
+        }
+        Ok(Self {
+            index1: index1 as u64,
+            index2: index2 as u64,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db_num = client.server.db_num();
+        if self.index1 as usize >= db_num || self.index2 as usize >= db_num {
+            client
+                .write_frame(&Frame::Error(Bytes::from_static(
+                    b"ERR DB index is out of range",
+                )))
+                .await?;
+            return Ok(());
+        }
+
+        client
+            .server
+            .swap_db(self.index1 as usize, self.index2 as usize);
+
+        client.write_frame(&shared::ok).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let mut out = BytesMut::new();
+        shared::extend_array(&mut out, 3);
+        shared::extend_bulk_string(&mut out, b"SWAPDB" as &[u8]);
+        shared::extend_bulk_string(&mut out, self.index1.to_string().as_bytes());
+        shared::extend_bulk_string(&mut out, self.index2.to_string().as_bytes());
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FlushDb {}
+
+impl FlushDb {
+    pub fn from(_frame: &mut CommandParser) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        client.db.touch_all_watched_keys();
+        client.db.clear();
+        client.write_frame(&shared::ok).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let mut out = BytesMut::new();
+        shared::extend_array(&mut out, 1);
+        shared::extend_bulk_string(&mut out, b"FLUSHDB" as &[u8]);
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FlushAll {}
+
+impl FlushAll {
+    pub fn from(_frame: &mut CommandParser) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        for db in client.server.databases() {
+            db.touch_all_watched_keys();
+            db.clear();
+        }
+        client.write_frame(&shared::ok).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let mut out = BytesMut::new();
+        shared::extend_array(&mut out, 1);
+        shared::extend_bulk_string(&mut out, b"FLUSHALL" as &[u8]);
+        out
+    }
+}
@@ -0,0 +1,586 @@
+use super::zset::{read_zset, write_zset};
+use super::CommandParser;
+use crate::client::Client;
+use crate::frame::Frame;
+use crate::geohash::{self, GeoShape};
+use crate::object::{RudisObject, RudisZSet, ZScoreRange};
+use crate::shared;
+use bytes::{Bytes, BytesMut};
+use std::io::{Error, ErrorKind, Result};
+
+fn parse_float(s: &[u8]) -> Result<f64> {
+    std::str::from_utf8(s)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|f| f.is_finite())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "value is not a valid float"))
+}
+
+/// Parse a distance, which can't be negative
+fn parse_distance(s: &[u8]) -> Result<f64> {
+    let distance = parse_float(s)?;
+    if distance < 0.0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "distance cannot be negative",
+        ));
+    }
+    Ok(distance)
+}
+
+/// The meters in the unit, `m`, `km`, `ft` or `mi`
+fn parse_unit(s: &[u8]) -> Result<f64> {
+    match &s.to_ascii_lowercase()[..] {
+        b"m" => Ok(1.0),
+        b"km" => Ok(1000.0),
+        b"ft" => Ok(0.3048),
+        b"mi" => Ok(1609.34),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "unsupported unit provided. please use M, KM, FT, MI",
+        )),
+    }
+}
+
+fn invalid_long_lat(longitude: f64, latitude: f64) -> Frame {
+    Frame::Error(Bytes::from(format!(
+        "ERR invalid longitude,latitude pair {:.6},{:.6}",
+        longitude, latitude
+    )))
+}
+
+/// A distance as redis replies it, with 4 decimals
+fn distance_reply(meters: f64, unit: f64) -> Frame {
+    Frame::Bulk(Bytes::from(format!("{:.4}", meters / unit)))
+}
+
+fn coord_reply((longitude, latitude): (f64, f64)) -> Frame {
+    Frame::Array(vec![Frame::Double(longitude), Frame::Double(latitude)])
+}
+
+#[derive(Debug, Clone)]
+pub struct GeoAdd {
+    pub key: Bytes,
+    pub nx: bool,
+    pub xx: bool,
+    /// reply the number of changed members instead of the added ones
+    pub ch: bool,
+    /// the longitude, latitude and member of each element
+    pub elements: Vec<(f64, f64, Bytes)>,
+}
+
+impl GeoAdd {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("GEOADD")?;
+        let mut cmd = Self {
+            key,
+            nx: false,
+            xx: false,
+            ch: false,
+            elements: Vec::new(),
+        };
+        let mut longitude = loop {
+            let arg = frame
+                .next_string()?
+                .ok_or_else(CommandParser::syntax_error)?;
+            match &arg.to_ascii_lowercase()[..] {
+                b"nx" => cmd.nx = true,
+                b"xx" => cmd.xx = true,
+                b"ch" => cmd.ch = true,
+                _ => break arg,
+            }
+        };
+        loop {
+            let latitude = frame
+                .next_string()?
+                .ok_or_else(CommandParser::syntax_error)?;
+            let member = frame
+                .next_string()?
+                .ok_or_else(CommandParser::syntax_error)?;
+            cmd.elements
+                .push((parse_float(&longitude)?, parse_float(&latitude)?, member));
+            longitude = match frame.next_string()? {
+                Some(longitude) => longitude,
+                None => break,
+            };
+        }
+        if cmd.nx && cmd.xx {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(cmd)
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let mut scores = Vec::with_capacity(self.elements.len());
+        for (longitude, latitude, _) in &self.elements {
+            match geohash::score_of(*longitude, *latitude) {
+                Some(score) => scores.push(score),
+                None => {
+                    client
+                        .write_frame(&invalid_long_lat(*longitude, *latitude))
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+        let db = client.db.clone();
+        let response = write_zset(&db, self.key, !self.xx, Frame::Integer(0), |z| {
+            let mut added = 0;
+            let mut updated = 0;
+            for (score, (_, _, member)) in scores.into_iter().zip(self.elements) {
+                match z.score(&member) {
+                    Some(current) if !self.nx && current != score => {
+                        z.insert(member, score);
+                        updated += 1;
+                    }
+                    Some(_) => {}
+                    None if self.xx => {}
+                    None => {
+                        z.insert(member, score);
+                        added += 1;
+                    }
+                }
+            }
+            Frame::Integer(if self.ch { added + updated } else { added })
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let flags = [
+            (self.nx, b"NX" as &[u8]),
+            (self.xx, b"XX"),
+            (self.ch, b"CH"),
+        ];
+        let coords: Vec<(String, String)> = self
+            .elements
+            .iter()
+            .map(|(longitude, latitude, _)| (longitude.to_string(), latitude.to_string()))
+            .collect();
+        shared::rewrite_args(
+            [b"GEOADD" as &[u8], &self.key]
+                .into_iter()
+                .chain(flags.iter().filter(|(on, _)| *on).map(|(_, flag)| *flag))
+                .chain(coords.iter().zip(&self.elements).flat_map(
+                    |((longitude, latitude), (_, _, member))| {
+                        [longitude.as_bytes(), latitude.as_bytes(), &member[..]]
+                    },
+                )),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GeoPos {
+    pub key: Bytes,
+    pub members: Vec<Bytes>,
+}
+
+impl GeoPos {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("GEOPOS")?;
+        let mut members = Vec::new();
+        while let Some(member) = frame.next_string()? {
+            members.push(member);
+        }
+        Ok(Self { key, members })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let missing = Frame::Array(vec![Frame::Null; self.members.len()]);
+        let response = read_zset(&client.db, &self.key, missing, |z| {
+            Frame::Array(
+                self.members
+                    .iter()
+                    .map(|member| match z.score(member) {
+                        Some(score) => coord_reply(geohash::long_lat_of(score)),
+                        None => Frame::Null,
+                    })
+                    .collect(),
+            )
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GeoDist {
+    pub key: Bytes,
+    pub member1: Bytes,
+    pub member2: Bytes,
+    /// the meters in the unit of the reply
+    pub unit: f64,
+}
+
+impl GeoDist {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("GEODIST")?;
+        let member1 = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let member2 = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let unit = match frame.next_string()? {
+            Some(unit) => parse_unit(&unit)?,
+            None => 1.0,
+        };
+        if frame.next_string()?.is_some() {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(Self {
+            key,
+            member1,
+            member2,
+            unit,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_zset(&client.db, &self.key, Frame::Null, |z| {
+            match (z.score(&self.member1), z.score(&self.member2)) {
+                (Some(score1), Some(score2)) => {
+                    let (lon1, lat1) = geohash::long_lat_of(score1);
+                    let (lon2, lat2) = geohash::long_lat_of(score2);
+                    distance_reply(geohash::distance(lon1, lat1, lon2, lat2), self.unit)
+                }
+                _ => Frame::Null,
+            }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GeoHash {
+    pub key: Bytes,
+    pub members: Vec<Bytes>,
+}
+
+impl GeoHash {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("GEOHASH")?;
+        let mut members = Vec::new();
+        while let Some(member) = frame.next_string()? {
+            members.push(member);
+        }
+        Ok(Self { key, members })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let missing = Frame::Array(vec![Frame::Null; self.members.len()]);
+        let response = read_zset(&client.db, &self.key, missing, |z| {
+            Frame::Array(
+                self.members
+                    .iter()
+                    .map(|member| match z.score(member) {
+                        Some(score) => Frame::Bulk(Bytes::from(geohash::geohash_string(score))),
+                        None => Frame::Null,
+                    })
+                    .collect(),
+            )
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// The centre of a search
+#[derive(Debug, Clone)]
+pub enum GeoFrom {
+    Member(Bytes),
+    LonLat(f64, f64),
+}
+
+/// A member found by a search
+struct GeoPoint {
+    member: Bytes,
+    score: f64,
+    /// the distance from the centre, in meters
+    distance: f64,
+    coord: (f64, f64),
+}
+
+/// The arguments of GEOSEARCH and GEOSEARCHSTORE
+#[derive(Debug, Clone)]
+pub struct GeoSearchSpec {
+    pub from: GeoFrom,
+    /// the shape, in meters
+    pub shape: GeoShape,
+    /// the meters in the unit of the shape, the distances are replied in it
+    pub unit: f64,
+    /// Some(true) for ASC, Some(false) for DESC
+    pub asc: Option<bool>,
+    /// COUNT and whether ANY was given
+    pub count: Option<(usize, bool)>,
+    pub withcoord: bool,
+    pub withdist: bool,
+    pub withhash: bool,
+    /// store the distances instead of the geohashes, for GEOSEARCHSTORE
+    pub storedist: bool,
+    /// the arguments as given, to rewrite the command
+    pub args: Vec<Bytes>,
+}
+
+impl GeoSearchSpec {
+    /// Parse the search, with the WITH options unless `store`, with
+    /// STOREDIST otherwise
+    fn from(frame: &mut CommandParser, store: bool) -> Result<Self> {
+        let mut args = Vec::new();
+        let mut next = |frame: &mut CommandParser| -> Result<Bytes> {
+            let arg = frame
+                .next_string()?
+                .ok_or_else(CommandParser::syntax_error)?;
+            args.push(arg.clone());
+            Ok(arg)
+        };
+        let (mut from, mut by) = (None, None);
+        let mut asc = None;
+        let mut count = None;
+        let (mut withcoord, mut withdist, mut withhash, mut storedist) =
+            (false, false, false, false);
+        while frame.has_next() {
+            let arg = next(frame)?;
+            match &arg.to_ascii_lowercase()[..] {
+                b"frommember" if from.is_none() => from = Some(GeoFrom::Member(next(frame)?)),
+                b"fromlonlat" if from.is_none() => {
+                    let longitude = parse_float(&next(frame)?)?;
+                    let latitude = parse_float(&next(frame)?)?;
+                    from = Some(GeoFrom::LonLat(longitude, latitude));
+                }
+                b"byradius" if by.is_none() => {
+                    let radius = parse_distance(&next(frame)?)?;
+                    let unit = parse_unit(&next(frame)?)?;
+                    by = Some((
+                        GeoShape::Circle {
+                            radius: radius * unit,
+                        },
+                        unit,
+                    ));
+                }
+                b"bybox" if by.is_none() => {
+                    let width = parse_distance(&next(frame)?)?;
+                    let height = parse_distance(&next(frame)?)?;
+                    let unit = parse_unit(&next(frame)?)?;
+                    by = Some((
+                        GeoShape::Box {
+                            width: width * unit,
+                            height: height * unit,
+                        },
+                        unit,
+                    ));
+                }
+                b"asc" => asc = Some(true),
+                b"desc" => asc = Some(false),
+                b"count" => {
+                    let n = std::str::from_utf8(&next(frame)?)
+                        .ok()
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "COUNT must be > 0"))?;
+                    count = Some((n, false));
+                }
+                // ANY only follows COUNT
+                b"any" => match &mut count {
+                    Some((_, any)) if !*any => *any = true,
+                    _ => return Err(CommandParser::syntax_error()),
+                },
+                b"withcoord" if !store => withcoord = true,
+                b"withdist" if !store => withdist = true,
+                b"withhash" if !store => withhash = true,
+                b"storedist" if store => storedist = true,
+                _ => return Err(CommandParser::syntax_error()),
+            }
+        }
+        let (Some(from), Some((shape, unit))) = (from, by) else {
+            return Err(CommandParser::syntax_error());
+        };
+        Ok(Self {
+            from,
+            shape,
+            unit,
+            asc,
+            count,
+            withcoord,
+            withdist,
+            withhash,
+            storedist,
+            args,
+        })
+    }
+
+    /// The members in the shape, in the requested order, Err with the error
+    /// reply if the centre is invalid
+    fn search(&self, z: &RudisZSet) -> std::result::Result<Vec<GeoPoint>, Frame> {
+        let centre = match &self.from {
+            GeoFrom::Member(member) => match z.score(member) {
+                Some(score) => geohash::long_lat_of(score),
+                None => {
+                    return Err(Frame::Error(Bytes::from_static(
+                        b"ERR could not decode requested zset member",
+                    )))
+                }
+            },
+            GeoFrom::LonLat(longitude, latitude) => {
+                if !geohash::valid_long_lat(*longitude, *latitude) {
+                    return Err(invalid_long_lat(*longitude, *latitude));
+                }
+                (*longitude, *latitude)
+            }
+        };
+        // with ANY the search stops at the first COUNT members found
+        let limit = match self.count {
+            Some((count, true)) => count,
+            _ => usize::MAX,
+        };
+        let mut points = Vec::new();
+        'cells: for (min, max) in geohash::search_ranges(&self.shape, centre.0, centre.1) {
+            let range = ZScoreRange {
+                min,
+                max,
+                minex: false,
+                maxex: true,
+            };
+            for (member, score) in z.range_by_score(&range) {
+                if points.len() >= limit {
+                    break 'cells;
+                }
+                let coord = geohash::long_lat_of(score);
+                if let Some(distance) = self.shape.distance_if_within(centre, coord) {
+                    points.push(GeoPoint {
+                        member: member.clone(),
+                        score,
+                        distance,
+                        coord,
+                    });
+                }
+            }
+        }
+        // COUNT without ANY takes the nearest members
+        let asc = match (self.asc, self.count) {
+            (None, Some((_, false))) => Some(true),
+            (asc, _) => asc,
+        };
+        match asc {
+            Some(true) => points.sort_by(|a, b| a.distance.total_cmp(&b.distance)),
+            Some(false) => points.sort_by(|a, b| b.distance.total_cmp(&a.distance)),
+            None => {}
+        }
+        if let Some((count, _)) = self.count {
+            points.truncate(count);
+        }
+        Ok(points)
+    }
+
+    fn reply(&self, points: Vec<GeoPoint>) -> Frame {
+        let plain = !(self.withcoord || self.withdist || self.withhash);
+        Frame::Array(
+            points
+                .into_iter()
+                .map(|point| {
+                    if plain {
+                        return Frame::Bulk(point.member);
+                    }
+                    let mut item = vec![Frame::Bulk(point.member)];
+                    if self.withdist {
+                        item.push(distance_reply(point.distance, self.unit));
+                    }
+                    if self.withhash {
+                        item.push(Frame::Integer(point.score as i64));
+                    }
+                    if self.withcoord {
+                        item.push(coord_reply(point.coord));
+                    }
+                    Frame::Array(item)
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GeoSearch {
+    pub key: Bytes,
+    pub spec: GeoSearchSpec,
+}
+
+impl GeoSearch {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("GEOSEARCH")?;
+        let spec = GeoSearchSpec::from(frame, false)?;
+        Ok(Self { key, spec })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_zset(&client.db, &self.key, Frame::new_array(), |z| {
+            match self.spec.search(z) {
+                Ok(points) => self.spec.reply(points),
+                Err(e) => e,
+            }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GeoSearchStore {
+    pub dst: Bytes,
+    pub key: Bytes,
+    pub spec: GeoSearchSpec,
+}
+
+impl GeoSearchStore {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let dst = frame.next_key("GEOSEARCHSTORE")?;
+        let key = frame.next_key("GEOSEARCHSTORE")?;
+        let spec = GeoSearchSpec::from(frame, true)?;
+        Ok(Self { dst, key, spec })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let found = match db.get(&self.key) {
+            Some(entry) => match &entry.value {
+                RudisObject::ZSet(z) => self.spec.search(z),
+                _ => Err(shared::wrong_type_err),
+            },
+            None => Ok(Vec::new()),
+        };
+        let points = match found {
+            Ok(points) => points,
+            Err(e) => {
+                client.write_frame(&e).await?;
+                return Ok(());
+            }
+        };
+        let len = points.len();
+        let mut z = RudisZSet::new();
+        for point in points {
+            let score = if self.spec.storedist {
+                point.distance / self.spec.unit
+            } else {
+                point.score
+            };
+            z.insert(point.member, score);
+        }
+        if z.is_empty() {
+            db.remove(&self.dst);
+        } else {
+            db.insert(self.dst, RudisObject::ZSet(z), None);
+        }
+        client.write_frame(&Frame::Integer(len as i64)).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args(
+            [b"GEOSEARCHSTORE" as &[u8], &self.dst, &self.key]
+                .into_iter()
+                .chain(self.spec.args.iter().map(|a| &a[..])),
+        )
+    }
+}
@@ -1,68 +1,182 @@
-use super::CommandParser;
+use super::{CommandParser, ScanArgs};
 use crate::client::Client;
-use crate::dbms::DictValue;
+use crate::dbms::{DatabaseRef, DictEntry, DictValue};
 use crate::frame::Frame;
-use crate::object::RudisObject;
+use crate::object::{RudisHash, RudisObject};
 use crate::shared;
 use bytes::{Bytes, BytesMut};
-use dashmap::mapref::entry::Entry;
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use std::collections::hash_map;
 use std::io::{Error, ErrorKind, Result};
 
+fn next_fields(frame: &mut CommandParser, cmd: &str) -> Result<Vec<Bytes>> {
+    let mut fields = Vec::new();
+    while let Some(field) = frame.next_string()? {
+        fields.push(field);
+    }
+    if fields.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} requires at least one field", cmd),
+        ));
+    }
+    Ok(fields)
+}
+
+/// Reply `f` applied to the hash at `key`, `missing` if there is no such key
+fn read_hash(
+    db: &DatabaseRef,
+    key: &Bytes,
+    missing: Frame,
+    f: impl FnOnce(&RudisHash) -> Frame,
+) -> Frame {
+    match db.get(key) {
+        Some(entry) => match &entry.value {
+            RudisObject::Hash(h) => f(h),
+            _ => shared::wrong_type_err,
+        },
+        None => missing,
+    }
+}
+
+/// Reply `f` applied to the hash at `key`, which is created if `create`,
+/// otherwise `missing` is replied if there is no such key. The key is deleted
+/// once the hash is empty
+fn write_hash(
+    db: &DatabaseRef,
+    key: Bytes,
+    create: bool,
+    missing: Frame,
+    f: impl FnOnce(&mut RudisHash) -> Frame,
+) -> Frame {
+    match db.entry(key) {
+        DictEntry::Occupied(mut oe) => {
+            let (reply, empty) = match &mut oe.get_mut().value {
+                RudisObject::Hash(h) => (f(h), h.is_empty()),
+                _ => return shared::wrong_type_err,
+            };
+            if empty {
+                oe.remove();
+            }
+            reply
+        }
+        DictEntry::Vacant(ve) => {
+            if !create {
+                return missing;
+            }
+            let mut h = RudisHash::new();
+            let reply = f(&mut h);
+            if !h.is_empty() {
+                ve.insert(DictValue::new(RudisObject::Hash(h), None));
+            }
+            reply
+        }
+    }
+}
+
+/// HSET and HMSET
 #[derive(Debug, Clone)]
 pub struct HSet {
     pub key: Bytes,
-    pub field: Bytes,
-    pub value: Bytes,
+    pub pairs: Vec<(Bytes, Bytes)>,
+    /// reply OK instead of the number of new fields
+    pub hmset: bool,
 }
 
 impl HSet {
-    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+    pub fn from(frame: &mut CommandParser, hmset: bool) -> Result<Self> {
         let key = frame
             .next_string()?
             .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "HSET requires a key"))?;
+        let mut pairs = Vec::new();
+        while let Some(field) = frame.next_string()? {
+            let value = frame
+                .next_string()?
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "HSET requires a value"))?;
+            pairs.push((field, value));
+        }
+        if pairs.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "HSET requires a field"));
+        }
+        Ok(Self { key, pairs, hmset })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_hash(&db, self.key, true, Frame::Null, |h| {
+            let mut added = 0;
+            for (field, value) in self.pairs {
+                if h.insert(field, value).is_none() {
+                    added += 1;
+                }
+            }
+            if self.hmset {
+                shared::ok
+            } else {
+                Frame::Integer(added)
+            }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let mut out = BytesMut::new();
+        shared::extend_array(&mut out, 2 + self.pairs.len() * 2);
+        shared::extend_bulk_string(
+            &mut out,
+            if self.hmset {
+                b"HMSET" as &[u8]
+            } else {
+                b"HSET"
+            },
+        );
+        shared::extend_bulk_string(&mut out, &self.key[..]);
+        for (field, value) in &self.pairs {
+            shared::extend_bulk_string(&mut out, &field[..]);
+            shared::extend_bulk_string(&mut out, &value[..]);
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HSetNx {
+    pub key: Bytes,
+    pub field: Bytes,
+    pub value: Bytes,
+}
+
+impl HSetNx {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("HSETNX")?;
         let field = frame
             .next_string()?
-            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "HSET requires a field"))?;
+            .ok_or_else(CommandParser::syntax_error)?;
         let value = frame
             .next_string()?
-            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "HSET requires a value"))?;
+            .ok_or_else(CommandParser::syntax_error)?;
         Ok(Self { key, field, value })
     }
 
     pub async fn apply(self, client: &mut Client) -> Result<()> {
-        match client.db.clone().entry(self.key) {
-            Entry::Occupied(mut oe) => match &mut oe.get_mut().value {
-                RudisObject::Hash(h) => {
-                    h.insert(self.field, BytesMut::from(&self.value[..]));
-                    client.write_frame(&Frame::Integer(1)).await?;
-                    Ok(())
-                }
-                _ => {
-                    client.write_frame(&shared::wrong_type_err).await?;
-                    return Err(Error::new(
-                        ErrorKind::InvalidInput,
-                        "WRONGTYPE Operation against a key holding the wrong kind of value",
-                    ));
+        let db = client.db.clone();
+        let response = write_hash(&db, self.key, true, Frame::Null, |h| {
+            match h.entry(self.field) {
+                hash_map::Entry::Occupied(_) => Frame::Integer(0),
+                hash_map::Entry::Vacant(ve) => {
+                    ve.insert(self.value);
+                    Frame::Integer(1)
                 }
-            },
-            Entry::Vacant(ve) => {
-                let mut h = std::collections::HashMap::new();
-                h.insert(self.field, BytesMut::from(&self.value[..]));
-                ve.insert(DictValue::new(RudisObject::new_hash_from(h), None));
-                client.write_frame(&Frame::Integer(1)).await?;
-                Ok(())
             }
-        }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
     }
 
     pub fn rewrite(&self) -> BytesMut {
-        let mut out = BytesMut::new();
-        shared::extend_array(&mut out, 4);
-        shared::extend_bulk_string(&mut out, b"HSET" as &[u8]);
-        shared::extend_bulk_string(&mut out, &self.key[..]);
-        shared::extend_bulk_string(&mut out, &self.field[..]);
-        shared::extend_bulk_string(&mut out, &self.value[..]);
-        out
+        shared::rewrite_args([b"HSETNX" as &[u8], &self.key, &self.field, &self.value])
     }
 }
 
@@ -84,30 +198,425 @@ impl HGet {
     }
 
     pub async fn apply(self, client: &mut Client) -> Result<()> {
-        match client.db.clone().get(&self.key) {
-            Some(entry) => match &entry.value {
-                RudisObject::Hash(h) => {
-                    if let Some(value) = h.get(&self.field) {
-                        client
-                            .write_frame(&Frame::Bulk(value.clone().freeze()))
-                            .await?;
-                    } else {
-                        client.write_frame(&Frame::Null).await?;
+        let response = read_hash(&client.db, &self.key, Frame::Null, |h| {
+            h.get(&self.field)
+                .map_or(Frame::Null, |value| Frame::Bulk(value.clone()))
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HMGet {
+    pub key: Bytes,
+    pub fields: Vec<Bytes>,
+}
+
+impl HMGet {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("HMGET")?;
+        let fields = next_fields(frame, "HMGET")?;
+        Ok(Self { key, fields })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let missing = Frame::Array(vec![Frame::Null; self.fields.len()]);
+        let response = read_hash(&client.db, &self.key, missing, |h| {
+            Frame::Array(
+                self.fields
+                    .iter()
+                    .map(|field| {
+                        h.get(field)
+                            .map_or(Frame::Null, |value| Frame::Bulk(value.clone()))
+                    })
+                    .collect(),
+            )
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HDel {
+    pub key: Bytes,
+    pub fields: Vec<Bytes>,
+}
+
+impl HDel {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("HDEL")?;
+        let fields = next_fields(frame, "HDEL")?;
+        Ok(Self { key, fields })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_hash(&db, self.key, false, Frame::Integer(0), |h| {
+            let deleted = self
+                .fields
+                .iter()
+                .filter(|field| h.remove(*field).is_some())
+                .count();
+            Frame::Integer(deleted as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args(
+            [b"HDEL" as &[u8], &self.key]
+                .into_iter()
+                .chain(self.fields.iter().map(|f| &f[..])),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HExists {
+    pub key: Bytes,
+    pub field: Bytes,
+}
+
+impl HExists {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("HEXISTS")?;
+        let field = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self { key, field })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_hash(&client.db, &self.key, Frame::Integer(0), |h| {
+            Frame::Integer(h.contains_key(&self.field) as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HLen {
+    pub key: Bytes,
+}
+
+impl HLen {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("HLEN")?;
+        Ok(Self { key })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_hash(&client.db, &self.key, Frame::Integer(0), |h| {
+            Frame::Integer(h.len() as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HStrLen {
+    pub key: Bytes,
+    pub field: Bytes,
+}
+
+impl HStrLen {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("HSTRLEN")?;
+        let field = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self { key, field })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_hash(&client.db, &self.key, Frame::Integer(0), |h| {
+            Frame::Integer(h.get(&self.field).map_or(0, |value| value.len()) as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// HKEYS, HVALS and HGETALL
+#[derive(Debug, Clone)]
+pub struct HGetAll {
+    pub key: Bytes,
+    pub fields: bool,
+    pub values: bool,
+}
+
+impl HGetAll {
+    pub fn from(frame: &mut CommandParser, fields: bool, values: bool) -> Result<Self> {
+        let key = frame.next_key("HGETALL")?;
+        Ok(Self {
+            key,
+            fields,
+            values,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let missing = if self.fields && self.values {
+            Frame::Map(Vec::new())
+        } else {
+            Frame::new_array()
+        };
+        let response = read_hash(&client.db, &self.key, missing, |h| {
+            if self.fields && self.values {
+                Frame::Map(
+                    h.iter()
+                        .map(|(field, value)| {
+                            (Frame::Bulk(field.clone()), Frame::Bulk(value.clone()))
+                        })
+                        .collect(),
+                )
+            } else if self.fields {
+                Frame::Array(h.keys().cloned().map(Frame::Bulk).collect())
+            } else {
+                Frame::Array(h.values().cloned().map(Frame::Bulk).collect())
+            }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HIncrBy {
+    pub key: Bytes,
+    pub field: Bytes,
+    pub increment: i64,
+}
+
+impl HIncrBy {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("HINCRBY")?;
+        let field = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let increment = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self {
+            key,
+            field,
+            increment,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_hash(&db, self.key, true, Frame::Null, |h| {
+            let current = match h.get(&self.field) {
+                Some(value) => match std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|v| v.parse::<i64>().ok())
+                {
+                    Some(current) => current,
+                    None => {
+                        return Frame::Error(Bytes::from_static(
+                            b"ERR hash value is not an integer",
+                        ))
                     }
-                    Ok(())
+                },
+                None => 0,
+            };
+            match current.checked_add(self.increment) {
+                Some(n) => {
+                    h.insert(self.field, Bytes::from(n.to_string()));
+                    Frame::Integer(n)
                 }
-                _ => {
-                    client.write_frame(&shared::wrong_type_err).await?;
-                    return Err(Error::new(
-                        ErrorKind::InvalidInput,
-                        "WRONGTYPE Operation against a key holding the wrong kind of value",
-                    ));
+                None => Frame::Error(Bytes::from_static(
+                    b"ERR increment or decrement would overflow",
+                )),
+            }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([
+            b"HINCRBY" as &[u8],
+            &self.key,
+            &self.field,
+            self.increment.to_string().as_bytes(),
+        ])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HIncrByFloat {
+    pub key: Bytes,
+    pub field: Bytes,
+    pub increment: f64,
+}
+
+impl HIncrByFloat {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("HINCRBYFLOAT")?;
+        let field = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let increment = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let increment = std::str::from_utf8(&increment)
+            .ok()
+            .and_then(|n| n.parse::<f64>().ok())
+            .filter(|n| n.is_finite())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "value is not a valid float"))?;
+        Ok(Self {
+            key,
+            field,
+            increment,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_hash(&db, self.key, true, Frame::Null, |h| {
+            let current = match h.get(&self.field) {
+                Some(value) => match std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .filter(|v| v.is_finite())
+                {
+                    Some(current) => current,
+                    None => {
+                        return Frame::Error(Bytes::from_static(b"ERR hash value is not a float"))
+                    }
+                },
+                None => 0.0,
+            };
+            let n = current + self.increment;
+            if !n.is_finite() {
+                return Frame::Error(Bytes::from_static(
+                    b"ERR increment would produce NaN or Infinity",
+                ));
+            }
+            let value = Bytes::from(n.to_string());
+            h.insert(self.field, value.clone());
+            Frame::Bulk(value)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([
+            b"HINCRBYFLOAT" as &[u8],
+            &self.key,
+            &self.field,
+            self.increment.to_string().as_bytes(),
+        ])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HScan {
+    pub key: Bytes,
+    pub args: ScanArgs,
+}
+
+impl HScan {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("HSCAN")?;
+        let args = ScanArgs::from(frame, false)?;
+        Ok(Self { key, args })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_hash(&client.db, &self.key, ScanArgs::reply(0, Vec::new()), |h| {
+            let (fields, next) = shared::scan_page(
+                h.iter()
+                    .map(|(field, value)| (shared::scan_hash(field), (field, value))),
+                self.args.cursor,
+                self.args.count,
+            );
+            let elements = fields
+                .into_iter()
+                .filter(|(field, _)| self.args.matches(field))
+                .flat_map(|(field, value)| [Frame::Bulk(field.clone()), Frame::Bulk(value.clone())])
+                .collect();
+            ScanArgs::reply(next.unwrap_or(0), elements)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HRandField {
+    pub key: Bytes,
+    /// reply an array of up to `count` distinct fields, or of exactly
+    /// `-count` fields that may repeat if negative
+    pub count: Option<i64>,
+    pub withvalues: bool,
+}
+
+impl HRandField {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("HRANDFIELD")?;
+        let count = frame.next_integer()?;
+        let withvalues = match frame.next_string()? {
+            Some(arg) if count.is_some() && arg.eq_ignore_ascii_case(b"withvalues") => true,
+            Some(_) => return Err(CommandParser::syntax_error()),
+            None => false,
+        };
+        Ok(Self {
+            key,
+            count,
+            withvalues,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let resp = client.resp;
+        let missing = match self.count {
+            Some(_) => Frame::new_array(),
+            None => Frame::Null,
+        };
+        let response = read_hash(&client.db, &self.key, missing, |h| {
+            let mut rng = rand::thread_rng();
+            let count = match self.count {
+                Some(count) => count,
+                None => {
+                    return h
+                        .keys()
+                        .choose(&mut rng)
+                        .map_or(Frame::Null, |field| Frame::Bulk(field.clone()))
                 }
-            },
-            None => {
-                client.write_frame(&Frame::Null).await?;
-                Ok(())
+            };
+            let pairs: Vec<(&Bytes, &Bytes)> = if count >= 0 {
+                h.iter().choose_multiple(&mut rng, count as usize)
+            } else {
+                let all: Vec<(&Bytes, &Bytes)> = h.iter().collect();
+                (0..count.unsigned_abs())
+                    .map(|_| all[rng.gen_range(0..all.len())])
+                    .collect()
+            };
+            let reply = pairs
+                .into_iter()
+                .map(|(field, value)| (Frame::Bulk(field.clone()), Frame::Bulk(value.clone())));
+            if !self.withvalues {
+                Frame::Array(reply.map(|(field, _)| field).collect())
+            } else if resp >= 3 {
+                Frame::Array(
+                    reply
+                        .map(|(field, value)| Frame::Array(vec![field, value]))
+                        .collect(),
+                )
+            } else {
+                Frame::Array(reply.flat_map(|(field, value)| [field, value]).collect())
             }
-        }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
     }
 }
@@ -0,0 +1,165 @@
+use super::CommandParser;
+use crate::client::Client;
+use crate::frame::Frame;
+use bytes::Bytes;
+use std::io::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone)]
+pub struct Hello {
+    /// keep the current protocol if not provided
+    pub protover: Option<Bytes>,
+    /// username and password
+    pub auth: Option<(Bytes, Bytes)>,
+    pub setname: Option<Bytes>,
+}
+
+impl Hello {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let protover = frame.next_string()?;
+        let mut auth = None;
+        let mut setname = None;
+        while let Some(option) = frame.next_string()? {
+            match &option.to_ascii_lowercase()[..] {
+                b"auth" => {
+                    let username = frame.next_string()?;
+                    let password = frame.next_string()?;
+                    match (username, password) {
+                        (Some(username), Some(password)) => auth = Some((username, password)),
+                        _ => {
+                            return Err(Error::new(
+                                ErrorKind::InvalidInput,
+                                "HELLO AUTH requires a username and a password",
+                            ))
+                        }
+                    }
+                }
+                b"setname" => {
+                    setname = Some(frame.next_string()?.ok_or_else(|| {
+                        Error::new(ErrorKind::InvalidInput, "HELLO SETNAME requires a name")
+                    })?);
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "HELLO option not supported",
+                    ))
+                }
+            }
+        }
+        Ok(Self {
+            protover,
+            auth,
+            setname,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let resp = match &self.protover {
+            None => client.resp,
+            Some(protover) => match std::str::from_utf8(protover)
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            {
+                Some(protover) if protover == 2 || protover == 3 => protover as u8,
+                Some(_) => {
+                    client
+                        .write_frame(&Frame::Error(Bytes::from_static(
+                            b"NOPROTO unsupported protocol version",
+                        )))
+                        .await?;
+                    return Ok(());
+                }
+                None => {
+                    client
+                        .write_frame(&Frame::Error(Bytes::from_static(
+                            b"ERR Protocol version is not an integer or out of range",
+                        )))
+                        .await?;
+                    return Ok(());
+                }
+            },
+        };
+
+        if let Some((username, password)) = self.auth {
+            if client
+                .server
+                .acl
+                .read()
+                .await
+                .authenticate(&username, &password)
+            {
+                client.user = username;
+                client.authenticated = true;
+            } else {
+                log::warn!(
+                    "Failed authentication of user {} from {}",
+                    String::from_utf8_lossy(&username),
+                    client.address
+                );
+                client
+                    .write_frame(&Frame::Error(Bytes::from_static(
+                        b"WRONGPASS invalid username-password pair or user is disabled.",
+                    )))
+                    .await?;
+                return Ok(());
+            }
+        }
+
+        if !client.authenticated {
+            client
+                .write_frame(&Frame::Error(Bytes::from_static(
+                    b"NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time",
+                )))
+                .await?;
+            return Ok(());
+        }
+
+        if let Some(name) = self.setname {
+            if name.iter().any(|&c| c <= b' ' || c > b'~') {
+                client
+                    .write_frame(&Frame::Error(Bytes::from_static(
+                        b"ERR Client names cannot contain spaces, newlines or special characters.",
+                    )))
+                    .await?;
+                return Ok(());
+            }
+            client.inner.write().await.name = String::from_utf8_lossy(&name).into_owned();
+        }
+
+        // the reply already uses the new protocol
+        client.resp = resp;
+        let response = Frame::Map(vec![
+            (
+                Frame::Bulk(Bytes::from_static(b"server")),
+                Frame::Bulk(Bytes::from_static(b"redis")),
+            ),
+            (
+                Frame::Bulk(Bytes::from_static(b"version")),
+                Frame::Bulk(Bytes::from_static(env!("CARGO_PKG_VERSION").as_bytes())),
+            ),
+            (
+                Frame::Bulk(Bytes::from_static(b"proto")),
+                Frame::Integer(resp as i64),
+            ),
+            (
+                Frame::Bulk(Bytes::from_static(b"id")),
+                Frame::Integer(client.id as i64),
+            ),
+            (
+                Frame::Bulk(Bytes::from_static(b"mode")),
+                Frame::Bulk(Bytes::from_static(b"standalone")),
+            ),
+            (
+                Frame::Bulk(Bytes::from_static(b"role")),
+                Frame::Bulk(Bytes::from_static(b"master")),
+            ),
+            (
+                Frame::Bulk(Bytes::from_static(b"modules")),
+                Frame::new_array(),
+            ),
+        ]);
+
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use super::CommandParser;
+use crate::client::Client;
+use crate::dbms::{DatabaseRef, DictEntry, DictValue};
+use crate::frame::Frame;
+use crate::hyperloglog::Hll;
+use crate::object::RudisObject;
+use crate::shared;
+use bytes::{Bytes, BytesMut};
+use std::io::Result;
+
+/// The HyperLogLog at `key`, None if there is no such key, Err with the
+/// error reply if the key doesn't hold one
+fn read_hll(db: &DatabaseRef, key: &Bytes) -> std::result::Result<Option<Hll>, Frame> {
+    match db.get(key) {
+        Some(entry) => match &entry.value {
+            RudisObject::String(s) => Hll::decode(s).map(Some).ok_or(shared::invalid_hll_err),
+            _ => Err(shared::wrong_type_err),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The union of the HyperLogLogs at `keys`, the missing keys are empty
+fn merge_hlls(db: &DatabaseRef, keys: &[Bytes]) -> std::result::Result<Hll, Frame> {
+    let mut merged = Hll::new();
+    for key in keys {
+        if let Some(hll) = read_hll(db, key)? {
+            merged.merge(&hll);
+        }
+    }
+    Ok(merged)
+}
+
+#[derive(Debug, Clone)]
+pub struct PfAdd {
+    pub key: Bytes,
+    pub elements: Vec<Bytes>,
+}
+
+impl PfAdd {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("PFADD")?;
+        let mut elements = Vec::new();
+        while let Some(element) = frame.next_string()? {
+            elements.push(element);
+        }
+        Ok(Self { key, elements })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = match client.db.entry(self.key) {
+            DictEntry::Occupied(mut oe) => match &mut oe.get_mut().value {
+                RudisObject::String(s) => match Hll::decode(s) {
+                    Some(mut hll) => {
+                        let mut changed = false;
+                        for element in &self.elements {
+                            changed |= hll.add(element);
+                        }
+                        if changed {
+                            s.value = hll.encode();
+                        }
+                        Frame::Integer(changed as i64)
+                    }
+                    None => shared::invalid_hll_err,
+                },
+                _ => shared::wrong_type_err,
+            },
+            DictEntry::Vacant(ve) => {
+                let mut hll = Hll::new();
+                for element in &self.elements {
+                    hll.add(element);
+                }
+                ve.insert(DictValue::new(
+                    RudisObject::new_string_from(hll.encode()),
+                    None,
+                ));
+                Frame::Integer(1)
+            }
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args(
+            [b"PFADD" as &[u8], &self.key]
+                .into_iter()
+                .chain(self.elements.iter().map(|e| &e[..])),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PfCount {
+    pub keys: Vec<Bytes>,
+}
+
+impl PfCount {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let mut keys = vec![frame.next_key("PFCOUNT")?];
+        while let Some(key) = frame.next_string()? {
+            keys.push(key);
+        }
+        Ok(Self { keys })
+    }
+
+    /// The cardinality of the single key, cached in its header
+    fn count(db: &DatabaseRef, key: &Bytes) -> Frame {
+        match db.get_mut(key) {
+            Some(mut entry) => match &mut entry.value {
+                RudisObject::String(s) => {
+                    if !Hll::is_hll(s) {
+                        return shared::invalid_hll_err;
+                    }
+                    if let Some(count) = Hll::cached_count(s) {
+                        return Frame::Integer(count as i64);
+                    }
+                    let count = match Hll::decode(s) {
+                        Some(hll) => hll.count(),
+                        None => return shared::invalid_hll_err,
+                    };
+                    // the strings are immutable to be shared with the
+                    // requests they were read from
+                    let mut value = BytesMut::from(&s[..]);
+                    Hll::cache_count(&mut value, count);
+                    s.value = value.freeze();
+                    Frame::Integer(count as i64)
+                }
+                _ => shared::wrong_type_err,
+            },
+            None => Frame::Integer(0),
+        }
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = if self.keys.len() == 1 {
+            Self::count(&client.db, &self.keys[0])
+        } else {
+            match merge_hlls(&client.db, &self.keys) {
+                Ok(hll) => Frame::Integer(hll.count() as i64),
+                Err(err) => err,
+            }
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PfMerge {
+    pub dst: Bytes,
+    pub keys: Vec<Bytes>,
+}
+
+impl PfMerge {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let dst = frame.next_key("PFMERGE")?;
+        let mut keys = Vec::new();
+        while let Some(key) = frame.next_string()? {
+            keys.push(key);
+        }
+        Ok(Self { dst, keys })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        // the destination is merged too
+        let keys: Vec<Bytes> = std::iter::once(&self.dst)
+            .chain(&self.keys)
+            .cloned()
+            .collect();
+        let mut merged = match merge_hlls(&db, &keys) {
+            Ok(merged) => merged,
+            Err(err) => {
+                client.write_frame(&err).await?;
+                return Ok(());
+            }
+        };
+        merged.set_dense();
+        match db.entry(self.dst) {
+            DictEntry::Occupied(mut oe) => {
+                oe.get_mut().value = RudisObject::new_string_from(merged.encode());
+            }
+            DictEntry::Vacant(ve) => {
+                ve.insert(DictValue::new(
+                    RudisObject::new_string_from(merged.encode()),
+                    None,
+                ));
+            }
+        }
+        client.write_frame(&shared::ok).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args(
+            [b"PFMERGE" as &[u8], &self.dst]
+                .into_iter()
+                .chain(self.keys.iter().map(|k| &k[..])),
+        )
+    }
+}
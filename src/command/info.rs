@@ -0,0 +1,149 @@
+use super::CommandParser;
+use crate::client::Client;
+use crate::frame::Frame;
+use crate::shared;
+use crate::zmalloc;
+use bytes::Bytes;
+use std::fmt::Write;
+use std::io::Result;
+use std::sync::atomic::Ordering;
+
+const DEFAULT_SECTIONS: &[&str] = &["server", "memory", "stats", "keyspace"];
+
+#[derive(Debug, Clone)]
+pub struct Info {
+    /// the default sections if empty
+    pub sections: Vec<Bytes>,
+}
+
+impl Info {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let mut sections = Vec::new();
+        while let Some(section) = frame.next_string()? {
+            sections.push(section);
+        }
+        Ok(Self { sections })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let sections: Vec<String> = if self.sections.is_empty() {
+            DEFAULT_SECTIONS.iter().map(|s| s.to_string()).collect()
+        } else {
+            self.sections
+                .iter()
+                .map(|s| String::from_utf8_lossy(s).to_ascii_lowercase())
+                .flat_map(|s| match s.as_str() {
+                    "all" | "default" | "everything" => {
+                        DEFAULT_SECTIONS.iter().map(|s| s.to_string()).collect()
+                    }
+                    _ => vec![s],
+                })
+                .collect()
+        };
+
+        let mut info = String::new();
+        for section in DEFAULT_SECTIONS {
+            if !sections.iter().any(|s| s == section) {
+                continue;
+            }
+            if !info.is_empty() {
+                info.push_str("\r\n");
+            }
+            match *section {
+                "server" => client.info_server(&mut info).await,
+                "memory" => client.info_memory(&mut info).await,
+                "stats" => client.info_stats(&mut info),
+                "keyspace" => client.info_keyspace(&mut info),
+                _ => unreachable!(),
+            }
+        }
+
+        client
+            .write_frame(&Frame::Verbatim(
+                Bytes::from_static(b"txt"),
+                Bytes::from(info),
+            ))
+            .await?;
+        Ok(())
+    }
+}
+
+impl Client {
+    async fn info_server(&self, info: &mut String) {
+        let config = self.config.read().await;
+        let uptime = (shared::now_ms() - self.server.start_time_ms) / 1000;
+        let _ = write!(
+            info,
+            "# Server\r\n\
+             redis_version:{}\r\n\
+             process_id:{}\r\n\
+             run_id:{}\r\n\
+             tcp_port:{}\r\n\
+             uptime_in_seconds:{}\r\n\
+             uptime_in_days:{}\r\n\
+             hz:{}\r\n",
+            env!("CARGO_PKG_VERSION"),
+            std::process::id(),
+            self.server.inner.read().await.runid,
+            config.port,
+            uptime,
+            uptime / (3600 * 24),
+            config.hz,
+        );
+    }
+
+    async fn info_memory(&self, info: &mut String) {
+        let config = self.config.read().await;
+        let _ = write!(
+            info,
+            "# Memory\r\n\
+             used_memory:{}\r\n\
+             maxmemory:{}\r\n\
+             maxmemory_policy:{}\r\n",
+            zmalloc::used_memory(),
+            config.maxmemory,
+            config.maxmemory_policy,
+        );
+    }
+
+    fn info_stats(&self, info: &mut String) {
+        let stats = &self.server.stats;
+        let expired_keys: u64 = self
+            .server
+            .databases()
+            .iter()
+            .map(|db| db.expired_keys.load(Ordering::Relaxed))
+            .sum();
+        let _ = write!(
+            info,
+            "# Stats\r\n\
+             expired_keys:{}\r\n\
+             expired_stale_perc:{:.2}\r\n\
+             expired_time_cap_reached_count:{}\r\n\
+             evicted_keys:{}\r\n",
+            expired_keys,
+            stats.expired_stale_perc() * 100.0,
+            stats.expired_time_cap_reached_count.load(Ordering::Relaxed),
+            stats.evicted_keys.load(Ordering::Relaxed),
+        );
+    }
+
+    fn info_keyspace(&self, info: &mut String) {
+        info.push_str("# Keyspace\r\n");
+        for db in self.server.databases() {
+            let keys = db.len();
+            if keys == 0 {
+                continue;
+            }
+            let expires = db.iter().filter(|entry| entry.is_volatile()).count();
+            let _ = write!(
+                info,
+                "db{}:keys={},expires={},avg_ttl={}\r\n",
+                db.index,
+                keys,
+                expires,
+                db.avg_ttl.load(Ordering::Relaxed)
+            );
+        }
+    }
+}
@@ -1,28 +1,119 @@
-use super::CommandParser;
+use super::{Command, CommandParser};
+use crate::blocked::BlockingCommand;
 use crate::client::Client;
-use crate::dbms::DictValue;
+use crate::dbms::{DatabaseRef, DictEntry, DictValue};
 use crate::frame::Frame;
 use crate::object::{RudisList, RudisObject};
 use crate::shared;
 use bytes::{Bytes, BytesMut};
-use dashmap::mapref::entry::Entry;
 use std::io::{Error, ErrorKind, Result};
+use tokio::time::{Duration, Instant};
+
+/// Parse `LEFT` or `RIGHT`, true for the left side
+fn next_side(frame: &mut CommandParser) -> Result<bool> {
+    let side = frame
+        .next_string()?
+        .ok_or_else(CommandParser::syntax_error)?;
+    match &side.to_ascii_lowercase()[..] {
+        b"left" => Ok(true),
+        b"right" => Ok(false),
+        _ => Err(CommandParser::syntax_error()),
+    }
+}
+
+fn side_name(left: bool) -> &'static [u8] {
+    if left {
+        b"LEFT"
+    } else {
+        b"RIGHT"
+    }
+}
+
+/// Resolve an index, negative ones counting from the end
+fn list_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    usize::try_from(index).ok().filter(|&index| index < len)
+}
+
+/// Reply `f` applied to the list at `key`, `missing` if there is no such key
+fn read_list(
+    db: &DatabaseRef,
+    key: &Bytes,
+    missing: Frame,
+    f: impl FnOnce(&RudisList) -> Frame,
+) -> Frame {
+    match db.get(key) {
+        Some(entry) => match &entry.value {
+            RudisObject::List(l) => f(l),
+            _ => shared::wrong_type_err,
+        },
+        None => missing,
+    }
+}
+
+/// Reply `f` applied to the list at `key`, which is created if `create`,
+/// otherwise `missing` is replied if there is no such key. The key is deleted
+/// once the list is empty
+fn write_list(
+    db: &DatabaseRef,
+    key: Bytes,
+    create: bool,
+    missing: Frame,
+    f: impl FnOnce(&mut RudisList) -> Frame,
+) -> Frame {
+    match db.entry(key) {
+        DictEntry::Occupied(mut oe) => {
+            let (reply, empty) = match &mut oe.get_mut().value {
+                RudisObject::List(l) => (f(l), l.is_empty()),
+                _ => return shared::wrong_type_err,
+            };
+            if empty {
+                oe.remove();
+            }
+            reply
+        }
+        DictEntry::Vacant(ve) => {
+            if !create {
+                return missing;
+            }
+            let mut l = RudisList::new();
+            let reply = f(&mut l);
+            if !l.is_empty() {
+                ve.insert(DictValue::new(RudisObject::List(l), None));
+            }
+            reply
+        }
+    }
+}
+
+/// Pop up to `count` elements from a side of the list
+fn pop_elements(l: &mut RudisList, left: bool, count: usize) -> Vec<Bytes> {
+    let count = count.min(l.len());
+    if left {
+        l.drain(..count).collect()
+    } else {
+        let len = l.len();
+        l.drain(len - count..).rev().collect()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ListPush {
     pub key: Bytes,
-    pub values: Vec<BytesMut>,
+    pub values: Vec<Bytes>,
     pub left: bool,
+    /// only push to an existing list, LPUSHX and RPUSHX
+    pub exists: bool,
 }
 
 impl ListPush {
-    pub fn from(frame: &mut CommandParser, left: bool) -> Result<Self> {
+    pub fn from(frame: &mut CommandParser, left: bool, exists: bool) -> Result<Self> {
         let key = frame
             .next_string()?
             .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "LPUSH requires a key"))?;
         let mut values = Vec::new();
         while let Some(value) = frame.next_string()? {
-            values.push(BytesMut::from(&value[..]));
+            values.push(value);
         }
         if values.is_empty() {
             return Err(Error::new(
@@ -30,10 +121,15 @@ impl ListPush {
                 "LPUSH requires at least one value",
             ));
         }
-        Ok(Self { key, values, left })
+        Ok(Self {
+            key,
+            values,
+            left,
+            exists,
+        })
     }
 
-    fn extend(l: &mut RudisList, values: Vec<BytesMut>, left: bool) {
+    fn extend(l: &mut RudisList, values: Vec<Bytes>, left: bool) {
         if left {
             for value in values {
                 l.push_front(value);
@@ -44,34 +140,26 @@ impl ListPush {
     }
 
     pub async fn apply(self, client: &mut Client) -> Result<()> {
-        match client.db.clone().entry(self.key) {
-            Entry::Occupied(mut x) => match &mut x.get_mut().value {
-                RudisObject::List(l) => {
-                    Self::extend(l, self.values, self.left);
-                    client.write_frame(&Frame::Integer(l.len() as i64)).await?;
-                    Ok(())
-                }
-                _ => {
-                    client.write_frame(&shared::wrong_type_err).await?;
-                    return Err(Error::new(
-                        ErrorKind::InvalidInput,
-                        "WRONGTYPE Operation against a key holding the wrong kind of value",
-                    ));
-                }
+        let db = client.db.clone();
+        let response = write_list(
+            &db,
+            self.key.clone(),
+            !self.exists,
+            Frame::Integer(0),
+            |l| {
+                Self::extend(l, self.values, self.left);
+                Frame::Integer(l.len() as i64)
             },
-            Entry::Vacant(ve) => {
-                let mut l = RudisList::new();
-                Self::extend(&mut l, self.values, self.left);
-                let len = l.len();
-                ve.insert(DictValue::new(RudisObject::List(l), None));
-                client.write_frame(&Frame::Integer(len as i64)).await?;
-                Ok(())
-            }
+        );
+        if let Frame::Integer(1..) = response {
+            db.signal_key_as_ready(&self.key);
         }
+        client.write_frame(&response).await?;
+        Ok(())
     }
 
     pub fn rewrite(&self) -> BytesMut {
-        // L/RPUSH key [value ...]
+        // L/RPUSH[X] key [value ...]
         let mut out = BytesMut::new();
 
         shared::extend_array(&mut out, 2 + self.values.len());
@@ -79,7 +167,12 @@ impl ListPush {
         // cmd
         shared::extend_bulk_string(
             &mut out,
-            if self.left { b"LPUSH" } else { b"RPUSH" } as &[u8],
+            match (self.left, self.exists) {
+                (true, false) => b"LPUSH" as &[u8],
+                (false, false) => b"RPUSH",
+                (true, true) => b"LPUSHX",
+                (false, true) => b"RPUSHX",
+            },
         );
 
         // key
@@ -98,6 +191,8 @@ impl ListPush {
 pub struct ListPop {
     pub key: Bytes,
     pub left: bool,
+    /// reply an array of up to `count` elements instead of a single one
+    pub count: Option<i64>,
 }
 
 impl ListPop {
@@ -105,52 +200,43 @@ impl ListPop {
         let key = frame
             .next_string()?
             .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "LPOP requires a key"))?;
-        Ok(Self { key, left })
+        let count = frame.next_integer()?;
+        if count.is_some_and(|count| count < 0) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "value is out of range, must be positive",
+            ));
+        }
+        Ok(Self { key, left, count })
     }
 
     pub async fn apply(self, client: &mut Client) -> Result<()> {
         let db = client.db.clone();
-        let x = match db.get_mut(&self.key) {
-            Some(mut entry) => match &mut entry.value {
-                RudisObject::List(l) => {
-                    let response = if self.left {
-                        l.pop_front()
-                    } else {
-                        l.pop_back()
-                    };
-                    if l.is_empty() {
-                        db.remove(&self.key);
-                    }
-                    if let Some(value) = response {
-                        client
-                            .write_frame(&Frame::new_bulk_from(value).sealed()?)
-                            .await?;
-                    } else {
-                        client.write_frame(&Frame::Null).await?;
-                    }
-                    Ok(())
-                }
-                _ => {
-                    client.write_frame(&shared::wrong_type_err).await?;
-                    return Err(Error::new(
-                        ErrorKind::InvalidInput,
-                        "WRONGTYPE Operation against a key holding the wrong kind of value",
-                    ));
-                }
-            },
+        let response = write_list(&db, self.key, false, Frame::Null, |l| match self.count {
+            Some(count) => Frame::Array(
+                pop_elements(l, self.left, count as usize)
+                    .into_iter()
+                    .map(Frame::Bulk)
+                    .collect(),
+            ),
             None => {
-                client.write_frame(&Frame::Null).await?;
-                Ok(())
+                let value = if self.left {
+                    l.pop_front()
+                } else {
+                    l.pop_back()
+                };
+                value.map_or(Frame::Null, Frame::Bulk)
             }
-        };
-        x
+        });
+        client.write_frame(&response).await?;
+        Ok(())
     }
 
     pub fn rewrite(&self) -> BytesMut {
-        // L/RPOP key
+        // L/RPOP key [count]
         let mut out = BytesMut::new();
 
-        shared::extend_array(&mut out, 2);
+        shared::extend_array(&mut out, if self.count.is_some() { 3 } else { 2 });
 
         // cmd
         shared::extend_bulk_string(&mut out, if self.left { b"LPOP" } else { b"RPOP" } as &[u8]);
@@ -158,6 +244,698 @@ impl ListPop {
         // key
         shared::extend_bulk_string(&mut out, &self.key[..]);
 
+        // count
+        if let Some(count) = self.count {
+            shared::extend_bulk_string(&mut out, count.to_string().as_bytes());
+        }
+
         out
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct LLen {
+    pub key: Bytes,
+}
+
+impl LLen {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("LLEN")?;
+        Ok(Self { key })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_list(&client.db, &self.key, Frame::Integer(0), |l| {
+            Frame::Integer(l.len() as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LRange {
+    pub key: Bytes,
+    pub start: i64,
+    pub stop: i64,
+}
+
+impl LRange {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("LRANGE")?;
+        let start = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let stop = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self { key, start, stop })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response =
+            read_list(
+                &client.db,
+                &self.key,
+                Frame::new_array(),
+                |l| match shared::index_range(self.start, self.stop, l.len()) {
+                    Some((start, stop)) => {
+                        Frame::Array(l.range(start..=stop).cloned().map(Frame::Bulk).collect())
+                    }
+                    None => Frame::new_array(),
+                },
+            );
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LIndex {
+    pub key: Bytes,
+    pub index: i64,
+}
+
+impl LIndex {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("LINDEX")?;
+        let index = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self { key, index })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_list(&client.db, &self.key, Frame::Null, |l| {
+            list_index(self.index, l.len()).map_or(Frame::Null, |i| Frame::Bulk(l[i].clone()))
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LSet {
+    pub key: Bytes,
+    pub index: i64,
+    pub value: Bytes,
+}
+
+impl LSet {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("LSET")?;
+        let index = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let value = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self { key, index, value })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_list(
+            &db,
+            self.key,
+            false,
+            shared::no_key_err,
+            |l| match list_index(self.index, l.len()) {
+                Some(i) => {
+                    l[i] = self.value;
+                    shared::ok
+                }
+                None => Frame::Error(Bytes::from_static(b"ERR index out of range")),
+            },
+        );
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([
+            b"LSET" as &[u8],
+            &self.key,
+            self.index.to_string().as_bytes(),
+            &self.value,
+        ])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LInsert {
+    pub key: Bytes,
+    pub before: bool,
+    pub pivot: Bytes,
+    pub value: Bytes,
+}
+
+impl LInsert {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("LINSERT")?;
+        let before = match &frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?
+            .to_ascii_lowercase()[..]
+        {
+            b"before" => true,
+            b"after" => false,
+            _ => return Err(CommandParser::syntax_error()),
+        };
+        let pivot = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let value = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self {
+            key,
+            before,
+            pivot,
+            value,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_list(&db, self.key, false, Frame::Integer(0), |l| {
+            match l.iter().position(|value| value == &self.pivot) {
+                Some(i) => {
+                    l.insert(if self.before { i } else { i + 1 }, self.value);
+                    Frame::Integer(l.len() as i64)
+                }
+                None => Frame::Integer(-1),
+            }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([
+            b"LINSERT" as &[u8],
+            &self.key,
+            if self.before { b"BEFORE" } else { b"AFTER" },
+            &self.pivot,
+            &self.value,
+        ])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LRem {
+    pub key: Bytes,
+    /// remove this many occurrences from the head, from the tail if
+    /// negative, all of them if 0
+    pub count: i64,
+    pub value: Bytes,
+}
+
+impl LRem {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("LREM")?;
+        let count = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let value = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self { key, count, value })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_list(&db, self.key, false, Frame::Integer(0), |l| {
+            let limit = match self.count {
+                0 => usize::MAX,
+                count => count.unsigned_abs() as usize,
+            };
+            let mut removed = 0;
+            let mut retain = |value: &Bytes| {
+                if removed < limit && value == &self.value {
+                    removed += 1;
+                    false
+                } else {
+                    true
+                }
+            };
+            if self.count < 0 {
+                l.make_contiguous().reverse();
+                l.retain(|value| retain(value));
+                l.make_contiguous().reverse();
+            } else {
+                l.retain(|value| retain(value));
+            }
+            Frame::Integer(removed as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([
+            b"LREM" as &[u8],
+            &self.key,
+            self.count.to_string().as_bytes(),
+            &self.value,
+        ])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LTrim {
+    pub key: Bytes,
+    pub start: i64,
+    pub stop: i64,
+}
+
+impl LTrim {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("LTRIM")?;
+        let start = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let stop = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self { key, start, stop })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_list(&db, self.key, false, shared::ok, |l| {
+            match shared::index_range(self.start, self.stop, l.len()) {
+                Some((start, stop)) => {
+                    l.truncate(stop + 1);
+                    l.drain(..start);
+                }
+                None => l.clear(),
+            }
+            shared::ok
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([
+            b"LTRIM" as &[u8],
+            &self.key,
+            self.start.to_string().as_bytes(),
+            self.stop.to_string().as_bytes(),
+        ])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LPos {
+    pub key: Bytes,
+    pub element: Bytes,
+    /// reply the rank-th match, counting from the tail if negative
+    pub rank: i64,
+    /// reply an array of up to `count` matches, all of them if 0
+    pub count: Option<usize>,
+    /// compare at most this many elements, all of them if 0
+    pub maxlen: usize,
+}
+
+impl LPos {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("LPOS")?;
+        let element = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let mut cmd = Self {
+            key,
+            element,
+            rank: 1,
+            count: None,
+            maxlen: 0,
+        };
+        while let Some(option) = frame.next_string()? {
+            let value = frame
+                .next_integer()?
+                .ok_or_else(CommandParser::syntax_error)?;
+            match &option.to_ascii_lowercase()[..] {
+                b"rank" if value != 0 => cmd.rank = value,
+                b"count" if value >= 0 => cmd.count = Some(value as usize),
+                b"maxlen" if value >= 0 => cmd.maxlen = value as usize,
+                _ => return Err(CommandParser::syntax_error()),
+            }
+        }
+        Ok(cmd)
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let missing = match self.count {
+            Some(_) => Frame::new_array(),
+            None => Frame::Null,
+        };
+        let response = read_list(&client.db, &self.key, missing, |l| {
+            let len = l.len();
+            let maxlen = if self.maxlen == 0 { len } else { self.maxlen };
+            let limit = match self.count {
+                Some(0) => usize::MAX,
+                Some(count) => count,
+                None => 1,
+            };
+            let positions = (0..len)
+                .map(|i| if self.rank > 0 { i } else { len - 1 - i })
+                .take(maxlen)
+                .filter(|&i| l[i] == self.element)
+                .skip(self.rank.unsigned_abs() as usize - 1)
+                .take(limit)
+                .map(|i| Frame::Integer(i as i64));
+            match self.count {
+                Some(_) => Frame::Array(positions.collect()),
+                None => positions.into_iter().next().unwrap_or(Frame::Null),
+            }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// LMOVE and RPOPLPUSH
+#[derive(Debug, Clone)]
+pub struct LMove {
+    pub src: Bytes,
+    pub dst: Bytes,
+    pub from_left: bool,
+    pub to_left: bool,
+}
+
+impl LMove {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let src = frame.next_key("LMOVE")?;
+        let dst = frame.next_key("LMOVE")?;
+        let from_left = next_side(frame)?;
+        let to_left = next_side(frame)?;
+        Ok(Self {
+            src,
+            dst,
+            from_left,
+            to_left,
+        })
+    }
+
+    pub fn from_rpoplpush(frame: &mut CommandParser) -> Result<Self> {
+        let src = frame.next_key("RPOPLPUSH")?;
+        let dst = frame.next_key("RPOPLPUSH")?;
+        Ok(Self {
+            src,
+            dst,
+            from_left: false,
+            to_left: true,
+        })
+    }
+
+    /// Move an element from the source list to the destination, Null if the
+    /// source doesn't exist
+    pub fn move_element(&self, db: &DatabaseRef) -> Frame {
+        // check both the types before anything is modified
+        match db.get(&self.src) {
+            Some(entry) if !matches!(entry.value, RudisObject::List(_)) => {
+                return shared::wrong_type_err
+            }
+            Some(_) => {}
+            None => return Frame::Null,
+        }
+        if let Some(entry) = db.get(&self.dst) {
+            if !matches!(entry.value, RudisObject::List(_)) {
+                return shared::wrong_type_err;
+            }
+        }
+        let popped = write_list(db, self.src.clone(), false, Frame::Null, |l| {
+            pop_elements(l, self.from_left, 1)
+                .pop()
+                .map_or(Frame::Null, Frame::Bulk)
+        });
+        if let Frame::Bulk(value) = &popped {
+            write_list(db, self.dst.clone(), true, Frame::Null, |l| {
+                ListPush::extend(l, vec![value.clone()], self.to_left);
+                Frame::Null
+            });
+            db.signal_key_as_ready(&self.dst);
+        }
+        popped
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = self.move_element(&client.db.clone());
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([
+            b"LMOVE" as &[u8],
+            &self.src,
+            &self.dst,
+            side_name(self.from_left),
+            side_name(self.to_left),
+        ])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LMPop {
+    pub keys: Vec<Bytes>,
+    pub left: bool,
+    pub count: usize,
+}
+
+impl LMPop {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let numkeys = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        if numkeys <= 0 || numkeys as usize > frame.remaining() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "numkeys should be greater than 0",
+            ));
+        }
+        let mut keys = Vec::with_capacity(numkeys as usize);
+        for _ in 0..numkeys {
+            keys.push(
+                frame
+                    .next_string()?
+                    .ok_or_else(CommandParser::syntax_error)?,
+            );
+        }
+        let left = next_side(frame)?;
+        let count = match frame.next_string()? {
+            Some(option) if option.eq_ignore_ascii_case(b"count") => {
+                match frame
+                    .next_integer()?
+                    .ok_or_else(CommandParser::syntax_error)?
+                {
+                    count if count > 0 => count as usize,
+                    _ => return Err(CommandParser::syntax_error()),
+                }
+            }
+            Some(_) => return Err(CommandParser::syntax_error()),
+            None => 1,
+        };
+        Ok(Self { keys, left, count })
+    }
+
+    /// Pop from the first non-empty list, return its key and the elements,
+    /// None if all the lists are empty
+    fn pop(&self, db: &DatabaseRef) -> std::result::Result<Option<(Bytes, Vec<Bytes>)>, Frame> {
+        for key in &self.keys {
+            let mut values = Vec::new();
+            let response = write_list(db, key.clone(), false, Frame::Null, |l| {
+                values = pop_elements(l, self.left, self.count);
+                shared::ok
+            });
+            match response {
+                Frame::Null => continue,
+                Frame::Error(_) => return Err(response),
+                _ => return Ok(Some((key.clone(), values))),
+            }
+        }
+        Ok(None)
+    }
+
+    fn reply(key: Bytes, values: Vec<Bytes>) -> Frame {
+        Frame::Array(vec![
+            Frame::Bulk(key),
+            Frame::Array(values.into_iter().map(Frame::Bulk).collect()),
+        ])
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = match self.pop(&client.db.clone()) {
+            Ok(Some((key, values))) => Self::reply(key, values),
+            Ok(None) => Frame::Null,
+            Err(err) => err,
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let numkeys = self.keys.len().to_string();
+        let count = self.count.to_string();
+        let mut args = vec![b"LMPOP" as &[u8], numkeys.as_bytes()];
+        args.extend(self.keys.iter().map(|k| &k[..]));
+        args.extend([side_name(self.left), b"COUNT", count.as_bytes()]);
+        shared::rewrite_args(args)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BlockingOp {
+    /// BLPOP and BRPOP, a single element
+    Pop(LMPop),
+    /// BLMPOP
+    MPop(LMPop),
+    /// BLMOVE
+    Move(LMove),
+}
+
+/// BLPOP, BRPOP, BLMPOP and BLMOVE, served at once if one of the lists has
+/// elements, otherwise the client blocks until one of them is pushed to or
+/// the timeout
+#[derive(Debug, Clone)]
+pub struct BlockingPop {
+    pub op: BlockingOp,
+    /// seconds to block for, 0 to block forever
+    pub timeout: f64,
+}
+
+impl BlockingPop {
+    fn next_timeout(frame: &mut CommandParser) -> Result<f64> {
+        let timeout = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        std::str::from_utf8(&timeout)
+            .ok()
+            .and_then(|timeout| timeout.parse::<f64>().ok())
+            .filter(|timeout| timeout.is_finite() && *timeout >= 0.0)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    "timeout is not a float or out of range",
+                )
+            })
+    }
+
+    /// `BLPOP key [key ...] timeout` and BRPOP
+    pub fn from_pop(frame: &mut CommandParser, left: bool) -> Result<Self> {
+        let mut keys = Vec::new();
+        while frame.remaining() > 1 {
+            keys.push(
+                frame
+                    .next_string()?
+                    .ok_or_else(CommandParser::syntax_error)?,
+            );
+        }
+        if keys.is_empty() {
+            return Err(CommandParser::syntax_error());
+        }
+        let timeout = Self::next_timeout(frame)?;
+        Ok(Self {
+            op: BlockingOp::Pop(LMPop {
+                keys,
+                left,
+                count: 1,
+            }),
+            timeout,
+        })
+    }
+
+    /// `BLMPOP timeout numkeys key [key ...] LEFT|RIGHT [COUNT count]`
+    pub fn from_mpop(frame: &mut CommandParser) -> Result<Self> {
+        let timeout = Self::next_timeout(frame)?;
+        let op = BlockingOp::MPop(LMPop::from(frame)?);
+        Ok(Self { op, timeout })
+    }
+
+    /// `BLMOVE source destination LEFT|RIGHT LEFT|RIGHT timeout`
+    pub fn from_move(frame: &mut CommandParser) -> Result<Self> {
+        let op = BlockingOp::Move(LMove::from(frame)?);
+        let timeout = Self::next_timeout(frame)?;
+        Ok(Self { op, timeout })
+    }
+
+    /// The keys the client blocks on
+    pub fn keys(&self) -> &[Bytes] {
+        match &self.op {
+            BlockingOp::Pop(pop) | BlockingOp::MPop(pop) => &pop.keys,
+            BlockingOp::Move(lmove) => std::slice::from_ref(&lmove.src),
+        }
+    }
+
+    /// The reply when nothing was popped before the timeout, BLMOVE replies
+    /// a null bulk and the others a null array
+    pub fn null_reply(&self) -> Frame {
+        match self.op {
+            BlockingOp::Move(_) => Frame::Null,
+            _ => Frame::NullArray,
+        }
+    }
+
+    /// Pop if one of the lists has elements and reply, None if they are all
+    /// empty. The pop actually done is what gets propagated
+    pub fn serve(&self, client: &mut Client) -> Option<Frame> {
+        let db = client.db.clone();
+        match &self.op {
+            BlockingOp::Pop(pop) | BlockingOp::MPop(pop) => {
+                let (key, mut values) = match pop.pop(&db) {
+                    Ok(popped) => popped?,
+                    Err(err) => return Some(err),
+                };
+                let single = matches!(self.op, BlockingOp::Pop(_));
+                client.propagate_as = Some(Command::LPop(ListPop {
+                    key: key.clone(),
+                    left: pop.left,
+                    count: (!single).then_some(pop.count as i64),
+                }));
+                if single {
+                    let value = values.pop().unwrap_or_default();
+                    Some(Frame::Array(vec![Frame::Bulk(key), Frame::Bulk(value)]))
+                } else {
+                    Some(LMPop::reply(key, values))
+                }
+            }
+            BlockingOp::Move(lmove) => match lmove.move_element(&db) {
+                Frame::Null => None,
+                Frame::Bulk(value) => {
+                    client.propagate_as = Some(Command::LMove(lmove.clone()));
+                    Some(Frame::Bulk(value))
+                }
+                err => Some(err),
+            },
+        }
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        // the timeout is kept in milliseconds by redis
+        if self.timeout * 1000.0 > i64::MAX as f64 {
+            client
+                .write_frame(&Frame::Error(Bytes::from_static(
+                    b"ERR timeout is out of range",
+                )))
+                .await?;
+            return Ok(());
+        }
+        if let Some(response) = self.serve(client) {
+            client.write_frame(&response).await?;
+            return Ok(());
+        }
+        // never block inside a transaction or while loading the AOF
+        if client.reply_capture.is_some() || client.connection.is_none() {
+            client.write_frame(&self.null_reply()).await?;
+            return Ok(());
+        }
+        // a deadline too far to be represented is never reached
+        let deadline = Some(self.timeout)
+            .filter(|timeout| *timeout > 0.0)
+            .and_then(|timeout| Instant::now().checked_add(Duration::from_secs_f64(timeout)));
+        client.block_for_keys(BlockingCommand::Pop(self), deadline);
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use super::CommandParser;
+use crate::client::{Client, REDIS_DIRTY_CAS, REDIS_DIRTY_EXEC, REDIS_MULTI};
+use crate::frame::Frame;
+use crate::shared;
+use bytes::Bytes;
+use std::io::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone)]
+pub struct Multi {}
+
+impl Multi {
+    pub fn from(_frame: &mut CommandParser) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        if client.flags().await & REDIS_MULTI != 0 {
+            client
+                .write_frame(&Frame::Error(Bytes::from_static(
+                    b"ERR MULTI calls can not be nested",
+                )))
+                .await?;
+            return Ok(());
+        }
+
+        client.add_flags(REDIS_MULTI).await;
+        client.write_frame(&shared::ok).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Exec {}
+
+impl Exec {
+    pub fn from(_frame: &mut CommandParser) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let flags = client.flags().await;
+        if flags & REDIS_MULTI == 0 {
+            client
+                .write_frame(&Frame::Error(Bytes::from_static(b"ERR EXEC without MULTI")))
+                .await?;
+            return Ok(());
+        }
+
+        // some command failed to queue, or a watched key was touched
+        if flags & (REDIS_DIRTY_EXEC | REDIS_DIRTY_CAS) != 0 {
+            client.discard_transaction().await;
+            let response = if flags & REDIS_DIRTY_EXEC != 0 {
+                Frame::Error(Bytes::from_static(
+                    b"EXECABORT Transaction discarded because of previous errors.",
+                ))
+            } else {
+                Frame::Null
+            };
+            client.write_frame(&response).await?;
+            return Ok(());
+        }
+
+        // unwatch ASAP, the transaction's own writes must not flag it dirty
+        let commands = std::mem::take(&mut client.mstate);
+        client.discard_transaction().await;
+
+        let mut executed = Vec::with_capacity(commands.len());
+        client.reply_capture = Some(Vec::with_capacity(commands.len()));
+        for cmd in commands {
+            let n_replies = client.reply_capture.as_ref().map_or(0, |r| r.len());
+            let res = Box::pin(client.handle_command(cmd.clone())).await;
+            if let Some(replies) = client.reply_capture.as_mut() {
+                // make sure every command has exactly one reply
+                if replies.len() == n_replies {
+                    let msg = match res {
+                        Err(e) => format!("ERR {}", e),
+                        Ok(()) => "ERR no reply".to_owned(),
+                    };
+                    replies.push(Frame::Error(Bytes::from(msg)));
+                }
+            }
+            let cmd = client.propagate_as.take().unwrap_or(cmd);
+            executed.push((cmd, client.db.index));
+        }
+        let replies = client.reply_capture.take().unwrap_or_default();
+
+        client.write_frame(&Frame::Array(replies)).await?;
+
+        client.propagate_transaction(executed).await;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Discard {}
+
+impl Discard {
+    pub fn from(_frame: &mut CommandParser) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        if client.flags().await & REDIS_MULTI == 0 {
+            client
+                .write_frame(&Frame::Error(Bytes::from_static(
+                    b"ERR DISCARD without MULTI",
+                )))
+                .await?;
+            return Ok(());
+        }
+
+        client.discard_transaction().await;
+        client.write_frame(&shared::ok).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Watch {
+    pub keys: Vec<Bytes>,
+}
+
+impl Watch {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let mut keys = Vec::new();
+        while let Some(key) = frame.next_string()? {
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "WATCH requires at least one key",
+            ));
+        }
+        Ok(Self { keys })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        if client.flags().await & REDIS_MULTI != 0 {
+            client
+                .write_frame(&Frame::Error(Bytes::from_static(
+                    b"ERR WATCH inside MULTI is not allowed",
+                )))
+                .await?;
+            return Ok(());
+        }
+
+        for key in self.keys {
+            client.watch_key(key).await;
+        }
+        client.write_frame(&shared::ok).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Unwatch {}
+
+impl Unwatch {
+    pub fn from(_frame: &mut CommandParser) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        client.unwatch_all_keys();
+        client.remove_flags(REDIS_DIRTY_CAS).await;
+        client.write_frame(&shared::ok).await?;
+        Ok(())
+    }
+}
@@ -14,8 +14,18 @@ impl Ping {
     }
 
     pub async fn apply(self, dst: &mut Client) -> Result<()> {
-        // Write the response back to the client
-        dst.write_frame(&shared::pong).await?;
+        // Write the response back to the client, subscribed RESP2 clients get
+        // a multi bulk reply so that it can't be taken for a published
+        // message
+        if dst.resp < 3 && dst.pubsub_count() > 0 {
+            dst.write_frame(&Frame::Array(vec![
+                Frame::Bulk(Bytes::from_static(b"pong")),
+                shared::empty_bulk,
+            ]))
+            .await?;
+        } else {
+            dst.write_frame(&shared::pong).await?;
+        }
 
         Ok(())
     }
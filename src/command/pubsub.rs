@@ -0,0 +1,218 @@
+use super::CommandParser;
+use crate::client::Client;
+use crate::frame::Frame;
+use bytes::Bytes;
+use std::io::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone)]
+pub struct Subscribe {
+    pub channels: Vec<Bytes>,
+}
+
+impl Subscribe {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let mut channels = Vec::new();
+        while let Some(channel) = frame.next_string()? {
+            channels.push(channel);
+        }
+        if channels.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "SUBSCRIBE requires at least one channel",
+            ));
+        }
+        Ok(Self { channels })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        for channel in self.channels {
+            client.pubsub_subscribe_channel(channel).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Unsubscribe {
+    /// unsubscribe from all the channels if empty
+    pub channels: Vec<Bytes>,
+}
+
+impl Unsubscribe {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let mut channels = Vec::new();
+        while let Some(channel) = frame.next_string()? {
+            channels.push(channel);
+        }
+        Ok(Self { channels })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        if self.channels.is_empty() {
+            return client.pubsub_unsubscribe_all_channels(true).await;
+        }
+        for channel in self.channels {
+            client.pubsub_unsubscribe_channel(channel, true).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PSubscribe {
+    pub patterns: Vec<Bytes>,
+}
+
+impl PSubscribe {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let mut patterns = Vec::new();
+        while let Some(pattern) = frame.next_string()? {
+            patterns.push(pattern);
+        }
+        if patterns.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "PSUBSCRIBE requires at least one pattern",
+            ));
+        }
+        Ok(Self { patterns })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        for pattern in self.patterns {
+            client.pubsub_subscribe_pattern(pattern).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PUnsubscribe {
+    /// unsubscribe from all the patterns if empty
+    pub patterns: Vec<Bytes>,
+}
+
+impl PUnsubscribe {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let mut patterns = Vec::new();
+        while let Some(pattern) = frame.next_string()? {
+            patterns.push(pattern);
+        }
+        Ok(Self { patterns })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        if self.patterns.is_empty() {
+            return client.pubsub_unsubscribe_all_patterns(true).await;
+        }
+        for pattern in self.patterns {
+            client.pubsub_unsubscribe_pattern(pattern, true).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Publish {
+    pub channel: Bytes,
+    pub message: Bytes,
+}
+
+impl Publish {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let channel = frame
+            .next_string()?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "PUBLISH requires a channel"))?;
+        let message = frame
+            .next_string()?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "PUBLISH requires a message"))?;
+        Ok(Self { channel, message })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let limit = client.config.read().await.client_output_buffer_limit_pubsub;
+        let receivers =
+            client
+                .server
+                .pubsub
+                .read()
+                .await
+                .publish(&self.channel, &self.message, &limit);
+
+        client
+            .write_frame(&Frame::Integer(receivers as i64))
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PubSubChannels {
+    pub pattern: Option<Bytes>,
+}
+
+impl PubSubChannels {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let pattern = frame.next_string()?;
+        Ok(Self { pattern })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let channels = client
+            .server
+            .pubsub
+            .read()
+            .await
+            .active_channels(self.pattern.as_ref());
+
+        let response = Frame::Array(channels.into_iter().map(Frame::Bulk).collect());
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PubSubNumSub {
+    pub channels: Vec<Bytes>,
+}
+
+impl PubSubNumSub {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let mut channels = Vec::new();
+        while let Some(channel) = frame.next_string()? {
+            channels.push(channel);
+        }
+        Ok(Self { channels })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = {
+            let pubsub = client.server.pubsub.read().await;
+            let mut array = Vec::with_capacity(2 * self.channels.len());
+            for channel in self.channels {
+                let count = pubsub.num_subscribers(&channel);
+                array.push(Frame::Bulk(channel));
+                array.push(Frame::Integer(count as i64));
+            }
+            Frame::Array(array)
+        };
+
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PubSubNumPat {}
+
+impl PubSubNumPat {
+    pub fn from(_frame: &mut CommandParser) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let count = client.server.pubsub.read().await.num_patterns();
+        client.write_frame(&Frame::Integer(count as i64)).await?;
+        Ok(())
+    }
+}
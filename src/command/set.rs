@@ -1,14 +1,100 @@
-use super::CommandParser;
+use super::{CommandParser, ScanArgs};
 use crate::client::Client;
-use crate::dbms::DictValue;
+use crate::dbms::{DatabaseRef, DictEntry, DictValue};
 use crate::frame::Frame;
-use crate::object::RudisObject;
+use crate::object::{RudisObject, RudisSet};
 use crate::shared;
 use bytes::{Bytes, BytesMut};
-use dashmap::mapref::entry::Entry;
+use rand::seq::IteratorRandom;
+use rand::Rng;
 use std::collections::HashSet;
 use std::io::{Error, ErrorKind, Result};
 
+use super::Command;
+
+fn next_keys(frame: &mut CommandParser, cmd: &str) -> Result<Vec<Bytes>> {
+    let mut keys = Vec::new();
+    while let Some(key) = frame.next_string()? {
+        keys.push(key);
+    }
+    if keys.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} requires a key", cmd),
+        ));
+    }
+    Ok(keys)
+}
+
+/// Apply `f` to the set at `key`, None if there is no such key, Err with the
+/// WRONGTYPE reply if the key doesn't hold a set
+fn with_set<T>(
+    db: &DatabaseRef,
+    key: &Bytes,
+    f: impl FnOnce(&RudisSet) -> T,
+) -> std::result::Result<Option<T>, Frame> {
+    match db.get(key) {
+        Some(entry) => match &entry.value {
+            RudisObject::Set(s) => Ok(Some(f(s))),
+            _ => Err(shared::wrong_type_err),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Reply `f` applied to the set at `key`, `missing` if there is no such key
+fn read_set(
+    db: &DatabaseRef,
+    key: &Bytes,
+    missing: Frame,
+    f: impl FnOnce(&RudisSet) -> Frame,
+) -> Frame {
+    match with_set(db, key, f) {
+        Ok(Some(reply)) => reply,
+        Ok(None) => missing,
+        Err(err) => err,
+    }
+}
+
+/// Reply `f` applied to the set at `key`, which is created if `create`,
+/// otherwise `missing` is replied if there is no such key. The key is deleted
+/// once the set is empty
+fn write_set(
+    db: &DatabaseRef,
+    key: Bytes,
+    create: bool,
+    missing: Frame,
+    f: impl FnOnce(&mut RudisSet) -> Frame,
+) -> Frame {
+    match db.entry(key) {
+        DictEntry::Occupied(mut oe) => {
+            let (reply, empty) = match &mut oe.get_mut().value {
+                RudisObject::Set(s) => (f(s), s.is_empty()),
+                _ => return shared::wrong_type_err,
+            };
+            if empty {
+                oe.remove();
+            }
+            reply
+        }
+        DictEntry::Vacant(ve) => {
+            if !create {
+                return missing;
+            }
+            let mut s = RudisSet::new();
+            let reply = f(&mut s);
+            if !s.is_empty() {
+                ve.insert(DictValue::new(RudisObject::Set(s), None));
+            }
+            reply
+        }
+    }
+}
+
+fn members_reply<'a>(members: impl IntoIterator<Item = &'a Bytes>) -> Frame {
+    Frame::Set(members.into_iter().cloned().map(Frame::Bulk).collect())
+}
+
 #[derive(Debug, Clone)]
 pub struct SAdd {
     pub key: Bytes,
@@ -24,41 +110,24 @@ impl SAdd {
         while let Some(member) = frame.next_string()? {
             members.push(member);
         }
+        if members.is_empty() {
+            return Err(CommandParser::syntax_error());
+        }
         Ok(Self { key, members })
     }
 
     pub async fn apply(self, dst: &mut Client) -> Result<()> {
-        match dst.db.clone().entry(self.key) {
-            Entry::Occupied(mut oe) => match &mut oe.get_mut().value {
-                RudisObject::Set(s) => {
-                    let mut added = 0;
-                    for member in self.members {
-                        if s.insert(member) {
-                            added += 1;
-                        }
-                    }
-                    dst.write_frame(&Frame::new_integer_from(added)).await?;
-                    Ok(())
-                }
-                _ => {
-                    dst.write_frame(&shared::wrong_type_err).await?;
-                    return Err(Error::new(
-                        ErrorKind::InvalidInput,
-                        "WRONGTYPE Operation against a key holding the wrong kind of value",
-                    ));
-                }
-            },
-            Entry::Vacant(ve) => {
-                let mut s = HashSet::new();
-                let len = self.members.len() as i64;
-                for member in self.members {
-                    s.insert(member);
-                }
-                ve.insert(DictValue::new(RudisObject::new_set_from(s), None));
-                dst.write_frame(&Frame::new_integer_from(len)).await?;
-                Ok(())
-            }
-        }
+        let db = dst.db.clone();
+        let response = write_set(&db, self.key, true, Frame::Integer(0), |s| {
+            let added = self
+                .members
+                .into_iter()
+                .filter(|member| s.insert(member.clone()))
+                .count();
+            Frame::Integer(added as i64)
+        });
+        dst.write_frame(&response).await?;
+        Ok(())
     }
 
     pub fn rewrite(&self) -> BytesMut {
@@ -89,35 +158,24 @@ impl SRem {
         while let Some(member) = frame.next_string()? {
             members.push(member);
         }
+        if members.is_empty() {
+            return Err(CommandParser::syntax_error());
+        }
         Ok(Self { key, members })
     }
 
     pub async fn apply(self, dst: &mut Client) -> Result<()> {
-        match dst.db.clone().get_mut(&self.key) {
-            Some(mut entry) => match &mut entry.value {
-                RudisObject::Set(s) => {
-                    let mut removed = 0;
-                    for member in self.members {
-                        if s.remove(&member) {
-                            removed += 1;
-                        }
-                    }
-                    dst.write_frame(&Frame::Integer(removed)).await?;
-                    Ok(())
-                }
-                _ => {
-                    dst.write_frame(&shared::wrong_type_err).await?;
-                    return Err(Error::new(
-                        ErrorKind::InvalidInput,
-                        "WRONGTYPE Operation against a key holding the wrong kind of value",
-                    ));
-                }
-            },
-            None => {
-                dst.write_frame(&Frame::Integer(0)).await?;
-                Ok(())
-            }
-        }
+        let db = dst.db.clone();
+        let response = write_set(&db, self.key, false, Frame::Integer(0), |s| {
+            let removed = self
+                .members
+                .iter()
+                .filter(|member| s.remove(*member))
+                .count();
+            Frame::Integer(removed as i64)
+        });
+        dst.write_frame(&response).await?;
+        Ok(())
     }
 
     pub fn rewrite(&self) -> BytesMut {
@@ -132,3 +190,443 @@ impl SRem {
         out
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct SMembers {
+    pub key: Bytes,
+}
+
+impl SMembers {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("SMEMBERS")?;
+        Ok(Self { key })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_set(&client.db, &self.key, Frame::Set(Vec::new()), |s| {
+            members_reply(s.iter())
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// SISMEMBER and SMISMEMBER
+#[derive(Debug, Clone)]
+pub struct SIsMember {
+    pub key: Bytes,
+    pub members: Vec<Bytes>,
+    /// reply an array, SMISMEMBER
+    pub multi: bool,
+}
+
+impl SIsMember {
+    pub fn from(frame: &mut CommandParser, multi: bool) -> Result<Self> {
+        let key = frame.next_key("SISMEMBER")?;
+        let mut members = vec![];
+        while let Some(member) = frame.next_string()? {
+            members.push(member);
+        }
+        if members.is_empty() || (!multi && members.len() > 1) {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(Self {
+            key,
+            members,
+            multi,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let empty = RudisSet::new();
+        let reply = |s: &RudisSet| {
+            let mut replies = self
+                .members
+                .iter()
+                .map(|member| Frame::Integer(s.contains(member) as i64));
+            if self.multi {
+                Frame::Array(replies.collect())
+            } else {
+                replies.next().unwrap_or(Frame::Integer(0))
+            }
+        };
+        let missing = reply(&empty);
+        let response = read_set(&client.db, &self.key, missing, reply);
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SCard {
+    pub key: Bytes,
+}
+
+impl SCard {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("SCARD")?;
+        Ok(Self { key })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_set(&client.db, &self.key, Frame::Integer(0), |s| {
+            Frame::Integer(s.len() as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SPop {
+    pub key: Bytes,
+    /// reply a set of up to `count` members instead of a single one
+    pub count: Option<i64>,
+}
+
+impl SPop {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("SPOP")?;
+        let count = frame.next_integer()?;
+        if count.is_some_and(|count| count < 0) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "value is out of range, must be positive",
+            ));
+        }
+        Ok(Self { key, count })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let missing = match self.count {
+            Some(_) => Frame::Set(Vec::new()),
+            None => Frame::Null,
+        };
+        let mut popped = Vec::new();
+        let response = write_set(&db, self.key.clone(), false, missing, |s| {
+            let count = self.count.unwrap_or(1) as usize;
+            popped = s
+                .iter()
+                .choose_multiple(&mut rand::thread_rng(), count)
+                .into_iter()
+                .cloned()
+                .collect();
+            for member in &popped {
+                s.remove(member);
+            }
+            match self.count {
+                Some(_) => members_reply(&popped),
+                None => popped.first().cloned().map_or(Frame::Null, Frame::Bulk),
+            }
+        });
+        // the members are picked at random, so the removal is propagated
+        if !popped.is_empty() {
+            client.propagate_as = Some(Command::SRem(SRem {
+                key: self.key,
+                members: popped,
+            }));
+        }
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SScan {
+    pub key: Bytes,
+    pub args: ScanArgs,
+}
+
+impl SScan {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("SSCAN")?;
+        let args = ScanArgs::from(frame, false)?;
+        Ok(Self { key, args })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_set(&client.db, &self.key, ScanArgs::reply(0, Vec::new()), |s| {
+            let (members, next) = shared::scan_page(
+                s.iter().map(|member| (shared::scan_hash(member), member)),
+                self.args.cursor,
+                self.args.count,
+            );
+            let elements = members
+                .into_iter()
+                .filter(|member| self.args.matches(member))
+                .map(|member| Frame::Bulk(member.clone()))
+                .collect();
+            ScanArgs::reply(next.unwrap_or(0), elements)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SRandMember {
+    pub key: Bytes,
+    /// reply an array of up to `count` distinct members, or of exactly
+    /// `-count` members that may repeat if negative
+    pub count: Option<i64>,
+}
+
+impl SRandMember {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("SRANDMEMBER")?;
+        let count = frame.next_integer()?;
+        Ok(Self { key, count })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let missing = match self.count {
+            Some(_) => Frame::new_array(),
+            None => Frame::Null,
+        };
+        let response = read_set(&client.db, &self.key, missing, |s| {
+            let mut rng = rand::thread_rng();
+            let members: Vec<&Bytes> = match self.count {
+                None => {
+                    return s
+                        .iter()
+                        .choose(&mut rng)
+                        .map_or(Frame::Null, |member| Frame::Bulk(member.clone()))
+                }
+                Some(count) if count >= 0 => s.iter().choose_multiple(&mut rng, count as usize),
+                Some(count) => {
+                    let all: Vec<&Bytes> = s.iter().collect();
+                    (0..count.unsigned_abs())
+                        .map(|_| all[rng.gen_range(0..all.len())])
+                        .collect()
+                }
+            };
+            Frame::Array(members.into_iter().cloned().map(Frame::Bulk).collect())
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SMove {
+    pub src: Bytes,
+    pub dst: Bytes,
+    pub member: Bytes,
+}
+
+impl SMove {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let src = frame.next_key("SMOVE")?;
+        let dst = frame.next_key("SMOVE")?;
+        let member = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self { src, dst, member })
+    }
+
+    fn move_member(&self, db: &DatabaseRef) -> Frame {
+        // check both the types before anything is modified
+        let found = match with_set(db, &self.src, |s| s.contains(&self.member)) {
+            Ok(found) => found.unwrap_or(false),
+            Err(err) => return err,
+        };
+        if let Err(err) = with_set(db, &self.dst, |_| ()) {
+            return err;
+        }
+        if !found {
+            return Frame::Integer(0);
+        }
+        if self.src == self.dst {
+            return Frame::Integer(1);
+        }
+        write_set(db, self.src.clone(), false, Frame::Integer(0), |s| {
+            s.remove(&self.member);
+            Frame::Integer(1)
+        });
+        write_set(db, self.dst.clone(), true, Frame::Integer(0), |s| {
+            s.insert(self.member.clone());
+            Frame::Integer(1)
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = self.move_member(&client.db.clone());
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([b"SMOVE" as &[u8], &self.src, &self.dst, &self.member])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+impl SetOp {
+    /// Compute the intersection, union or difference of the sets, the
+    /// missing keys are empty sets
+    fn compute(
+        self,
+        db: &DatabaseRef,
+        keys: &[Bytes],
+    ) -> std::result::Result<HashSet<Bytes>, Frame> {
+        let mut result: Option<HashSet<Bytes>> = None;
+        for key in keys {
+            let source = with_set(db, key, |s| match (self, result.as_mut()) {
+                (_, None) => Some(s.iter().cloned().collect()),
+                (SetOp::Inter, Some(result)) => {
+                    result.retain(|member| s.contains(member));
+                    None
+                }
+                (SetOp::Union, Some(result)) => {
+                    result.extend(s.iter().cloned());
+                    None
+                }
+                (SetOp::Diff, Some(result)) => {
+                    result.retain(|member| !s.contains(member));
+                    None
+                }
+            })?;
+            match source {
+                Some(Some(first)) => result = Some(first),
+                Some(None) => {}
+                // a missing key empties the intersection, and is nothing to
+                // add to or remove from the union and the difference
+                None if self == SetOp::Inter => result = Some(HashSet::new()),
+                None => {
+                    result.get_or_insert_with(HashSet::new);
+                }
+            }
+        }
+        Ok(result.unwrap_or_default())
+    }
+}
+
+/// SINTER, SUNION and SDIFF
+#[derive(Debug, Clone)]
+pub struct SetOpQuery {
+    pub op: SetOp,
+    pub keys: Vec<Bytes>,
+}
+
+impl SetOpQuery {
+    pub fn from(frame: &mut CommandParser, op: SetOp) -> Result<Self> {
+        let keys = next_keys(frame, "SINTER")?;
+        Ok(Self { op, keys })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = match self.op.compute(&client.db, &self.keys) {
+            Ok(result) => members_reply(&result),
+            Err(err) => err,
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// SINTERSTORE, SUNIONSTORE and SDIFFSTORE
+#[derive(Debug, Clone)]
+pub struct SetOpStore {
+    pub op: SetOp,
+    pub dst: Bytes,
+    pub keys: Vec<Bytes>,
+}
+
+impl SetOpStore {
+    pub fn from(frame: &mut CommandParser, op: SetOp) -> Result<Self> {
+        let dst = frame.next_key("SINTERSTORE")?;
+        let keys = next_keys(frame, "SINTERSTORE")?;
+        Ok(Self { op, dst, keys })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = match self.op.compute(&db, &self.keys) {
+            Ok(result) => {
+                let len = result.len();
+                if result.is_empty() {
+                    db.remove(&self.dst);
+                } else {
+                    db.insert(self.dst, RudisObject::new_set_from(result), None);
+                }
+                Frame::Integer(len as i64)
+            }
+            Err(err) => err,
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let cmd = match self.op {
+            SetOp::Inter => b"SINTERSTORE" as &[u8],
+            SetOp::Union => b"SUNIONSTORE",
+            SetOp::Diff => b"SDIFFSTORE",
+        };
+        shared::rewrite_args(
+            [cmd, &self.dst]
+                .into_iter()
+                .chain(self.keys.iter().map(|k| &k[..])),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SInterCard {
+    pub keys: Vec<Bytes>,
+    /// stop counting at this cardinality, 0 for no limit
+    pub limit: usize,
+}
+
+impl SInterCard {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let numkeys = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        if numkeys <= 0 || numkeys as usize > frame.remaining() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "numkeys should be greater than 0",
+            ));
+        }
+        let mut keys = Vec::with_capacity(numkeys as usize);
+        for _ in 0..numkeys {
+            keys.push(
+                frame
+                    .next_string()?
+                    .ok_or_else(CommandParser::syntax_error)?,
+            );
+        }
+        let limit = match frame.next_string()? {
+            Some(option) if option.eq_ignore_ascii_case(b"limit") => {
+                match frame
+                    .next_integer()?
+                    .ok_or_else(CommandParser::syntax_error)?
+                {
+                    limit if limit >= 0 => limit as usize,
+                    _ => return Err(CommandParser::syntax_error()),
+                }
+            }
+            Some(_) => return Err(CommandParser::syntax_error()),
+            None => 0,
+        };
+        Ok(Self { keys, limit })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = match SetOp::Inter.compute(&client.db, &self.keys) {
+            Ok(result) if self.limit > 0 => Frame::Integer(result.len().min(self.limit) as i64),
+            Ok(result) => Frame::Integer(result.len() as i64),
+            Err(err) => err,
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
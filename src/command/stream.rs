@@ -0,0 +1,2167 @@
+use super::{Command, CommandParser};
+use crate::blocked::BlockingCommand;
+use crate::client::Client;
+use crate::dbms::{DatabaseRef, DictEntry, DictValue};
+use crate::frame::Frame;
+use crate::object::{RudisObject, RudisStream, StreamGroup, StreamId};
+use crate::shared;
+use bytes::{Bytes, BytesMut};
+use std::io::Result;
+use std::ops::Bound;
+use tokio::time::{Duration, Instant};
+
+fn next_arg(frame: &mut CommandParser) -> Result<Bytes> {
+    frame.next_string()?.ok_or_else(CommandParser::syntax_error)
+}
+
+fn parse_u64(s: &[u8]) -> Result<u64> {
+    std::str::from_utf8(s)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(CommandParser::syntax_error)
+}
+
+fn next_u64(frame: &mut CommandParser) -> Result<u64> {
+    parse_u64(&next_arg(frame)?)
+}
+
+/// A count where the negative ones are 0
+fn next_count(frame: &mut CommandParser) -> Result<usize> {
+    let count = frame
+        .next_integer()?
+        .ok_or_else(CommandParser::syntax_error)?;
+    Ok(count.max(0) as usize)
+}
+
+/// A strict ID, `ms-seq` or `ms`
+fn parse_id(s: &[u8]) -> Result<StreamId> {
+    StreamId::parse(s, 0).ok_or_else(CommandParser::syntax_error)
+}
+
+/// Bound of a range of IDs, `-`, `+`, an ID or an ID excluded with `(`, the
+/// missing sequence being the first or last one of the millisecond. None if
+/// the excluded ID leaves nothing in the range
+fn parse_range_bound(s: &[u8], start: bool) -> Result<Option<StreamId>> {
+    match s {
+        b"-" => return Ok(Some(StreamId::MIN)),
+        b"+" => return Ok(Some(StreamId::MAX)),
+        _ => {}
+    }
+    let missing_seq = if start { 0 } else { u64::MAX };
+    match s.strip_prefix(b"(") {
+        Some(id) => {
+            let id = StreamId::parse(id, missing_seq).ok_or_else(CommandParser::syntax_error)?;
+            Ok(if start { id.incr() } else { id.decr() })
+        }
+        None => StreamId::parse(s, missing_seq)
+            .map(Some)
+            .ok_or_else(CommandParser::syntax_error),
+    }
+}
+
+fn error(msg: &str) -> Frame {
+    Frame::Error(Bytes::from(msg.to_string()))
+}
+
+fn no_group_err(key: &Bytes, group: &Bytes) -> Frame {
+    error(&format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group)
+    ))
+}
+
+fn no_key_group_err(key: &Bytes, group: &Bytes) -> Frame {
+    error(&format!(
+        "NOGROUP No such consumer group '{}' for key name '{}'",
+        String::from_utf8_lossy(group),
+        String::from_utf8_lossy(key)
+    ))
+}
+
+/// Apply `f` to the stream at `key`, None if there is no such key, Err with
+/// the WRONGTYPE reply if the key doesn't hold a stream
+fn with_stream<T>(
+    db: &DatabaseRef,
+    key: &Bytes,
+    f: impl FnOnce(&RudisStream) -> T,
+) -> std::result::Result<Option<T>, Frame> {
+    match db.get(key) {
+        Some(entry) => match &entry.value {
+            RudisObject::Stream(s) => Ok(Some(f(s))),
+            _ => Err(shared::wrong_type_err),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Reply `f` applied to the stream at `key`, `missing` if there is no such key
+fn read_stream(
+    db: &DatabaseRef,
+    key: &Bytes,
+    missing: Frame,
+    f: impl FnOnce(&RudisStream) -> Frame,
+) -> Frame {
+    match with_stream(db, key, f) {
+        Ok(Some(reply)) => reply,
+        Ok(None) => missing,
+        Err(err) => err,
+    }
+}
+
+/// Reply `f` applied to the stream at `key`, which is created if `create`
+/// and `f` doesn't fail, otherwise `missing` is replied if there is no such
+/// key. Unlike the other types, the stream stays once it is empty
+fn write_stream(
+    db: &DatabaseRef,
+    key: Bytes,
+    create: bool,
+    missing: Frame,
+    f: impl FnOnce(&mut RudisStream) -> Frame,
+) -> Frame {
+    match db.entry(key) {
+        DictEntry::Occupied(mut oe) => match &mut oe.get_mut().value {
+            RudisObject::Stream(s) => f(s),
+            _ => shared::wrong_type_err,
+        },
+        DictEntry::Vacant(ve) => {
+            if !create {
+                return missing;
+            }
+            let mut s = RudisStream::new();
+            let reply = f(&mut s);
+            if !matches!(reply, Frame::Error(_)) {
+                ve.insert(DictValue::new(RudisObject::Stream(s), None));
+            }
+            reply
+        }
+    }
+}
+
+/// Reply `f` applied to the stream at `key` and its group, the NOGROUP error
+/// if either doesn't exist
+fn write_group(
+    db: &DatabaseRef,
+    key: &Bytes,
+    name: &Bytes,
+    f: impl FnOnce(&mut RudisStream, &mut StreamGroup) -> Frame,
+) -> Frame {
+    let missing = no_group_err(key, name);
+    write_stream(db, key.clone(), false, missing.clone(), |s| {
+        // taken out to be updated along with the entries
+        let mut group = match s.groups.remove(name) {
+            Some(group) => group,
+            None => return missing,
+        };
+        let reply = f(s, &mut group);
+        s.groups.insert(name.clone(), group);
+        reply
+    })
+}
+
+fn id_reply(id: &StreamId) -> Frame {
+    Frame::Bulk(id.to_bytes())
+}
+
+fn entry_reply(id: &StreamId, fields: &[(Bytes, Bytes)]) -> Frame {
+    Frame::Array(vec![
+        id_reply(id),
+        Frame::Array(
+            fields
+                .iter()
+                .flat_map(|(field, value)| [Frame::Bulk(field.clone()), Frame::Bulk(value.clone())])
+                .collect(),
+        ),
+    ])
+}
+
+/// The entries read from each stream, a map for the RESP3 clients
+fn streams_reply(resp: u8, streams: Vec<(Bytes, Frame)>) -> Frame {
+    if resp >= 3 {
+        Frame::Map(
+            streams
+                .into_iter()
+                .map(|(key, entries)| (Frame::Bulk(key), entries))
+                .collect(),
+        )
+    } else {
+        Frame::Array(
+            streams
+                .into_iter()
+                .map(|(key, entries)| Frame::Array(vec![Frame::Bulk(key), entries]))
+                .collect(),
+        )
+    }
+}
+
+fn rewrite(args: &[Bytes]) -> BytesMut {
+    shared::rewrite_args(args.iter().map(|arg| &arg[..]))
+}
+
+/// `MAXLEN|MINID [=|~] threshold [LIMIT count]` of XADD and XTRIM
+#[derive(Debug, Clone, Default)]
+pub struct StreamTrim {
+    pub maxlen: Option<u64>,
+    pub minid: Option<StreamId>,
+    /// `~`, the entries are still trimmed exactly, but at most `limit` of
+    /// them
+    pub approx: bool,
+    pub limit: Option<u64>,
+}
+
+impl StreamTrim {
+    /// Parse the option if it is a trim one, false otherwise
+    fn parse(&mut self, option: &[u8], frame: &mut CommandParser) -> Result<bool> {
+        let maxlen = if option.eq_ignore_ascii_case(b"maxlen") {
+            true
+        } else if option.eq_ignore_ascii_case(b"minid") {
+            false
+        } else if option.eq_ignore_ascii_case(b"limit") && self.is_set() {
+            self.limit = Some(next_u64(frame)?).filter(|limit| *limit > 0);
+            return Ok(true);
+        } else {
+            return Ok(false);
+        };
+        let mut threshold = next_arg(frame)?;
+        match &threshold[..] {
+            b"~" => self.approx = true,
+            b"=" => self.approx = false,
+            _ => {}
+        }
+        if &threshold[..] == b"~" || &threshold[..] == b"=" {
+            threshold = next_arg(frame)?;
+        }
+        if maxlen {
+            self.maxlen = Some(parse_u64(&threshold)?);
+            self.minid = None;
+        } else {
+            self.minid = Some(parse_id(&threshold)?);
+            self.maxlen = None;
+        }
+        Ok(true)
+    }
+
+    fn is_set(&self) -> bool {
+        self.maxlen.is_some() || self.minid.is_some()
+    }
+
+    /// LIMIT is only allowed with `~`
+    fn check(&self) -> Result<()> {
+        if self.limit.is_some() && !self.approx {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(())
+    }
+
+    fn apply(&self, s: &mut RudisStream) -> u64 {
+        if !self.is_set() {
+            return 0;
+        }
+        s.trim(self.maxlen, self.minid, self.limit.filter(|_| self.approx))
+    }
+
+    fn extend_args(&self, args: &mut Vec<Bytes>) {
+        let threshold = match (self.maxlen, self.minid) {
+            (Some(maxlen), _) => {
+                args.push(Bytes::from_static(b"MAXLEN"));
+                maxlen.to_string()
+            }
+            (None, Some(minid)) => {
+                args.push(Bytes::from_static(b"MINID"));
+                minid.to_string()
+            }
+            (None, None) => return,
+        };
+        if self.approx {
+            args.push(Bytes::from_static(b"~"));
+        }
+        args.push(Bytes::from(threshold));
+        if let Some(limit) = self.limit {
+            args.push(Bytes::from_static(b"LIMIT"));
+            args.push(Bytes::from(limit.to_string()));
+        }
+    }
+}
+
+/// ID argument of XADD
+#[derive(Debug, Clone, Copy)]
+pub enum XAddId {
+    /// `*`
+    Auto,
+    /// `ms-*`, the sequence is generated
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+#[derive(Debug, Clone)]
+pub struct XAdd {
+    pub key: Bytes,
+    pub nomkstream: bool,
+    pub trim: StreamTrim,
+    pub id: XAddId,
+    pub fields: Vec<(Bytes, Bytes)>,
+}
+
+impl XAdd {
+    /// `XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]]
+    /// *|id field value [field value ...]`
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("XADD")?;
+        let mut nomkstream = false;
+        let mut trim = StreamTrim::default();
+        let id = loop {
+            let arg = next_arg(frame)?;
+            if arg.eq_ignore_ascii_case(b"nomkstream") {
+                nomkstream = true;
+            } else if !trim.parse(&arg, frame)? {
+                break arg;
+            }
+        };
+        trim.check()?;
+        let id = if &id[..] == b"*" {
+            XAddId::Auto
+        } else if let Some(ms) = id.strip_suffix(b"-*") {
+            XAddId::AutoSeq(parse_u64(ms)?)
+        } else {
+            XAddId::Explicit(parse_id(&id)?)
+        };
+        let mut fields = Vec::new();
+        while let Some(field) = frame.next_string()? {
+            fields.push((field, next_arg(frame)?));
+        }
+        if fields.is_empty() {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(Self {
+            key,
+            nomkstream,
+            trim,
+            id,
+            fields,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let now = shared::now_ms();
+        let mut added = None;
+        let response = write_stream(&db, self.key.clone(), !self.nomkstream, Frame::Null, |s| {
+            let id = match self.id {
+                XAddId::Explicit(id) if id.is_zero() => {
+                    return error("ERR The ID specified in XADD must be greater than 0-0")
+                }
+                XAddId::Explicit(id) => Some(id).filter(|id| *id > s.last_id),
+                XAddId::AutoSeq(ms) => s.next_id(now, Some(ms)),
+                XAddId::Auto if s.last_id == StreamId::MAX => return error(
+                    "ERR The stream has exhausted the last possible ID, unable to add more items",
+                ),
+                XAddId::Auto => s.next_id(now, None),
+            };
+            let id = match id {
+                Some(id) => id,
+                None => {
+                    return error(
+                        "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+                    )
+                }
+            };
+            s.insert(id, self.fields.clone());
+            self.trim.apply(s);
+            added = Some(id);
+            id_reply(&id)
+        });
+        if let Some(id) = added {
+            // every reader gets the new entries
+            db.signal_key_as_ready_to_all(&self.key);
+            client.propagate_as = Some(Command::XAdd(XAdd {
+                id: XAddId::Explicit(id),
+                ..self
+            }));
+        }
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let mut args = vec![Bytes::from_static(b"XADD"), self.key.clone()];
+        if self.nomkstream {
+            args.push(Bytes::from_static(b"NOMKSTREAM"));
+        }
+        self.trim.extend_args(&mut args);
+        args.push(match self.id {
+            XAddId::Auto => Bytes::from_static(b"*"),
+            XAddId::AutoSeq(ms) => Bytes::from(format!("{}-*", ms)),
+            XAddId::Explicit(id) => id.to_bytes(),
+        });
+        for (field, value) in &self.fields {
+            args.push(field.clone());
+            args.push(value.clone());
+        }
+        rewrite(&args)
+    }
+}
+
+/// XRANGE and XREVRANGE
+#[derive(Debug, Clone)]
+pub struct XRange {
+    pub key: Bytes,
+    /// None if the range is empty
+    pub range: Option<(StreamId, StreamId)>,
+    pub count: Option<usize>,
+    pub rev: bool,
+}
+
+impl XRange {
+    /// `XRANGE key start end [COUNT count]`, XREVRANGE takes the end first
+    pub fn from(frame: &mut CommandParser, rev: bool) -> Result<Self> {
+        let key = frame.next_key(if rev { "XREVRANGE" } else { "XRANGE" })?;
+        let first = next_arg(frame)?;
+        let second = next_arg(frame)?;
+        let (start, end) = if rev {
+            (second, first)
+        } else {
+            (first, second)
+        };
+        let start = parse_range_bound(&start, true)?;
+        let end = parse_range_bound(&end, false)?;
+        let count = match frame.next_string()? {
+            Some(option) if option.eq_ignore_ascii_case(b"count") => Some(next_count(frame)?),
+            Some(_) => return Err(CommandParser::syntax_error()),
+            None => None,
+        };
+        if frame.has_next() {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(Self {
+            key,
+            range: start.zip(end),
+            count,
+            rev,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_stream(&client.db, &self.key, Frame::new_array(), |s| {
+            let (start, end) = match self.range {
+                Some(range) => range,
+                None => return Frame::new_array(),
+            };
+            let count = self.count.unwrap_or(usize::MAX);
+            let entries = s.range(start, end);
+            let entries: Vec<Frame> = if self.rev {
+                entries
+                    .rev()
+                    .take(count)
+                    .map(|(id, fields)| entry_reply(id, fields))
+                    .collect()
+            } else {
+                entries
+                    .take(count)
+                    .map(|(id, fields)| entry_reply(id, fields))
+                    .collect()
+            };
+            Frame::Array(entries)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct XLen {
+    pub key: Bytes,
+}
+
+impl XLen {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("XLEN")?;
+        Ok(Self { key })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_stream(&client.db, &self.key, Frame::Integer(0), |s| {
+            Frame::Integer(s.len() as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct XDel {
+    pub key: Bytes,
+    pub ids: Vec<StreamId>,
+}
+
+impl XDel {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("XDEL")?;
+        let mut ids = vec![parse_id(&next_arg(frame)?)?];
+        while let Some(id) = frame.next_string()? {
+            ids.push(parse_id(&id)?);
+        }
+        Ok(Self { key, ids })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = write_stream(&client.db, self.key, false, Frame::Integer(0), |s| {
+            let deleted = self.ids.iter().filter(|id| s.remove(id)).count();
+            Frame::Integer(deleted as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let mut args = vec![Bytes::from_static(b"XDEL"), self.key.clone()];
+        args.extend(self.ids.iter().map(StreamId::to_bytes));
+        rewrite(&args)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct XTrim {
+    pub key: Bytes,
+    pub trim: StreamTrim,
+}
+
+impl XTrim {
+    /// `XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]`
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("XTRIM")?;
+        let mut trim = StreamTrim::default();
+        while let Some(option) = frame.next_string()? {
+            if !trim.parse(&option, frame)? {
+                return Err(CommandParser::syntax_error());
+            }
+        }
+        if !trim.is_set() {
+            return Err(CommandParser::syntax_error());
+        }
+        trim.check()?;
+        Ok(Self { key, trim })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = write_stream(&client.db, self.key, false, Frame::Integer(0), |s| {
+            Frame::Integer(self.trim.apply(s) as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let mut args = vec![Bytes::from_static(b"XTRIM"), self.key.clone()];
+        self.trim.extend_args(&mut args);
+        rewrite(&args)
+    }
+}
+
+/// ID argument of XREAD and XREADGROUP
+#[derive(Debug, Clone, Copy)]
+pub enum XReadId {
+    /// `$`, the entries added after the call
+    Last,
+    /// `>`, the entries never delivered to the group
+    New,
+    /// the entries after the ID, the pending ones of the consumer for
+    /// XREADGROUP
+    After(StreamId),
+}
+
+/// XREAD and XREADGROUP, served at once if one of the streams has entries
+/// to read, otherwise the client blocks until entries are added or the
+/// timeout
+#[derive(Debug, Clone)]
+pub struct XRead {
+    /// the group and the consumer of XREADGROUP
+    pub group: Option<(Bytes, Bytes)>,
+    pub count: Option<usize>,
+    /// milliseconds to block for, 0 to block forever
+    pub block: Option<u64>,
+    pub noack: bool,
+    pub keys: Vec<Bytes>,
+    pub ids: Vec<XReadId>,
+}
+
+impl XRead {
+    /// `XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id
+    /// [id ...]` and `XREADGROUP GROUP group consumer [COUNT count] [BLOCK
+    /// milliseconds] [NOACK] STREAMS key [key ...] id [id ...]`
+    pub fn from(frame: &mut CommandParser, group: bool) -> Result<Self> {
+        let group = if group {
+            let option = next_arg(frame)?;
+            if !option.eq_ignore_ascii_case(b"group") {
+                return Err(CommandParser::syntax_error());
+            }
+            Some((next_arg(frame)?, next_arg(frame)?))
+        } else {
+            None
+        };
+        let mut count = None;
+        let mut block = None;
+        let mut noack = false;
+        loop {
+            let option = next_arg(frame)?;
+            if option.eq_ignore_ascii_case(b"count") {
+                count = Some(next_count(frame)?).filter(|count| *count > 0);
+            } else if option.eq_ignore_ascii_case(b"block") {
+                block = Some(next_u64(frame)?);
+            } else if option.eq_ignore_ascii_case(b"noack") && group.is_some() {
+                noack = true;
+            } else if option.eq_ignore_ascii_case(b"streams") {
+                break;
+            } else {
+                return Err(CommandParser::syntax_error());
+            }
+        }
+
+        let remaining = frame.remaining();
+        if remaining == 0 || !remaining.is_multiple_of(2) {
+            return Err(CommandParser::syntax_error());
+        }
+        let mut keys = Vec::with_capacity(remaining / 2);
+        for _ in 0..remaining / 2 {
+            keys.push(next_arg(frame)?);
+        }
+        let mut ids = Vec::with_capacity(keys.len());
+        while let Some(id) = frame.next_string()? {
+            ids.push(match &id[..] {
+                b"$" if group.is_none() => XReadId::Last,
+                b">" if group.is_some() => XReadId::New,
+                _ => XReadId::After(parse_id(&id)?),
+            });
+        }
+        Ok(Self {
+            group,
+            count,
+            block,
+            noack,
+            keys,
+            ids,
+        })
+    }
+
+    /// The keys the client blocks on
+    pub fn keys(&self) -> &[Bytes] {
+        &self.keys
+    }
+
+    /// Read the streams and reply, None if there is nothing to read yet
+    pub fn serve(&self, client: &mut Client) -> Option<Frame> {
+        let reply = match &self.group {
+            Some((group, consumer)) => self.read_group(&client.db.clone(), group, consumer),
+            None => self.read(&client.db),
+        };
+        let streams = match reply {
+            Ok(streams) => streams?,
+            Err(err) => return Some(err),
+        };
+        if self.group.is_some() {
+            client.propagate_as = Some(Command::XReadGroup(self.clone()));
+        }
+        Some(streams_reply(client.resp, streams))
+    }
+
+    fn read(&self, db: &DatabaseRef) -> std::result::Result<Option<Vec<(Bytes, Frame)>>, Frame> {
+        let count = self.count.unwrap_or(usize::MAX);
+        let mut streams = Vec::new();
+        for (key, id) in self.keys.iter().zip(&self.ids) {
+            let after = match id {
+                XReadId::After(id) => *id,
+                _ => continue,
+            };
+            let entries = with_stream(db, key, |s| {
+                s.entries
+                    .range((Bound::Excluded(after), Bound::Unbounded))
+                    .take(count)
+                    .map(|(id, fields)| entry_reply(id, fields))
+                    .collect::<Vec<_>>()
+            })?;
+            if let Some(entries) = entries.filter(|entries| !entries.is_empty()) {
+                streams.push((key.clone(), Frame::Array(entries)));
+            }
+        }
+        Ok((!streams.is_empty()).then_some(streams))
+    }
+
+    fn read_group(
+        &self,
+        db: &DatabaseRef,
+        group: &Bytes,
+        consumer: &Bytes,
+    ) -> std::result::Result<Option<Vec<(Bytes, Frame)>>, Frame> {
+        // the groups are all checked before anything is delivered
+        for key in &self.keys {
+            let exists = with_stream(db, key, |s| s.groups.contains_key(group))?;
+            if exists != Some(true) {
+                return Err(error(&format!(
+                    "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                    String::from_utf8_lossy(key),
+                    String::from_utf8_lossy(group)
+                )));
+            }
+        }
+
+        let now = shared::now_ms();
+        let count = self.count.unwrap_or(usize::MAX);
+        let mut streams = Vec::new();
+        for (key, id) in self.keys.iter().zip(&self.ids) {
+            let mut entries = Vec::new();
+            let reply = write_group(db, key, group, |s, g| {
+                g.consumer(consumer, now).seen_time = now;
+                match id {
+                    XReadId::After(after) => {
+                        // the history of the consumer, the deleted entries
+                        // are replied as nil
+                        let pending: Vec<StreamId> = g.consumers[consumer]
+                            .pending
+                            .range((Bound::Excluded(*after), Bound::Unbounded))
+                            .take(count)
+                            .copied()
+                            .collect();
+                        for id in pending {
+                            if let Some(p) = g.pel.get_mut(&id) {
+                                p.delivery_time = now;
+                                p.delivery_count += 1;
+                            }
+                            entries.push(match s.entries.get(&id) {
+                                Some(fields) => entry_reply(&id, fields),
+                                None => Frame::Array(vec![id_reply(&id), Frame::Null]),
+                            });
+                        }
+                    }
+                    _ => {
+                        let new: Vec<StreamId> = s
+                            .entries
+                            .range((Bound::Excluded(g.last_id), Bound::Unbounded))
+                            .take(count)
+                            .map(|(id, _)| *id)
+                            .collect();
+                        for id in new {
+                            s.advance_group(g, id);
+                            if !self.noack {
+                                g.assign(id, consumer, now).delivery_count = 1;
+                            }
+                            entries.push(entry_reply(&id, &s.entries[&id]));
+                        }
+                        if !entries.is_empty() {
+                            g.consumer(consumer, now).active_time = Some(now);
+                        }
+                    }
+                }
+                Frame::Null
+            });
+            if let Frame::Error(_) = reply {
+                return Err(reply);
+            }
+            if !entries.is_empty() || matches!(id, XReadId::After(_)) {
+                streams.push((key.clone(), Frame::Array(entries)));
+            }
+        }
+        Ok((!streams.is_empty()).then_some(streams))
+    }
+
+    pub async fn apply(mut self, client: &mut Client) -> Result<()> {
+        // `$` reads the entries added from now on
+        for (key, id) in self.keys.iter().zip(self.ids.iter_mut()) {
+            if let XReadId::Last = id {
+                match with_stream(&client.db, key, |s| s.last_id) {
+                    Ok(last_id) => *id = XReadId::After(last_id.unwrap_or_default()),
+                    Err(err) => {
+                        client.write_frame(&err).await?;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        if let Some(response) = self.serve(client) {
+            client.write_frame(&response).await?;
+            return Ok(());
+        }
+        // never block inside a transaction or while loading the AOF
+        let block = match self.block {
+            Some(block) if client.reply_capture.is_none() && client.connection.is_some() => block,
+            _ => {
+                client.write_frame(&Frame::Null).await?;
+                return Ok(());
+            }
+        };
+        // a deadline too far to be represented is never reached
+        let deadline = Some(block)
+            .filter(|block| *block > 0)
+            .and_then(|block| Instant::now().checked_add(Duration::from_millis(block)));
+        client.block_for_keys(BlockingCommand::Read(self), deadline);
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let mut args = match &self.group {
+            Some((group, consumer)) => vec![
+                Bytes::from_static(b"XREADGROUP"),
+                Bytes::from_static(b"GROUP"),
+                group.clone(),
+                consumer.clone(),
+            ],
+            None => vec![Bytes::from_static(b"XREAD")],
+        };
+        if let Some(count) = self.count {
+            args.push(Bytes::from_static(b"COUNT"));
+            args.push(Bytes::from(count.to_string()));
+        }
+        if self.noack {
+            args.push(Bytes::from_static(b"NOACK"));
+        }
+        args.push(Bytes::from_static(b"STREAMS"));
+        args.extend(self.keys.iter().cloned());
+        args.extend(self.ids.iter().map(|id| match id {
+            XReadId::Last => Bytes::from_static(b"$"),
+            XReadId::New => Bytes::from_static(b">"),
+            XReadId::After(id) => id.to_bytes(),
+        }));
+        rewrite(&args)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum XGroupOp {
+    /// the ID is None for `$`, the last entry
+    Create {
+        id: Option<StreamId>,
+        mkstream: bool,
+        entries_read: Option<u64>,
+    },
+    SetId {
+        id: Option<StreamId>,
+        entries_read: Option<u64>,
+    },
+    Destroy,
+    CreateConsumer(Bytes),
+    DelConsumer(Bytes),
+}
+
+#[derive(Debug, Clone)]
+pub struct XGroup {
+    pub key: Bytes,
+    pub group: Bytes,
+    pub op: XGroupOp,
+}
+
+impl XGroup {
+    fn next_group_id(frame: &mut CommandParser) -> Result<Option<StreamId>> {
+        let id = next_arg(frame)?;
+        if &id[..] == b"$" {
+            return Ok(None);
+        }
+        parse_id(&id).map(Some)
+    }
+
+    /// `ENTRIESREAD entries-read`, -1 when unknown
+    fn parse_entries_read(frame: &mut CommandParser) -> Result<Option<u64>> {
+        match frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?
+        {
+            -1 => Ok(None),
+            n if n >= 0 => Ok(Some(n as u64)),
+            _ => Err(CommandParser::syntax_error()),
+        }
+    }
+
+    /// `XGROUP CREATE key group id|$ [MKSTREAM] [ENTRIESREAD entries-read]`,
+    /// `XGROUP SETID key group id|$ [ENTRIESREAD entries-read]`, `XGROUP
+    /// DESTROY key group`, `XGROUP CREATECONSUMER key group consumer` and
+    /// `XGROUP DELCONSUMER key group consumer`
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let subcommand = next_arg(frame)?.to_ascii_lowercase();
+        let key = frame.next_key("XGROUP")?;
+        let group = next_arg(frame)?;
+        let op = match &subcommand[..] {
+            b"create" | b"setid" => {
+                let id = Self::next_group_id(frame)?;
+                let create = &subcommand[..] == b"create";
+                let mut mkstream = false;
+                let mut entries_read = None;
+                while let Some(option) = frame.next_string()? {
+                    if option.eq_ignore_ascii_case(b"mkstream") && create {
+                        mkstream = true;
+                    } else if option.eq_ignore_ascii_case(b"entriesread") {
+                        entries_read = Self::parse_entries_read(frame)?;
+                    } else {
+                        return Err(CommandParser::syntax_error());
+                    }
+                }
+                if create {
+                    XGroupOp::Create {
+                        id,
+                        mkstream,
+                        entries_read,
+                    }
+                } else {
+                    XGroupOp::SetId { id, entries_read }
+                }
+            }
+            b"destroy" => XGroupOp::Destroy,
+            b"createconsumer" => XGroupOp::CreateConsumer(next_arg(frame)?),
+            b"delconsumer" => XGroupOp::DelConsumer(next_arg(frame)?),
+            _ => return Err(CommandParser::syntax_error()),
+        };
+        if frame.has_next() {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(Self { key, group, op })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let now = shared::now_ms();
+        let missing = error(
+            "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.",
+        );
+        let create = matches!(self.op, XGroupOp::Create { mkstream: true, .. });
+        let mut resolved = None;
+        let response = write_stream(&db, self.key.clone(), create, missing, |s| {
+            let group = s.groups.get_mut(&self.group);
+            match (&self.op, group) {
+                (XGroupOp::Create { .. }, Some(_)) => {
+                    error("BUSYGROUP Consumer Group name already exists")
+                }
+                (
+                    XGroupOp::Create {
+                        id, entries_read, ..
+                    },
+                    None,
+                ) => {
+                    let id = id.unwrap_or(s.last_id);
+                    resolved = Some(id);
+                    s.groups
+                        .insert(self.group.clone(), StreamGroup::new(id, *entries_read));
+                    shared::ok
+                }
+                (XGroupOp::SetId { id, entries_read }, Some(group)) => {
+                    let id = id.unwrap_or(s.last_id);
+                    resolved = Some(id);
+                    group.last_id = id;
+                    group.entries_read = *entries_read;
+                    shared::ok
+                }
+                (XGroupOp::Destroy, group) => {
+                    let destroyed = group.is_some();
+                    s.groups.remove(&self.group);
+                    Frame::Integer(destroyed as i64)
+                }
+                (XGroupOp::CreateConsumer(consumer), Some(group)) => {
+                    let created = !group.consumers.contains_key(consumer);
+                    group.consumer(consumer, now);
+                    Frame::Integer(created as i64)
+                }
+                (XGroupOp::DelConsumer(consumer), Some(group)) => {
+                    match group.consumers.remove(consumer) {
+                        Some(c) => {
+                            for id in &c.pending {
+                                group.pel.remove(id);
+                            }
+                            Frame::Integer(c.pending.len() as i64)
+                        }
+                        None => Frame::Integer(0),
+                    }
+                }
+                (_, None) => no_key_group_err(&self.key, &self.group),
+            }
+        });
+        // `$` is propagated as the ID it stood for
+        if let Some(resolved) = resolved {
+            let mut cmd = self.clone();
+            match &mut cmd.op {
+                XGroupOp::Create { id, .. } | XGroupOp::SetId { id, .. } => *id = Some(resolved),
+                _ => {}
+            }
+            client.propagate_as = Some(Command::XGroup(cmd));
+        }
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let id_arg = |id: &Option<StreamId>| match id {
+            Some(id) => id.to_bytes(),
+            None => Bytes::from_static(b"$"),
+        };
+        let entries_read_args = |args: &mut Vec<Bytes>, entries_read: &Option<u64>| {
+            if let Some(n) = entries_read {
+                args.push(Bytes::from_static(b"ENTRIESREAD"));
+                args.push(Bytes::from(n.to_string()));
+            }
+        };
+        let mut args = vec![Bytes::from_static(b"XGROUP")];
+        let subcommand: &'static [u8] = match &self.op {
+            XGroupOp::Create { .. } => b"CREATE",
+            XGroupOp::SetId { .. } => b"SETID",
+            XGroupOp::Destroy => b"DESTROY",
+            XGroupOp::CreateConsumer(_) => b"CREATECONSUMER",
+            XGroupOp::DelConsumer(_) => b"DELCONSUMER",
+        };
+        args.extend([
+            Bytes::from_static(subcommand),
+            self.key.clone(),
+            self.group.clone(),
+        ]);
+        match &self.op {
+            XGroupOp::Create {
+                id,
+                mkstream,
+                entries_read,
+            } => {
+                args.push(id_arg(id));
+                if *mkstream {
+                    args.push(Bytes::from_static(b"MKSTREAM"));
+                }
+                entries_read_args(&mut args, entries_read);
+            }
+            XGroupOp::SetId { id, entries_read } => {
+                args.push(id_arg(id));
+                entries_read_args(&mut args, entries_read);
+            }
+            XGroupOp::Destroy => {}
+            XGroupOp::CreateConsumer(consumer) | XGroupOp::DelConsumer(consumer) => {
+                args.push(consumer.clone());
+            }
+        }
+        rewrite(&args)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct XAck {
+    pub key: Bytes,
+    pub group: Bytes,
+    pub ids: Vec<StreamId>,
+}
+
+impl XAck {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("XACK")?;
+        let group = next_arg(frame)?;
+        let mut ids = vec![parse_id(&next_arg(frame)?)?];
+        while let Some(id) = frame.next_string()? {
+            ids.push(parse_id(&id)?);
+        }
+        Ok(Self { key, group, ids })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = write_stream(&client.db, self.key, false, Frame::Integer(0), |s| match s
+            .groups
+            .get_mut(&self.group)
+        {
+            Some(group) => {
+                let acked = self.ids.iter().filter(|id| group.ack(id)).count();
+                Frame::Integer(acked as i64)
+            }
+            None => Frame::Integer(0),
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let mut args = vec![
+            Bytes::from_static(b"XACK"),
+            self.key.clone(),
+            self.group.clone(),
+        ];
+        args.extend(self.ids.iter().map(StreamId::to_bytes));
+        rewrite(&args)
+    }
+}
+
+/// The extended form of XPENDING
+#[derive(Debug, Clone)]
+pub struct XPendingRange {
+    pub min_idle: Option<u64>,
+    /// None if the range is empty
+    pub range: Option<(StreamId, StreamId)>,
+    pub count: usize,
+    pub consumer: Option<Bytes>,
+}
+
+#[derive(Debug, Clone)]
+pub struct XPending {
+    pub key: Bytes,
+    pub group: Bytes,
+    pub range: Option<XPendingRange>,
+}
+
+impl XPending {
+    /// `XPENDING key group [[IDLE min-idle-time] start end count
+    /// [consumer]]`
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("XPENDING")?;
+        let group = next_arg(frame)?;
+        let range = match frame.next_string()? {
+            Some(mut start) => {
+                let mut min_idle = None;
+                if start.eq_ignore_ascii_case(b"idle") {
+                    min_idle = Some(next_u64(frame)?);
+                    start = next_arg(frame)?;
+                }
+                let start = parse_range_bound(&start, true)?;
+                let end = parse_range_bound(&next_arg(frame)?, false)?;
+                let count = next_count(frame)?;
+                let consumer = frame.next_string()?;
+                if frame.has_next() {
+                    return Err(CommandParser::syntax_error());
+                }
+                Some(XPendingRange {
+                    min_idle,
+                    range: start.zip(end),
+                    count,
+                    consumer,
+                })
+            }
+            None => None,
+        };
+        Ok(Self { key, group, range })
+    }
+
+    fn summary(group: &StreamGroup) -> Frame {
+        let (first, last) = match (group.pel.keys().next(), group.pel.keys().next_back()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => {
+                return Frame::Array(vec![
+                    Frame::Integer(0),
+                    Frame::Null,
+                    Frame::Null,
+                    Frame::Null,
+                ])
+            }
+        };
+        let consumers = group
+            .consumers
+            .iter()
+            .filter(|(_, c)| !c.pending.is_empty())
+            .map(|(name, c)| {
+                Frame::Array(vec![
+                    Frame::Bulk(name.clone()),
+                    Frame::Bulk(Bytes::from(c.pending.len().to_string())),
+                ])
+            })
+            .collect();
+        Frame::Array(vec![
+            Frame::Integer(group.pel.len() as i64),
+            id_reply(first),
+            id_reply(last),
+            Frame::Array(consumers),
+        ])
+    }
+
+    fn extended(group: &StreamGroup, range: &XPendingRange, now: u64) -> Frame {
+        let (start, end) = match range.range {
+            Some((start, end)) if start <= end => (start, end),
+            _ => return Frame::new_array(),
+        };
+        let ids: Box<dyn Iterator<Item = &StreamId>> = match &range.consumer {
+            Some(consumer) => match group.consumers.get(consumer) {
+                Some(c) => Box::new(c.pending.range(start..=end)),
+                None => return Frame::new_array(),
+            },
+            None => Box::new(group.pel.range(start..=end).map(|(id, _)| id)),
+        };
+        let entries = ids
+            .filter_map(|id| {
+                let p = &group.pel[id];
+                let idle = now.saturating_sub(p.delivery_time);
+                range.min_idle.is_none_or(|min| idle >= min).then(|| {
+                    Frame::Array(vec![
+                        id_reply(id),
+                        Frame::Bulk(p.consumer.clone()),
+                        Frame::Integer(idle as i64),
+                        Frame::Integer(p.delivery_count as i64),
+                    ])
+                })
+            })
+            .take(range.count)
+            .collect();
+        Frame::Array(entries)
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let missing = no_group_err(&self.key, &self.group);
+        let now = shared::now_ms();
+        let response = read_stream(&client.db, &self.key, missing.clone(), |s| {
+            match (s.groups.get(&self.group), &self.range) {
+                (Some(group), None) => Self::summary(group),
+                (Some(group), Some(range)) => Self::extended(group, range, now),
+                (None, _) => missing,
+            }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// What a claim did, what gets propagated
+struct Claimed {
+    ids: Vec<StreamId>,
+    delivery_time: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct XClaim {
+    pub key: Bytes,
+    pub group: Bytes,
+    pub consumer: Bytes,
+    pub min_idle: u64,
+    pub ids: Vec<StreamId>,
+    pub idle: Option<u64>,
+    pub time: Option<u64>,
+    pub retrycount: Option<u64>,
+    pub force: bool,
+    pub justid: bool,
+    pub lastid: Option<StreamId>,
+}
+
+impl XClaim {
+    /// `XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME
+    /// unix-time-milliseconds] [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID
+    /// lastid]`
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("XCLAIM")?;
+        let group = next_arg(frame)?;
+        let consumer = next_arg(frame)?;
+        let min_idle = next_u64(frame)?;
+        let mut cmd = Self {
+            key,
+            group,
+            consumer,
+            min_idle,
+            ids: Vec::new(),
+            idle: None,
+            time: None,
+            retrycount: None,
+            force: false,
+            justid: false,
+            lastid: None,
+        };
+        // the IDs go until the first option
+        let mut option = None;
+        while let Some(arg) = frame.next_string()? {
+            match StreamId::parse(&arg, 0) {
+                Some(id) => cmd.ids.push(id),
+                None => {
+                    option = Some(arg);
+                    break;
+                }
+            }
+        }
+        if cmd.ids.is_empty() {
+            return Err(CommandParser::syntax_error());
+        }
+        while let Some(arg) = match option.take() {
+            Some(arg) => Some(arg),
+            None => frame.next_string()?,
+        } {
+            match &arg.to_ascii_lowercase()[..] {
+                b"idle" => cmd.idle = Some(next_u64(frame)?),
+                b"time" => cmd.time = Some(next_u64(frame)?),
+                b"retrycount" => cmd.retrycount = Some(next_u64(frame)?),
+                b"force" => cmd.force = true,
+                b"justid" => cmd.justid = true,
+                b"lastid" => cmd.lastid = Some(parse_id(&next_arg(frame)?)?),
+                _ => return Err(CommandParser::syntax_error()),
+            }
+        }
+        Ok(cmd)
+    }
+
+    fn claim(&self, s: &RudisStream, g: &mut StreamGroup, now: u64) -> (Vec<Frame>, Claimed) {
+        let delivery_time = match (self.idle, self.time) {
+            (Some(idle), _) => now.saturating_sub(idle),
+            (None, Some(time)) => time.min(now),
+            (None, None) => now,
+        };
+        if let Some(lastid) = self.lastid {
+            if lastid > g.last_id {
+                g.last_id = lastid;
+            }
+        }
+        g.consumer(&self.consumer, now).seen_time = now;
+
+        let mut replies = Vec::new();
+        let mut ids = Vec::new();
+        for &id in &self.ids {
+            let exists = s.entries.contains_key(&id);
+            match g.pel.get(&id) {
+                // FORCE creates the pending entry of an existing one
+                None if !(self.force && exists) => continue,
+                None => {}
+                // the pending entries deleted from the stream are dropped
+                Some(_) if !exists => {
+                    g.ack(&id);
+                    ids.push(id);
+                    continue;
+                }
+                Some(p) if now.saturating_sub(p.delivery_time) < self.min_idle => continue,
+                Some(_) => {}
+            }
+            let created = !g.pel.contains_key(&id);
+            let p = g.assign(id, &self.consumer, delivery_time);
+            if let Some(count) = self.retrycount {
+                p.delivery_count = count;
+            } else if !self.justid && !created {
+                p.delivery_count += 1;
+            }
+            ids.push(id);
+            replies.push(if self.justid {
+                id_reply(&id)
+            } else {
+                entry_reply(&id, &s.entries[&id])
+            });
+        }
+        if !replies.is_empty() {
+            g.consumer(&self.consumer, now).active_time = Some(now);
+        }
+        (replies, Claimed { ids, delivery_time })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let now = shared::now_ms();
+        let mut claimed = None;
+        let response = write_group(&client.db, &self.key, &self.group, |s, g| {
+            let (replies, outcome) = self.claim(s, g, now);
+            claimed = Some(outcome);
+            Frame::Array(replies)
+        });
+        // propagated with the times it used and without the idle check
+        if let Some(claimed) = claimed {
+            client.propagate_as = Some(if !claimed.ids.is_empty() {
+                Command::XClaim(XClaim {
+                    min_idle: 0,
+                    ids: claimed.ids,
+                    idle: None,
+                    time: Some(claimed.delivery_time),
+                    ..self
+                })
+            } else if self.lastid.is_some() {
+                // nothing is idle enough, only LASTID and the consumer apply
+                Command::XClaim(XClaim {
+                    min_idle: u64::MAX,
+                    idle: None,
+                    time: None,
+                    retrycount: None,
+                    force: false,
+                    ..self
+                })
+            } else {
+                create_consumer(self.key, self.group, self.consumer)
+            });
+        }
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let mut args = vec![
+            Bytes::from_static(b"XCLAIM"),
+            self.key.clone(),
+            self.group.clone(),
+            self.consumer.clone(),
+            Bytes::from(self.min_idle.to_string()),
+        ];
+        args.extend(self.ids.iter().map(StreamId::to_bytes));
+        let options: [(&'static [u8], Option<u64>); 3] = [
+            (b"IDLE", self.idle),
+            (b"TIME", self.time),
+            (b"RETRYCOUNT", self.retrycount),
+        ];
+        for (name, value) in options {
+            if let Some(value) = value {
+                args.push(Bytes::from_static(name));
+                args.push(Bytes::from(value.to_string()));
+            }
+        }
+        if self.force {
+            args.push(Bytes::from_static(b"FORCE"));
+        }
+        if self.justid {
+            args.push(Bytes::from_static(b"JUSTID"));
+        }
+        if let Some(lastid) = self.lastid {
+            args.push(Bytes::from_static(b"LASTID"));
+            args.push(lastid.to_bytes());
+        }
+        rewrite(&args)
+    }
+}
+
+/// XGROUP CREATECONSUMER, what a claim that changed nothing else does
+fn create_consumer(key: Bytes, group: Bytes, consumer: Bytes) -> Command {
+    Command::XGroup(XGroup {
+        key,
+        group,
+        op: XGroupOp::CreateConsumer(consumer),
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct XAutoClaim {
+    pub key: Bytes,
+    pub group: Bytes,
+    pub consumer: Bytes,
+    pub min_idle: u64,
+    /// None if the range is empty
+    pub start: Option<StreamId>,
+    pub count: usize,
+    pub justid: bool,
+}
+
+impl XAutoClaim {
+    /// `XAUTOCLAIM key group consumer min-idle-time start [COUNT count]
+    /// [JUSTID]`
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("XAUTOCLAIM")?;
+        let group = next_arg(frame)?;
+        let consumer = next_arg(frame)?;
+        let min_idle = next_u64(frame)?;
+        let start = parse_range_bound(&next_arg(frame)?, true)?;
+        let mut count = 100;
+        let mut justid = false;
+        while let Some(option) = frame.next_string()? {
+            if option.eq_ignore_ascii_case(b"count") {
+                count = next_u64(frame)? as usize;
+                if count == 0 || count > usize::MAX / 10 {
+                    return Err(CommandParser::syntax_error());
+                }
+            } else if option.eq_ignore_ascii_case(b"justid") {
+                justid = true;
+            } else {
+                return Err(CommandParser::syntax_error());
+            }
+        }
+        Ok(Self {
+            key,
+            group,
+            consumer,
+            min_idle,
+            start,
+            count,
+            justid,
+        })
+    }
+
+    /// Claim the idle entries from the start, scanning up to 10 times as
+    /// many entries as the count. Replies the ID to continue from, 0-0 once
+    /// the end is reached, the claimed entries and the deleted ones
+    fn claim(&self, s: &RudisStream, g: &mut StreamGroup, now: u64) -> (Frame, Claimed) {
+        g.consumer(&self.consumer, now).seen_time = now;
+        let attempts = self.count * 10;
+        let scanned: Vec<StreamId> = match self.start {
+            Some(start) => g
+                .pel
+                .range(start..)
+                .map(|(id, _)| *id)
+                .take(attempts + 1)
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut cursor = StreamId::MIN;
+        let mut claimed = Vec::new();
+        let mut deleted = Vec::new();
+        let mut replies = Vec::new();
+        for (i, id) in scanned.into_iter().enumerate() {
+            if i == attempts || claimed.len() == self.count {
+                cursor = id;
+                break;
+            }
+            if !s.entries.contains_key(&id) {
+                g.ack(&id);
+                deleted.push(id);
+                continue;
+            }
+            let idle = now.saturating_sub(g.pel[&id].delivery_time);
+            if idle < self.min_idle {
+                continue;
+            }
+            let p = g.assign(id, &self.consumer, now);
+            if !self.justid {
+                p.delivery_count += 1;
+            }
+            claimed.push(id);
+            replies.push(if self.justid {
+                id_reply(&id)
+            } else {
+                entry_reply(&id, &s.entries[&id])
+            });
+        }
+        if !claimed.is_empty() {
+            g.consumer(&self.consumer, now).active_time = Some(now);
+        }
+        let reply = Frame::Array(vec![
+            id_reply(&cursor),
+            Frame::Array(replies),
+            Frame::Array(deleted.iter().map(id_reply).collect()),
+        ]);
+        claimed.extend(deleted);
+        (
+            reply,
+            Claimed {
+                ids: claimed,
+                delivery_time: now,
+            },
+        )
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let now = shared::now_ms();
+        let mut claimed = None;
+        let response = write_group(&client.db, &self.key, &self.group, |s, g| {
+            let (reply, outcome) = self.claim(s, g, now);
+            claimed = Some(outcome);
+            reply
+        });
+        // propagated as the XCLAIM of the entries claimed or deleted
+        if let Some(claimed) = claimed {
+            client.propagate_as = Some(if claimed.ids.is_empty() {
+                create_consumer(self.key, self.group, self.consumer)
+            } else {
+                Command::XClaim(XClaim {
+                    key: self.key,
+                    group: self.group,
+                    consumer: self.consumer,
+                    min_idle: 0,
+                    ids: claimed.ids,
+                    idle: None,
+                    time: Some(claimed.delivery_time),
+                    retrycount: None,
+                    force: false,
+                    justid: self.justid,
+                    lastid: None,
+                })
+            });
+        }
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum XInfoOp {
+    /// the count of entries and pending entries of FULL, None without it
+    Stream(Option<usize>),
+    Groups,
+    Consumers(Bytes),
+}
+
+#[derive(Debug, Clone)]
+pub struct XInfo {
+    pub key: Bytes,
+    pub op: XInfoOp,
+}
+
+impl XInfo {
+    /// `XINFO STREAM key [FULL [COUNT count]]`, `XINFO GROUPS key` and
+    /// `XINFO CONSUMERS key group`
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let subcommand = next_arg(frame)?.to_ascii_lowercase();
+        let key = frame.next_key("XINFO")?;
+        let op = match &subcommand[..] {
+            b"stream" => match frame.next_string()? {
+                Some(full) if full.eq_ignore_ascii_case(b"full") => {
+                    let count = match frame.next_string()? {
+                        Some(option) if option.eq_ignore_ascii_case(b"count") => next_count(frame)?,
+                        Some(_) => return Err(CommandParser::syntax_error()),
+                        None => 10,
+                    };
+                    XInfoOp::Stream(Some(count))
+                }
+                Some(_) => return Err(CommandParser::syntax_error()),
+                None => XInfoOp::Stream(None),
+            },
+            b"groups" => XInfoOp::Groups,
+            b"consumers" => XInfoOp::Consumers(next_arg(frame)?),
+            _ => return Err(CommandParser::syntax_error()),
+        };
+        if frame.has_next() {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(Self { key, op })
+    }
+
+    fn map(fields: Vec<(&'static str, Frame)>) -> Frame {
+        Frame::Map(
+            fields
+                .into_iter()
+                .map(|(name, value)| (Frame::Bulk(Bytes::from_static(name.as_bytes())), value))
+                .collect(),
+        )
+    }
+
+    fn optional(n: Option<u64>) -> Frame {
+        n.map_or(Frame::Null, |n| Frame::Integer(n as i64))
+    }
+
+    fn stream(s: &RudisStream, full: Option<usize>) -> Frame {
+        let mut fields = vec![
+            ("length", Frame::Integer(s.len() as i64)),
+            ("last-generated-id", id_reply(&s.last_id)),
+            ("max-deleted-entry-id", id_reply(&s.max_deleted_id)),
+            ("entries-added", Frame::Integer(s.entries_added as i64)),
+            ("recorded-first-entry-id", id_reply(&s.first_id())),
+        ];
+        let count = match full {
+            // 0 for everything
+            Some(count) => Some(count).filter(|count| *count > 0).unwrap_or(usize::MAX),
+            None => {
+                let entry = |entry: Option<(&StreamId, &Vec<(Bytes, Bytes)>)>| {
+                    entry.map_or(Frame::Null, |(id, fields)| entry_reply(id, fields))
+                };
+                fields.extend([
+                    ("groups", Frame::Integer(s.groups.len() as i64)),
+                    ("first-entry", entry(s.entries.iter().next())),
+                    ("last-entry", entry(s.entries.iter().next_back())),
+                ]);
+                return Self::map(fields);
+            }
+        };
+
+        let entries = s
+            .entries
+            .iter()
+            .take(count)
+            .map(|(id, fields)| entry_reply(id, fields))
+            .collect();
+        let groups = s
+            .groups
+            .iter()
+            .map(|(name, group)| {
+                let pending = group
+                    .pel
+                    .iter()
+                    .take(count)
+                    .map(|(id, p)| {
+                        Frame::Array(vec![
+                            id_reply(id),
+                            Frame::Bulk(p.consumer.clone()),
+                            Frame::Integer(p.delivery_time as i64),
+                            Frame::Integer(p.delivery_count as i64),
+                        ])
+                    })
+                    .collect();
+                let consumers = group
+                    .consumers
+                    .iter()
+                    .map(|(name, c)| {
+                        let pending = c
+                            .pending
+                            .iter()
+                            .take(count)
+                            .map(|id| {
+                                let p = &group.pel[id];
+                                Frame::Array(vec![
+                                    id_reply(id),
+                                    Frame::Integer(p.delivery_time as i64),
+                                    Frame::Integer(p.delivery_count as i64),
+                                ])
+                            })
+                            .collect();
+                        Self::map(vec![
+                            ("name", Frame::Bulk(name.clone())),
+                            ("seen-time", Frame::Integer(c.seen_time as i64)),
+                            (
+                                "active-time",
+                                Frame::Integer(c.active_time.map_or(-1, |t| t as i64)),
+                            ),
+                            ("pel-count", Frame::Integer(c.pending.len() as i64)),
+                            ("pending", Frame::Array(pending)),
+                        ])
+                    })
+                    .collect();
+                Self::map(vec![
+                    ("name", Frame::Bulk(name.clone())),
+                    ("last-delivered-id", id_reply(&group.last_id)),
+                    ("entries-read", Self::optional(group.entries_read)),
+                    ("lag", Self::optional(s.lag(group))),
+                    ("pel-count", Frame::Integer(group.pel.len() as i64)),
+                    ("pending", Frame::Array(pending)),
+                    ("consumers", Frame::Array(consumers)),
+                ])
+            })
+            .collect();
+        fields.extend([
+            ("entries", Frame::Array(entries)),
+            ("groups", Frame::Array(groups)),
+        ]);
+        Self::map(fields)
+    }
+
+    fn groups(s: &RudisStream) -> Frame {
+        Frame::Array(
+            s.groups
+                .iter()
+                .map(|(name, group)| {
+                    Self::map(vec![
+                        ("name", Frame::Bulk(name.clone())),
+                        ("consumers", Frame::Integer(group.consumers.len() as i64)),
+                        ("pending", Frame::Integer(group.pel.len() as i64)),
+                        ("last-delivered-id", id_reply(&group.last_id)),
+                        ("entries-read", Self::optional(group.entries_read)),
+                        ("lag", Self::optional(s.lag(group))),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    fn consumers(group: &StreamGroup, now: u64) -> Frame {
+        Frame::Array(
+            group
+                .consumers
+                .iter()
+                .map(|(name, c)| {
+                    let inactive = c.active_time.map_or(-1, |t| now.saturating_sub(t) as i64);
+                    Self::map(vec![
+                        ("name", Frame::Bulk(name.clone())),
+                        ("pending", Frame::Integer(c.pending.len() as i64)),
+                        (
+                            "idle",
+                            Frame::Integer(now.saturating_sub(c.seen_time) as i64),
+                        ),
+                        ("inactive", Frame::Integer(inactive)),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let now = shared::now_ms();
+        let response = read_stream(&client.db, &self.key, shared::no_key_err, |s| {
+            match &self.op {
+                XInfoOp::Stream(full) => Self::stream(s, *full),
+                XInfoOp::Groups => Self::groups(s),
+                XInfoOp::Consumers(group) => match s.groups.get(group) {
+                    Some(group) => Self::consumers(group, now),
+                    None => no_key_group_err(&self.key, group),
+                },
+            }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct XSetId {
+    pub key: Bytes,
+    pub last_id: StreamId,
+    pub entries_added: Option<u64>,
+    pub max_deleted_id: Option<StreamId>,
+}
+
+impl XSetId {
+    /// `XSETID key last-id [ENTRIESADDED entries-added] [MAXDELETEDID
+    /// max-deleted-id]`
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("XSETID")?;
+        let last_id = parse_id(&next_arg(frame)?)?;
+        let mut entries_added = None;
+        let mut max_deleted_id = None;
+        while let Some(option) = frame.next_string()? {
+            if option.eq_ignore_ascii_case(b"entriesadded") {
+                entries_added = Some(next_u64(frame)?);
+            } else if option.eq_ignore_ascii_case(b"maxdeletedid") {
+                max_deleted_id = Some(parse_id(&next_arg(frame)?)?);
+            } else {
+                return Err(CommandParser::syntax_error());
+            }
+        }
+        Ok(Self {
+            key,
+            last_id,
+            entries_added,
+            max_deleted_id,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = write_stream(
+            &client.db,
+            self.key.clone(),
+            false,
+            shared::no_key_err,
+            |s| {
+                let top = s.entries.keys().next_back().copied();
+                if top.is_some_and(|top| self.last_id < top) {
+                    return error(
+                        "ERR The ID specified in XSETID is smaller than the target stream top item",
+                    );
+                }
+                if self.entries_added.is_some_and(|n| n < s.len() as u64) {
+                    return error(
+                    "ERR The entries_added specified in XSETID is smaller than the target stream length",
+                );
+                }
+                if self.max_deleted_id.is_some_and(|id| id > self.last_id) {
+                    return error(
+                    "ERR The ID specified in XSETID is smaller than the provided max_deleted_entry_id",
+                );
+                }
+                s.last_id = self.last_id;
+                if let Some(n) = self.entries_added {
+                    s.entries_added = n;
+                }
+                if let Some(id) = self.max_deleted_id {
+                    s.max_deleted_id = id;
+                }
+                shared::ok
+            },
+        );
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let mut args = vec![
+            Bytes::from_static(b"XSETID"),
+            self.key.clone(),
+            self.last_id.to_bytes(),
+        ];
+        if let Some(n) = self.entries_added {
+            args.push(Bytes::from_static(b"ENTRIESADDED"));
+            args.push(Bytes::from(n.to_string()));
+        }
+        if let Some(id) = self.max_deleted_id {
+            args.push(Bytes::from_static(b"MAXDELETEDID"));
+            args.push(id.to_bytes());
+        }
+        rewrite(&args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ConfigRef};
+    use crate::server::Server;
+
+    async fn client() -> Client {
+        let server = Server::from_config(ConfigRef::new(Config {
+            rdb_filename: "/nonexistent/dump.rdb".to_owned(),
+            ..Default::default()
+        }))
+        .await;
+        let mut client = Client::new(server, None, "127.0.0.1:0".parse().unwrap());
+        client.reply_capture = Some(Vec::new());
+        client
+    }
+
+    /// The RESP2 reply of the command, its error for the ones not parsed
+    async fn call(client: &mut Client, args: &[&str]) -> String {
+        let frame = Frame::Array(
+            args.iter()
+                .map(|a| Frame::new_bulk_from(a.to_string()))
+                .collect(),
+        );
+        match Command::from(frame) {
+            Ok(cmd) => client.handle_command(cmd).await.unwrap(),
+            Err(err) => return format!("-{}", err),
+        }
+        let reply = client.reply_capture.as_mut().unwrap().pop().unwrap();
+        String::from_utf8(reply.serialize(2).to_vec()).unwrap()
+    }
+
+    fn stream<T>(client: &Client, key: &str, f: impl FnOnce(&RudisStream) -> T) -> T {
+        let value = client.db.get(&Bytes::from(key.to_owned())).unwrap();
+        match &value.value {
+            RudisObject::Stream(s) => f(s),
+            _ => panic!("not a stream"),
+        }
+    }
+
+    fn bulk(s: &str) -> String {
+        format!("${}\r\n{}\r\n", s.len(), s)
+    }
+
+    #[test]
+    fn stream_ids() {
+        assert_eq!(StreamId::parse(b"5-3", 0), Some(StreamId::new(5, 3)));
+        assert_eq!(
+            StreamId::parse(b"5", u64::MAX),
+            Some(StreamId::new(5, u64::MAX))
+        );
+        assert_eq!(StreamId::parse(b"5-", 0), None);
+        assert_eq!(StreamId::parse(b"-5", 0), None);
+        assert_eq!(StreamId::parse(b"18446744073709551616", 0), None);
+        assert_eq!(StreamId::new(5, 3).to_string(), "5-3");
+
+        assert_eq!(StreamId::new(5, 3).incr(), Some(StreamId::new(5, 4)));
+        assert_eq!(StreamId::new(5, u64::MAX).incr(), Some(StreamId::new(6, 0)));
+        assert_eq!(StreamId::MAX.incr(), None);
+        assert_eq!(StreamId::new(5, 0).decr(), Some(StreamId::new(4, u64::MAX)));
+        assert_eq!(StreamId::MIN.decr(), None);
+
+        let mut s = RudisStream::new();
+        assert_eq!(s.next_id(100, None), Some(StreamId::new(100, 0)));
+        s.insert(StreamId::new(100, 0), Vec::new());
+        // the clock went backwards
+        assert_eq!(s.next_id(90, None), Some(StreamId::new(100, 1)));
+        assert_eq!(s.next_id(90, Some(100)), Some(StreamId::new(100, 1)));
+        assert_eq!(s.next_id(90, Some(101)), Some(StreamId::new(101, 0)));
+        assert_eq!(s.next_id(90, Some(99)), None);
+        s.insert(StreamId::new(100, u64::MAX), Vec::new());
+        assert_eq!(s.next_id(90, Some(100)), None);
+        assert_eq!(s.next_id(90, None), Some(StreamId::new(101, 0)));
+    }
+
+    #[tokio::test]
+    async fn xadd_ids() {
+        let mut c = client().await;
+        let smaller =
+            "-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n";
+        assert_eq!(
+            call(&mut c, &["XADD", "s", "5-1", "f", "v"]).await,
+            bulk("5-1")
+        );
+        assert_eq!(call(&mut c, &["XADD", "s", "5-1", "f", "v"]).await, smaller);
+        assert_eq!(call(&mut c, &["XADD", "s", "5", "f", "v"]).await, smaller);
+        assert_eq!(call(&mut c, &["XADD", "s", "4-*", "f", "v"]).await, smaller);
+        assert_eq!(
+            call(&mut c, &["XADD", "s", "5-*", "f", "v"]).await,
+            bulk("5-2")
+        );
+        assert_eq!(
+            call(&mut c, &["XADD", "s", "7-*", "f", "v"]).await,
+            bulk("7-0")
+        );
+        assert_eq!(
+            call(&mut c, &["XADD", "t", "0-0", "f", "v"]).await,
+            "-ERR The ID specified in XADD must be greater than 0-0\r\n"
+        );
+        assert_eq!(
+            call(&mut c, &["XADD", "t", "0-*", "f", "v"]).await,
+            bulk("0-1")
+        );
+
+        // the IDs of the deleted entries are not reused
+        assert_eq!(call(&mut c, &["XDEL", "s", "7-0"]).await, ":1\r\n");
+        assert_eq!(call(&mut c, &["XADD", "s", "7-0", "f", "v"]).await, smaller);
+        assert_eq!(
+            call(&mut c, &["XADD", "s", "7-*", "f", "v"]).await,
+            bulk("7-1")
+        );
+        assert_eq!(call(&mut c, &["XSETID", "s", "9-0"]).await, "+OK\r\n");
+        assert_eq!(
+            call(&mut c, &["XADD", "s", "9-*", "f", "v"]).await,
+            bulk("9-1")
+        );
+        assert_eq!(
+            call(&mut c, &["XRANGE", "s", "(5-1", "(9-1"]).await,
+            format!(
+                "*2\r\n*2\r\n{}*2\r\n$1\r\nf\r\n$1\r\nv\r\n*2\r\n{}*2\r\n$1\r\nf\r\n$1\r\nv\r\n",
+                bulk("5-2"),
+                bulk("7-1")
+            )
+        );
+
+        let now = shared::now_ms();
+        call(&mut c, &["XADD", "s", "*", "f", "v"]).await;
+        let last = stream(&c, "s", |s| s.last_id);
+        assert!(last.ms >= now && last.seq == 0, "{}", last);
+
+        let max = StreamId::MAX.to_string();
+        assert_eq!(
+            call(&mut c, &["XADD", "s", &max, "f", "v"]).await,
+            bulk(&max)
+        );
+        assert_eq!(
+            call(&mut c, &["XADD", "s", "*", "f", "v"]).await,
+            "-ERR The stream has exhausted the last possible ID, unable to add more items\r\n"
+        );
+        assert_eq!(stream(&c, "s", |s| (s.len(), s.entries_added)), (6, 7));
+    }
+
+    #[tokio::test]
+    async fn consumer_groups() {
+        let mut c = client().await;
+        for id in ["1-0", "2-0", "3-0"] {
+            call(&mut c, &["XADD", "s", id, "f", "v"]).await;
+        }
+        assert_eq!(
+            call(&mut c, &["XGROUP", "CREATE", "s", "g", "0"]).await,
+            "+OK\r\n"
+        );
+        assert_eq!(
+            call(&mut c, &["XGROUP", "CREATE", "s", "g", "$"]).await,
+            "-BUSYGROUP Consumer Group name already exists\r\n"
+        );
+        assert_eq!(
+            call(
+                &mut c,
+                &["XREADGROUP", "GROUP", "nog", "a", "STREAMS", "s", ">"]
+            )
+            .await,
+            "-NOGROUP No such key 's' or consumer group 'nog' in XREADGROUP with GROUP option\r\n"
+        );
+
+        // the new entries are delivered once, to a single consumer
+        let read = call(
+            &mut c,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "alice",
+                "COUNT",
+                "2",
+                "STREAMS",
+                "s",
+                ">",
+            ],
+        )
+        .await;
+        assert!(read.contains("1-0") && read.contains("2-0") && !read.contains("3-0"));
+        let read = call(
+            &mut c,
+            &["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"],
+        )
+        .await;
+        assert!(read.contains("3-0") && !read.contains("1-0"));
+        // the history of a consumer is its pending entries
+        let read = call(
+            &mut c,
+            &["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", "0"],
+        )
+        .await;
+        assert!(read.contains("3-0") && !read.contains("1-0"));
+        assert_eq!(
+            call(&mut c, &["XPENDING", "s", "g"]).await,
+            format!(
+                "*4\r\n:3\r\n{}{}*2\r\n*2\r\n{}{}*2\r\n{}{}",
+                bulk("1-0"),
+                bulk("3-0"),
+                bulk("alice"),
+                bulk("2"),
+                bulk("bob"),
+                bulk("1")
+            )
+        );
+        stream(&c, "s", |s| {
+            let g = &s.groups[&Bytes::from("g")];
+            assert_eq!(g.last_id, StreamId::new(3, 0));
+            assert_eq!(g.entries_read, Some(3));
+            assert_eq!(g.pel[&StreamId::new(3, 0)].delivery_count, 2);
+            assert_eq!(g.pel[&StreamId::new(1, 0)].delivery_count, 1);
+        });
+
+        assert_eq!(
+            call(&mut c, &["XACK", "s", "g", "1-0", "9-0"]).await,
+            ":1\r\n"
+        );
+        assert_eq!(call(&mut c, &["XACK", "s", "g", "1-0"]).await, ":0\r\n");
+
+        // claimed by another consumer
+        let claimed = call(&mut c, &["XCLAIM", "s", "g", "carol", "0", "2-0", "JUSTID"]).await;
+        assert_eq!(claimed, format!("*1\r\n{}", bulk("2-0")));
+        let claimed = call(
+            &mut c,
+            &["XAUTOCLAIM", "s", "g", "dave", "0", "2-1", "COUNT", "1"],
+        )
+        .await;
+        assert!(
+            claimed.starts_with(&format!("*3\r\n{}*1\r\n*2\r\n{}", bulk("0-0"), bulk("3-0"))),
+            "{}",
+            claimed
+        );
+        stream(&c, "s", |s| {
+            let g = &s.groups[&Bytes::from("g")];
+            let pending = |name: &str| g.consumers[&Bytes::from(name.to_owned())].pending.clone();
+            assert!(pending("alice").is_empty() && pending("bob").is_empty());
+            assert_eq!(pending("carol"), [StreamId::new(2, 0)].into());
+            assert_eq!(pending("dave"), [StreamId::new(3, 0)].into());
+            assert_eq!(g.pel[&StreamId::new(2, 0)].consumer, "carol");
+        });
+        assert_eq!(
+            call(&mut c, &["XGROUP", "DELCONSUMER", "s", "g", "dave"]).await,
+            ":1\r\n"
+        );
+        assert_eq!(
+            call(&mut c, &["XGROUP", "DELCONSUMER", "s", "g", "alice"]).await,
+            ":0\r\n"
+        );
+        stream(&c, "s", |s| {
+            assert_eq!(s.groups[&Bytes::from("g")].pel.len(), 1)
+        });
+    }
+
+    #[tokio::test]
+    async fn group_lag() {
+        let mut c = client().await;
+        for id in ["1-0", "2-0", "3-0", "4-0"] {
+            call(&mut c, &["XADD", "s", id, "f", "v"]).await;
+        }
+        call(&mut c, &["XGROUP", "CREATE", "s", "g", "0"]).await;
+        let lag = |c: &Client| stream(c, "s", |s| s.lag(&s.groups[&Bytes::from("g")]));
+        assert_eq!(lag(&c), Some(4));
+        call(
+            &mut c,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "a",
+                "COUNT",
+                "1",
+                "STREAMS",
+                "s",
+                ">",
+            ],
+        )
+        .await;
+        assert_eq!(lag(&c), Some(3));
+
+        // set to an arbitrary ID
+        call(&mut c, &["XGROUP", "SETID", "s", "g", "2-0"]).await;
+        assert_eq!(lag(&c), None);
+        call(
+            &mut c,
+            &["XGROUP", "SETID", "s", "g", "0", "ENTRIESREAD", "0"],
+        )
+        .await;
+        assert_eq!(lag(&c), Some(4));
+        call(&mut c, &["XGROUP", "SETID", "s", "g", "$"]).await;
+        assert_eq!(lag(&c), Some(0));
+
+        // an entry deleted after the group's last ID makes the lag unknown
+        call(
+            &mut c,
+            &["XGROUP", "SETID", "s", "g", "1-0", "ENTRIESREAD", "1"],
+        )
+        .await;
+        assert_eq!(lag(&c), Some(3));
+        call(&mut c, &["XDEL", "s", "3-0"]).await;
+        assert_eq!(lag(&c), None);
+        let info = call(&mut c, &["XINFO", "GROUPS", "s"]).await;
+        assert!(info.ends_with("$3\r\nlag\r\n$-1\r\n"), "{}", info);
+        // until the group reads past it
+        call(
+            &mut c,
+            &["XREADGROUP", "GROUP", "g", "a", "STREAMS", "s", ">"],
+        )
+        .await;
+        assert_eq!(lag(&c), Some(0));
+
+        // without deletions the first entry is the (added - len + 1)th
+        for id in ["1-0", "2-0", "3-0", "4-0"] {
+            call(&mut c, &["XADD", "t", id, "f", "v"]).await;
+        }
+        call(&mut c, &["XTRIM", "t", "MAXLEN", "2"]).await;
+        stream(&c, "t", |s| {
+            assert_eq!(s.first_id(), StreamId::new(3, 0));
+            assert_eq!(s.estimate_entries_read(StreamId::new(1, 5)), Some(2));
+            assert_eq!(s.estimate_entries_read(StreamId::new(3, 0)), Some(3));
+            assert_eq!(s.estimate_entries_read(StreamId::new(3, 5)), None);
+            assert_eq!(s.estimate_entries_read(StreamId::new(4, 0)), Some(4));
+            assert_eq!(s.estimate_entries_read(StreamId::new(5, 0)), None);
+        });
+    }
+}
@@ -1,13 +1,14 @@
 use super::CommandParser;
 use crate::client::Client;
-use crate::dbms::DictValue;
+use crate::dbms::{DatabaseRef, DictEntry, DictValue};
 use crate::frame::Frame;
 use crate::object::RudisObject;
 use crate::shared;
 use bytes::{Bytes, BytesMut};
-use dashmap::mapref::entry::Entry;
 use std::io::{Error, ErrorKind, Result};
 
+use super::Command;
+
 #[derive(Debug, Clone)]
 pub struct Get {
     pub key: Bytes,
@@ -44,55 +45,131 @@ impl Get {
 const REDIS_SET_NO_FLAGS: u32 = 0;
 const REDIS_SET_NX: u32 = 1 << 0; /* Set if key not exists. */
 const REDIS_SET_XX: u32 = 1 << 1; /* Set if key exists. */
+const REDIS_SET_KEEPTTL: u32 = 1 << 2; /* Set and keep the ttl. */
+const REDIS_SET_GET: u32 = 1 << 3; /* Set and return the old value. */
+
+/// Expiration of SET and GETEX
+#[derive(Debug, Clone, Copy)]
+pub enum Expiry {
+    /// milliseconds from now, EX and PX
+    In(u64),
+    /// unix time in milliseconds, EXAT and PXAT
+    At(u64),
+}
+
+impl Expiry {
+    pub fn expire_at_ms(&self) -> u64 {
+        match *self {
+            Expiry::In(ms) => shared::now_ms() + ms,
+            Expiry::At(ms) => ms,
+        }
+    }
+
+    /// Parse the time following the EX, PX, EXAT or PXAT `option` of `cmd`,
+    /// None if `option` is none of them
+    fn from(option: &[u8], frame: &mut CommandParser, cmd: &str) -> Result<Option<Self>> {
+        let unit = match option {
+            b"ex" | b"exat" => 1000,
+            b"px" | b"pxat" => 1,
+            _ => return Ok(None),
+        };
+        let time = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Expiry::new(time, unit, option.ends_with(b"at"), cmd).map(Some)
+    }
+
+    /// The expiration `time` in `unit` milliseconds, a unix time if `at`.
+    /// Like redis, the time must be positive and the expire time must fit in
+    /// a signed 64 bits integer of milliseconds
+    fn new(time: i64, unit: u64, at: bool, cmd: &str) -> Result<Self> {
+        let invalid =
+            || CommandParser::reply_error(format!("ERR invalid expire time in '{}' command", cmd));
+        if time <= 0 {
+            return Err(invalid());
+        }
+        let ms = (time as u64)
+            .checked_mul(unit)
+            .filter(|ms| *ms <= i64::MAX as u64)
+            .ok_or_else(invalid)?;
+        if at {
+            Ok(Expiry::At(ms))
+        } else if ms > i64::MAX as u64 - shared::now_ms() {
+            Err(invalid())
+        } else {
+            Ok(Expiry::In(ms))
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Set {
     pub key: Bytes,
-    pub val: BytesMut,
+    pub val: Bytes,
     pub flags: u32,
-    pub expire: Option<u64>, // milliseconds
+    pub expire: Option<Expiry>,
+}
+
+/// The string at `key`, None if there is no such key, Err with the WRONGTYPE
+/// reply if the key doesn't hold a string
+fn get_string(db: &DatabaseRef, key: &Bytes) -> std::result::Result<Option<Bytes>, Frame> {
+    match db.get(key) {
+        Some(entry) => match &entry.value {
+            RudisObject::String(s) => Ok(Some(s.value.clone())),
+            _ => Err(shared::wrong_type_err),
+        },
+        None => Ok(None),
+    }
 }
 
-async fn generic_set(
+fn generic_set(
+    db: &DatabaseRef,
     key: Bytes,
-    val: BytesMut,
+    val: Bytes,
     flags: u32,
-    expire: Option<u64>,
-    client: &mut Client,
-) -> Result<()> {
-    match client.db.clone().entry(key) {
-        Entry::Occupied(mut oe) => {
+    expire: Option<Expiry>,
+) -> Frame {
+    match db.entry(key) {
+        DictEntry::Occupied(mut oe) => {
+            let reply = if flags & REDIS_SET_GET != 0 {
+                match &oe.get().value {
+                    RudisObject::String(s) => Frame::Bulk(s.value.clone()),
+                    _ => return shared::wrong_type_err,
+                }
+            } else {
+                shared::ok
+            };
+
             if flags & REDIS_SET_NX != 0 {
-                client.write_frame(&shared::null_bulk).await.unwrap();
-                return Ok(());
+                return if flags & REDIS_SET_GET != 0 {
+                    reply
+                } else {
+                    shared::null_bulk
+                };
             }
 
-            {
-                let entry = oe.get_mut();
-                entry.value = RudisObject::new_string_from(val);
-                entry.expire_at = expire.map(|ms| shared::now_ms() + ms);
+            let entry = oe.get_mut();
+            entry.value = RudisObject::new_string_from(val);
+            if flags & REDIS_SET_KEEPTTL == 0 {
+                entry.expire_at = expire.map(|expire| expire.expire_at_ms());
             }
-
-            drop(oe);
-
-            client.write_frame(&shared::ok).await.unwrap();
-
-            Ok(())
+            reply
         }
-        Entry::Vacant(ve) => {
+        DictEntry::Vacant(ve) => {
             if flags & REDIS_SET_XX != 0 {
-                client.write_frame(&shared::null_bulk).await.unwrap();
-                return Ok(());
+                return shared::null_bulk;
             }
 
             ve.insert(DictValue::new(
                 RudisObject::new_string_from(val),
-                expire.map(|ms| shared::now_ms() + ms),
+                expire.map(|expire| expire.expire_at_ms()),
             ));
 
-            client.write_frame(&shared::ok).await.unwrap();
-
-            Ok(())
+            if flags & REDIS_SET_GET != 0 {
+                shared::null_bulk
+            } else {
+                shared::ok
+            }
         }
     }
 }
@@ -100,7 +177,7 @@ async fn generic_set(
 impl Set {
     pub fn from(frame: &mut CommandParser) -> Result<Self> {
         if frame.remaining() < 2 {
-            return Err(Error::new(ErrorKind::Other, shared::syntax_err.to_string()));
+            return Err(CommandParser::syntax_error());
         }
         // The first two elements of the array are the key and value
         let key = frame.next_string()?.unwrap();
@@ -111,85 +188,105 @@ impl Set {
 
         while frame.has_next() {
             let val = frame.next_string()?.unwrap().to_ascii_lowercase();
-            if &val == b"nx" {
+            if &val == b"nx" && flags & REDIS_SET_XX == 0 {
                 flags |= REDIS_SET_NX;
-            } else if &val == b"xx" {
+            } else if &val == b"xx" && flags & REDIS_SET_NX == 0 {
                 flags |= REDIS_SET_XX;
-            } else if &val == b"ex" {
-                // expire time in seconds
-                let time = {
-                    if let Some(maybe_time) = frame.next_integer()? {
-                        maybe_time as u64
-                    } else {
-                        return Err(Error::new(ErrorKind::Other, shared::syntax_err.to_string()));
-                    }
-                };
-                expire = Some(time * 1000);
-            } else if &val == b"px" {
-                // expire time in milliseconds
-                let time = {
-                    if let Some(maybe_time) = frame.next_integer()? {
-                        maybe_time as u64
-                    } else {
-                        return Err(Error::new(ErrorKind::Other, shared::syntax_err.to_string()));
-                    }
-                };
-                expire = Some(time);
+            } else if &val == b"get" {
+                flags |= REDIS_SET_GET;
+            } else if &val == b"keepttl" && expire.is_none() {
+                flags |= REDIS_SET_KEEPTTL;
+            } else if expire.is_none() && flags & REDIS_SET_KEEPTTL == 0 {
+                expire = Expiry::from(&val, frame, "set")?;
+                if expire.is_none() {
+                    return Err(CommandParser::syntax_error());
+                }
             } else {
                 // error
-                return Err(Error::new(ErrorKind::Other, shared::syntax_err.to_string()));
+                return Err(CommandParser::syntax_error());
             }
         }
 
         Ok(Self {
             key,
-            val: BytesMut::from(&val[..]),
+            val,
             flags,
             expire,
         })
     }
 
+    /// SETEX and PSETEX, the time is in `unit` milliseconds
+    pub fn from_setex(frame: &mut CommandParser, unit: u64) -> Result<Self> {
+        let key = frame
+            .next_string()?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "SETEX requires a key"))?;
+        let time = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let cmd = if unit == 1 { "psetex" } else { "setex" };
+        let expire = Expiry::new(time, unit, false, cmd)?;
+        let val = frame
+            .next_string()?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "SETEX requires a value"))?;
+        Ok(Self {
+            key,
+            val,
+            flags: REDIS_SET_NO_FLAGS,
+            expire: Some(expire),
+        })
+    }
+
     pub async fn apply(self, client: &mut Client) -> Result<()> {
-        generic_set(self.key, self.val, self.flags, self.expire, client).await
+        let response = generic_set(&client.db, self.key, self.val, self.flags, self.expire);
+        client.write_frame(&response).await?;
+        Ok(())
     }
 
     pub fn rewrite(&self) -> BytesMut {
-        let mut out = BytesMut::new();
-        shared::extend_array(&mut out, 3 + if self.expire.is_some() { 2 } else { 0 });
-        shared::extend_bulk_string(&mut out, b"SET" as &[u8]);
-        shared::extend_bulk_string(&mut out, &self.key[..]);
-        shared::extend_bulk_string(&mut out, &self.val[..]);
+        let mut args = vec![b"SET" as &[u8], &self.key, &self.val];
+        if self.flags & REDIS_SET_NX != 0 {
+            args.push(b"NX");
+        }
+        if self.flags & REDIS_SET_XX != 0 {
+            args.push(b"XX");
+        }
+        if self.flags & REDIS_SET_KEEPTTL != 0 {
+            args.push(b"KEEPTTL");
+        }
+        // the relative times are written as unix times, so that replaying
+        // the file later doesn't extend them
+        let expire_at;
         if let Some(expire) = self.expire {
-            shared::extend_bulk_string(&mut out, b"PX" as &[u8]);
-            shared::extend_bulk_string(&mut out, expire.to_string().as_bytes());
+            expire_at = expire.expire_at_ms().to_string();
+            args.push(b"PXAT");
+            args.push(expire_at.as_bytes());
         }
-        out
+        shared::rewrite_args(args)
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct SetNx {
     pub key: Bytes,
-    pub val: BytesMut,
+    pub val: Bytes,
 }
 
 impl SetNx {
     pub fn from(frame: &mut CommandParser) -> Result<Self> {
         if frame.remaining() < 2 {
-            return Err(Error::new(ErrorKind::Other, shared::syntax_err.to_string()));
+            return Err(CommandParser::syntax_error());
         }
         // The first two elements of the array are the key and value
         let key = frame.next_string()?.unwrap();
         let val = frame.next_string()?.unwrap();
 
-        Ok(Self {
-            key,
-            val: BytesMut::from(&val[..]),
-        })
+        Ok(Self { key, val })
     }
 
     pub async fn apply(self, client: &mut Client) -> Result<()> {
-        generic_set(self.key, self.val, REDIS_SET_NX, None, client).await
+        let response = generic_set(&client.db, self.key, self.val, REDIS_SET_NX, None);
+        client.write_frame(&response).await?;
+        Ok(())
     }
 
     pub fn rewrite(&self) -> BytesMut {
@@ -206,11 +303,11 @@ async fn generic_incdec(key: Bytes, incr: i64, client: &mut Client) -> Result<()
     // Increment the value in the shared database state
     let response = {
         let mut value = client.db.entry(key).or_insert_with(|| {
-            DictValue::new(RudisObject::new_string_from(BytesMut::from("0")), None)
+            DictValue::new(RudisObject::new_string_from(Bytes::from_static(b"0")), None)
         });
         if let RudisObject::String(s) = &mut value.value {
             if let Some(n) = s.parse_int() {
-                s.value = BytesMut::from((n + incr).to_string().as_bytes());
+                s.value = Bytes::from((n + incr).to_string());
                 Frame::Integer(n + incr)
             } else {
                 Frame::Error(Bytes::from_static(
@@ -372,9 +469,9 @@ impl Append {
         let response = {
             // locked write
             match client.db.entry(self.key) {
-                Entry::Occupied(mut oe) => {
+                DictEntry::Occupied(mut oe) => {
                     if let RudisObject::String(s) = &mut oe.get_mut().value {
-                        s.extend_from_slice(&self.value);
+                        s.modify(|value| value.extend_from_slice(&self.value));
                         Frame::Integer(s.len() as i64)
                     } else {
                         Frame::Error(Bytes::from_static(
@@ -382,9 +479,9 @@ impl Append {
                         ))
                     }
                 }
-                Entry::Vacant(ve) => {
+                DictEntry::Vacant(ve) => {
                     ve.insert(DictValue::new(
-                        RudisObject::new_string_from(BytesMut::from(&self.value[..])),
+                        RudisObject::new_string_from(self.value.clone()),
                         None,
                     ));
                     Frame::Integer(self.value.len() as i64)
@@ -443,3 +540,543 @@ impl Strlen {
         Ok(())
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct MGet {
+    pub keys: Vec<Bytes>,
+}
+
+impl MGet {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let mut keys = vec![frame.next_key("MGET")?];
+        while let Some(key) = frame.next_string()? {
+            keys.push(key);
+        }
+        Ok(Self { keys })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        // the keys not holding a string are replied as missing
+        let values = self
+            .keys
+            .iter()
+            .map(|key| match get_string(&client.db, key) {
+                Ok(Some(value)) => Frame::Bulk(value),
+                _ => Frame::Null,
+            })
+            .collect();
+        client.write_frame(&Frame::Array(values)).await?;
+        Ok(())
+    }
+}
+
+/// MSET and MSETNX
+#[derive(Debug, Clone)]
+pub struct MSet {
+    pub pairs: Vec<(Bytes, Bytes)>,
+    /// set none of the keys if any of them exists, MSETNX
+    pub nx: bool,
+}
+
+impl MSet {
+    pub fn from(frame: &mut CommandParser, nx: bool) -> Result<Self> {
+        if frame.remaining() == 0 || !frame.remaining().is_multiple_of(2) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "wrong number of arguments for MSET",
+            ));
+        }
+        let mut pairs = Vec::with_capacity(frame.remaining() / 2);
+        while let Some(key) = frame.next_string()? {
+            let value = frame
+                .next_string()?
+                .ok_or_else(CommandParser::syntax_error)?;
+            pairs.push((key, value));
+        }
+        Ok(Self { pairs, nx })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        if self.nx && self.pairs.iter().any(|(key, _)| db.contains_key(key)) {
+            client.write_frame(&Frame::Integer(0)).await?;
+            return Ok(());
+        }
+        for (key, value) in self.pairs {
+            db.insert(key, RudisObject::new_string_from(value), None);
+        }
+        let response = if self.nx {
+            Frame::Integer(1)
+        } else {
+            shared::ok
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let cmd = if self.nx { b"MSETNX" as &[u8] } else { b"MSET" };
+        shared::rewrite_args(
+            std::iter::once(cmd).chain(
+                self.pairs
+                    .iter()
+                    .flat_map(|(key, value)| [&key[..], &value[..]]),
+            ),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GetSet {
+    pub key: Bytes,
+    pub val: Bytes,
+}
+
+impl GetSet {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("GETSET")?;
+        let val = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self { key, val })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = generic_set(&client.db, self.key, self.val, REDIS_SET_GET, None);
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([b"GETSET" as &[u8], &self.key, &self.val])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GetDel {
+    pub key: Bytes,
+}
+
+impl GetDel {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("GETDEL")?;
+        Ok(Self { key })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = match client.db.entry(self.key) {
+            DictEntry::Occupied(oe) => match &oe.get().value {
+                RudisObject::String(s) => {
+                    let value = s.value.clone();
+                    oe.remove();
+                    Frame::Bulk(value)
+                }
+                _ => shared::wrong_type_err,
+            },
+            DictEntry::Vacant(_) => Frame::Null,
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([b"GETDEL" as &[u8], &self.key])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GetEx {
+    pub key: Bytes,
+    pub expire: Option<Expiry>,
+    /// remove the time to live, PERSIST
+    pub persist: bool,
+}
+
+impl GetEx {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("GETEX")?;
+        let mut expire = None;
+        let mut persist = false;
+        if let Some(option) = frame.next_string()? {
+            let option = option.to_ascii_lowercase();
+            if &option == b"persist" {
+                persist = true;
+            } else {
+                expire = Some(
+                    Expiry::from(&option, frame, "getex")?
+                        .ok_or_else(CommandParser::syntax_error)?,
+                );
+            }
+        }
+        if frame.has_next() {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(Self {
+            key,
+            expire,
+            persist,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = match client.db.get_mut(&self.key) {
+            Some(mut entry) => match &entry.value {
+                RudisObject::String(s) => {
+                    let value = s.value.clone();
+                    if self.persist {
+                        entry.expire_at = None;
+                    } else if let Some(expire) = self.expire {
+                        entry.expire_at = Some(expire.expire_at_ms());
+                    }
+                    Frame::Bulk(value)
+                }
+                _ => shared::wrong_type_err,
+            },
+            None => Frame::Null,
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let expire_at;
+        let mut args = vec![b"GETEX" as &[u8], &self.key];
+        if self.persist {
+            args.push(b"PERSIST");
+        } else if let Some(expire) = self.expire {
+            expire_at = expire.expire_at_ms().to_string();
+            args.push(b"PXAT");
+            args.push(expire_at.as_bytes());
+        }
+        shared::rewrite_args(args)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GetRange {
+    pub key: Bytes,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl GetRange {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("GETRANGE")?;
+        let start = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let end = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self { key, start, end })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = match get_string(&client.db, &self.key) {
+            Ok(Some(value)) => match shared::index_range(self.start, self.end, value.len()) {
+                Some((start, end)) => Frame::Bulk(value.slice(start..=end)),
+                None => shared::empty_bulk,
+            },
+            Ok(None) => shared::empty_bulk,
+            Err(err) => err,
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SetRange {
+    pub key: Bytes,
+    pub offset: usize,
+    pub value: Bytes,
+}
+
+impl SetRange {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("SETRANGE")?;
+        let offset = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let value = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        if offset < 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "offset is out of range",
+            ));
+        }
+        Ok(Self {
+            key,
+            offset: offset as usize,
+            value,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = if self.offset + self.value.len() > 512 * 1024 * 1024 {
+            Frame::Error(Bytes::from_static(
+                b"ERR string exceeds maximum allowed size (512MB)",
+            ))
+        } else {
+            match client.db.entry(self.key) {
+                DictEntry::Occupied(mut oe) => {
+                    if let RudisObject::String(s) = &mut oe.get_mut().value {
+                        if !self.value.is_empty() {
+                            let end = self.offset + self.value.len();
+                            s.modify(|value| {
+                                if value.len() < end {
+                                    value.resize(end, 0);
+                                }
+                                value[self.offset..end].copy_from_slice(&self.value);
+                            });
+                        }
+                        Frame::Integer(s.len() as i64)
+                    } else {
+                        shared::wrong_type_err
+                    }
+                }
+                DictEntry::Vacant(_) if self.value.is_empty() => Frame::Integer(0),
+                DictEntry::Vacant(ve) => {
+                    let mut value = BytesMut::zeroed(self.offset);
+                    value.extend_from_slice(&self.value);
+                    let len = value.len();
+                    ve.insert(DictValue::new(
+                        RudisObject::new_string_from(value.freeze()),
+                        None,
+                    ));
+                    Frame::Integer(len as i64)
+                }
+            }
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([
+            b"SETRANGE" as &[u8],
+            &self.key,
+            self.offset.to_string().as_bytes(),
+            &self.value,
+        ])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IncrByFloat {
+    pub key: Bytes,
+    pub increment: f64,
+}
+
+impl IncrByFloat {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("INCRBYFLOAT")?;
+        let increment = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let increment = std::str::from_utf8(&increment)
+            .ok()
+            .and_then(|n| n.parse::<f64>().ok())
+            .filter(|n| !n.is_nan())
+            .ok_or_else(|| CommandParser::reply_error("ERR value is not a valid float"))?;
+        Ok(Self { key, increment })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = {
+            let mut value = client.db.entry(self.key.clone()).or_insert_with(|| {
+                DictValue::new(RudisObject::new_string_from(Bytes::from_static(b"0")), None)
+            });
+            if let RudisObject::String(s) = &mut value.value {
+                match std::str::from_utf8(s)
+                    .ok()
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .filter(|v| v.is_finite())
+                    .map(|current| current + self.increment)
+                {
+                    Some(n) if n.is_finite() => {
+                        s.value = Bytes::from(n.to_string());
+                        Ok(s.value.clone())
+                    }
+                    Some(_) => Err(Frame::Error(Bytes::from_static(
+                        b"ERR increment would produce NaN or Infinity",
+                    ))),
+                    None => Err(Frame::Error(Bytes::from_static(
+                        b"ERR value is not a valid float",
+                    ))),
+                }
+            } else {
+                Err(shared::wrong_type_err)
+            }
+        };
+
+        let response = match response {
+            Ok(value) => {
+                // the float formatting may differ on reload, so the result is
+                // propagated instead of the increment
+                client.propagate_as = Some(Command::Set(Set {
+                    key: self.key,
+                    val: value.clone(),
+                    flags: REDIS_SET_KEEPTTL,
+                    expire: None,
+                }));
+                Frame::Bulk(value)
+            }
+            Err(err) => err,
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Lcs {
+    pub key1: Bytes,
+    pub key2: Bytes,
+    /// reply the length only
+    pub len: bool,
+    /// reply the matching ranges
+    pub idx: bool,
+    /// skip the ranges shorter than this
+    pub minmatchlen: usize,
+    /// reply the length of each range
+    pub withmatchlen: bool,
+}
+
+impl Lcs {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key1 = frame.next_key("LCS")?;
+        let key2 = frame.next_key("LCS")?;
+        let mut lcs = Self {
+            key1,
+            key2,
+            len: false,
+            idx: false,
+            minmatchlen: 0,
+            withmatchlen: false,
+        };
+        while let Some(option) = frame.next_string()? {
+            match &option.to_ascii_lowercase()[..] {
+                b"len" => lcs.len = true,
+                b"idx" => lcs.idx = true,
+                b"withmatchlen" => lcs.withmatchlen = true,
+                b"minmatchlen" => {
+                    lcs.minmatchlen = frame
+                        .next_integer()?
+                        .ok_or_else(CommandParser::syntax_error)?
+                        .max(0) as usize
+                }
+                _ => return Err(CommandParser::syntax_error()),
+            }
+        }
+        if lcs.len && lcs.idx {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "If you want both the length and indexes, please just use IDX.",
+            ));
+        }
+        Ok(lcs)
+    }
+
+    fn reply(&self, a: &[u8], b: &[u8]) -> Frame {
+        // dp[i][j] is the length of the LCS of a[..i] and b[..j]
+        let width = b.len() + 1;
+        let mut dp = vec![0u32; (a.len() + 1) * width];
+        for i in 1..=a.len() {
+            for j in 1..=b.len() {
+                dp[i * width + j] = if a[i - 1] == b[j - 1] {
+                    dp[(i - 1) * width + j - 1] + 1
+                } else {
+                    dp[(i - 1) * width + j].max(dp[i * width + j - 1])
+                };
+            }
+        }
+        let len = dp[a.len() * width + b.len()] as usize;
+        if self.len {
+            return Frame::Integer(len as i64);
+        }
+
+        // walk back from the end, collecting the LCS and the ranges matching
+        // in both strings, see lcsCommand in redis
+        let mut result = vec![0u8; len];
+        let mut matches = Vec::new();
+        let mut range: Option<(usize, usize, usize, usize)> = None;
+        let (mut i, mut j, mut k) = (a.len(), b.len(), len);
+        while i > 0 && j > 0 {
+            let mut emit = false;
+            if a[i - 1] == b[j - 1] {
+                result[k - 1] = a[i - 1];
+                range = match range {
+                    None => Some((i - 1, i - 1, j - 1, j - 1)),
+                    Some((a_start, a_end, b_start, b_end)) if a_start == i && b_start == j => {
+                        Some((a_start - 1, a_end, b_start - 1, b_end))
+                    }
+                    Some(range) => {
+                        emit = true;
+                        Some(range)
+                    }
+                };
+                if range.is_some_and(|(a_start, _, b_start, _)| a_start == 0 || b_start == 0) {
+                    emit = true;
+                }
+                i -= 1;
+                j -= 1;
+                k -= 1;
+            } else {
+                if dp[(i - 1) * width + j] > dp[i * width + j - 1] {
+                    i -= 1;
+                } else {
+                    j -= 1;
+                }
+                emit = range.is_some();
+            }
+
+            if emit {
+                if let Some((a_start, a_end, b_start, b_end)) = range.take() {
+                    let match_len = a_end - a_start + 1;
+                    if self.idx && match_len >= self.minmatchlen {
+                        let pair = |start: usize, end: usize| {
+                            Frame::Array(vec![
+                                Frame::Integer(start as i64),
+                                Frame::Integer(end as i64),
+                            ])
+                        };
+                        let mut m = vec![pair(a_start, a_end), pair(b_start, b_end)];
+                        if self.withmatchlen {
+                            m.push(Frame::Integer(match_len as i64));
+                        }
+                        matches.push(Frame::Array(m));
+                    }
+                }
+            }
+        }
+
+        if self.idx {
+            Frame::Map(vec![
+                (
+                    Frame::new_bulk_from_slice(b"matches" as &[u8]),
+                    Frame::Array(matches),
+                ),
+                (
+                    Frame::new_bulk_from_slice(b"len" as &[u8]),
+                    Frame::Integer(len as i64),
+                ),
+            ])
+        } else {
+            Frame::Bulk(Bytes::from(result))
+        }
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let values = get_string(&client.db, &self.key1)
+            .and_then(|a| Ok((a, get_string(&client.db, &self.key2)?)));
+        let response = match values {
+            Ok((a, b)) => self.reply(&a.unwrap_or_default(), &b.unwrap_or_default()),
+            Err(err) => err,
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,1110 @@
+use super::{CommandParser, ScanArgs};
+use crate::client::Client;
+use crate::dbms::{DatabaseRef, DictEntry, DictValue};
+use crate::frame::Frame;
+use crate::object::{RudisObject, RudisZSet, ZLexBound, ZLexRange, ZScoreRange};
+use crate::shared;
+use bytes::{Bytes, BytesMut};
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind, Result};
+
+/// Parse a score, e.g. `1.5`, `-inf` or `+inf`
+fn parse_score(s: &[u8]) -> Result<f64> {
+    std::str::from_utf8(s)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|score| !score.is_nan())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "value is not a valid float"))
+}
+
+/// Parse a score bound, excluded if prefixed by `(`
+fn parse_score_bound(s: &[u8]) -> Result<(f64, bool)> {
+    match s.strip_prefix(b"(") {
+        Some(s) => Ok((parse_score(s)?, true)),
+        None => Ok((parse_score(s)?, false)),
+    }
+}
+
+fn parse_score_range(min: &[u8], max: &[u8]) -> Result<ZScoreRange> {
+    let (min, minex) = parse_score_bound(min)?;
+    let (max, maxex) = parse_score_bound(max)?;
+    Ok(ZScoreRange {
+        min,
+        max,
+        minex,
+        maxex,
+    })
+}
+
+/// Parse a member bound, `[member`, `(member`, `-` or `+`
+fn parse_lex_bound(s: &Bytes) -> Result<ZLexBound> {
+    match &s[..] {
+        b"-" => Ok(ZLexBound::Min),
+        b"+" => Ok(ZLexBound::Max),
+        [b'[', ..] => Ok(ZLexBound::Included(s.slice(1..))),
+        [b'(', ..] => Ok(ZLexBound::Excluded(s.slice(1..))),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "min or max not valid string range item",
+        )),
+    }
+}
+
+fn parse_lex_range(min: &Bytes, max: &Bytes) -> Result<ZLexRange> {
+    Ok(ZLexRange {
+        min: parse_lex_bound(min)?,
+        max: parse_lex_bound(max)?,
+    })
+}
+
+/// Skip `offset` members and take `count` of them, all if negative
+fn page<'a>(
+    members: impl Iterator<Item = (&'a Bytes, f64)>,
+    offset: usize,
+    count: i64,
+) -> Vec<(Bytes, f64)> {
+    let count = usize::try_from(count).unwrap_or(usize::MAX);
+    members
+        .skip(offset)
+        .take(count)
+        .map(|(member, score)| (member.clone(), score))
+        .collect()
+}
+
+/// Reply the members, with their scores as `[member, score]` pairs for RESP3
+/// or flattened for RESP2
+fn members_reply(members: Vec<(Bytes, f64)>, withscores: bool, resp: u8) -> Frame {
+    if !withscores {
+        return Frame::Array(members.into_iter().map(|(m, _)| Frame::Bulk(m)).collect());
+    }
+    if resp >= 3 {
+        return Frame::Array(
+            members
+                .into_iter()
+                .map(|(m, s)| Frame::Array(vec![Frame::Bulk(m), Frame::Double(s)]))
+                .collect(),
+        );
+    }
+    let mut reply = Vec::with_capacity(members.len() * 2);
+    for (member, score) in members {
+        reply.push(Frame::Bulk(member));
+        reply.push(Frame::Double(score));
+    }
+    Frame::Array(reply)
+}
+
+/// Reply `f` applied to the sorted set at `key`, `missing` if there is no
+/// such key
+pub(super) fn read_zset(
+    db: &DatabaseRef,
+    key: &Bytes,
+    missing: Frame,
+    f: impl FnOnce(&RudisZSet) -> Frame,
+) -> Frame {
+    match db.get(key) {
+        Some(entry) => match &entry.value {
+            RudisObject::ZSet(z) => f(z),
+            _ => shared::wrong_type_err,
+        },
+        None => missing,
+    }
+}
+
+/// Reply `f` applied to the sorted set at `key`, which is created if
+/// `create`, otherwise `missing` is replied if there is no such key. The key
+/// is deleted once the set is empty
+pub(super) fn write_zset(
+    db: &DatabaseRef,
+    key: Bytes,
+    create: bool,
+    missing: Frame,
+    f: impl FnOnce(&mut RudisZSet) -> Frame,
+) -> Frame {
+    match db.entry(key) {
+        DictEntry::Occupied(mut oe) => {
+            let (reply, empty) = match &mut oe.get_mut().value {
+                RudisObject::ZSet(z) => (f(z), z.is_empty()),
+                _ => return shared::wrong_type_err,
+            };
+            if empty {
+                oe.remove();
+            }
+            reply
+        }
+        DictEntry::Vacant(ve) => {
+            if !create {
+                return missing;
+            }
+            let mut z = RudisZSet::new();
+            let reply = f(&mut z);
+            if !z.is_empty() {
+                ve.insert(DictValue::new(RudisObject::ZSet(z), None));
+            }
+            reply
+        }
+    }
+}
+
+/// Replace the key with the sorted set, or delete it if the set is empty
+fn store_zset(db: &DatabaseRef, key: Bytes, z: RudisZSet) {
+    if z.is_empty() {
+        db.remove(&key);
+    } else {
+        db.insert(key, RudisObject::ZSet(z), None);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZAdd {
+    pub key: Bytes,
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    /// reply the number of changed members instead of the added ones
+    pub ch: bool,
+    /// increment the score of a single member, like ZINCRBY
+    pub incr: bool,
+    pub elements: Vec<(f64, Bytes)>,
+}
+
+impl ZAdd {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("ZADD")?;
+        let mut cmd = Self {
+            key,
+            nx: false,
+            xx: false,
+            gt: false,
+            lt: false,
+            ch: false,
+            incr: false,
+            elements: Vec::new(),
+        };
+        let mut score = loop {
+            let arg = frame
+                .next_string()?
+                .ok_or_else(CommandParser::syntax_error)?;
+            match &arg.to_ascii_lowercase()[..] {
+                b"nx" => cmd.nx = true,
+                b"xx" => cmd.xx = true,
+                b"gt" => cmd.gt = true,
+                b"lt" => cmd.lt = true,
+                b"ch" => cmd.ch = true,
+                b"incr" => cmd.incr = true,
+                _ => break arg,
+            }
+        };
+        loop {
+            let member = frame
+                .next_string()?
+                .ok_or_else(CommandParser::syntax_error)?;
+            cmd.elements.push((parse_score(&score)?, member));
+            score = match frame.next_string()? {
+                Some(score) => score,
+                None => break,
+            };
+        }
+        if (cmd.nx && cmd.xx)
+            || (cmd.gt && cmd.lt)
+            || (cmd.nx && (cmd.gt || cmd.lt))
+            || (cmd.incr && cmd.elements.len() > 1)
+        {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(cmd)
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_zset(&db, self.key, !self.xx, Frame::Null, |z| {
+            let mut added = 0;
+            let mut updated = 0;
+            // the score of the last member, for INCR
+            let mut result = None;
+            for (score, member) in self.elements {
+                match z.score(&member) {
+                    Some(current) => {
+                        if self.nx {
+                            continue;
+                        }
+                        let score = if self.incr { current + score } else { score };
+                        if score.is_nan() {
+                            return Frame::Error(Bytes::from_static(
+                                b"ERR resulting score is not a number (NaN)",
+                            ));
+                        }
+                        if (self.gt && score <= current) || (self.lt && score >= current) {
+                            continue;
+                        }
+                        if score != current {
+                            z.insert(member, score);
+                            updated += 1;
+                        }
+                        result = Some(score);
+                    }
+                    None => {
+                        if self.xx {
+                            continue;
+                        }
+                        z.insert(member, score);
+                        added += 1;
+                        result = Some(score);
+                    }
+                }
+            }
+            if self.incr {
+                result.map_or(Frame::Null, Frame::Double)
+            } else if self.ch {
+                Frame::Integer(added + updated)
+            } else {
+                Frame::Integer(added)
+            }
+        });
+        let response = match response {
+            // XX on a missing key adds nothing
+            Frame::Null if !self.incr => Frame::Integer(0),
+            response => response,
+        };
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let flags = [
+            (self.nx, b"NX" as &[u8]),
+            (self.xx, b"XX"),
+            (self.gt, b"GT"),
+            (self.lt, b"LT"),
+            (self.ch, b"CH"),
+            (self.incr, b"INCR"),
+        ];
+        let scores: Vec<String> = self.elements.iter().map(|(s, _)| s.to_string()).collect();
+        shared::rewrite_args(
+            [b"ZADD" as &[u8], &self.key]
+                .into_iter()
+                .chain(flags.iter().filter(|(on, _)| *on).map(|(_, flag)| *flag))
+                .chain(
+                    scores
+                        .iter()
+                        .zip(&self.elements)
+                        .flat_map(|(score, (_, member))| [score.as_bytes(), &member[..]]),
+                ),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZIncrBy {
+    pub key: Bytes,
+    pub increment: f64,
+    pub member: Bytes,
+}
+
+impl ZIncrBy {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("ZINCRBY")?;
+        let increment = parse_score(
+            &frame
+                .next_string()?
+                .ok_or_else(CommandParser::syntax_error)?,
+        )?;
+        let member = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self {
+            key,
+            increment,
+            member,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_zset(&db, self.key, true, Frame::Null, |z| {
+            let score = z.score(&self.member).unwrap_or(0.0) + self.increment;
+            if score.is_nan() {
+                return Frame::Error(Bytes::from_static(
+                    b"ERR resulting score is not a number (NaN)",
+                ));
+            }
+            z.insert(self.member, score);
+            Frame::Double(score)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([
+            b"ZINCRBY" as &[u8],
+            &self.key,
+            self.increment.to_string().as_bytes(),
+            &self.member,
+        ])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZRem {
+    pub key: Bytes,
+    pub members: Vec<Bytes>,
+}
+
+impl ZRem {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("ZREM")?;
+        let mut members = vec![];
+        while let Some(member) = frame.next_string()? {
+            members.push(member);
+        }
+        if members.is_empty() {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(Self { key, members })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_zset(&db, self.key, false, Frame::Integer(0), |z| {
+            let removed = self
+                .members
+                .iter()
+                .filter(|member| z.remove(member).is_some())
+                .count();
+            Frame::Integer(removed as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args(
+            [b"ZREM" as &[u8], &self.key]
+                .into_iter()
+                .chain(self.members.iter().map(|m| &m[..])),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZScore {
+    pub key: Bytes,
+    pub member: Bytes,
+}
+
+impl ZScore {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("ZSCORE")?;
+        let member = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self { key, member })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_zset(&client.db, &self.key, Frame::Null, |z| {
+            z.score(&self.member).map_or(Frame::Null, Frame::Double)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZScan {
+    pub key: Bytes,
+    pub args: ScanArgs,
+}
+
+impl ZScan {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("ZSCAN")?;
+        let args = ScanArgs::from(frame, false)?;
+        Ok(Self { key, args })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_zset(&client.db, &self.key, ScanArgs::reply(0, Vec::new()), |z| {
+            let (members, next) = shared::scan_page(
+                z.iter()
+                    .map(|(member, score)| (shared::scan_hash(member), (member, score))),
+                self.args.cursor,
+                self.args.count,
+            );
+            let elements = members
+                .into_iter()
+                .filter(|(member, _)| self.args.matches(member))
+                .flat_map(|(member, score)| {
+                    [
+                        Frame::Bulk(member.clone()),
+                        Frame::Bulk(Bytes::from(score.to_string())),
+                    ]
+                })
+                .collect();
+            ScanArgs::reply(next.unwrap_or(0), elements)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZMScore {
+    pub key: Bytes,
+    pub members: Vec<Bytes>,
+}
+
+impl ZMScore {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("ZMSCORE")?;
+        let mut members = vec![];
+        while let Some(member) = frame.next_string()? {
+            members.push(member);
+        }
+        if members.is_empty() {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(Self { key, members })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let missing = Frame::Array(vec![Frame::Null; self.members.len()]);
+        let response = read_zset(&client.db, &self.key, missing, |z| {
+            Frame::Array(
+                self.members
+                    .iter()
+                    .map(|member| z.score(member).map_or(Frame::Null, Frame::Double))
+                    .collect(),
+            )
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZCard {
+    pub key: Bytes,
+}
+
+impl ZCard {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("ZCARD")?;
+        Ok(Self { key })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_zset(&client.db, &self.key, Frame::Integer(0), |z| {
+            Frame::Integer(z.len() as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZCount {
+    pub key: Bytes,
+    pub range: ZScoreRange,
+}
+
+impl ZCount {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("ZCOUNT")?;
+        let min = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let max = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let range = parse_score_range(&min, &max)?;
+        Ok(Self { key, range })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_zset(&client.db, &self.key, Frame::Integer(0), |z| {
+            Frame::Integer(z.range_by_score(&self.range).len() as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// ZRANK and ZREVRANK
+#[derive(Debug, Clone)]
+pub struct ZRank {
+    pub key: Bytes,
+    pub member: Bytes,
+    pub withscore: bool,
+    pub rev: bool,
+}
+
+impl ZRank {
+    pub fn from(frame: &mut CommandParser, rev: bool) -> Result<Self> {
+        let key = frame.next_key("ZRANK")?;
+        let member = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let withscore = match frame.next_string()? {
+            Some(arg) if arg.eq_ignore_ascii_case(b"withscore") => true,
+            Some(_) => return Err(CommandParser::syntax_error()),
+            None => false,
+        };
+        Ok(Self {
+            key,
+            member,
+            withscore,
+            rev,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let response = read_zset(&client.db, &self.key, Frame::Null, |z| {
+            let rank = match z.rank(&self.member) {
+                Some(rank) if self.rev => z.len() - 1 - rank,
+                Some(rank) => rank,
+                None => return Frame::Null,
+            };
+            if self.withscore {
+                let score = z.score(&self.member).unwrap_or_default();
+                Frame::Array(vec![Frame::Integer(rank as i64), Frame::Double(score)])
+            } else {
+                Frame::Integer(rank as i64)
+            }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ZRangeBy {
+    Rank(i64, i64),
+    Score(ZScoreRange),
+    Lex(ZLexRange),
+}
+
+/// The `start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count]` arguments
+/// of ZRANGE and ZRANGESTORE
+#[derive(Debug, Clone)]
+pub struct ZRangeSpec {
+    pub by: ZRangeBy,
+    pub rev: bool,
+    /// offset and count of LIMIT, a negative count for all the members
+    pub limit: Option<(i64, i64)>,
+    /// the arguments as given, to rewrite the command
+    pub args: Vec<Bytes>,
+}
+
+impl ZRangeSpec {
+    /// Parse the range, and WITHSCORES if `withscores_allowed`
+    fn from(frame: &mut CommandParser, withscores_allowed: bool) -> Result<(Self, bool)> {
+        let start = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let stop = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let mut args = vec![start.clone(), stop.clone()];
+        let (mut byscore, mut bylex, mut rev, mut withscores) = (false, false, false, false);
+        let mut limit = None;
+        while let Some(arg) = frame.next_string()? {
+            match &arg.to_ascii_lowercase()[..] {
+                b"byscore" => byscore = true,
+                b"bylex" => bylex = true,
+                b"rev" => rev = true,
+                b"withscores" if withscores_allowed => withscores = true,
+                b"limit" => {
+                    let offset = frame
+                        .next_integer()?
+                        .ok_or_else(CommandParser::syntax_error)?;
+                    let count = frame
+                        .next_integer()?
+                        .ok_or_else(CommandParser::syntax_error)?;
+                    args.push(arg);
+                    args.push(Bytes::from(offset.to_string()));
+                    args.push(Bytes::from(count.to_string()));
+                    limit = Some((offset, count));
+                    continue;
+                }
+                _ => return Err(CommandParser::syntax_error()),
+            }
+            args.push(arg);
+        }
+        // with REV the range is given from max to min
+        let (min, max) = if rev {
+            (&stop, &start)
+        } else {
+            (&start, &stop)
+        };
+        let by = match (byscore, bylex) {
+            (true, true) => return Err(CommandParser::syntax_error()),
+            (true, false) => ZRangeBy::Score(parse_score_range(min, max)?),
+            (false, true) if withscores => return Err(CommandParser::syntax_error()),
+            (false, true) => ZRangeBy::Lex(parse_lex_range(min, max)?),
+            (false, false) if limit.is_some() => return Err(CommandParser::syntax_error()),
+            (false, false) => {
+                let parse = |s: &Bytes| {
+                    std::str::from_utf8(s)
+                        .ok()
+                        .and_then(|s| s.parse::<i64>().ok())
+                        .ok_or_else(CommandParser::syntax_error)
+                };
+                ZRangeBy::Rank(parse(&start)?, parse(&stop)?)
+            }
+        };
+        Ok((
+            Self {
+                by,
+                rev,
+                limit,
+                args,
+            },
+            withscores,
+        ))
+    }
+
+    /// The members in the range, in the requested order
+    fn range(&self, z: &RudisZSet) -> Vec<(Bytes, f64)> {
+        let (offset, count) = self.limit.unwrap_or((0, -1));
+        let offset = match usize::try_from(offset) {
+            Ok(offset) => offset,
+            Err(_) => return Vec::new(),
+        };
+        match &self.by {
+            ZRangeBy::Rank(start, stop) => match shared::index_range(*start, *stop, z.len()) {
+                Some((start, stop)) if self.rev => {
+                    let last = z.len() - 1;
+                    page(z.range_by_rank(last - stop, last - start).rev(), 0, -1)
+                }
+                Some((start, stop)) => page(z.range_by_rank(start, stop), 0, -1),
+                None => Vec::new(),
+            },
+            ZRangeBy::Score(range) if self.rev => {
+                page(z.range_by_score(range).rev(), offset, count)
+            }
+            ZRangeBy::Score(range) => page(z.range_by_score(range), offset, count),
+            ZRangeBy::Lex(range) if self.rev => page(z.range_by_lex(range).rev(), offset, count),
+            ZRangeBy::Lex(range) => page(z.range_by_lex(range), offset, count),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZRange {
+    pub key: Bytes,
+    pub spec: ZRangeSpec,
+    pub withscores: bool,
+}
+
+impl ZRange {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("ZRANGE")?;
+        let (spec, withscores) = ZRangeSpec::from(frame, true)?;
+        Ok(Self {
+            key,
+            spec,
+            withscores,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let resp = client.resp;
+        let response = read_zset(&client.db, &self.key, Frame::new_array(), |z| {
+            members_reply(self.spec.range(z), self.withscores, resp)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZRangeStore {
+    pub dst: Bytes,
+    pub key: Bytes,
+    pub spec: ZRangeSpec,
+}
+
+impl ZRangeStore {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let dst = frame.next_key("ZRANGESTORE")?;
+        let key = frame.next_key("ZRANGESTORE")?;
+        let (spec, _) = ZRangeSpec::from(frame, false)?;
+        Ok(Self { dst, key, spec })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let members = match db.get(&self.key) {
+            Some(entry) => match &entry.value {
+                RudisObject::ZSet(z) => self.spec.range(z),
+                _ => {
+                    drop(entry);
+                    client.write_frame(&shared::wrong_type_err).await?;
+                    return Ok(());
+                }
+            },
+            None => Vec::new(),
+        };
+        let len = members.len();
+        let mut z = RudisZSet::new();
+        for (member, score) in members {
+            z.insert(member, score);
+        }
+        store_zset(&db, self.dst, z);
+        client.write_frame(&Frame::Integer(len as i64)).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args(
+            [b"ZRANGESTORE" as &[u8], &self.dst, &self.key]
+                .into_iter()
+                .chain(self.spec.args.iter().map(|a| &a[..])),
+        )
+    }
+}
+
+/// ZPOPMIN and ZPOPMAX
+#[derive(Debug, Clone)]
+pub struct ZPop {
+    pub key: Bytes,
+    pub count: Option<i64>,
+    pub max: bool,
+}
+
+impl ZPop {
+    pub fn from(frame: &mut CommandParser, max: bool) -> Result<Self> {
+        let key = frame.next_key(if max { "ZPOPMAX" } else { "ZPOPMIN" })?;
+        let count = frame.next_integer()?;
+        if count.is_some_and(|count| count < 0) {
+            return Err(CommandParser::syntax_error());
+        }
+        Ok(Self { key, count, max })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let resp = client.resp;
+        let response = write_zset(&db, self.key, false, Frame::new_array(), |z| {
+            let count = self.count.unwrap_or(1) as usize;
+            let mut popped = Vec::with_capacity(count.min(z.len()));
+            while popped.len() < count {
+                let element = if self.max {
+                    z.pop_last()
+                } else {
+                    z.pop_first()
+                };
+                match element {
+                    Some(element) => popped.push(element),
+                    None => break,
+                }
+            }
+            // a single member is replied flat, as in RESP2
+            match self.count {
+                Some(_) => members_reply(popped, true, resp),
+                None => members_reply(popped, true, 2),
+            }
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let cmd = if self.max {
+            b"ZPOPMAX" as &[u8]
+        } else {
+            b"ZPOPMIN"
+        };
+        let count = self.count.map(|count| count.to_string());
+        shared::rewrite_args(
+            [cmd, &self.key]
+                .into_iter()
+                .chain(count.as_deref().map(str::as_bytes)),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZRemRangeByRank {
+    pub key: Bytes,
+    pub start: i64,
+    pub stop: i64,
+}
+
+impl ZRemRangeByRank {
+    pub fn from(frame: &mut CommandParser) -> Result<Self> {
+        let key = frame.next_key("ZREMRANGEBYRANK")?;
+        let start = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let stop = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        Ok(Self { key, start, stop })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_zset(&db, self.key, false, Frame::Integer(0), |z| {
+            let members = match shared::index_range(self.start, self.stop, z.len()) {
+                Some((start, stop)) => page(z.range_by_rank(start, stop), 0, -1),
+                None => Vec::new(),
+            };
+            for (member, _) in &members {
+                z.remove(member);
+            }
+            Frame::Integer(members.len() as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        shared::rewrite_args([
+            b"ZREMRANGEBYRANK" as &[u8],
+            &self.key,
+            self.start.to_string().as_bytes(),
+            self.stop.to_string().as_bytes(),
+        ])
+    }
+}
+
+/// ZREMRANGEBYSCORE and ZREMRANGEBYLEX
+#[derive(Debug, Clone)]
+pub struct ZRemRangeBy {
+    pub key: Bytes,
+    pub by: ZRangeBy,
+    /// the min and max as given, to rewrite the command
+    pub min: Bytes,
+    pub max: Bytes,
+}
+
+impl ZRemRangeBy {
+    pub fn from(frame: &mut CommandParser, bylex: bool) -> Result<Self> {
+        let key = frame.next_key("ZREMRANGEBY")?;
+        let min = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let max = frame
+            .next_string()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        let by = if bylex {
+            ZRangeBy::Lex(parse_lex_range(&min, &max)?)
+        } else {
+            ZRangeBy::Score(parse_score_range(&min, &max)?)
+        };
+        Ok(Self { key, by, min, max })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+        let response = write_zset(&db, self.key, false, Frame::Integer(0), |z| {
+            let members = match &self.by {
+                ZRangeBy::Score(range) => page(z.range_by_score(range), 0, -1),
+                ZRangeBy::Lex(range) => page(z.range_by_lex(range), 0, -1),
+                ZRangeBy::Rank(..) => Vec::new(),
+            };
+            for (member, _) in &members {
+                z.remove(member);
+            }
+            Frame::Integer(members.len() as i64)
+        });
+        client.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let cmd = match self.by {
+            ZRangeBy::Lex(_) => b"ZREMRANGEBYLEX" as &[u8],
+            _ => b"ZREMRANGEBYSCORE",
+        };
+        shared::rewrite_args([cmd, &self.key, &self.min, &self.max])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZSetOp {
+    Union,
+    Inter,
+    Diff,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ZAggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl ZAggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // inf + -inf is taken as 0
+            ZAggregate::Sum => Some(a + b).filter(|s| !s.is_nan()).unwrap_or(0.0),
+            ZAggregate::Min => a.min(b),
+            ZAggregate::Max => a.max(b),
+        }
+    }
+}
+
+/// ZUNIONSTORE, ZINTERSTORE and ZDIFFSTORE, the plain sets are taken as
+/// sorted sets whose scores are all 1
+#[derive(Debug, Clone)]
+pub struct ZSetOpStore {
+    pub op: ZSetOp,
+    pub dst: Bytes,
+    pub keys: Vec<Bytes>,
+    pub weights: Vec<f64>,
+    pub aggregate: ZAggregate,
+}
+
+impl ZSetOpStore {
+    pub fn from(frame: &mut CommandParser, op: ZSetOp) -> Result<Self> {
+        let dst = frame.next_key("ZUNIONSTORE")?;
+        let numkeys = frame
+            .next_integer()?
+            .ok_or_else(CommandParser::syntax_error)?;
+        if numkeys <= 0 || numkeys as usize > frame.remaining() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "at least 1 input key is needed",
+            ));
+        }
+        let mut keys = Vec::with_capacity(numkeys as usize);
+        for _ in 0..numkeys {
+            keys.push(
+                frame
+                    .next_string()?
+                    .ok_or_else(CommandParser::syntax_error)?,
+            );
+        }
+        let mut weights = vec![1.0; keys.len()];
+        let mut aggregate = ZAggregate::Sum;
+        while let Some(arg) = frame.next_string()? {
+            match &arg.to_ascii_lowercase()[..] {
+                b"weights" if op != ZSetOp::Diff => {
+                    for weight in weights.iter_mut() {
+                        *weight = parse_score(
+                            &frame
+                                .next_string()?
+                                .ok_or_else(CommandParser::syntax_error)?,
+                        )?;
+                    }
+                }
+                b"aggregate" if op != ZSetOp::Diff => {
+                    let arg = frame
+                        .next_string()?
+                        .ok_or_else(CommandParser::syntax_error)?;
+                    aggregate = match &arg.to_ascii_lowercase()[..] {
+                        b"sum" => ZAggregate::Sum,
+                        b"min" => ZAggregate::Min,
+                        b"max" => ZAggregate::Max,
+                        _ => return Err(CommandParser::syntax_error()),
+                    };
+                }
+                _ => return Err(CommandParser::syntax_error()),
+            }
+        }
+        Ok(Self {
+            op,
+            dst,
+            keys,
+            weights,
+            aggregate,
+        })
+    }
+
+    pub async fn apply(self, client: &mut Client) -> Result<()> {
+        let db = client.db.clone();
+
+        // read all the sources before the destination is written
+        let mut sources = Vec::with_capacity(self.keys.len());
+        for (key, weight) in self.keys.iter().zip(&self.weights) {
+            let weighted = |score: f64| Some(score * weight).filter(|s| !s.is_nan()).unwrap_or(0.0);
+            let source: HashMap<Bytes, f64> = match db.get(key) {
+                Some(entry) => match &entry.value {
+                    RudisObject::ZSet(z) => {
+                        z.iter().map(|(m, s)| (m.clone(), weighted(s))).collect()
+                    }
+                    RudisObject::Set(s) => s.iter().map(|m| (m.clone(), weighted(1.0))).collect(),
+                    _ => {
+                        drop(entry);
+                        client.write_frame(&shared::wrong_type_err).await?;
+                        return Ok(());
+                    }
+                },
+                None => HashMap::new(),
+            };
+            sources.push(source);
+        }
+
+        let mut z = RudisZSet::new();
+        let (first, others) = sources.split_first().unwrap();
+        match self.op {
+            ZSetOp::Union => {
+                let mut union: HashMap<Bytes, f64> = HashMap::new();
+                for source in &sources {
+                    for (member, &score) in source {
+                        union
+                            .entry(member.clone())
+                            .and_modify(|s| *s = self.aggregate.apply(*s, score))
+                            .or_insert(score);
+                    }
+                }
+                for (member, score) in union {
+                    z.insert(member, score);
+                }
+            }
+            ZSetOp::Inter => {
+                'members: for (member, &score) in first {
+                    let mut score = score;
+                    for source in others {
+                        match source.get(member) {
+                            Some(&other) => score = self.aggregate.apply(score, other),
+                            None => continue 'members,
+                        }
+                    }
+                    z.insert(member.clone(), score);
+                }
+            }
+            ZSetOp::Diff => {
+                let others: HashSet<&Bytes> = others.iter().flat_map(|s| s.keys()).collect();
+                for (member, &score) in first {
+                    if !others.contains(member) {
+                        z.insert(member.clone(), score);
+                    }
+                }
+            }
+        }
+
+        let len = z.len();
+        store_zset(&db, self.dst, z);
+        client.write_frame(&Frame::Integer(len as i64)).await?;
+        Ok(())
+    }
+
+    pub fn rewrite(&self) -> BytesMut {
+        let cmd = match self.op {
+            ZSetOp::Union => b"ZUNIONSTORE" as &[u8],
+            ZSetOp::Inter => b"ZINTERSTORE",
+            ZSetOp::Diff => b"ZDIFFSTORE",
+        };
+        let numkeys = self.keys.len().to_string();
+        let weights: Vec<String> = self.weights.iter().map(|w| w.to_string()).collect();
+        let mut args = vec![cmd, &self.dst, numkeys.as_bytes()];
+        args.extend(self.keys.iter().map(|k| &k[..]));
+        if self.op != ZSetOp::Diff {
+            args.push(b"WEIGHTS");
+            args.extend(weights.iter().map(|w| w.as_bytes()));
+            args.push(b"AGGREGATE");
+            args.push(match self.aggregate {
+                ZAggregate::Sum => b"SUM",
+                ZAggregate::Min => b"MIN",
+                ZAggregate::Max => b"MAX",
+            });
+        }
+        shared::rewrite_args(args)
+    }
+}
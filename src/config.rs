@@ -3,7 +3,10 @@ use std::{fmt::Display, ops::Deref};
 
 use crate::{
     aof::{AofFsync, AofOption},
+    evict::MaxmemoryPolicy,
+    frame::ProtoLimits,
     rdb::AutoSave,
+    shared,
 };
 use tokio::sync::RwLock;
 use toml::Table;
@@ -60,6 +63,83 @@ pub struct Config {
     pub aof_state: AofOption,
     pub aof_fsync: AofFsync,
     pub aof_filename: String,
+    /// password of the default user
+    pub requirepass: Option<String>,
+    /// file the ACL users are loaded from and saved to
+    pub aclfile: Option<String>,
+    /// memory limit in bytes, 0 for no limit
+    pub maxmemory: u64,
+    pub maxmemory_policy: MaxmemoryPolicy,
+    /// number of keys sampled per database to find the keys to evict
+    pub maxmemory_samples: usize,
+    /// close the connections idle for this many seconds, 0 to never close
+    pub timeout: u64,
+    /// TCP keepalive interval in seconds, 0 to disable
+    pub tcp_keepalive: u64,
+    /// max length of a bulk in the requests
+    pub proto_max_bulk_len: u64,
+    /// max number of arguments of a request
+    pub proto_max_multibulk_len: u64,
+    /// max nesting depth of the aggregates in the requests
+    pub proto_max_depth: u64,
+    /// max size of the query buffer of a client
+    pub client_query_buffer_limit: u64,
+    /// limits on the messages queued for a subscribed client
+    pub client_output_buffer_limit_pubsub: OutputBufferLimit,
+}
+
+/// Limits on the bytes queued for a client, see client-output-buffer-limit in
+/// redis. The client is disconnected once past the hard limit, or past the
+/// soft limit for more than `soft_seconds`. 0 disables a limit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputBufferLimit {
+    pub hard: u64,
+    pub soft: u64,
+    pub soft_seconds: u64,
+}
+
+impl OutputBufferLimit {
+    /// Parse `<hard> <soft> <soft seconds>`, e.g. `32mb 8mb 60`
+    pub fn parse(s: &str) -> Option<OutputBufferLimit> {
+        let mut args = s.split_whitespace();
+        let limit = OutputBufferLimit {
+            hard: shared::parse_memory(args.next()?)?,
+            soft: shared::parse_memory(args.next()?)?,
+            soft_seconds: args.next()?.parse().ok()?,
+        };
+        match args.next() {
+            Some(_) => None,
+            None => Some(limit),
+        }
+    }
+}
+
+impl Default for OutputBufferLimit {
+    /// The default of the pubsub clients
+    fn default() -> Self {
+        OutputBufferLimit {
+            hard: 32 * 1024 * 1024,
+            soft: 8 * 1024 * 1024,
+            soft_seconds: 60,
+        }
+    }
+}
+
+impl Display for OutputBufferLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.hard, self.soft, self.soft_seconds)
+    }
+}
+
+impl Config {
+    /// Limits on the frames read from the clients
+    pub fn proto_limits(&self) -> ProtoLimits {
+        ProtoLimits {
+            max_bulk_len: self.proto_max_bulk_len as usize,
+            max_multibulk_len: self.proto_max_multibulk_len as usize,
+            max_depth: self.proto_max_depth as usize,
+        }
+    }
 }
 
 impl Default for Config {
@@ -71,6 +151,18 @@ impl Default for Config {
             aof_state: AofOption::Off,
             aof_fsync: AofFsync::Everysec,
             aof_filename: "appendonly.aof".to_owned(),
+            requirepass: None,
+            aclfile: None,
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::NoEviction,
+            maxmemory_samples: 5,
+            timeout: 0,
+            tcp_keepalive: 300,
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            proto_max_multibulk_len: 1024 * 1024,
+            proto_max_depth: 8,
+            client_query_buffer_limit: 1024 * 1024 * 1024,
+            client_output_buffer_limit_pubsub: OutputBufferLimit::default(),
             db_num: 16,
             hz: 10,
             verbosity: Verbosity::Normal,
@@ -146,6 +238,55 @@ impl Config {
                 AutoSave { seconds, changes }
             })
             .collect();
+        let requirepass = table
+            .get("requirepass")
+            .map(|v| v.as_str().unwrap().to_string());
+        let aclfile = table
+            .get("aclfile")
+            .map(|v| v.as_str().unwrap().to_string());
+        let maxmemory = match table.get("maxmemory") {
+            Some(v) if v.is_integer() => v.as_integer().unwrap() as u64,
+            Some(v) => shared::parse_memory(v.as_str().unwrap()).unwrap(),
+            None => 0,
+        };
+        let maxmemory_policy = table
+            .get("maxmemory_policy")
+            .map(|v| MaxmemoryPolicy::parse(v.as_str().unwrap()).unwrap())
+            .unwrap_or_default();
+        let maxmemory_samples = table
+            .get("maxmemory_samples")
+            .map(|v| v.as_integer().unwrap() as usize)
+            .unwrap_or(5);
+        let timeout = table
+            .get("timeout")
+            .map(|v| v.as_integer().unwrap() as u64)
+            .unwrap_or(0);
+        let tcp_keepalive = table
+            .get("tcp_keepalive")
+            .map(|v| v.as_integer().unwrap() as u64)
+            .unwrap_or(300);
+        let proto_max_bulk_len = match table.get("proto_max_bulk_len") {
+            Some(v) if v.is_integer() => v.as_integer().unwrap() as u64,
+            Some(v) => shared::parse_memory(v.as_str().unwrap()).unwrap(),
+            None => 512 * 1024 * 1024,
+        };
+        let proto_max_multibulk_len = table
+            .get("proto_max_multibulk_len")
+            .map(|v| v.as_integer().unwrap() as u64)
+            .unwrap_or(1024 * 1024);
+        let proto_max_depth = table
+            .get("proto_max_depth")
+            .map(|v| v.as_integer().unwrap() as u64)
+            .unwrap_or(8);
+        let client_query_buffer_limit = match table.get("client_query_buffer_limit") {
+            Some(v) if v.is_integer() => v.as_integer().unwrap() as u64,
+            Some(v) => shared::parse_memory(v.as_str().unwrap()).unwrap(),
+            None => 1024 * 1024 * 1024,
+        };
+        let client_output_buffer_limit_pubsub = table
+            .get("client_output_buffer_limit_pubsub")
+            .map(|v| OutputBufferLimit::parse(v.as_str().unwrap()).unwrap())
+            .unwrap_or_default();
         Config {
             bindaddr,
             port,
@@ -157,6 +298,18 @@ impl Config {
             save_params,
             aof_state,
             aof_fsync,
+            requirepass,
+            aclfile,
+            maxmemory,
+            maxmemory_policy,
+            maxmemory_samples,
+            timeout,
+            tcp_keepalive,
+            proto_max_bulk_len,
+            proto_max_multibulk_len,
+            proto_max_depth,
+            client_query_buffer_limit,
+            client_output_buffer_limit_pubsub,
         }
     }
 }
@@ -1,47 +1,70 @@
-use crate::frame::Frame;
-use bytes::{Buf, BytesMut};
-use std::io::Cursor;
+use crate::frame::{CheckState, Frame, ProtoLimits};
+use bytes::BytesMut;
 use std::io::{Error, ErrorKind, Result};
+use std::os::fd::AsRawFd;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
 
 #[derive(Debug)]
 pub struct Connection {
     pub stream: TcpStream,
     pub buffer: BytesMut,
+    /// progress on the request partially read in the buffer
+    check_state: CheckState,
+    /// replies waiting to be flushed
+    pub wbuffer: BytesMut,
+    /// limits on the requests
+    pub limits: ProtoLimits,
+    /// max size the query buffer can grow to
+    pub query_buffer_limit: usize,
 }
 
 const BUFFER_SIZE: usize = 16 * 1024;
+/// The replies are flushed once this many bytes are pending, even if more
+/// pipelined requests are waiting
+pub const REPLY_FLUSH_SIZE: usize = 64 * 1024;
+/// A grown query buffer is released after being empty for this long
+const QUERY_BUFFER_IDLE_TIME: Duration = Duration::from_secs(2);
 
 impl Connection {
     pub fn from(stream: TcpStream) -> Connection {
         Connection {
             stream,
             buffer: BytesMut::with_capacity(BUFFER_SIZE),
+            check_state: CheckState::default(),
+            wbuffer: BytesMut::with_capacity(BUFFER_SIZE),
+            limits: ProtoLimits::UNLIMITED,
+            query_buffer_limit: usize::MAX,
         }
     }
 
     // read a frame from the connection
     pub async fn read_frame(&mut self) -> Result<Option<Frame>> {
         loop {
-            // log::debug!(
-            //     "buffer cap = {}, len = {}, content = {:?}",
-            //     self.buffer.capacity(),
-            //     self.buffer.len(),
-            //     &self.buffer
-            // );
-
-            if let Some(frame) = self.parse_frame().await? {
+            if let Some(frame) = self.parse_frame()? {
                 return Ok(Some(frame));
             }
 
             // no enough data, need to read more
-            // ensure the buffer has enough capacity
-            let len = self.buffer.len();
-            let max_read = BUFFER_SIZE - len;
-            if max_read == 0 {
-                return Err(Error::new(ErrorKind::Other, "frame is too large"));
+            if self.buffer.len() >= self.query_buffer_limit {
+                return Err(Error::new(ErrorKind::Other, "query buffer limit reached"));
+            }
+
+            // shrink a buffer grown by a big request once the client is idle
+            if self.buffer.is_empty() && self.buffer.capacity() > BUFFER_SIZE {
+                let readable = timeout(QUERY_BUFFER_IDLE_TIME, self.stream.readable()).await;
+                if readable.is_err() {
+                    self.buffer = BytesMut::with_capacity(BUFFER_SIZE);
+                    continue;
+                }
+            }
+
+            // grow the buffer, doubling its capacity, until the pending
+            // frame fits
+            if self.buffer.capacity() - self.buffer.len() < BUFFER_SIZE {
+                self.buffer.reserve(BUFFER_SIZE);
             }
 
             let n_read = self.stream.read_buf(&mut self.buffer).await?;
@@ -57,17 +80,94 @@ impl Connection {
         }
     }
 
-    async fn parse_frame(&mut self) -> Result<Option<Frame>> {
-        let mut cur = Cursor::new(&self.buffer);
-        if let Some(frame) = Frame::parse(&mut cur)? {
-            // if a frame is parsed successfully, advance the buffer
-            self.buffer.advance(cur.position() as usize);
-            return Ok(Some(frame));
+    /// Wait for the peer to close the connection, buffering what it sends in
+    /// the meantime, used while the client is blocked
+    pub async fn wait_closed(&mut self) -> Result<()> {
+        loop {
+            if self.buffer.len() >= self.query_buffer_limit {
+                return Err(Error::other("query buffer limit reached"));
+            }
+            if self.buffer.capacity() - self.buffer.len() < BUFFER_SIZE {
+                self.buffer.reserve(BUFFER_SIZE);
+            }
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                log::debug!("connection closed");
+                return Ok(());
+            }
         }
-        Ok(None)
     }
 
-    pub async fn write_frame(&mut self, frame: &Frame) -> std::io::Result<usize> {
-        self.stream.write(&frame.serialize()).await
+    /// Parse a frame already in the read buffer, without reading the stream
+    pub fn parse_frame(&mut self) -> Result<Option<Frame>> {
+        loop {
+            // the requests not starting with '*' are inline commands
+            let frame = match self.buffer.first() {
+                None => return Ok(None),
+                Some(b'*') => {
+                    Frame::parse_request(&mut self.buffer, &self.limits, &mut self.check_state)?
+                }
+                Some(_) => Frame::parse_inline(&mut self.buffer)?,
+            };
+            match frame {
+                // empty requests, e.g. blank lines, are skipped
+                Some(Frame::Array(args)) if args.is_empty() => {}
+                Some(frame) => return Ok(Some(frame)),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Enable TCP keepalive, probing the peer every `interval` seconds and
+    /// dropping it after 3 unanswered probes, see anetKeepAlive in redis
+    pub fn set_keepalive(&self, interval: u64) -> Result<()> {
+        let fd = self.stream.as_raw_fd();
+        let set = |level, name, value: libc::c_int| {
+            let ret = unsafe {
+                libc::setsockopt(
+                    fd,
+                    level,
+                    name,
+                    &value as *const libc::c_int as *const libc::c_void,
+                    std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+                )
+            };
+            if ret == -1 {
+                Err(Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        };
+        let interval = interval.min(libc::c_int::MAX as u64) as libc::c_int;
+        set(libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+        set(libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, interval)?;
+        set(
+            libc::IPPROTO_TCP,
+            libc::TCP_KEEPINTVL,
+            (interval / 3).max(1),
+        )?;
+        set(libc::IPPROTO_TCP, libc::TCP_KEEPCNT, 3)?;
+        Ok(())
+    }
+
+    /// Append the frame to the output buffer, it is sent on the next flush
+    pub fn write_frame(&mut self, frame: &Frame, resp: u8) -> usize {
+        let len = self.wbuffer.len();
+        frame.encode(&mut self.wbuffer, resp);
+        self.wbuffer.len() - len
+    }
+
+    /// Send all the buffered replies
+    pub async fn flush(&mut self) -> Result<()> {
+        if self.wbuffer.is_empty() {
+            return Ok(());
+        }
+        // large replies may not fit in the socket buffer at once
+        self.stream.write_all(&self.wbuffer).await?;
+        self.wbuffer.clear();
+        // release the memory held by a large reply
+        if self.wbuffer.capacity() > REPLY_FLUSH_SIZE {
+            self.wbuffer = BytesMut::with_capacity(BUFFER_SIZE);
+        }
+        Ok(())
     }
 }
@@ -1,12 +1,19 @@
+use crate::client::REDIS_DIRTY_CAS;
+use crate::evict;
 use crate::object::RudisObject;
 use crate::shared;
 use bytes::Bytes;
-use dashmap::mapref::entry::Entry;
+use dashmap::iter::Iter;
+use dashmap::mapref::entry::{self, Entry};
 use dashmap::mapref::one::{Ref, RefMut};
 use dashmap::DashMap;
+use parking_lot::Mutex;
+use rand::Rng;
+use std::collections::{BTreeSet, HashMap, VecDeque};
 use std::ops::{Deref, DerefMut};
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
+use tokio::sync::Notify;
 
 #[derive(Default, Clone)]
 pub struct DatabaseRef {
@@ -14,12 +21,10 @@ pub struct DatabaseRef {
     inner: Arc<Dict>,
 }
 
-static mut ID: AtomicU32 = AtomicU32::new(0);
-
 impl DatabaseRef {
-    pub fn new() -> DatabaseRef {
+    pub fn new(index: u32) -> DatabaseRef {
         DatabaseRef {
-            index: unsafe { ID.fetch_add(1, Ordering::Relaxed) },
+            index,
             inner: Arc::new(Dict::new()),
         }
     }
@@ -39,15 +44,56 @@ impl DerefMut for DatabaseRef {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct DictValue {
     pub value: RudisObject,
     pub expire_at: Option<u64>,
+    /// LRU clock of the last access
+    pub lru: AtomicU32,
+    /// LFU access time and counter, see evict::lfu_decr_and_return
+    pub lfu: AtomicU32,
+}
+
+impl Clone for DictValue {
+    fn clone(&self) -> Self {
+        DictValue {
+            value: self.value.clone(),
+            expire_at: self.expire_at,
+            lru: AtomicU32::new(self.lru.load(Ordering::Relaxed)),
+            lfu: AtomicU32::new(self.lfu.load(Ordering::Relaxed)),
+        }
+    }
 }
 
 impl DictValue {
     pub fn new(value: RudisObject, expire_at: Option<u64>) -> DictValue {
-        DictValue { value, expire_at }
+        DictValue {
+            value,
+            expire_at,
+            lru: AtomicU32::new(evict::lru_clock()),
+            lfu: AtomicU32::new((evict::lfu_time_in_minutes() << 8) | evict::LFU_INIT_VAL as u32),
+        }
+    }
+
+    /// Update the access metadata used by the eviction policies
+    pub fn touch(&self) {
+        self.lru.store(evict::lru_clock(), Ordering::Relaxed);
+        let counter = evict::lfu_decr_and_return(self.lfu.load(Ordering::Relaxed));
+        let counter = evict::lfu_log_incr(counter);
+        self.lfu.store(
+            (evict::lfu_time_in_minutes() << 8) | counter as u32,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Milliseconds since the last access
+    pub fn idle_time(&self) -> u64 {
+        evict::estimate_idle_time(self.lru.load(Ordering::Relaxed))
+    }
+
+    /// The LFU access counter, decayed by the time since the last access
+    pub fn lfu_counter(&self) -> u8 {
+        evict::lfu_decr_and_return(self.lfu.load(Ordering::Relaxed))
     }
 
     pub fn is_volatile(&self) -> bool {
@@ -65,19 +111,240 @@ impl DictValue {
     }
 }
 
-#[derive(Default, Clone, Debug)]
+/// Stripes of the scan index, a power of two
+const SCAN_INDEX_STRIPES: usize = 64;
+
+/// The keys of a database ordered by their scan hash, so that SCAN finds
+/// the keys from its cursor on without visiting the others. The hashes are
+/// split in ranges, each behind its own lock
+#[derive(Debug)]
+struct ScanIndex {
+    stripes: Vec<Mutex<BTreeSet<(u64, Bytes)>>>,
+}
+
+impl Default for ScanIndex {
+    fn default() -> Self {
+        ScanIndex {
+            stripes: (0..SCAN_INDEX_STRIPES).map(|_| Mutex::default()).collect(),
+        }
+    }
+}
+
+impl ScanIndex {
+    /// The stripe of the hashes the cursor is in
+    fn stripe_of(cursor: u64) -> usize {
+        (cursor >> (64 - SCAN_INDEX_STRIPES.trailing_zeros())) as usize
+    }
+
+    fn insert(&self, key: &Bytes) {
+        let hash = shared::scan_hash(key);
+        self.stripes[Self::stripe_of(hash)]
+            .lock()
+            .insert((hash, key.clone()));
+    }
+
+    fn remove(&self, key: &Bytes) {
+        let hash = shared::scan_hash(key);
+        self.stripes[Self::stripe_of(hash)]
+            .lock()
+            .remove(&(hash, key.clone()));
+    }
+
+    fn clear(&self) {
+        for stripe in &self.stripes {
+            stripe.lock().clear();
+        }
+    }
+
+    /// Take `count` keys from `cursor` on, and the others of the same hash
+    /// as the last one, return them with the cursor of the next page, 0
+    /// once the keys are all taken
+    fn page(&self, mut cursor: u64, count: usize) -> (Vec<Bytes>, u64) {
+        let count = count.max(1);
+        let mut keys = Vec::new();
+        let mut last = None;
+        loop {
+            let index = Self::stripe_of(cursor);
+            for (hash, key) in self.stripes[index].lock().range((cursor, Bytes::new())..) {
+                if keys.len() >= count && last != Some(*hash) {
+                    return (keys, *hash);
+                }
+                keys.push(key.clone());
+                last = Some(*hash);
+            }
+            if index + 1 == SCAN_INDEX_STRIPES {
+                return (keys, 0);
+            }
+            cursor = ((index + 1) as u64) << (64 - SCAN_INDEX_STRIPES.trailing_zeros());
+        }
+    }
+}
+
+/// Entry of a key of a database, see Dict::entry
+pub enum DictEntry<'a> {
+    Occupied(OccupiedEntry<'a>),
+    Vacant(VacantEntry<'a>),
+}
+
+impl<'a> DictEntry<'a> {
+    pub fn or_insert_with(self, value: impl FnOnce() -> DictValue) -> RefMut<'a, Bytes, DictValue> {
+        match self {
+            DictEntry::Occupied(oe) => oe.into_ref(),
+            DictEntry::Vacant(ve) => ve.insert(value()),
+        }
+    }
+}
+
+pub struct OccupiedEntry<'a> {
+    entry: entry::OccupiedEntry<'a, Bytes, DictValue>,
+    index: &'a ScanIndex,
+}
+
+impl<'a> OccupiedEntry<'a> {
+    pub fn get(&self) -> &DictValue {
+        self.entry.get()
+    }
+
+    pub fn get_mut(&mut self) -> &mut DictValue {
+        self.entry.get_mut()
+    }
+
+    pub fn into_ref(self) -> RefMut<'a, Bytes, DictValue> {
+        self.entry.into_ref()
+    }
+
+    /// Delete the key
+    pub fn remove(self) -> DictValue {
+        self.index.remove(self.entry.key());
+        self.entry.remove()
+    }
+}
+
+pub struct VacantEntry<'a> {
+    entry: entry::VacantEntry<'a, Bytes, DictValue>,
+    index: &'a ScanIndex,
+}
+
+impl<'a> VacantEntry<'a> {
+    pub fn insert(self, value: DictValue) -> RefMut<'a, Bytes, DictValue> {
+        self.index.insert(self.entry.key());
+        self.entry.insert(value)
+    }
+}
+
+#[derive(Default, Debug)]
 pub struct Dict {
-    pub dict: DashMap<Bytes, DictValue>, // millisecond timestamp
+    /// the keys are added and deleted through the methods of Dict, which
+    /// keep the scan index up to date
+    dict: DashMap<Bytes, DictValue>,
+    /// WATCHed keys, key -> (client id -> client flags)
+    pub watched_keys: DashMap<Bytes, HashMap<u64, Arc<AtomicU32>>>,
+    /// clients blocked on a list key, key -> (client id, notify) in the order
+    /// they blocked
+    pub blocking_keys: DashMap<Bytes, VecDeque<(u64, Arc<Notify>)>>,
+    /// number of keys deleted because they expired
+    pub expired_keys: AtomicU64,
+    /// average TTL in milliseconds of the volatile keys sampled by the
+    /// active expire cycle
+    pub avg_ttl: AtomicU64,
+    scan_index: ScanIndex,
 }
 
 impl Dict {
     pub fn new() -> Dict {
         Dict {
             dict: DashMap::new(),
+            watched_keys: DashMap::new(),
+            blocking_keys: DashMap::new(),
+            expired_keys: AtomicU64::new(0),
+            avg_ttl: AtomicU64::new(0),
+            scan_index: ScanIndex::default(),
+        }
+    }
+
+    pub fn watch_key(&self, key: Bytes, client_id: u64, flags: Arc<AtomicU32>) {
+        self.watched_keys
+            .entry(key)
+            .or_default()
+            .insert(client_id, flags);
+    }
+
+    pub fn unwatch_key(&self, key: &Bytes, client_id: u64) {
+        if let Entry::Occupied(mut oe) = self.watched_keys.entry(key.clone()) {
+            oe.get_mut().remove(&client_id);
+            if oe.get().is_empty() {
+                oe.remove();
+            }
+        }
+    }
+
+    /// Mark the transactions of all the clients watching the key as dirty
+    pub fn touch_watched_key(&self, key: &Bytes) {
+        if self.watched_keys.is_empty() {
+            return;
+        }
+        if let Some(clients) = self.watched_keys.get(key) {
+            for flags in clients.values() {
+                flags.fetch_or(REDIS_DIRTY_CAS, Ordering::Relaxed);
+            }
         }
     }
 
-    pub fn check_expired(&self, key: &Bytes) {
+    pub fn block_key(&self, key: Bytes, client_id: u64, notify: Arc<Notify>) {
+        self.blocking_keys
+            .entry(key)
+            .or_default()
+            .push_back((client_id, notify));
+    }
+
+    pub fn unblock_key(&self, key: &Bytes, client_id: u64) {
+        if let Entry::Occupied(mut oe) = self.blocking_keys.entry(key.clone()) {
+            oe.get_mut().retain(|(id, _)| *id != client_id);
+            if oe.get().is_empty() {
+                oe.remove();
+            }
+        }
+    }
+
+    /// Wake the client blocked on the key for the longest time, called when
+    /// elements are pushed to the key. The client passes the wakeup on when
+    /// it unblocks and elements are left
+    pub fn signal_key_as_ready(&self, key: &Bytes) {
+        if self.blocking_keys.is_empty() {
+            return;
+        }
+        if let Some(clients) = self.blocking_keys.get(key) {
+            if let Some((_, notify)) = clients.front() {
+                notify.notify_one();
+            }
+        }
+    }
+
+    /// Wake every client blocked on the key, called when entries are added
+    /// to a stream, which all its readers get
+    pub fn signal_key_as_ready_to_all(&self, key: &Bytes) {
+        if self.blocking_keys.is_empty() {
+            return;
+        }
+        if let Some(clients) = self.blocking_keys.get(key) {
+            for (_, notify) in clients.iter() {
+                notify.notify_one();
+            }
+        }
+    }
+
+    /// Touch every watched key, used when the whole database is flushed or
+    /// swapped
+    pub fn touch_all_watched_keys(&self) {
+        for clients in self.watched_keys.iter() {
+            for flags in clients.values() {
+                flags.fetch_or(REDIS_DIRTY_CAS, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Delete the key if it is expired, return whether it was deleted
+    pub fn check_expired(&self, key: &Bytes) -> bool {
         let exist_and_expire = {
             if let Some(entry) = self.dict.get(key) {
                 entry.is_expired()
@@ -85,29 +352,82 @@ impl Dict {
                 false
             }
         };
-        if exist_and_expire {
-            self.dict.remove(key);
+        if !exist_and_expire {
+            return false;
+        }
+        // the key may have been overwritten in the meantime
+        match self.dict.entry(key.clone()) {
+            Entry::Occupied(oe) if oe.get().is_expired() => {
+                self.scan_index.remove(key);
+                oe.remove();
+                self.expired_keys.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            _ => false,
         }
     }
 
     pub fn get(&self, key: &Bytes) -> Option<Ref<'_, Bytes, DictValue>> {
         self.check_expired(key);
-        self.dict.get(key)
+        let entry = self.dict.get(key);
+        if let Some(entry) = &entry {
+            entry.touch();
+        }
+        entry
     }
 
     pub fn get_mut(&self, key: &Bytes) -> Option<RefMut<'_, Bytes, DictValue>> {
         self.check_expired(key);
-        self.dict.get_mut(key)
+        self.touch_watched_key(key);
+        let entry = self.dict.get_mut(key);
+        if let Some(entry) = &entry {
+            entry.touch();
+        }
+        entry
     }
 
     pub fn remove(&self, key: &Bytes) -> Option<(Bytes, DictValue)> {
         self.check_expired(key);
-        self.dict.remove(key)
+        let removed = match self.dict.entry(key.clone()) {
+            Entry::Occupied(oe) => {
+                self.scan_index.remove(key);
+                Some(oe.remove_entry())
+            }
+            Entry::Vacant(_) => None,
+        };
+        if removed.is_some() {
+            self.touch_watched_key(key);
+        }
+        removed
     }
 
-    pub fn entry(&self, key: Bytes) -> Entry<'_, Bytes, DictValue> {
+    pub fn entry(&self, key: Bytes) -> DictEntry<'_> {
         self.check_expired(&key);
-        self.dict.entry(key)
+        self.touch_watched_key(&key);
+        let index = &self.scan_index;
+        match self.dict.entry(key) {
+            Entry::Occupied(entry) => DictEntry::Occupied(OccupiedEntry { entry, index }),
+            Entry::Vacant(entry) => DictEntry::Vacant(VacantEntry { entry, index }),
+        }
+    }
+
+    /// The number of keys, including the expired ones not deleted yet
+    pub fn len(&self) -> usize {
+        self.dict.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dict.is_empty()
+    }
+
+    /// The number of keys the dict can hold without reallocating
+    pub fn capacity(&self) -> usize {
+        self.dict.capacity()
+    }
+
+    /// Visit all the entries, the expired ones are not deleted
+    pub fn iter(&self) -> Iter<'_, Bytes, DictValue> {
+        self.dict.iter()
     }
 
     pub fn contains_key(&self, key: &Bytes) -> bool {
@@ -120,20 +440,97 @@ impl Dict {
         value: RudisObject,
         expire_at: Option<u64>,
     ) -> Option<DictValue> {
-        self.dict.insert(key, DictValue::new(value, expire_at))
+        self.touch_watched_key(&key);
+        self.insert_value(key, DictValue::new(value, expire_at))
+    }
+
+    /// Insert the value as is, e.g. with the expire and the access metadata
+    /// it had in another database
+    pub fn insert_value(&self, key: Bytes, value: DictValue) -> Option<DictValue> {
+        match self.dict.entry(key) {
+            Entry::Occupied(mut oe) => Some(oe.insert(value)),
+            Entry::Vacant(ve) => {
+                self.scan_index.insert(ve.key());
+                ve.insert(value);
+                None
+            }
+        }
     }
 
     pub fn rename(&self, key: &Bytes, new_key: Bytes) -> bool {
-        if let Some(v) = self.dict.remove(key) {
-            self.dict.insert(new_key.clone(), v.1);
+        if let Some((_, value)) = self.remove(key) {
+            self.touch_watched_key(&new_key);
+            self.insert_value(new_key, value);
             true
         } else {
             false
         }
     }
 
+    /// Delete all the keys
+    pub fn clear(&self) {
+        // a key added meanwhile at worst stays in the index, which SCAN skips
+        self.scan_index.clear();
+        self.dict.clear();
+    }
+
+    /// Visit the entries starting from a random position of a random shard,
+    /// until `count` of them are accepted by `f` or `max_visits` entries were
+    /// visited, see dictGetSomeKeys in redis
+    pub fn sample<T>(
+        &self,
+        count: usize,
+        max_visits: usize,
+        mut f: impl FnMut(&Bytes, &DictValue) -> Option<T>,
+    ) -> Vec<T> {
+        let mut visits = 0;
+        let mut samples = Vec::with_capacity(count);
+        let shards = self.dict.shards();
+        let mut rng = rand::thread_rng();
+        let first = rng.gen_range(0..shards.len());
+        for i in 0..shards.len() {
+            let shard = shards[(first + i) % shards.len()].read();
+            if shard.is_empty() {
+                continue;
+            }
+            let offset = rng.gen_range(0..shard.len());
+            for (key, value) in shard.iter().skip(offset).chain(shard.iter().take(offset)) {
+                if visits == max_visits {
+                    return samples;
+                }
+                visits += 1;
+                if let Some(sample) = f(key, value.get()) {
+                    samples.push(sample);
+                    if samples.len() == count {
+                        return samples;
+                    }
+                }
+            }
+        }
+        samples
+    }
+
+    /// Visit `count` entries from `cursor` on, return the cursor of the next
+    /// call, 0 once all the entries were visited. The entries are visited in
+    /// the order of the hash of their key, so a key present for the whole
+    /// scan is visited whatever is added or removed in between, see
+    /// scanGenericCommand in redis
+    pub fn scan<T>(
+        &self,
+        cursor: u64,
+        count: usize,
+        mut f: impl FnMut(&Bytes, &DictValue) -> Option<T>,
+    ) -> (u64, Vec<T>) {
+        let (keys, cursor) = self.scan_index.page(cursor, count);
+        let found = keys
+            .iter()
+            .filter_map(|key| f(key, self.dict.get(key)?.value()))
+            .collect();
+        (cursor, found)
+    }
+
     pub fn expire_at(&self, key: &Bytes, expire_at_ms: u64) -> bool {
-        if let Some(mut v) = self.dict.get_mut(key) {
+        if let Some(mut v) = self.get_mut(key) {
             v.expire_at = Some(expire_at_ms);
             true
         } else {
@@ -142,16 +539,108 @@ impl Dict {
     }
 }
 
-impl Deref for Dict {
-    type Target = DashMap<Bytes, DictValue>;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
 
-    fn deref(&self) -> &Self::Target {
-        &self.dict
+    fn key(i: usize) -> Bytes {
+        Bytes::from(format!("key:{}", i))
     }
-}
 
-impl DerefMut for Dict {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.dict
+    fn string() -> RudisObject {
+        RudisObject::new_string_from(Bytes::from_static(b"v"))
+    }
+
+    /// All the keys of a full scan, by pages of `count`
+    fn scan_all(dict: &Dict, count: usize) -> Vec<Bytes> {
+        let mut keys = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, page) = dict.scan(cursor, count, |key, _| Some(key.clone()));
+            keys.extend(page);
+            if next == 0 {
+                return keys;
+            }
+            assert!(next > cursor);
+            cursor = next;
+        }
+    }
+
+    fn indexed(dict: &Dict) -> usize {
+        dict.scan_index.stripes.iter().map(|s| s.lock().len()).sum()
+    }
+
+    #[test]
+    fn scan_returns_every_key_once() {
+        let dict = Dict::new();
+        for i in 0..1000 {
+            dict.insert(key(i), string(), None);
+        }
+        for count in [1, 7, 100, 2000] {
+            let keys = scan_all(&dict, count);
+            assert_eq!(keys.len(), 1000);
+            assert_eq!(keys.iter().collect::<HashSet<_>>().len(), 1000);
+        }
+        assert_eq!(scan_all(&Dict::new(), 10), Vec::<Bytes>::new());
+    }
+
+    #[test]
+    fn scan_while_the_keys_change() {
+        let dict = Dict::new();
+        for i in 0..500 {
+            dict.insert(key(i), string(), None);
+        }
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        let mut round = 0;
+        loop {
+            let (next, page) = dict.scan(cursor, 10, |key, _| Some(key.clone()));
+            seen.extend(page);
+            // delete the keys from 250 on, add new ones
+            round += 1;
+            dict.remove(&key(250 + round));
+            dict.insert(key(1000 + round), string(), None);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        let seen_once = |i| seen.iter().filter(|k| **k == key(i)).count() == 1;
+        assert!((0..=250).all(seen_once));
+        assert_eq!(seen.iter().collect::<HashSet<_>>().len(), seen.len());
+    }
+
+    #[test]
+    fn index_follows_the_keys() {
+        let dict = Dict::new();
+        for i in 0..10 {
+            dict.insert(key(i), string(), None);
+        }
+        // overwritten
+        dict.insert(key(0), string(), None);
+        assert_eq!(indexed(&dict), 10);
+
+        dict.remove(&key(1));
+        assert!(dict.rename(&key(2), key(20)));
+        assert!(!dict.rename(&key(2), key(21)));
+        if let DictEntry::Vacant(entry) = dict.entry(key(30)) {
+            entry.insert(DictValue::new(string(), None));
+        }
+        dict.insert(key(3), string(), Some(1));
+        assert!(dict.check_expired(&key(3)));
+
+        let mut keys = scan_all(&dict, 3);
+        keys.sort();
+        let mut expected: Vec<Bytes> = [0, 4, 5, 6, 7, 8, 9, 20, 30].map(key).into();
+        expected.sort();
+        assert_eq!(keys, expected);
+        assert_eq!(indexed(&dict), 9);
+
+        dict.clear();
+        assert_eq!((dict.len(), indexed(&dict)), (0, 0));
+        assert!(scan_all(&dict, 10).is_empty());
+        dict.insert(key(0), string(), None);
+        assert_eq!(scan_all(&dict, 10), [key(0)]);
     }
 }
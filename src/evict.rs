@@ -0,0 +1,476 @@
+use crate::dbms::{DatabaseRef, DictValue};
+use crate::server::Server;
+use crate::shared;
+use crate::zmalloc;
+use bytes::Bytes;
+use rand::Rng;
+use std::fmt::Display;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::Ordering;
+
+pub const LRU_CLOCK_MAX: u32 = (1 << 24) - 1; /* Max value of the LRU clock */
+pub const LRU_CLOCK_RESOLUTION: u64 = 1000; /* LRU clock resolution in ms */
+pub const LFU_INIT_VAL: u8 = 5;
+const LFU_LOG_FACTOR: f64 = 10.0;
+const LFU_DECAY_TIME: u32 = 1; /* minutes */
+const EVPOOL_SIZE: usize = 16;
+/// Entries visited per key sampled before moving on to the next database,
+/// so that a few volatile keys among many don't scan the whole keyspace
+const EVICTION_VISITS_PER_SAMPLE: usize = 10;
+
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum MaxmemoryPolicy {
+    #[default]
+    NoEviction,
+    AllKeysLru,
+    AllKeysLfu,
+    VolatileLru,
+    VolatileLfu,
+    VolatileTtl,
+    AllKeysRandom,
+    VolatileRandom,
+}
+
+impl MaxmemoryPolicy {
+    pub fn parse(s: &str) -> Option<MaxmemoryPolicy> {
+        match s {
+            "noeviction" => Some(MaxmemoryPolicy::NoEviction),
+            "allkeys-lru" => Some(MaxmemoryPolicy::AllKeysLru),
+            "allkeys-lfu" => Some(MaxmemoryPolicy::AllKeysLfu),
+            "volatile-lru" => Some(MaxmemoryPolicy::VolatileLru),
+            "volatile-lfu" => Some(MaxmemoryPolicy::VolatileLfu),
+            "volatile-ttl" => Some(MaxmemoryPolicy::VolatileTtl),
+            "allkeys-random" => Some(MaxmemoryPolicy::AllKeysRandom),
+            "volatile-random" => Some(MaxmemoryPolicy::VolatileRandom),
+            _ => None,
+        }
+    }
+
+    /// Only the keys with an expire are evicted
+    pub fn is_volatile(&self) -> bool {
+        matches!(
+            self,
+            MaxmemoryPolicy::VolatileLru
+                | MaxmemoryPolicy::VolatileLfu
+                | MaxmemoryPolicy::VolatileTtl
+                | MaxmemoryPolicy::VolatileRandom
+        )
+    }
+}
+
+impl Display for MaxmemoryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaxmemoryPolicy::NoEviction => write!(f, "noeviction"),
+            MaxmemoryPolicy::AllKeysLru => write!(f, "allkeys-lru"),
+            MaxmemoryPolicy::AllKeysLfu => write!(f, "allkeys-lfu"),
+            MaxmemoryPolicy::VolatileLru => write!(f, "volatile-lru"),
+            MaxmemoryPolicy::VolatileLfu => write!(f, "volatile-lfu"),
+            MaxmemoryPolicy::VolatileTtl => write!(f, "volatile-ttl"),
+            MaxmemoryPolicy::AllKeysRandom => write!(f, "allkeys-random"),
+            MaxmemoryPolicy::VolatileRandom => write!(f, "volatile-random"),
+        }
+    }
+}
+
+/// The LRU clock, in seconds and wrapping at 24 bits
+pub fn lru_clock() -> u32 {
+    ((shared::now_ms() / LRU_CLOCK_RESOLUTION) & LRU_CLOCK_MAX as u64) as u32
+}
+
+/// Milliseconds since the object with the given LRU clock was accessed
+pub fn estimate_idle_time(lru: u32) -> u64 {
+    let clock = lru_clock();
+    if clock >= lru {
+        (clock - lru) as u64 * LRU_CLOCK_RESOLUTION
+    } else {
+        (clock as u64 + (LRU_CLOCK_MAX - lru) as u64) * LRU_CLOCK_RESOLUTION
+    }
+}
+
+/// The LFU data is the last decrement time in minutes (16 bits) and the
+/// logarithmic access counter (8 bits)
+pub fn lfu_time_in_minutes() -> u32 {
+    ((shared::now_ms() / 1000 / 60) & 65535) as u32
+}
+
+fn lfu_time_elapsed(ldt: u32) -> u32 {
+    let now = lfu_time_in_minutes();
+    if now >= ldt {
+        now - ldt
+    } else {
+        65535 - ldt + now
+    }
+}
+
+/// Increment the counter logarithmically, the greater the counter the less
+/// likely it is incremented
+pub fn lfu_log_incr(counter: u8) -> u8 {
+    if counter == 255 {
+        return 255;
+    }
+    let baseval = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    let p = 1.0 / (baseval * LFU_LOG_FACTOR + 1.0);
+    if rand::random::<f64>() < p {
+        counter + 1
+    } else {
+        counter
+    }
+}
+
+/// The counter decremented by the number of decay periods elapsed since the
+/// last decrement
+pub fn lfu_decr_and_return(lfu: u32) -> u8 {
+    let ldt = lfu >> 8;
+    let counter = (lfu & 255) as u8;
+    let num_periods = lfu_time_elapsed(ldt) / LFU_DECAY_TIME;
+    if num_periods > counter as u32 {
+        0
+    } else {
+        counter - num_periods as u8
+    }
+}
+
+/// A key that may be evicted, the greater the idle score the better
+struct EvictionCandidate {
+    idle: u64,
+    db: DatabaseRef,
+    key: Bytes,
+}
+
+impl Server {
+    /// Memory counted against maxmemory, the AOF buffers are not counted
+    async fn used_memory_for_eviction(&self) -> u64 {
+        let used = zmalloc::used_memory();
+        let aof_state = self.aof_state.read().await;
+        let not_counted =
+            aof_state.aof_buf.buffer.capacity() + aof_state.aof_rewrite_buf_blocks.capacity();
+        used.saturating_sub(not_counted) as u64
+    }
+
+    /// Evict keys according to the maxmemory policy until the used memory is
+    /// under the limit. Fails if the limit is still reached but nothing can
+    /// be evicted.
+    pub async fn perform_evictions(&self) -> Result<()> {
+        let (maxmemory, policy, samples) = {
+            let config = self.config.read().await;
+            (
+                config.maxmemory,
+                config.maxmemory_policy,
+                config.maxmemory_samples,
+            )
+        };
+        if maxmemory == 0 {
+            return Ok(());
+        }
+
+        let mut pool = Vec::with_capacity(EVPOOL_SIZE);
+        let mut evicted = 0;
+        while self.used_memory_for_eviction().await > maxmemory {
+            let victim = match policy {
+                MaxmemoryPolicy::NoEviction => None,
+                MaxmemoryPolicy::AllKeysRandom | MaxmemoryPolicy::VolatileRandom => {
+                    self.random_victim(policy.is_volatile(), samples)
+                }
+                _ => self.pooled_victim(&mut pool, policy, samples),
+            };
+
+            let (db, key) = match victim {
+                Some(victim) => victim,
+                None => {
+                    if evicted > 0 {
+                        log::debug!("evicted {} keys, nothing left to evict", evicted);
+                    }
+                    return Err(Error::new(
+                        ErrorKind::OutOfMemory,
+                        "used memory is over maxmemory",
+                    ));
+                }
+            };
+
+            // the candidate may have been deleted since it was sampled
+            if db.remove(&key).is_some() {
+                self.propagate_deletion(db.index, key).await;
+                self.stats.evicted_keys.fetch_add(1, Ordering::Relaxed);
+                evicted += 1;
+            }
+        }
+
+        if evicted > 0 {
+            log::debug!("evicted {} keys", evicted);
+        }
+        Ok(())
+    }
+
+    /// Sample the keys of every database into the eviction pool and pop the
+    /// best candidate, see evictionPoolPopulate in redis
+    fn pooled_victim(
+        &self,
+        pool: &mut Vec<EvictionCandidate>,
+        policy: MaxmemoryPolicy,
+        samples: usize,
+    ) -> Option<(DatabaseRef, Bytes)> {
+        for db in self.databases() {
+            if db.is_empty() {
+                continue;
+            }
+            let max_visits = samples * EVICTION_VISITS_PER_SAMPLE;
+            let sampled = db.sample(samples, max_visits, |key, value| {
+                if policy.is_volatile() && !value.is_volatile() {
+                    return None;
+                }
+                Some((idle_score(policy, value), key.clone()))
+            });
+            for (idle, key) in sampled {
+                if pool.iter().any(|c| c.db.index == db.index && c.key == key) {
+                    continue;
+                }
+                pool.push(EvictionCandidate {
+                    idle,
+                    db: db.clone(),
+                    key,
+                });
+            }
+        }
+
+        // keep the best candidates at the end of the pool
+        pool.sort_by_key(|c| c.idle);
+        if pool.len() > EVPOOL_SIZE {
+            pool.drain(..pool.len() - EVPOOL_SIZE);
+        }
+        pool.pop().map(|c| (c.db, c.key))
+    }
+
+    fn random_victim(&self, volatile: bool, samples: usize) -> Option<(DatabaseRef, Bytes)> {
+        let mut dbs = self.databases();
+        let start = rand::thread_rng().gen_range(0..dbs.len());
+        dbs.rotate_left(start);
+        dbs.into_iter().find_map(|db| {
+            let key = db
+                .sample(1, samples * EVICTION_VISITS_PER_SAMPLE, |key, value| {
+                    (!volatile || value.is_volatile()).then(|| key.clone())
+                })
+                .pop()?;
+            Some((db, key))
+        })
+    }
+}
+
+fn idle_score(policy: MaxmemoryPolicy, value: &DictValue) -> u64 {
+    match policy {
+        MaxmemoryPolicy::AllKeysLfu | MaxmemoryPolicy::VolatileLfu => {
+            255 - value.lfu_counter() as u64
+        }
+        // the sooner it expires the better
+        MaxmemoryPolicy::VolatileTtl => u64::MAX - value.expire_at.unwrap_or(u64::MAX),
+        _ => value.idle_time(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ConfigRef};
+    use crate::object::RudisObject;
+    use std::sync::Arc;
+
+    async fn server(maxmemory: u64, policy: MaxmemoryPolicy) -> Arc<Server> {
+        Server::from_config(ConfigRef::new(Config {
+            rdb_filename: "/nonexistent/dump.rdb".to_owned(),
+            db_num: 2,
+            maxmemory,
+            maxmemory_policy: policy,
+            ..Default::default()
+        }))
+        .await
+    }
+
+    fn key(i: usize) -> Bytes {
+        Bytes::from(format!("key:{}", i))
+    }
+
+    fn insert(server: &Server, db: usize, i: usize, expire_at: Option<u64>) {
+        server.get(db).insert(
+            key(i),
+            RudisObject::new_string_from(Bytes::from_static(b"v")),
+            expire_at,
+        );
+    }
+
+    #[test]
+    fn parse_policies() {
+        for name in [
+            "noeviction",
+            "allkeys-lru",
+            "allkeys-lfu",
+            "volatile-lru",
+            "volatile-lfu",
+            "volatile-ttl",
+            "allkeys-random",
+            "volatile-random",
+        ] {
+            let policy = MaxmemoryPolicy::parse(name).unwrap();
+            assert_eq!(policy.to_string(), name);
+            assert_eq!(policy.is_volatile(), name.starts_with("volatile"));
+        }
+        assert_eq!(MaxmemoryPolicy::parse("lru"), None);
+    }
+
+    #[test]
+    fn lru_idle_time_wraps() {
+        // the clock may tick once while the test runs
+        let clock = lru_clock();
+        assert!(estimate_idle_time(clock) <= LRU_CLOCK_RESOLUTION);
+        let idle = estimate_idle_time((clock + LRU_CLOCK_MAX - 9) & LRU_CLOCK_MAX);
+        assert!(idle == 10 * LRU_CLOCK_RESOLUTION || idle == 11 * LRU_CLOCK_RESOLUTION);
+
+        // accessed just before the clock wrapped
+        if clock + 2 <= LRU_CLOCK_MAX {
+            let idle = estimate_idle_time(clock + 2);
+            assert!(idle >= (LRU_CLOCK_MAX as u64 - 2) * LRU_CLOCK_RESOLUTION);
+        }
+    }
+
+    #[test]
+    fn lfu_counter_decays() {
+        let now = lfu_time_in_minutes();
+        assert_eq!(lfu_decr_and_return((now << 8) | 10), 10);
+        let ten_minutes_ago = (now + 65535 - 10) % 65535;
+        assert_eq!(lfu_decr_and_return((ten_minutes_ago << 8) | 30), 20);
+        assert_eq!(lfu_decr_and_return((ten_minutes_ago << 8) | 5), 0);
+
+        assert_eq!(lfu_log_incr(255), 255);
+        // below LFU_INIT_VAL the counter is always incremented
+        assert_eq!(lfu_log_incr(0), 1);
+        let mut counter = LFU_INIT_VAL;
+        for _ in 0..1000 {
+            counter = lfu_log_incr(counter);
+        }
+        assert!(counter > LFU_INIT_VAL && counter < 100);
+    }
+
+    #[tokio::test]
+    async fn noeviction_evicts_nothing() {
+        let server = server(1, MaxmemoryPolicy::NoEviction).await;
+        insert(&server, 0, 0, None);
+        let err = server.perform_evictions().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OutOfMemory);
+        assert!(server.get(0).contains_key(&key(0)));
+
+        // no limit
+        let server = self::server(0, MaxmemoryPolicy::AllKeysLru).await;
+        insert(&server, 0, 0, None);
+        server.perform_evictions().await.unwrap();
+        assert!(server.get(0).contains_key(&key(0)));
+    }
+
+    #[tokio::test]
+    async fn volatile_policies_only_evict_volatile_keys() {
+        for policy in [
+            MaxmemoryPolicy::VolatileLru,
+            MaxmemoryPolicy::VolatileLfu,
+            MaxmemoryPolicy::VolatileTtl,
+            MaxmemoryPolicy::VolatileRandom,
+        ] {
+            let server = server(1, policy).await;
+            let expire_at = Some(shared::now_ms() + 100_000);
+            for i in 0..20 {
+                insert(
+                    &server,
+                    i % 2,
+                    i,
+                    (i % 4 < 2).then_some(expire_at).flatten(),
+                );
+            }
+            let err = server.perform_evictions().await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::OutOfMemory, "{}", policy);
+            for i in 0..20 {
+                let db = server.get(i % 2);
+                assert_eq!(db.contains_key(&key(i)), i % 4 >= 2, "{} {}", policy, i);
+            }
+            assert_eq!(server.stats.evicted_keys.load(Ordering::Relaxed), 10);
+        }
+    }
+
+    #[tokio::test]
+    async fn allkeys_policies_evict_every_key() {
+        for policy in [
+            MaxmemoryPolicy::AllKeysLru,
+            MaxmemoryPolicy::AllKeysLfu,
+            MaxmemoryPolicy::AllKeysRandom,
+        ] {
+            let server = server(1, policy).await;
+            for i in 0..20 {
+                insert(&server, i % 2, i, None);
+            }
+            assert!(server.perform_evictions().await.is_err());
+            for i in 0..20 {
+                assert!(!server.get(i % 2).contains_key(&key(i)), "{} {}", policy, i);
+            }
+            assert_eq!(server.stats.evicted_keys.load(Ordering::Relaxed), 20);
+        }
+    }
+
+    #[tokio::test]
+    async fn pool_picks_the_best_candidate() {
+        // the keys are all sampled, so the candidates are picked in order
+        let server = server(1, MaxmemoryPolicy::AllKeysLru).await;
+        for i in 0..3 {
+            insert(&server, 1, i, None);
+        }
+        let clock = lru_clock();
+        for (i, idle) in [(0, 5), (1, 60), (2, 20)] {
+            let db = server.get(1);
+            let value = db.get(&key(i)).unwrap();
+            value.lru.store(
+                (clock + LRU_CLOCK_MAX - idle) & LRU_CLOCK_MAX,
+                Ordering::Relaxed,
+            );
+        }
+        let mut pool = Vec::new();
+        for expected in [1, 2, 0] {
+            let (db, victim) = server
+                .pooled_victim(&mut pool, MaxmemoryPolicy::AllKeysLru, 5)
+                .unwrap();
+            assert_eq!((db.index, victim.clone()), (1, key(expected)));
+            db.remove(&victim);
+        }
+
+        let server = self::server(1, MaxmemoryPolicy::AllKeysLfu).await;
+        for i in 0..3 {
+            insert(&server, 0, i, None);
+        }
+        let now = lfu_time_in_minutes();
+        for (i, counter) in [(0, 20), (1, 1), (2, 7)] {
+            let db = server.get(0);
+            let value = db.get(&key(i)).unwrap();
+            value.lfu.store((now << 8) | counter, Ordering::Relaxed);
+        }
+        let mut pool = Vec::new();
+        for expected in [1, 2, 0] {
+            let (db, victim) = server
+                .pooled_victim(&mut pool, MaxmemoryPolicy::AllKeysLfu, 5)
+                .unwrap();
+            assert_eq!(victim, key(expected));
+            db.remove(&victim);
+        }
+
+        let server = self::server(1, MaxmemoryPolicy::VolatileTtl).await;
+        let now = shared::now_ms();
+        for (i, ttl) in [(0, 3000), (1, 1000), (2, 2000)] {
+            insert(&server, 0, i, Some(now + ttl));
+        }
+        insert(&server, 0, 3, None);
+        let mut pool = Vec::new();
+        for expected in [1, 2, 0] {
+            let (db, victim) = server
+                .pooled_victim(&mut pool, MaxmemoryPolicy::VolatileTtl, 5)
+                .unwrap();
+            assert_eq!(victim, key(expected));
+            db.remove(&victim);
+        }
+        assert!(server
+            .pooled_victim(&mut pool, MaxmemoryPolicy::VolatileTtl, 5)
+            .is_none());
+    }
+}
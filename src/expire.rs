@@ -0,0 +1,209 @@
+use crate::server::Server;
+use crate::shared;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+/// Volatile keys sampled per database and loop
+const ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP: usize = 20;
+/// Entries visited at most per loop looking for volatile keys
+const ACTIVE_EXPIRE_CYCLE_MAX_VISITS: usize = ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP * 20;
+/// Max percentage of the cron period a cycle can use
+const ACTIVE_EXPIRE_CYCLE_SLOW_TIME_PERC: u64 = 25;
+/// Keep expiring a database while more than this percentage of the sampled
+/// keys were expired
+const ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE: usize = 10;
+/// Databases visited per cycle
+const CRON_DBS_PER_CALL: usize = 16;
+
+impl Server {
+    /// Delete the expired keys nobody accesses, sampling the volatile keys
+    /// of a few databases until the ratio of expired keys is acceptable or
+    /// the time limit is reached, see activeExpireCycle in redis
+    pub async fn active_expire_cycle(&self) {
+        let hz = self.config.read().await.hz.max(1) as u64;
+        let timelimit =
+            Duration::from_micros(1_000_000 * ACTIVE_EXPIRE_CYCLE_SLOW_TIME_PERC / hz / 100);
+        let start = Instant::now();
+
+        // keep the keyspace unchanged while a transaction is executed
+        let _keyspace = self.keyspace_lock.read().await;
+
+        let dbs = self.databases();
+        let dbs_per_call = dbs.len().min(CRON_DBS_PER_CALL);
+        let current_db = self.expire_current_db.load(Ordering::Relaxed);
+        let mut total_sampled = 0;
+        let mut total_expired = 0;
+        let mut timelimit_exit = false;
+
+        for j in 0..dbs_per_call {
+            if timelimit_exit {
+                break;
+            }
+            let db = &dbs[(current_db + j) % dbs.len()];
+            self.expire_current_db
+                .store((current_db + j + 1) % dbs.len(), Ordering::Relaxed);
+
+            loop {
+                if db.is_empty() {
+                    db.avg_ttl.store(0, Ordering::Relaxed);
+                    break;
+                }
+                let sampled = db.sample(
+                    ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP,
+                    ACTIVE_EXPIRE_CYCLE_MAX_VISITS,
+                    |key, value| value.expire_at.map(|at| (key.clone(), at)),
+                );
+                if sampled.is_empty() {
+                    break;
+                }
+
+                let now = shared::now_ms();
+                let mut expired = 0;
+                let mut ttl_sum = 0;
+                let mut ttl_samples = 0;
+                for (key, expire_at) in &sampled {
+                    if db.check_expired(key) {
+                        self.propagate_deletion(db.index, key.clone()).await;
+                        expired += 1;
+                    } else if *expire_at > now {
+                        ttl_sum += *expire_at - now;
+                        ttl_samples += 1;
+                    }
+                }
+                total_sampled += sampled.len();
+                total_expired += expired;
+
+                // running average, the last samples weighting 2%
+                if let Some(avg_ttl) = ttl_sum.checked_div(ttl_samples) {
+                    let old = db.avg_ttl.load(Ordering::Relaxed);
+                    let new = if old == 0 {
+                        avg_ttl
+                    } else {
+                        old / 50 * 49 + avg_ttl / 50
+                    };
+                    db.avg_ttl.store(new, Ordering::Relaxed);
+                }
+
+                if start.elapsed() > timelimit {
+                    timelimit_exit = true;
+                    self.stats
+                        .expired_time_cap_reached_count
+                        .fetch_add(1, Ordering::Relaxed);
+                    break;
+                }
+
+                if expired * 100 <= sampled.len() * ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE {
+                    break;
+                }
+            }
+        }
+
+        let elapsed = start.elapsed();
+        if total_expired > 0 {
+            log::debug!(
+                "expired {} of {} sampled keys in {:?}",
+                total_expired,
+                total_sampled,
+                elapsed
+            );
+        }
+
+        let current_perc = if total_sampled > 0 {
+            total_expired as f64 / total_sampled as f64
+        } else {
+            0.0
+        };
+        self.stats
+            .set_expired_stale_perc(current_perc * 0.05 + self.stats.expired_stale_perc() * 0.95);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ConfigRef};
+    use crate::dbms::DatabaseRef;
+    use crate::object::RudisObject;
+    use bytes::Bytes;
+    use std::sync::Arc;
+
+    async fn server(db_num: usize) -> Arc<Server> {
+        Server::from_config(ConfigRef::new(Config {
+            rdb_filename: "/nonexistent/dump.rdb".to_owned(),
+            db_num,
+            ..Default::default()
+        }))
+        .await
+    }
+
+    fn insert(db: &DatabaseRef, prefix: &str, n: usize, expire_at: Option<u64>) {
+        for i in 0..n {
+            db.insert(
+                Bytes::from(format!("{}:{}", prefix, i)),
+                RudisObject::new_string_from(Bytes::from_static(b"v")),
+                expire_at,
+            );
+        }
+    }
+
+    /// The keys actually stored, expired or not
+    fn stored(db: &DatabaseRef) -> usize {
+        db.sample(usize::MAX, usize::MAX, |_, _| Some(())).len()
+    }
+
+    #[tokio::test]
+    async fn expired_keys_are_deleted() {
+        let server = server(1).await;
+        let db = server.get(0);
+        let now = shared::now_ms();
+        insert(&db, "expired", 100, Some(now - 1));
+        insert(&db, "persistent", 50, None);
+        assert_eq!(stored(&db), 150);
+
+        // only expired keys are sampled, so the cycle goes on until they
+        // are all deleted
+        server.active_expire_cycle().await;
+        assert_eq!(stored(&db), 50);
+        assert_eq!(db.expired_keys.load(Ordering::Relaxed), 100);
+        assert!(server.stats.expired_stale_perc() > 0.0);
+
+        insert(&db, "volatile", 50, Some(now + 100_000));
+        server.active_expire_cycle().await;
+        assert_eq!(stored(&db), 100);
+        let avg_ttl = db.avg_ttl.load(Ordering::Relaxed);
+        assert!(avg_ttl > 90_000 && avg_ttl <= 100_000, "{}", avg_ttl);
+
+        // the running average keeps most of the previous value
+        db.avg_ttl.store(200_000, Ordering::Relaxed);
+        server.active_expire_cycle().await;
+        let avg_ttl = db.avg_ttl.load(Ordering::Relaxed);
+        assert!(avg_ttl > 190_000 && avg_ttl < 200_000, "{}", avg_ttl);
+        assert_eq!(stored(&db), 100);
+
+        // nothing left to sample
+        db.clear();
+        server.active_expire_cycle().await;
+        assert_eq!(db.avg_ttl.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn cycle_resumes_from_the_next_database() {
+        let server = server(CRON_DBS_PER_CALL + 4).await;
+        let db = server.get(CRON_DBS_PER_CALL + 1);
+        insert(&db, "expired", 10, Some(shared::now_ms() - 1));
+
+        server.active_expire_cycle().await;
+        assert_eq!(stored(&db), 10);
+        assert_eq!(
+            server.expire_current_db.load(Ordering::Relaxed),
+            CRON_DBS_PER_CALL
+        );
+
+        server.active_expire_cycle().await;
+        assert_eq!(stored(&db), 0);
+        assert_eq!(
+            server.expire_current_db.load(Ordering::Relaxed),
+            CRON_DBS_PER_CALL - 4
+        );
+    }
+}
@@ -1,8 +1,54 @@
+use crate::shared;
 use bytes::{Buf, Bytes, BytesMut};
 use std::fmt::Display;
 use std::io::{Cursor, Error, ErrorKind, Result};
 use std::str::FromStr;
 
+/// Max length of an inline request still waiting for its newline
+pub const PROTO_INLINE_MAX_SIZE: usize = 64 * 1024;
+/// Payloads from this length on may share the memory of the request they
+/// were read from instead of being copied, when they fill most of the read
+/// buffer, see PROTO_MBULK_BIG_ARG in redis
+pub const PROTO_MBULK_BIG_ARG: usize = 32 * 1024;
+
+/// Limits on the frames read from the clients, checked before anything is
+/// allocated for them
+#[derive(Clone, Copy, Debug)]
+pub struct ProtoLimits {
+    /// max length of a bulk
+    pub max_bulk_len: usize,
+    /// max number of elements of an aggregate
+    pub max_multibulk_len: usize,
+    /// max nesting depth of the aggregates, 1 for a flat array
+    pub max_depth: usize,
+}
+
+impl ProtoLimits {
+    pub const UNLIMITED: ProtoLimits = ProtoLimits {
+        max_bulk_len: usize::MAX,
+        max_multibulk_len: usize::MAX,
+        max_depth: usize::MAX,
+    };
+}
+
+/// The errors in the frames sent by the clients, replied as
+/// `-ERR Protocol error: <msg>`
+fn protocol_error(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// How far `check` went in a frame not completely received yet, so that it
+/// doesn't start over each time more of it is read, see multibulklen and
+/// bulklen in redis
+#[derive(Clone, Debug, Default)]
+pub struct CheckState {
+    /// offset of the first element not checked yet
+    pos: usize,
+    /// elements left in each of the aggregates being checked, the innermost
+    /// last
+    pending: Vec<usize>,
+}
+
 #[derive(Clone, Debug)]
 pub enum Frame {
     Simple(Bytes),
@@ -10,7 +56,23 @@ pub enum Frame {
     Integer(i64),
     Bulk(Bytes),
     Null,
+    /// the null reply of the commands replying an array, e.g. a blocking
+    /// pop that timed out
+    NullArray,
     Array(Vec<Frame>),
+
+    // RESP3 types, replied as the closest RESP2 type to the RESP2 clients
+    Map(Vec<(Frame, Frame)>),
+    Set(Vec<Frame>),
+    Double(f64),
+    Boolean(bool),
+    BigNumber(Bytes),
+    /// the 3 bytes format, e.g. txt, and the string
+    Verbatim(Bytes, Bytes),
+    /// out of band data, e.g. the published messages
+    Push(Vec<Frame>),
+    /// the attributes and the reply they are attached to
+    Attribute(Vec<(Frame, Frame)>, Box<Frame>),
 }
 
 impl Frame {
@@ -41,16 +103,17 @@ impl Frame {
         }
     }
 
-    fn next_line<'a>(src: &'a mut Cursor<&BytesMut>) -> Option<&'a [u8]> {
+    fn next_line<'a>(src: &mut Cursor<&'a [u8]>) -> Option<&'a [u8]> {
         let begin = src.position() as usize;
+        let buf = *src.get_ref();
 
-        for i in begin..src.get_ref().len() {
-            if src.get_ref()[i] == b'\n' {
+        for i in begin..buf.len() {
+            if buf[i] == b'\n' {
                 src.set_position((i + 1) as u64);
-                if i > 0 && src.get_ref()[i - 1] == b'\r' {
-                    return Some(&src.get_ref()[begin..i - 1]);
+                if i > 0 && buf[i - 1] == b'\r' {
+                    return Some(&buf[begin..i - 1]);
                 }
-                return Some(&src.get_ref()[begin..i]);
+                return Some(&buf[begin..i]);
             }
         }
 
@@ -58,120 +121,610 @@ impl Frame {
         None
     }
 
-    fn next_utf8_str<'a>(src: &'a mut Cursor<&BytesMut>) -> Result<Option<&'a str>> {
+    fn next_utf8_str<'a>(src: &mut Cursor<&'a [u8]>) -> Result<Option<&'a str>> {
         if let Some(line) = Self::next_line(src) {
-            if let Ok(s) = std::str::from_utf8(&line) {
+            if let Ok(s) = std::str::from_utf8(line) {
                 Ok(Some(s))
             } else {
-                Err(Error::new(ErrorKind::Other, "parse error"))
+                Err(protocol_error("invalid utf8 string"))
             }
         } else {
             Ok(None)
         }
     }
 
-    fn parse_into<T: FromStr>(src: &mut Cursor<&BytesMut>) -> Result<Option<T>> {
+    fn parse_into<T: FromStr>(src: &mut Cursor<&[u8]>) -> Result<Option<T>> {
         if let Some(s) = Self::next_utf8_str(src)? {
             if let Ok(n) = T::from_str(s) {
                 Ok(Some(n))
             } else {
-                Err(Error::new(ErrorKind::Other, "parse error"))
+                Err(protocol_error("invalid number"))
             }
         } else {
+            // the numbers are short, don't wait forever for the newline
+            if src.remaining() > PROTO_INLINE_MAX_SIZE {
+                return Err(protocol_error("too big count string"));
+            }
             Ok(None)
         }
     }
 
-    pub fn parse(cur: &mut Cursor<&BytesMut>) -> Result<Option<Frame>> {
+    fn parse_double(src: &mut Cursor<&[u8]>) -> Result<Option<f64>> {
+        match Self::next_utf8_str(src)? {
+            Some("inf") => Ok(Some(f64::INFINITY)),
+            Some("-inf") => Ok(Some(f64::NEG_INFINITY)),
+            Some(s) => s
+                .parse()
+                .map(Some)
+                .map_err(|_| protocol_error("invalid double")),
+            None => Ok(None),
+        }
+    }
+
+    fn parse_boolean(src: &mut Cursor<&[u8]>) -> Result<Option<bool>> {
+        match Self::next_line(src) {
+            Some(b"t") => Ok(Some(true)),
+            Some(b"f") => Ok(Some(false)),
+            Some(_) => Err(protocol_error("invalid boolean")),
+            None => Ok(None),
+        }
+    }
+
+    /// Parse the `<len>\r\n` header of a blob type, None for the null blob
+    fn parse_blob_len(
+        src: &mut Cursor<&[u8]>,
+        max_bulk_len: usize,
+    ) -> Result<Option<Option<usize>>> {
+        match Self::parse_into::<i64>(src)? {
+            Some(-1) => Ok(Some(None)),
+            Some(len) if len < -1 || len as u64 > max_bulk_len as u64 => {
+                Err(protocol_error("invalid bulk length"))
+            }
+            Some(len) => Ok(Some(Some(len as usize))),
+            None => Ok(None),
+        }
+    }
+
+    /// Parse the `<len>\r\n` header of an aggregate type, None for the null
+    /// array
+    fn parse_aggregate_len(
+        src: &mut Cursor<&[u8]>,
+        max_multibulk_len: usize,
+    ) -> Result<Option<Option<usize>>> {
+        match Self::parse_into::<i64>(src)? {
+            Some(-1) => Ok(Some(None)),
+            Some(len) if len < -1 || len as u64 > max_multibulk_len as u64 => {
+                Err(protocol_error("invalid multibulk length"))
+            }
+            Some(len) => Ok(Some(Some(len as usize))),
+            None => Ok(None),
+        }
+    }
+
+    /// Check if a complete frame is buffered, resuming from where the
+    /// previous call stopped on the same, since grown, buffer
+    fn check(buf: &[u8], limits: &ProtoLimits, state: &mut CheckState) -> Result<bool> {
+        let mut cur = Cursor::new(buf);
+        cur.set_position(state.pos as u64);
+        loop {
+            match Self::check_element(&mut cur, limits, state.pending.len())? {
+                None => return Ok(false),
+                Some(0) => {}
+                Some(len) => {
+                    state.pos = cur.position() as usize;
+                    state.pending.push(len);
+                    continue;
+                }
+            }
+            state.pos = cur.position() as usize;
+            // complete the aggregates whose last element this was
+            loop {
+                match state.pending.last_mut() {
+                    None => return Ok(true),
+                    Some(1) => {
+                        state.pending.pop();
+                    }
+                    Some(left) => {
+                        *left -= 1;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check the element at the cursor, leaving the cursor at its end. A
+    /// complete element is Some(0), the header of an aggregate is Some of
+    /// the number of elements that follow it. `depth` is the number of
+    /// aggregates the element is nested in
+    fn check_element(
+        cur: &mut Cursor<&[u8]>,
+        limits: &ProtoLimits,
+        depth: usize,
+    ) -> Result<Option<usize>> {
         if !cur.has_remaining() {
             return Ok(None);
         }
 
-        let checkpoint: u64 = cur.position();
+        let complete = |complete: bool| Ok(complete.then_some(0));
         let byte = cur.get_u8();
         match byte {
-            b'+' => {
-                if let Some(line) = Self::next_line(cur) {
-                    let frame = Frame::Simple(Bytes::copy_from_slice(line));
-                    return Ok(Some(frame));
+            b'+' | b'-' | b'_' | b'(' => complete(Self::next_line(cur).is_some()),
+            b':' => complete(Self::parse_into::<i64>(cur)?.is_some()),
+            b',' => complete(Self::parse_double(cur)?.is_some()),
+            b'#' => complete(Self::parse_boolean(cur)?.is_some()),
+            b'$' | b'!' | b'=' => match Self::parse_blob_len(cur, limits.max_bulk_len)? {
+                None => Ok(None),
+                Some(None) => Ok(Some(0)),
+                Some(Some(len)) => {
+                    // 2 for \r\n
+                    if cur.remaining() < len + 2 {
+                        return Ok(None);
+                    }
+                    if byte == b'=' && (len < 4 || cur.chunk()[3] != b':') {
+                        return Err(protocol_error("invalid verbatim string"));
+                    }
+                    cur.advance(len + 2);
+                    Ok(Some(0))
                 }
+            },
+            b'*' | b'~' | b'>' | b'%' | b'|' => {
+                if depth >= limits.max_depth {
+                    return Err(protocol_error("too deeply nested aggregate"));
+                }
+                let len = match Self::parse_aggregate_len(cur, limits.max_multibulk_len)? {
+                    None => return Ok(None),
+                    Some(None) => return Ok(Some(0)),
+                    Some(Some(len)) => len,
+                };
+                Ok(Some(match byte {
+                    // the key value pairs, the attributes are followed by
+                    // the reply they describe
+                    b'%' => len.saturating_mul(2),
+                    b'|' => len.saturating_mul(2).saturating_add(1),
+                    _ => len,
+                }))
             }
-            b'-' => {
-                if let Some(line) = Self::next_line(cur) {
-                    let frame = Frame::Error(Bytes::copy_from_slice(line));
-                    return Ok(Some(frame));
+            _ => Err(protocol_error("illegal frame")),
+        }
+    }
+
+    /// A payload of `src`, read in a buffer of `alloc` bytes. The payload is
+    /// copied unless it fills most of the buffer, so that a value kept in the
+    /// database doesn't pin a much bigger read buffer
+    fn payload(src: &Bytes, alloc: usize, begin: usize, len: usize) -> Bytes {
+        if len >= PROTO_MBULK_BIG_ARG && len > alloc / 2 {
+            src.slice(begin..begin + len)
+        } else {
+            Bytes::copy_from_slice(&src[begin..begin + len])
+        }
+    }
+
+    /// Decode a frame validated by `check`, read in a buffer of `alloc`
+    /// bytes, the big payloads are slices of `src`
+    fn decode(src: &Bytes, alloc: usize, cur: &mut Cursor<&[u8]>) -> Result<Frame> {
+        let incomplete = || protocol_error("incomplete frame");
+
+        let byte = cur.get_u8();
+        match byte {
+            b'+' | b'-' | b'(' => {
+                let begin = cur.position() as usize;
+                let len = Self::next_line(cur).ok_or_else(incomplete)?.len();
+                let line = Self::payload(src, alloc, begin, len);
+                Ok(match byte {
+                    b'+' => Frame::Simple(line),
+                    b'-' => Frame::Error(line),
+                    _ => Frame::BigNumber(line),
+                })
+            }
+            b'_' => {
+                Self::next_line(cur).ok_or_else(incomplete)?;
+                Ok(Frame::Null)
+            }
+            b':' => Ok(Frame::Integer(
+                Self::parse_into(cur)?.ok_or_else(incomplete)?,
+            )),
+            b',' => Ok(Frame::Double(
+                Self::parse_double(cur)?.ok_or_else(incomplete)?,
+            )),
+            b'#' => Ok(Frame::Boolean(
+                Self::parse_boolean(cur)?.ok_or_else(incomplete)?,
+            )),
+            b'$' | b'!' | b'=' => {
+                let len = match Self::parse_blob_len(cur, usize::MAX)?.ok_or_else(incomplete)? {
+                    Some(len) => len,
+                    None => return Ok(Frame::Null),
+                };
+                let begin = cur.position() as usize;
+                cur.advance(len + 2);
+                Ok(match byte {
+                    b'$' => Frame::Bulk(Self::payload(src, alloc, begin, len)),
+                    b'!' => Frame::Error(Self::payload(src, alloc, begin, len)),
+                    _ => Frame::Verbatim(
+                        Self::payload(src, alloc, begin, 3),
+                        Self::payload(src, alloc, begin + 4, len - 4),
+                    ),
+                })
+            }
+            b'*' | b'~' | b'>' => {
+                let len =
+                    match Self::parse_aggregate_len(cur, usize::MAX)?.ok_or_else(incomplete)? {
+                        Some(len) => len,
+                        None => return Ok(Frame::Null),
+                    };
+                let mut elements = Vec::with_capacity(len);
+                for _ in 0..len {
+                    elements.push(Self::decode(src, alloc, cur)?);
                 }
+                Ok(match byte {
+                    b'~' => Frame::Set(elements),
+                    b'>' => Frame::Push(elements),
+                    _ => Frame::Array(elements),
+                })
             }
-            b':' => {
-                if let Some(n) = Self::parse_into(cur)? {
-                    let frame = Frame::Integer(n);
-                    return Ok(Some(frame));
+            b'%' | b'|' => {
+                let len =
+                    match Self::parse_aggregate_len(cur, usize::MAX)?.ok_or_else(incomplete)? {
+                        Some(len) => len,
+                        None => return Ok(Frame::Null),
+                    };
+                let mut pairs = Vec::with_capacity(len);
+                for _ in 0..len {
+                    pairs.push((
+                        Self::decode(src, alloc, cur)?,
+                        Self::decode(src, alloc, cur)?,
+                    ));
+                }
+                if byte == b'%' {
+                    return Ok(Frame::Map(pairs));
                 }
+                // the attributes come before the reply they describe
+                let frame = Self::decode(src, alloc, cur)?;
+                Ok(Frame::Attribute(pairs, Box::new(frame)))
             }
-            b'$' => {
-                if let Some(len) = Self::parse_into::<i64>(cur)? {
-                    if len == -1 {
-                        return Ok(Some(Frame::Null));
-                    }
+            _ => Err(protocol_error("illegal frame")),
+        }
+    }
 
-                    if cur.remaining() as i64 >= len + 2 {
-                        // 2 for \r\n
-                        let bulk = Bytes::copy_from_slice(&cur.chunk()[..len as usize]);
-                        cur.advance(len as usize + 2);
-                        return Ok(Some(Frame::Bulk(bulk)));
-                    }
+    /// Parse a frame from the head of `buf`, None if it is incomplete. The
+    /// frame is split off the buffer and its big payloads share its memory
+    pub fn parse(buf: &mut BytesMut) -> Result<Option<Frame>> {
+        Self::parse_request(buf, &ProtoLimits::UNLIMITED, &mut CheckState::default())
+    }
+
+    /// Parse a request of a client, refusing the frames over the `limits`
+    /// before they are buffered. `state` is the progress on the frame at the
+    /// head of `buf`, it is reset once the frame is complete
+    pub fn parse_request(
+        buf: &mut BytesMut,
+        limits: &ProtoLimits,
+        state: &mut CheckState,
+    ) -> Result<Option<Frame>> {
+        if !Self::check(buf, limits, state)? {
+            return Ok(None);
+        }
+        let len = state.pos;
+        *state = CheckState::default();
+
+        let alloc = buf.capacity();
+        let src = buf.split_to(len).freeze();
+        Self::decode(&src, alloc, &mut Cursor::new(&src[..])).map(Some)
+    }
+
+    /// Parse an inline command, i.e. arguments separated by whitespace and
+    /// ended by a newline as typed in telnet, into an array of bulks, see
+    /// processInlineBuffer in redis
+    pub fn parse_inline(buf: &mut BytesMut) -> Result<Option<Frame>> {
+        let mut cur = Cursor::new(&buf[..]);
+        match Self::next_line(&mut cur) {
+            Some(line) => {
+                let args = shared::split_args(line)
+                    .ok_or_else(|| protocol_error("unbalanced quotes in request"))?;
+                let len = cur.position() as usize;
+                buf.advance(len);
+                Ok(Some(Frame::Array(
+                    args.into_iter().map(Frame::Bulk).collect(),
+                )))
+            }
+            None => {
+                if buf.len() > PROTO_INLINE_MAX_SIZE {
+                    return Err(protocol_error("too big inline request"));
                 }
+                Ok(None)
             }
-            b'*' => {
-                if let Some(len) = Self::parse_into(cur)? {
-                    let mut array = Vec::with_capacity(len);
-                    for _ in 0..len {
-                        if let Some(frame) = Frame::parse(cur)? {
-                            array.push(frame);
-                        } else {
-                            // failed to parse array element
-                            break;
-                        }
-                    }
+        }
+    }
 
-                    // check if array is fully read, if not rollback
-                    if array.len() == len as usize {
-                        return Ok(Some(Frame::Array(array)));
-                    }
+    /// Serialize the frame for a client speaking the given protocol version,
+    /// the RESP3 types are replaced by their RESP2 counterparts for RESP2
+    pub fn serialize(&self, resp: u8) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode(&mut buf, resp);
+        buf.freeze()
+    }
+
+    /// Append the binary-safe encoding of the frame to `buf`
+    pub fn encode(&self, buf: &mut BytesMut, resp: u8) {
+        let resp3 = resp >= 3;
+        match self {
+            Frame::Simple(s) => encode_line(buf, b'+', s),
+            Frame::Error(s) => encode_line(buf, b'-', s),
+            Frame::Integer(n) => encode_header(buf, b':', *n),
+            Frame::Bulk(b) => encode_blob(buf, b'$', b),
+            Frame::Null | Frame::NullArray if resp3 => buf.extend_from_slice(b"_\r\n"),
+            Frame::Null => buf.extend_from_slice(b"$-1\r\n"),
+            Frame::NullArray => buf.extend_from_slice(b"*-1\r\n"),
+            Frame::Array(a) => encode_aggregate(buf, b'*', a, resp),
+            Frame::Set(s) => encode_aggregate(buf, b'~', s, resp),
+            Frame::Push(p) => encode_aggregate(buf, b'>', p, resp),
+            Frame::Map(m) => {
+                if resp3 {
+                    encode_header(buf, b'%', m.len() as i64);
+                } else {
+                    // flattened to key value key value...
+                    encode_header(buf, b'*', m.len() as i64 * 2);
                 }
+                encode_pairs(buf, m, resp);
+            }
+            Frame::Double(n) if resp3 => encode_line(buf, b',', format_double(*n).as_bytes()),
+            Frame::Double(n) => encode_blob(buf, b'$', format_double(*n).as_bytes()),
+            Frame::Boolean(b) if resp3 => encode_line(buf, b'#', if *b { b"t" } else { b"f" }),
+            Frame::Boolean(b) => encode_header(buf, b':', *b as i64),
+            Frame::BigNumber(n) if resp3 => encode_line(buf, b'(', n),
+            Frame::BigNumber(n) => encode_blob(buf, b'$', n),
+            Frame::Verbatim(format, s) if resp3 => {
+                encode_header(buf, b'=', s.len() as i64 + 4);
+                buf.extend_from_slice(format);
+                buf.extend_from_slice(b":");
+                buf.extend_from_slice(s);
+                buf.extend_from_slice(b"\r\n");
             }
-            _ => {
-                return Err(Error::new(ErrorKind::InvalidInput, "illegal frame"));
+            Frame::Verbatim(_, s) => encode_blob(buf, b'$', s),
+            Frame::Attribute(attrs, frame) => {
+                // RESP2 has no attributes, only the reply is sent
+                if resp3 {
+                    encode_header(buf, b'|', attrs.len() as i64);
+                    encode_pairs(buf, attrs, resp);
+                }
+                frame.encode(buf, resp);
             }
-        } // match
+        }
+    }
+}
+
+/// `<prefix><n>\r\n`, e.g. integers and the headers of aggregates
+fn encode_header(buf: &mut BytesMut, prefix: u8, n: i64) {
+    use std::fmt::Write;
+    buf.extend_from_slice(&[prefix]);
+    // writing into a BytesMut never fails
+    let _ = write!(buf, "{}\r\n", n);
+}
+
+/// `<prefix><line>\r\n`, e.g. simple strings and errors
+fn encode_line(buf: &mut BytesMut, prefix: u8, line: &[u8]) {
+    buf.reserve(line.len() + 3);
+    buf.extend_from_slice(&[prefix]);
+    buf.extend_from_slice(line);
+    buf.extend_from_slice(b"\r\n");
+}
 
-        // data not enough, rollback
-        cur.set_position(checkpoint);
-        Ok(None)
+/// `<prefix><len>\r\n<bytes>\r\n`, e.g. bulk strings
+fn encode_blob(buf: &mut BytesMut, prefix: u8, blob: &[u8]) {
+    encode_header(buf, prefix, blob.len() as i64);
+    buf.reserve(blob.len() + 2);
+    buf.extend_from_slice(blob);
+    buf.extend_from_slice(b"\r\n");
+}
+
+fn encode_aggregate(buf: &mut BytesMut, prefix: u8, frames: &[Frame], resp: u8) {
+    let prefix = if resp < 3 { b'*' } else { prefix };
+    encode_header(buf, prefix, frames.len() as i64);
+    for frame in frames {
+        frame.encode(buf, resp);
     }
+}
 
-    pub fn serialize(&self) -> Bytes {
-        let mut buf = BytesMut::new();
-        buf.extend_from_slice(&self.to_string().into_bytes());
-        buf.freeze()
+fn encode_pairs(buf: &mut BytesMut, pairs: &[(Frame, Frame)], resp: u8) {
+    for (key, value) in pairs {
+        key.encode(buf, resp);
+        value.encode(buf, resp);
+    }
+}
+
+/// Format a double the way redis does, e.g. `inf` and `-inf`
+fn format_double(n: f64) -> String {
+    if n.is_infinite() {
+        if n > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else if n.is_nan() {
+        "nan".to_string()
+    } else {
+        n.to_string()
     }
 }
 
+/// The RESP2 encoding, lossily decoded, for debugging only
 impl Display for Frame {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Frame::Simple(s) => write!(f, "+{}\r\n", String::from_utf8_lossy(s)),
-            Frame::Error(s) => write!(f, "-{}\r\n", String::from_utf8_lossy(s)),
-            Frame::Integer(n) => write!(f, ":{}\r\n", n),
-            Frame::Bulk(b) => write!(f, "${}\r\n{}\r\n", b.len(), String::from_utf8_lossy(b)),
-            Frame::Null => write!(f, "$-1\r\n"),
-            Frame::Array(a) => {
-                write!(f, "*{}\r\n", a.len())?;
-                for frame in a {
-                    write!(f, "{}", frame)?;
-                }
-                Ok(())
+        write!(f, "{}", String::from_utf8_lossy(&self.serialize(2)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_all(input: &[u8]) -> (Vec<Frame>, BytesMut) {
+        let mut buf = BytesMut::from(input);
+        let mut frames = Vec::new();
+        while let Some(frame) = Frame::parse(&mut buf).unwrap() {
+            frames.push(frame);
+        }
+        (frames, buf)
+    }
+
+    #[test]
+    fn parse_waits_for_complete_frames() {
+        let input = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nhello\r\n";
+        // every prefix is left in the buffer until the frame is complete
+        for len in 0..input.len() {
+            let mut buf = BytesMut::from(&input[..len]);
+            assert!(Frame::parse(&mut buf).unwrap().is_none());
+            assert_eq!(&buf[..], &input[..len]);
+        }
+        let (frames, rest) = parse_all(&[&input[..], b"*1\r\n$4\r\nPI"].concat());
+        assert_eq!(frames.len(), 1);
+        assert_eq!(&frames[0].serialize(2)[..], &input[..]);
+        assert_eq!(&rest[..], b"*1\r\n$4\r\nPI");
+    }
+
+    #[test]
+    fn parse_resumes_where_it_stopped() {
+        let input = b"*3\r\n*2\r\n$1\r\na\r\n:1\r\n%1\r\n+k\r\n$1\r\nv\r\n$0\r\n\r\n";
+        let next = b"*1\r\n$4\r\nPING\r\n";
+        let mut buf = BytesMut::new();
+        let mut state = CheckState::default();
+        for (i, byte) in input.iter().enumerate() {
+            assert!(
+                Frame::parse_request(&mut buf, &ProtoLimits::UNLIMITED, &mut state)
+                    .unwrap()
+                    .is_none()
+            );
+            if i == 20 {
+                // the nested array is checked, the map is not
+                assert_eq!(&buf[state.pos..], b"%");
+                assert_eq!(state.pending, [2]);
             }
+            buf.extend_from_slice(&[*byte]);
         }
+        buf.extend_from_slice(next);
+        let frame = Frame::parse_request(&mut buf, &ProtoLimits::UNLIMITED, &mut state)
+            .unwrap()
+            .unwrap();
+        assert_eq!(&frame.serialize(3)[..], &input[..]);
+        assert_eq!(&buf[..], next);
+        assert_eq!((state.pos, state.pending.len()), (0, 0));
+        let frame = Frame::parse_request(&mut buf, &ProtoLimits::UNLIMITED, &mut state)
+            .unwrap()
+            .unwrap();
+        assert_eq!(&frame.serialize(2)[..], next);
+    }
+
+    #[test]
+    fn parse_resp3_roundtrip() {
+        let inputs: [&[u8]; 13] = [
+            b"+OK\r\n",
+            b"-ERR wrong\r\n",
+            b":-42\r\n",
+            b"$0\r\n\r\n",
+            b"_\r\n",
+            b",3.5\r\n",
+            b",-inf\r\n",
+            b"#t\r\n",
+            b"(12345678901234567890\r\n",
+            b"=9\r\ntxt:hello\r\n",
+            b"%1\r\n+key\r\n*2\r\n:1\r\n~1\r\n#f\r\n",
+            b">2\r\n$7\r\nmessage\r\n$2\r\nhi\r\n",
+            b"|1\r\n+ttl\r\n:3\r\n$1\r\nv\r\n",
+        ];
+        for input in inputs {
+            let (frames, rest) = parse_all(input);
+            assert_eq!(frames.len(), 1, "{:?}", input);
+            assert!(rest.is_empty());
+            assert_eq!(&frames[0].serialize(3)[..], input);
+        }
+        // the null blob and array are read as Null
+        let (frames, _) = parse_all(b"$-1\r\n*-1\r\n");
+        assert!(matches!(frames[..], [Frame::Null, Frame::Null]));
+    }
+
+    #[test]
+    fn parse_binary_safe_bulks() {
+        let (frames, _) = parse_all(b"$4\r\na\r\nb\r\n");
+        assert!(matches!(&frames[0], Frame::Bulk(b) if &b[..] == b"a\r\nb"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for input in [
+            &b"?\r\n"[..],
+            b"$-2\r\n",
+            b":abc\r\n",
+            b"#x\r\n",
+            b"=2\r\nab\r\n",
+        ] {
+            assert!(
+                Frame::parse(&mut BytesMut::from(input)).is_err(),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn parse_request_limits() {
+        let limits = ProtoLimits {
+            max_bulk_len: 4,
+            max_multibulk_len: 2,
+            max_depth: 1,
+        };
+        let parse = |input: &[u8]| {
+            let mut state = CheckState::default();
+            Frame::parse_request(&mut BytesMut::from(input), &limits, &mut state)
+        };
+        assert!(parse(b"*2\r\n$4\r\nPING\r\n$4\r\nPONG\r\n")
+            .unwrap()
+            .is_some());
+        // refused from their headers, before the payloads are buffered
+        assert!(parse(b"$5\r\n").is_err());
+        assert!(parse(b"*3\r\n").is_err());
+        assert!(parse(b"*1\r\n*1\r\n").is_err());
+        let mut buf = BytesMut::from(&b":1"[..]);
+        buf.resize(PROTO_INLINE_MAX_SIZE + 2, b'1');
+        assert!(Frame::parse_request(&mut buf, &limits, &mut CheckState::default()).is_err());
+    }
+
+    #[test]
+    fn parse_copies_small_payloads() {
+        let big = vec![b'x'; PROTO_MBULK_BIG_ARG];
+        let request = |capacity: usize| {
+            let mut buf = BytesMut::with_capacity(capacity);
+            buf.extend_from_slice(b"*2\r\n$1\r\nk\r\n");
+            buf.extend_from_slice(format!("${}\r\n", big.len()).as_bytes());
+            buf.extend_from_slice(&big);
+            buf.extend_from_slice(b"\r\n");
+            buf
+        };
+        let parse = |mut buf: BytesMut| {
+            let range = buf.as_ptr_range();
+            let Frame::Array(args) = Frame::parse(&mut buf).unwrap().unwrap() else {
+                panic!("not an array");
+            };
+            let (Frame::Bulk(small), Frame::Bulk(large)) = (&args[0], &args[1]) else {
+                panic!("not bulks");
+            };
+            assert_eq!(&large[..], &big[..]);
+            (
+                range.contains(&small.as_ptr()),
+                range.contains(&large.as_ptr()),
+            )
+        };
+
+        // the big payload fills the buffer
+        assert_eq!(parse(request(big.len() + 64)), (false, true));
+        // but not a buffer several times bigger
+        assert_eq!(parse(request(4 * PROTO_MBULK_BIG_ARG)), (false, false));
+    }
+
+    #[test]
+    fn parse_inline_requests() {
+        let mut buf = BytesMut::from(&b"SET k \"a b\"\r\nGET"[..]);
+        let frame = Frame::parse_inline(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            &frame.serialize(2)[..],
+            b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$3\r\na b\r\n"
+        );
+        assert_eq!(&buf[..], b"GET");
+        assert!(Frame::parse_inline(&mut buf).unwrap().is_none());
+        assert!(Frame::parse_inline(&mut BytesMut::from(&b"GET \"k\n"[..])).is_err());
     }
 }
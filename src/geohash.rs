@@ -0,0 +1,517 @@
+/* The geohashes of redis, see geohash.c and geohash_helper.c: the longitude
+ * and the latitude are each quantized on GEO_STEP_MAX bits, interleaved with
+ * the latitude in the even bits, and the resulting 52 bits are stored as the
+ * score of a sorted set member. Nearby points share a prefix, so the points
+ * in an area are a range of scores. */
+
+pub const GEO_STEP_MAX: u8 = 26; /* 26 * 2 = 52 bits. */
+pub const GEO_LAT_MIN: f64 = -85.05112878;
+pub const GEO_LAT_MAX: f64 = 85.05112878;
+pub const GEO_LONG_MIN: f64 = -180.0;
+pub const GEO_LONG_MAX: f64 = 180.0;
+
+/// Earth's quadratic mean radius for WGS-84
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+const MERCATOR_MAX: f64 = 20037726.37;
+
+/// A geohash of `step` bits per coordinate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GeoHashBits {
+    pub bits: u64,
+    pub step: u8,
+}
+
+impl GeoHashBits {
+    /// The hash of a neighbour dropped from a search
+    fn is_zero(&self) -> bool {
+        self.bits == 0 && self.step == 0
+    }
+
+    /// The hash shifted to 52 bits, the lowest score of its area
+    pub fn align52(&self) -> u64 {
+        self.bits << (52 - self.step as u32 * 2)
+    }
+
+    /// Move the hash by `d` cells along the longitude
+    fn move_x(&mut self, d: i8) {
+        if d == 0 {
+            return;
+        }
+        let mut x = self.bits & 0xaaaaaaaaaaaaaaaa;
+        let y = self.bits & 0x5555555555555555;
+        let zz = 0x5555555555555555u64 >> (64 - self.step as u32 * 2);
+        if d > 0 {
+            x = x.wrapping_add(zz + 1);
+        } else {
+            x |= zz;
+            x = x.wrapping_sub(zz + 1);
+        }
+        x &= 0xaaaaaaaaaaaaaaaa >> (64 - self.step as u32 * 2);
+        self.bits = x | y;
+    }
+
+    /// Move the hash by `d` cells along the latitude
+    fn move_y(&mut self, d: i8) {
+        if d == 0 {
+            return;
+        }
+        let x = self.bits & 0xaaaaaaaaaaaaaaaa;
+        let mut y = self.bits & 0x5555555555555555;
+        let zz = 0xaaaaaaaaaaaaaaaau64 >> (64 - self.step as u32 * 2);
+        if d > 0 {
+            y = y.wrapping_add(zz + 1);
+        } else {
+            y |= zz;
+            y = y.wrapping_sub(zz + 1);
+        }
+        y &= 0x5555555555555555 >> (64 - self.step as u32 * 2);
+        self.bits = x | y;
+    }
+
+    fn moved(&self, dx: i8, dy: i8) -> GeoHashBits {
+        let mut hash = *self;
+        hash.move_x(dx);
+        hash.move_y(dy);
+        hash
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GeoHashRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// The coordinate ranges of the scores
+const LONG_RANGE: GeoHashRange = GeoHashRange {
+    min: GEO_LONG_MIN,
+    max: GEO_LONG_MAX,
+};
+const LAT_RANGE: GeoHashRange = GeoHashRange {
+    min: GEO_LAT_MIN,
+    max: GEO_LAT_MAX,
+};
+
+/// The cell of a hash
+#[derive(Debug, Clone, Copy)]
+pub struct GeoHashArea {
+    pub hash: GeoHashBits,
+    pub longitude: GeoHashRange,
+    pub latitude: GeoHashRange,
+}
+
+/// Interleave the lower bits of `x` and `y`, `x` in the even bits, see
+/// https://graphics.stanford.edu/~seander/bithacks.html#InterleaveBMN
+fn interleave64(x: u32, y: u32) -> u64 {
+    const B: [u64; 5] = [
+        0x5555555555555555,
+        0x3333333333333333,
+        0x0F0F0F0F0F0F0F0F,
+        0x00FF00FF00FF00FF,
+        0x0000FFFF0000FFFF,
+    ];
+    const S: [u32; 5] = [1, 2, 4, 8, 16];
+    let spread = |mut v: u64| {
+        for i in (0..5).rev() {
+            v = (v | (v << S[i])) & B[i];
+        }
+        v
+    };
+    spread(x as u64) | (spread(y as u64) << 1)
+}
+
+/// Reverse of interleave64, `x` in the lower 32 bits and `y` in the upper
+fn deinterleave64(interleaved: u64) -> u64 {
+    const B: [u64; 6] = [
+        0x5555555555555555,
+        0x3333333333333333,
+        0x0F0F0F0F0F0F0F0F,
+        0x00FF00FF00FF00FF,
+        0x0000FFFF0000FFFF,
+        0x00000000FFFFFFFF,
+    ];
+    const S: [u32; 6] = [0, 1, 2, 4, 8, 16];
+    let squash = |mut v: u64| {
+        for i in 0..6 {
+            v = (v | (v >> S[i])) & B[i];
+        }
+        v
+    };
+    squash(interleaved) | (squash(interleaved >> 1) << 32)
+}
+
+/// Whether the coordinates can be indexed
+pub fn valid_long_lat(longitude: f64, latitude: f64) -> bool {
+    (GEO_LONG_MIN..=GEO_LONG_MAX).contains(&longitude)
+        && (GEO_LAT_MIN..=GEO_LAT_MAX).contains(&latitude)
+}
+
+/// The hash of the coordinates in the ranges, None if they are out of them
+pub fn encode(
+    long_range: &GeoHashRange,
+    lat_range: &GeoHashRange,
+    longitude: f64,
+    latitude: f64,
+    step: u8,
+) -> Option<GeoHashBits> {
+    if !valid_long_lat(longitude, latitude)
+        || !(lat_range.min..=lat_range.max).contains(&latitude)
+        || !(long_range.min..=long_range.max).contains(&longitude)
+    {
+        return None;
+    }
+    let cells = (1u64 << step) as f64;
+    let lat_offset = (latitude - lat_range.min) / (lat_range.max - lat_range.min) * cells;
+    let long_offset = (longitude - long_range.min) / (long_range.max - long_range.min) * cells;
+    Some(GeoHashBits {
+        bits: interleave64(lat_offset as u32, long_offset as u32),
+        step,
+    })
+}
+
+pub fn encode_wgs84(longitude: f64, latitude: f64, step: u8) -> Option<GeoHashBits> {
+    encode(&LONG_RANGE, &LAT_RANGE, longitude, latitude, step)
+}
+
+/// The cell of the hash in the ranges
+pub fn decode(
+    long_range: &GeoHashRange,
+    lat_range: &GeoHashRange,
+    hash: GeoHashBits,
+) -> GeoHashArea {
+    let hash_sep = deinterleave64(hash.bits);
+    let lat_scale = lat_range.max - lat_range.min;
+    let long_scale = long_range.max - long_range.min;
+    let ilato = hash_sep as u32 as f64;
+    let ilono = (hash_sep >> 32) as u32 as f64;
+    let cells = (1u64 << hash.step) as f64;
+    GeoHashArea {
+        hash,
+        latitude: GeoHashRange {
+            min: lat_range.min + (ilato / cells) * lat_scale,
+            max: lat_range.min + ((ilato + 1.0) / cells) * lat_scale,
+        },
+        longitude: GeoHashRange {
+            min: long_range.min + (ilono / cells) * long_scale,
+            max: long_range.min + ((ilono + 1.0) / cells) * long_scale,
+        },
+    }
+}
+
+pub fn decode_wgs84(hash: GeoHashBits) -> GeoHashArea {
+    decode(&LONG_RANGE, &LAT_RANGE, hash)
+}
+
+/// The centre of the cell, as `(longitude, latitude)`
+fn area_to_long_lat(area: &GeoHashArea) -> (f64, f64) {
+    let longitude =
+        ((area.longitude.min + area.longitude.max) / 2.0).clamp(GEO_LONG_MIN, GEO_LONG_MAX);
+    let latitude = ((area.latitude.min + area.latitude.max) / 2.0).clamp(GEO_LAT_MIN, GEO_LAT_MAX);
+    (longitude, latitude)
+}
+
+/// The sorted set score of the coordinates, None if they are out of range
+pub fn score_of(longitude: f64, latitude: f64) -> Option<f64> {
+    encode_wgs84(longitude, latitude, GEO_STEP_MAX).map(|hash| hash.align52() as f64)
+}
+
+/// The `(longitude, latitude)` of a sorted set score
+pub fn long_lat_of(score: f64) -> (f64, f64) {
+    let hash = GeoHashBits {
+        bits: score as u64,
+        step: GEO_STEP_MAX,
+    };
+    area_to_long_lat(&decode_wgs84(hash))
+}
+
+/// The standard 11 characters geohash of a sorted set score, which is
+/// encoded with the latitude range [-90, 90] instead of the mercator one
+pub fn geohash_string(score: f64) -> String {
+    const ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+    let (longitude, latitude) = long_lat_of(score);
+    let lat_range = GeoHashRange {
+        min: -90.0,
+        max: 90.0,
+    };
+    let bits = encode(&LONG_RANGE, &lat_range, longitude, latitude, GEO_STEP_MAX)
+        .map_or(0, |hash| hash.bits);
+    (0..11)
+        .map(|i| {
+            // the 52 bits only fill 10 characters and a bit
+            let idx = if i == 10 {
+                0
+            } else {
+                (bits >> (52 - (i + 1) * 5)) & 0x1f
+            };
+            ALPHABET[idx as usize] as char
+        })
+        .collect()
+}
+
+fn deg_rad(deg: f64) -> f64 {
+    deg.to_radians()
+}
+
+fn rad_deg(rad: f64) -> f64 {
+    rad.to_degrees()
+}
+
+/// The distance in meters between two latitudes of the same longitude
+fn lat_distance(lat1: f64, lat2: f64) -> f64 {
+    EARTH_RADIUS_IN_METERS * (deg_rad(lat2) - deg_rad(lat1)).abs()
+}
+
+/// The haversine distance in meters between two points
+pub fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let lat1r = deg_rad(lat1);
+    let lat2r = deg_rad(lat2);
+    let v = ((deg_rad(lon2) - deg_rad(lon1)) / 2.0).sin();
+    // the same longitude, the distance is along the meridian
+    if v == 0.0 {
+        return lat_distance(lat1, lat2);
+    }
+    let u = ((lat2r - lat1r) / 2.0).sin();
+    let a = u * u + lat1r.cos() * lat2r.cos() * v * v;
+    2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+}
+
+/// The area searched by GEOSEARCH, the sizes are in meters
+#[derive(Debug, Clone, Copy)]
+pub enum GeoShape {
+    Circle { radius: f64 },
+    Box { width: f64, height: f64 },
+}
+
+impl GeoShape {
+    /// The distance in meters of the point from the centre, None if the
+    /// point is out of the shape
+    pub fn distance_if_within(&self, centre: (f64, f64), point: (f64, f64)) -> Option<f64> {
+        match *self {
+            GeoShape::Circle { radius } => {
+                let d = distance(centre.0, centre.1, point.0, point.1);
+                (d <= radius).then_some(d)
+            }
+            GeoShape::Box { width, height } => {
+                if lat_distance(point.1, centre.1) > height / 2.0 {
+                    return None;
+                }
+                if distance(point.0, point.1, centre.0, point.1) > width / 2.0 {
+                    return None;
+                }
+                Some(distance(centre.0, centre.1, point.0, point.1))
+            }
+        }
+    }
+
+    /// Half the width and half the height
+    fn half_sizes(&self) -> (f64, f64) {
+        match *self {
+            GeoShape::Circle { radius } => (radius, radius),
+            GeoShape::Box { width, height } => (width / 2.0, height / 2.0),
+        }
+    }
+
+    /// The `[min_lon, min_lat, max_lon, max_lat]` bounding box of the shape
+    fn bounding_box(&self, longitude: f64, latitude: f64) -> [f64; 4] {
+        let (width, height) = self.half_sizes();
+        let lat_delta = rad_deg(height / EARTH_RADIUS_IN_METERS);
+        let long_delta_top =
+            rad_deg(width / EARTH_RADIUS_IN_METERS / deg_rad(latitude + lat_delta).cos());
+        let long_delta_bottom =
+            rad_deg(width / EARTH_RADIUS_IN_METERS / deg_rad(latitude - lat_delta).cos());
+        // the box is the widest on the side nearest to the equator
+        let long_delta = if latitude < 0.0 {
+            long_delta_bottom
+        } else {
+            long_delta_top
+        };
+        [
+            longitude - long_delta,
+            latitude - lat_delta,
+            longitude + long_delta,
+            latitude + lat_delta,
+        ]
+    }
+}
+
+/// The precision, in bits per coordinate, of the cells covering a radius
+fn estimate_steps_by_radius(mut range_meters: f64, latitude: f64) -> u8 {
+    if range_meters == 0.0 {
+        return GEO_STEP_MAX;
+    }
+    let mut step: i32 = 1;
+    while range_meters < MERCATOR_MAX {
+        range_meters *= 2.0;
+        step += 1;
+    }
+    step -= 2; /* Make sure range is included in most of the base cases. */
+    // the cells are narrower near the poles
+    if !(-66.0..=66.0).contains(&latitude) {
+        step -= 1;
+        if !(-80.0..=80.0).contains(&latitude) {
+            step -= 1;
+        }
+    }
+    step.clamp(1, GEO_STEP_MAX as i32) as u8
+}
+
+/// The cell of the centre and its 8 neighbours
+fn neighbours(hash: GeoHashBits) -> [GeoHashBits; 9] {
+    [
+        hash,
+        hash.moved(0, 1),
+        hash.moved(0, -1),
+        hash.moved(1, 0),
+        hash.moved(-1, 0),
+        hash.moved(1, 1),
+        hash.moved(-1, 1),
+        hash.moved(1, -1),
+        hash.moved(-1, -1),
+    ]
+}
+
+// the positions in the neighbours
+const NORTH: usize = 1;
+const SOUTH: usize = 2;
+const EAST: usize = 3;
+const WEST: usize = 4;
+const NORTH_EAST: usize = 5;
+const NORTH_WEST: usize = 6;
+const SOUTH_EAST: usize = 7;
+const SOUTH_WEST: usize = 8;
+
+/// The `[min, max)` score ranges of the cells covering the shape around the
+/// centre, see geohashCalculateAreasByShapeWGS84 in redis
+pub fn search_ranges(shape: &GeoShape, longitude: f64, latitude: f64) -> Vec<(f64, f64)> {
+    let [min_lon, min_lat, max_lon, max_lat] = shape.bounding_box(longitude, latitude);
+    let radius_meters = match *shape {
+        GeoShape::Circle { radius } => radius,
+        GeoShape::Box { width, height } => ((width / 2.0).powi(2) + (height / 2.0).powi(2)).sqrt(),
+    };
+    let mut steps = estimate_steps_by_radius(radius_meters, latitude);
+    let Some(mut hash) = encode_wgs84(longitude, latitude, steps) else {
+        return Vec::new();
+    };
+    let mut cells = neighbours(hash);
+
+    // the cells may be too small to cover the box, then a step less is used
+    let decrease_step = decode_wgs84(cells[NORTH]).latitude.max < max_lat
+        || decode_wgs84(cells[SOUTH]).latitude.min > min_lat
+        || decode_wgs84(cells[EAST]).longitude.max < max_lon
+        || decode_wgs84(cells[WEST]).longitude.min > min_lon;
+    if steps > 1 && decrease_step {
+        steps -= 1;
+        hash = match encode_wgs84(longitude, latitude, steps) {
+            Some(hash) => hash,
+            None => return Vec::new(),
+        };
+        cells = neighbours(hash);
+    }
+
+    // drop the neighbours out of the box, the centre cell already covers
+    // that side
+    if steps >= 2 {
+        let area = decode_wgs84(hash);
+        let mut drop = |sides: [usize; 3]| {
+            for side in sides {
+                cells[side] = GeoHashBits::default();
+            }
+        };
+        if area.latitude.min < min_lat {
+            drop([SOUTH, SOUTH_WEST, SOUTH_EAST]);
+        }
+        if area.latitude.max > max_lat {
+            drop([NORTH, NORTH_EAST, NORTH_WEST]);
+        }
+        if area.longitude.min < min_lon {
+            drop([WEST, SOUTH_WEST, NORTH_WEST]);
+        }
+        if area.longitude.max > max_lon {
+            drop([EAST, SOUTH_EAST, NORTH_EAST]);
+        }
+    }
+
+    let mut ranges: Vec<(f64, f64)> = Vec::with_capacity(cells.len());
+    for cell in cells.iter().filter(|cell| !cell.is_zero()) {
+        let next = GeoHashBits {
+            bits: cell.bits + 1,
+            step: cell.step,
+        };
+        let range = (cell.align52() as f64, next.align52() as f64);
+        // with the smallest steps the neighbours may wrap to the same cell
+        if !ranges.contains(&range) {
+            ranges.push(range);
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // GEOADD Sicily 13.361389 38.115556 Palermo 15.087269 37.502669 Catania
+    const PALERMO: (f64, f64) = (13.361389, 38.115556);
+    const CATANIA: (f64, f64) = (15.087269, 37.502669);
+
+    #[test]
+    fn scores_as_redis() {
+        assert_eq!(score_of(PALERMO.0, PALERMO.1), Some(3479099956230698.0));
+        assert_eq!(score_of(CATANIA.0, CATANIA.1), Some(3479447370796909.0));
+        assert_eq!(score_of(0.0, 86.0), None);
+        assert_eq!(score_of(181.0, 0.0), None);
+    }
+
+    #[test]
+    fn positions_as_redis() {
+        // GEOPOS Sicily Palermo Catania
+        let (longitude, latitude) = long_lat_of(3479099956230698.0);
+        assert!((longitude - 13.36138933897018433).abs() < 1e-9);
+        assert!((latitude - 38.11555639549629859).abs() < 1e-9);
+        let (longitude, latitude) = long_lat_of(3479447370796909.0);
+        assert!((longitude - 15.08726745843887329).abs() < 1e-9);
+        assert!((latitude - 37.50266842333162032).abs() < 1e-9);
+    }
+
+    #[test]
+    fn geohash_strings_as_redis() {
+        // GEOHASH Sicily Palermo Catania
+        assert_eq!(geohash_string(3479099956230698.0), "sqc8b49rny0");
+        assert_eq!(geohash_string(3479447370796909.0), "sqdtr74hyu0");
+    }
+
+    #[test]
+    fn distance_as_redis() {
+        // GEODIST Sicily Palermo Catania
+        let (lon1, lat1) = long_lat_of(3479099956230698.0);
+        let (lon2, lat2) = long_lat_of(3479447370796909.0);
+        assert!((distance(lon1, lat1, lon2, lat2) - 166274.1516).abs() < 1e-3);
+        assert_eq!(distance(lon1, lat1, lon1, lat1), 0.0);
+    }
+
+    #[test]
+    fn interleave_roundtrip() {
+        for (x, y) in [
+            (0, 0),
+            (1, 0),
+            (0, 1),
+            (0x3ffffff, 0x1234567),
+            (u32::MAX, 0),
+        ] {
+            let interleaved = interleave64(x, y);
+            assert_eq!(interleaved & 1, x as u64 & 1);
+            assert_eq!(deinterleave64(interleaved), x as u64 | (y as u64) << 32);
+        }
+    }
+
+    #[test]
+    fn search_ranges_contain_the_members() {
+        let shape = GeoShape::Circle { radius: 200_000.0 };
+        let ranges = search_ranges(&shape, 15.0, 37.0);
+        for score in [3479099956230698.0, 3479447370796909.0] {
+            assert!(ranges
+                .iter()
+                .any(|(min, max)| *min <= score && score < *max));
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use crate::shared::murmur_hash64a;
+use bytes::Bytes;
+
+/* The HyperLogLog strings have the layout of redis, see hyperloglog.c:
+ *
+ * +------+---+-----+----------+
+ * | HYLL | E | N/U | Cardin.  |
+ * +------+---+-----+----------+
+ *
+ * the 4 bytes magic, the 1 byte encoding, 3 unused bytes, the 8 bytes
+ * cached cardinality in little endian with the most significant bit set when
+ * stale, then the registers, either dense or sparse. */
+
+pub const HLL_P: u32 = 14; /* The greater is P, the smaller the error. */
+pub const HLL_Q: u32 = 64 - HLL_P; /* Bits of the hash used to count the zeroes. */
+pub const HLL_REGISTERS: usize = 1 << HLL_P;
+const HLL_BITS: usize = 6; /* Enough to count up to 63 leading zeroes. */
+const HLL_REGISTER_MAX: u8 = (1 << HLL_BITS) - 1;
+const HLL_HDR_SIZE: usize = 16;
+const HLL_DENSE_SIZE: usize = HLL_HDR_SIZE + (HLL_REGISTERS * HLL_BITS).div_ceil(8);
+const HLL_DENSE: u8 = 0;
+const HLL_SPARSE: u8 = 1;
+const HLL_ALPHA_INF: f64 = 0.721_347_520_444_481_7; /* 1 / (2 * ln(2)) */
+/// Size past which a sparse HyperLogLog is converted to dense, see
+/// hll-sparse-max-bytes in redis
+pub const HLL_SPARSE_MAX_BYTES: usize = 3000;
+
+/* The sparse opcodes: ZERO 00xxxxxx, a run of 1-64 zero registers, XZERO
+ * 01xxxxxx yyyyyyyy, a run of 1-16384 zero registers, and VAL 1vvvvvxx, a
+ * run of 1-4 registers of the value 1-32. */
+const HLL_SPARSE_XZERO_BIT: u8 = 0x40;
+const HLL_SPARSE_VAL_BIT: u8 = 0x80;
+const HLL_SPARSE_ZERO_MAX_LEN: usize = 64;
+const HLL_SPARSE_XZERO_MAX_LEN: usize = 16384;
+const HLL_SPARSE_VAL_MAX_VALUE: u8 = 32;
+const HLL_SPARSE_VAL_MAX_LEN: usize = 4;
+
+/// The registers of a HyperLogLog, one byte each
+#[derive(Debug, Clone)]
+pub struct Hll {
+    registers: Vec<u8>,
+    /// the string was sparse, it stays so while it fits
+    sparse: bool,
+}
+
+impl Default for Hll {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hll {
+    pub fn new() -> Hll {
+        Hll {
+            registers: vec![0; HLL_REGISTERS],
+            sparse: true,
+        }
+    }
+
+    /// Whether `s` has the header of a HyperLogLog and registers which fit
+    /// its encoding, the opcodes of a sparse one must cover all the
+    /// registers
+    pub fn is_hll(s: &[u8]) -> bool {
+        s.len() >= HLL_HDR_SIZE
+            && &s[..4] == b"HYLL"
+            && match s[4] {
+                HLL_DENSE => s.len() == HLL_DENSE_SIZE,
+                HLL_SPARSE => sparse_registers(s) == Some(HLL_REGISTERS),
+                _ => false,
+            }
+    }
+
+    /// The cardinality cached in the header of `s`, None if it is stale
+    pub fn cached_count(s: &[u8]) -> Option<u64> {
+        if s[15] & 0x80 != 0 {
+            return None;
+        }
+        let mut card = [0; 8];
+        card.copy_from_slice(&s[8..16]);
+        Some(u64::from_le_bytes(card))
+    }
+
+    /// Set the cached cardinality in the header of `s`
+    pub fn cache_count(s: &mut [u8], count: u64) {
+        s[8..16].copy_from_slice(&count.to_le_bytes());
+    }
+
+    /// Decode the registers of `s`, None if it isn't a HyperLogLog, e.g. the
+    /// sparse encoding is corrupted
+    pub fn decode(s: &[u8]) -> Option<Hll> {
+        if !Self::is_hll(s) {
+            return None;
+        }
+        let mut hll = Hll::new();
+        if s[4] == HLL_DENSE {
+            hll.sparse = false;
+            let dense = &s[HLL_HDR_SIZE..];
+            for (i, register) in hll.registers.iter_mut().enumerate() {
+                *register = dense_get(dense, i);
+            }
+            return Some(hll);
+        }
+
+        let mut index = 0;
+        let mut p = HLL_HDR_SIZE;
+        while p < s.len() {
+            let (len, run, value) = sparse_opcode(s, p)?;
+            if value != 0 {
+                hll.registers[index..index + run].fill(value);
+            }
+            index += run;
+            p += len;
+        }
+        Some(hll)
+    }
+
+    /// Encode the registers with a stale cached cardinality, sparse if it
+    /// was and still fits
+    pub fn encode(&self) -> Bytes {
+        let mut s = Vec::with_capacity(HLL_DENSE_SIZE);
+        s.extend_from_slice(b"HYLL");
+        s.extend_from_slice(&[HLL_DENSE, 0, 0, 0]);
+        s.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0x80]);
+        if self.sparse && self.encode_sparse(&mut s) {
+            s[4] = HLL_SPARSE;
+            return Bytes::from(s);
+        }
+        s.truncate(HLL_HDR_SIZE);
+        s.resize(HLL_DENSE_SIZE, 0);
+        let dense = &mut s[HLL_HDR_SIZE..];
+        for (i, &register) in self.registers.iter().enumerate() {
+            dense_set(dense, i, register);
+        }
+        Bytes::from(s)
+    }
+
+    /// Append the sparse encoding of the registers to `s`, false if a
+    /// register is too large for it or it would be too long
+    fn encode_sparse(&self, s: &mut Vec<u8>) -> bool {
+        let mut i = 0;
+        while i < HLL_REGISTERS {
+            let value = self.registers[i];
+            let mut run = 1;
+            while i + run < HLL_REGISTERS && self.registers[i + run] == value {
+                run += 1;
+            }
+            i += run;
+            if value > HLL_SPARSE_VAL_MAX_VALUE {
+                return false;
+            }
+            while run > 0 {
+                if value != 0 {
+                    let len = run.min(HLL_SPARSE_VAL_MAX_LEN);
+                    s.push(HLL_SPARSE_VAL_BIT | ((value - 1) << 2) | (len - 1) as u8);
+                    run -= len;
+                } else if run <= HLL_SPARSE_ZERO_MAX_LEN {
+                    s.push((run - 1) as u8);
+                    run = 0;
+                } else {
+                    let len = run.min(HLL_SPARSE_XZERO_MAX_LEN) - 1;
+                    s.push(HLL_SPARSE_XZERO_BIT | (len >> 8) as u8);
+                    s.push((len & 0xff) as u8);
+                    run -= len + 1;
+                }
+            }
+            if s.len() > HLL_SPARSE_MAX_BYTES {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Add `element`, true if a register changed
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let (index, count) = pattern_len(element);
+        if self.registers[index] < count {
+            self.registers[index] = count;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Merge the registers of `other`, keeping the largest ones
+    pub fn merge(&mut self, other: &Hll) {
+        for (register, &value) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(value);
+        }
+    }
+
+    /// Encode as dense from now on, as redis does for PFMERGE
+    pub fn set_dense(&mut self) {
+        self.sparse = false;
+    }
+
+    /// Estimate the cardinality, see hllCount in redis
+    pub fn count(&self) -> u64 {
+        let m = HLL_REGISTERS as f64;
+        let mut histogram = [0u32; 64];
+        for &register in &self.registers {
+            histogram[register as usize] += 1;
+        }
+        let q = HLL_Q as usize;
+        let mut z = m * tau((m - histogram[q + 1] as f64) / m);
+        for j in (1..=q).rev() {
+            z += histogram[j] as f64;
+            z *= 0.5;
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+        (HLL_ALPHA_INF * m * m / z).round() as u64
+    }
+}
+
+/// The opcode of the sparse encoding at `p`: its length in bytes, the
+/// number of registers it covers and their value. None if it is truncated
+fn sparse_opcode(s: &[u8], p: usize) -> Option<(usize, usize, u8)> {
+    let op = s[p];
+    if op & HLL_SPARSE_VAL_BIT != 0 {
+        Some((1, (op & 0x3) as usize + 1, ((op >> 2) & 0x1f) + 1))
+    } else if op & HLL_SPARSE_XZERO_BIT != 0 {
+        let low = *s.get(p + 1)?;
+        Some((2, (((op & 0x3f) as usize) << 8 | low as usize) + 1, 0))
+    } else {
+        Some((1, (op & 0x3f) as usize + 1, 0))
+    }
+}
+
+/// The number of registers covered by the opcodes of the sparse `s`, None
+/// if an opcode is truncated or they cover more than HLL_REGISTERS
+fn sparse_registers(s: &[u8]) -> Option<usize> {
+    let mut index = 0;
+    let mut p = HLL_HDR_SIZE;
+    while p < s.len() {
+        let (len, run, _) = sparse_opcode(s, p)?;
+        index += run;
+        p += len;
+        if index > HLL_REGISTERS {
+            return None;
+        }
+    }
+    Some(index)
+}
+
+fn dense_get(dense: &[u8], register: usize) -> u8 {
+    let byte = register * HLL_BITS / 8;
+    let fb = (register * HLL_BITS) & 7;
+    let b0 = dense[byte] as u16;
+    let b1 = dense.get(byte + 1).copied().unwrap_or(0) as u16;
+    (((b0 >> fb) | (b1 << (8 - fb))) & HLL_REGISTER_MAX as u16) as u8
+}
+
+fn dense_set(dense: &mut [u8], register: usize, value: u8) {
+    let byte = register * HLL_BITS / 8;
+    let fb = (register * HLL_BITS) & 7;
+    let value = value as u16;
+    dense[byte] &= !((HLL_REGISTER_MAX as u16) << fb) as u8;
+    dense[byte] |= (value << fb) as u8;
+    if let Some(next) = dense.get_mut(byte + 1) {
+        *next &= !((HLL_REGISTER_MAX as u16) >> (8 - fb)) as u8;
+        *next |= (value >> (8 - fb)) as u8;
+    }
+}
+
+/// The register of `element` and the length of the run of zeroes of its hash
+/// plus one, see hllPatLen in redis
+fn pattern_len(element: &[u8]) -> (usize, u8) {
+    let mut hash = murmur_hash64a(element, 0xadc83b19);
+    let index = (hash & (HLL_REGISTERS as u64 - 1)) as usize;
+    hash >>= HLL_P;
+    // make sure the loop terminates
+    hash |= 1 << HLL_Q;
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let z_prime = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z_prime == z {
+            return z / 3.0;
+        }
+    }
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let z_prime = z;
+        z += x * y;
+        y += y;
+        if z_prime == z {
+            return z;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hll_of(elements: &[&str]) -> Hll {
+        let mut hll = Hll::new();
+        for element in elements {
+            hll.add(element.as_bytes());
+        }
+        hll
+    }
+
+    #[test]
+    fn murmur_hash64a_matches_redis() {
+        let seed = 0xadc83b19;
+        assert_eq!(murmur_hash64a(b"", seed), 0xd8dfea6585bc9732);
+        assert_eq!(murmur_hash64a(b"a", seed), 0x53d2470a9b43b1a7);
+        assert_eq!(murmur_hash64a(b"foo", seed), 0xe64609b8b0141cb4);
+        assert_eq!(murmur_hash64a(b"hello world", seed), 0xa919bc3051f624b7);
+        assert_eq!(
+            murmur_hash64a(b"0123456789abcdef0", seed),
+            0xca1802fd45a1ff6c
+        );
+        assert_eq!(pattern_len(b"a"), (12711, 2));
+        assert_eq!(pattern_len(b"foo"), (7348, 5));
+    }
+
+    #[test]
+    fn encode_sparse_as_redis() {
+        // PFADD hll a then GET hll: XZERO of 12711 registers, VAL 2, XZERO
+        // of the 3672 others, with a stale cached cardinality
+        let mut expected = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80".to_vec();
+        expected.extend_from_slice(b"\x71\xa6\x84\x4e\x57");
+        assert_eq!(&hll_of(&["a"]).encode()[..], &expected[..]);
+
+        // an empty HyperLogLog is a single XZERO of all the registers
+        assert_eq!(&Hll::new().encode()[HLL_HDR_SIZE..], b"\x7f\xff");
+    }
+
+    #[test]
+    fn encode_decode_roundtrip() {
+        let mut hll = Hll::new();
+        for i in 0..200 {
+            hll.add(format!("e{}", i).as_bytes());
+        }
+
+        let sparse = hll.encode();
+        assert!(Hll::is_hll(&sparse));
+        assert_eq!(sparse[4], HLL_SPARSE);
+        assert_eq!(Hll::decode(&sparse).unwrap().registers, hll.registers);
+
+        let mut dense = hll.clone();
+        dense.set_dense();
+        let encoded = dense.encode();
+        assert_eq!(encoded[4], HLL_DENSE);
+        assert_eq!(encoded.len(), HLL_DENSE_SIZE);
+        let decoded = Hll::decode(&encoded).unwrap();
+        assert_eq!(decoded.registers, hll.registers);
+        assert!(!decoded.sparse);
+
+        // a run past the last register
+        let mut corrupted = sparse.to_vec();
+        corrupted.push(0x00);
+        assert!(Hll::decode(&corrupted).is_none());
+    }
+
+    #[test]
+    fn sparse_must_cover_all_registers() {
+        let header = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+        // three VAL opcodes of 4 registers, with a valid cached cardinality
+        let short = [&header[..], b"\xff\xff\xff"].concat();
+        assert!(!Hll::is_hll(&short));
+        assert!(Hll::decode(&short).is_none());
+        // a truncated XZERO
+        let truncated = [&header[..], b"\x7f"].concat();
+        assert!(!Hll::is_hll(&truncated));
+        // no opcodes at all
+        assert!(!Hll::is_hll(header));
+        // XZERO of 16380 registers then a VAL of 4
+        let exact = [&header[..], b"\x7f\xfb\xff"].concat();
+        assert!(Hll::is_hll(&exact));
+        assert_eq!(&Hll::decode(&exact).unwrap().registers[16380..], [32; 4]);
+    }
+
+    #[test]
+    fn sparse_promoted_to_dense() {
+        let mut hll = Hll::new();
+        let mut promoted = None;
+        for i in 0..20000 {
+            hll.add(format!("element:{}", i).as_bytes());
+            let encoded = hll.encode();
+            if encoded[4] == HLL_SPARSE {
+                assert!(encoded.len() <= HLL_SPARSE_MAX_BYTES);
+            } else {
+                assert_eq!(encoded.len(), HLL_DENSE_SIZE);
+                promoted = Some(i);
+                break;
+            }
+        }
+        assert!(promoted.is_some());
+
+        // the registers over 32 don't fit the sparse VAL opcode
+        let mut hll = Hll::new();
+        hll.registers[0] = HLL_SPARSE_VAL_MAX_VALUE + 1;
+        assert_eq!(hll.encode()[4], HLL_DENSE);
+    }
+
+    #[test]
+    fn count_as_redis() {
+        let hll = hll_of(&["a", "b", "c", "d", "e", "f", "g"]);
+        assert_eq!(hll.count(), 7);
+
+        let mut hll = hll_of(&["foo", "bar", "zap"]);
+        assert!(!hll.add(b"zap"));
+        assert_eq!(hll.count(), 3);
+        hll.merge(&hll_of(&["1", "2", "3"]));
+        assert_eq!(hll.count(), 6);
+
+        assert_eq!(Hll::new().count(), 0);
+        let mut hll = Hll::new();
+        for i in 0..100000 {
+            hll.add(i.to_string().as_bytes());
+        }
+        let error = (hll.count() as f64 - 100000.0).abs() / 100000.0;
+        assert!(error < 0.02, "error {}", error);
+    }
+}
@@ -1,11 +1,21 @@
+pub mod acl;
 pub mod aof;
+pub mod blocked;
 pub mod client;
 pub mod command;
 pub mod config;
 pub mod connection;
 pub mod dbms;
+pub mod evict;
+pub mod expire;
 pub mod frame;
+pub mod geohash;
+pub mod hyperloglog;
+pub mod multi;
 pub mod object;
+pub mod pubsub;
 pub mod rdb;
 pub mod server;
 pub mod shared;
+pub mod zmalloc;
+pub mod zskiplist;
@@ -0,0 +1,42 @@
+use crate::client::{Client, REDIS_DIRTY_CAS, REDIS_DIRTY_EXEC, REDIS_MULTI};
+use bytes::Bytes;
+use std::sync::Arc;
+
+impl Client {
+    /// Flag the transaction as DIRTY_EXEC so that EXEC will fail, called when
+    /// a command can't be queued
+    pub async fn flag_transaction(&self) {
+        if self.flags().await & REDIS_MULTI != 0 {
+            self.add_flags(REDIS_DIRTY_EXEC).await;
+        }
+    }
+
+    /// Drop the queued commands and leave the MULTI context
+    pub async fn discard_transaction(&mut self) {
+        self.mstate.clear();
+        self.remove_flags(REDIS_MULTI | REDIS_DIRTY_CAS | REDIS_DIRTY_EXEC)
+            .await;
+        self.unwatch_all_keys();
+    }
+
+    /// Watch a key of the selected database, EXEC will abort if the key is
+    /// touched before it is called
+    pub async fn watch_key(&mut self, key: Bytes) {
+        if self
+            .watched_keys
+            .iter()
+            .any(|(db, k)| Arc::ptr_eq(db, &self.db) && *k == key)
+        {
+            return;
+        }
+        let flags = self.inner.read().await.flags.clone();
+        self.db.watch_key(key.clone(), self.id, flags);
+        self.watched_keys.push((self.db.clone(), key));
+    }
+
+    pub fn unwatch_all_keys(&mut self) {
+        for (db, key) in self.watched_keys.drain(..) {
+            db.unwatch_key(&key, self.id);
+        }
+    }
+}
@@ -1,18 +1,32 @@
 use crate::frame::Frame;
+use crate::zskiplist::{ZSkipList, ZSkipListIter};
 use bytes::{Bytes, BytesMut};
-use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::ops::{Deref, DerefMut};
 
 #[derive(Debug, Clone)]
 pub struct RudisString {
-    pub value: BytesMut,
+    pub value: Bytes,
 }
 
 impl RudisString {
-    pub fn from(value: BytesMut) -> RudisString {
+    pub fn from(value: Bytes) -> RudisString {
         RudisString { value }
     }
 
+    /// Apply `f` to the string in place if no reply or request shares its
+    /// memory, otherwise to a copy
+    pub fn modify<T>(&mut self, f: impl FnOnce(&mut BytesMut) -> T) -> T {
+        let mut value = std::mem::take(&mut self.value)
+            .try_into_mut()
+            .unwrap_or_else(|shared| BytesMut::from(&shared[..]));
+        let result = f(&mut value);
+        self.value = value.freeze();
+        result
+    }
+
     pub fn parse_int(&self) -> Option<i64> {
         let value = self.value.as_ref();
         let value = std::str::from_utf8(value).ok()?;
@@ -21,7 +35,7 @@ impl RudisString {
 }
 
 impl Deref for RudisString {
-    type Target = BytesMut;
+    type Target = Bytes;
 
     fn deref(&self) -> &Self::Target {
         &self.value
@@ -36,7 +50,7 @@ impl DerefMut for RudisString {
 
 #[derive(Debug, Clone)]
 pub struct RudisList {
-    value: VecDeque<BytesMut>,
+    value: VecDeque<Bytes>,
 }
 
 impl RudisList {
@@ -48,7 +62,7 @@ impl RudisList {
 }
 
 impl Deref for RudisList {
-    type Target = VecDeque<BytesMut>;
+    type Target = VecDeque<Bytes>;
 
     fn deref(&self) -> &Self::Target {
         &self.value
@@ -90,7 +104,7 @@ impl DerefMut for RudisSet {
 
 #[derive(Debug, Clone)]
 pub struct RudisHash {
-    value: HashMap<Bytes, BytesMut>,
+    value: HashMap<Bytes, Bytes>,
 }
 
 impl RudisHash {
@@ -102,7 +116,7 @@ impl RudisHash {
 }
 
 impl Deref for RudisHash {
-    type Target = HashMap<Bytes, BytesMut>;
+    type Target = HashMap<Bytes, Bytes>;
 
     fn deref(&self) -> &Self::Target {
         &self.value
@@ -115,30 +129,505 @@ impl DerefMut for RudisHash {
     }
 }
 
+/// Score of a sorted set member, the scores are never NaN so they are
+/// totally ordered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZScore(pub f64);
+
+impl Eq for ZScore {}
+
+impl PartialOrd for ZScore {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ZScore {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Range of scores, e.g. `(1 5` for 1 < score <= 5
+#[derive(Debug, Clone, Copy)]
+pub struct ZScoreRange {
+    pub min: f64,
+    pub max: f64,
+    /// whether the min is excluded
+    pub minex: bool,
+    /// whether the max is excluded
+    pub maxex: bool,
+}
+
+impl ZScoreRange {
+    pub fn contains(&self, score: f64) -> bool {
+        self.above_min(score) && self.below_max(score)
+    }
+
+    pub fn above_min(&self, score: f64) -> bool {
+        if self.minex {
+            score > self.min
+        } else {
+            score >= self.min
+        }
+    }
+
+    pub fn below_max(&self, score: f64) -> bool {
+        if self.maxex {
+            score < self.max
+        } else {
+            score <= self.max
+        }
+    }
+}
+
+/// Bound of a range of members, e.g. `[a`, `(a`, `-` or `+`
 #[derive(Debug, Clone)]
+pub enum ZLexBound {
+    /// `-`, before every member
+    Min,
+    /// `+`, after every member
+    Max,
+    Included(Bytes),
+    Excluded(Bytes),
+}
+
+/// Range of members of a sorted set whose members all have the same score
+#[derive(Debug, Clone)]
+pub struct ZLexRange {
+    pub min: ZLexBound,
+    pub max: ZLexBound,
+}
+
+impl ZLexRange {
+    pub fn contains(&self, member: &[u8]) -> bool {
+        self.above_min(member) && self.below_max(member)
+    }
+
+    pub fn above_min(&self, member: &[u8]) -> bool {
+        match &self.min {
+            ZLexBound::Min => true,
+            ZLexBound::Max => false,
+            ZLexBound::Included(min) => member >= &min[..],
+            ZLexBound::Excluded(min) => member > &min[..],
+        }
+    }
+
+    pub fn below_max(&self, member: &[u8]) -> bool {
+        match &self.max {
+            ZLexBound::Min => false,
+            ZLexBound::Max => true,
+            ZLexBound::Included(max) => member <= &max[..],
+            ZLexBound::Excluded(max) => member < &max[..],
+        }
+    }
+}
+
+/// Sorted set indexed both by member, to look up the scores, and by score,
+/// to walk the members in order, see zset in redis
+#[derive(Debug, Clone, Default)]
 pub struct RudisZSet {
-    value: BTreeMap<Bytes, f64>,
+    dict: HashMap<Bytes, f64>,
+    /// the members ordered by score, then lexicographically
+    index: ZSkipList,
 }
 
 impl RudisZSet {
     pub fn new() -> RudisZSet {
-        RudisZSet {
-            value: BTreeMap::new(),
+        RudisZSet::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.dict.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dict.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.dict.get(member).copied()
+    }
+
+    /// Add the member or update its score, return the previous score
+    pub fn insert(&mut self, member: Bytes, score: f64) -> Option<f64> {
+        let old = self.dict.insert(member.clone(), score);
+        if let Some(old) = old {
+            if old == score {
+                return Some(old);
+            }
+            self.index.delete(old, &member);
         }
+        self.index.insert(score, member);
+        old
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let (member, score) = self.dict.remove_entry(member)?;
+        self.index.delete(score, &member);
+        Some(score)
+    }
+
+    pub fn pop_first(&mut self) -> Option<(Bytes, f64)> {
+        let (member, score) = self.index.iter().next()?;
+        let member = member.clone();
+        self.remove(&member)?;
+        Some((member, score))
+    }
+
+    pub fn pop_last(&mut self) -> Option<(Bytes, f64)> {
+        let (member, score) = self.index.iter().next_back()?;
+        let member = member.clone();
+        self.remove(&member)?;
+        Some((member, score))
+    }
+
+    /// 0-based position of the member in the score order
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.dict.get(member)?;
+        self.index.rank(*score, member)
+    }
+
+    /// The members in the score order
+    pub fn iter(&self) -> ZSkipListIter<'_> {
+        self.index.iter()
+    }
+
+    /// The members from the 0-based rank `start` to `stop` included, in the
+    /// score order
+    pub fn range_by_rank(&self, start: usize, stop: usize) -> ZSkipListIter<'_> {
+        self.index
+            .range_by_rank(start, stop.min(self.len().saturating_sub(1)))
+    }
+
+    /// The members whose score is in the range, in the score order
+    pub fn range_by_score(&self, range: &ZScoreRange) -> ZSkipListIter<'_> {
+        self.index.range_by_score(range)
+    }
+
+    /// The members in the lexicographic range, only meaningful when all the
+    /// members have the same score
+    pub fn range_by_lex(&self, range: &ZLexRange) -> ZSkipListIter<'_> {
+        self.index.range_by_lex(range)
     }
 }
 
-impl Deref for RudisZSet {
-    type Target = BTreeMap<Bytes, f64>;
+/// ID of a stream entry, the milliseconds time it was added at and a
+/// sequence number among the entries of the same millisecond
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.value
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    /// Parse `ms-seq`, or `ms` with the sequence `missing_seq`
+    pub fn parse(s: &[u8], missing_seq: u64) -> Option<StreamId> {
+        let s = std::str::from_utf8(s).ok()?;
+        match s.split_once('-') {
+            Some((ms, seq)) => Some(StreamId::new(ms.parse().ok()?, seq.parse().ok()?)),
+            None => Some(StreamId::new(s.parse().ok()?, missing_seq)),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == StreamId::MIN
+    }
+
+    /// The smallest ID greater than this one
+    pub fn incr(&self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+
+    /// The greatest ID smaller than this one
+    pub fn decr(&self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_sub(1)?, u64::MAX)),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        Bytes::from(self.to_string())
     }
 }
 
-impl DerefMut for RudisZSet {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.value
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// An entry delivered to a consumer of a group and not acknowledged yet
+#[derive(Debug, Clone)]
+pub struct StreamPending {
+    pub consumer: Bytes,
+    /// the last time it was delivered, in milliseconds
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StreamConsumer {
+    /// the last time the consumer attempted an interaction
+    pub seen_time: u64,
+    /// the last time the consumer read or claimed entries, None if it never
+    /// did
+    pub active_time: Option<u64>,
+    /// the IDs of its entries in the pending entries list of the group
+    pub pending: BTreeSet<StreamId>,
+}
+
+impl StreamConsumer {
+    pub fn new(now: u64) -> StreamConsumer {
+        StreamConsumer {
+            seen_time: now,
+            ..Default::default()
+        }
+    }
+}
+
+/// Consumer group of a stream, see streamCG in redis
+#[derive(Debug, Clone, Default)]
+pub struct StreamGroup {
+    /// the last entry delivered to the consumers
+    pub last_id: StreamId,
+    /// the number of entries read by the group, None when it can't be known,
+    /// e.g. after the group was set to an arbitrary ID
+    pub entries_read: Option<u64>,
+    /// the pending entries list, the entries delivered and not acknowledged
+    pub pel: BTreeMap<StreamId, StreamPending>,
+    pub consumers: BTreeMap<Bytes, StreamConsumer>,
+}
+
+impl StreamGroup {
+    pub fn new(last_id: StreamId, entries_read: Option<u64>) -> StreamGroup {
+        StreamGroup {
+            last_id,
+            entries_read,
+            ..Default::default()
+        }
+    }
+
+    /// The consumer, created if it doesn't exist
+    pub fn consumer(&mut self, name: &Bytes, now: u64) -> &mut StreamConsumer {
+        self.consumers
+            .entry(name.clone())
+            .or_insert_with(|| StreamConsumer::new(now))
+    }
+
+    /// Assign the pending entry to `consumer`, delivered at `delivery_time`,
+    /// creating it delivered once if needed, and return it
+    pub fn assign(
+        &mut self,
+        id: StreamId,
+        consumer: &Bytes,
+        delivery_time: u64,
+    ) -> &mut StreamPending {
+        if let Some(old) = self.pel.get(&id).map(|p| p.consumer.clone()) {
+            if let Some(old) = self.consumers.get_mut(&old) {
+                old.pending.remove(&id);
+            }
+        }
+        if let Some(c) = self.consumers.get_mut(consumer) {
+            c.pending.insert(id);
+        }
+        let pending = self.pel.entry(id).or_insert_with(|| StreamPending {
+            consumer: consumer.clone(),
+            delivery_time,
+            delivery_count: 1,
+        });
+        pending.consumer = consumer.clone();
+        pending.delivery_time = delivery_time;
+        pending
+    }
+
+    /// Acknowledge the entry, true if it was pending
+    pub fn ack(&mut self, id: &StreamId) -> bool {
+        match self.pel.remove(id) {
+            Some(pending) => {
+                if let Some(c) = self.consumers.get_mut(&pending.consumer) {
+                    c.pending.remove(id);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Append-only log of entries of field-value pairs ordered by ID, see
+/// t_stream.c in redis
+#[derive(Debug, Clone, Default)]
+pub struct RudisStream {
+    pub entries: BTreeMap<StreamId, Vec<(Bytes, Bytes)>>,
+    /// the ID of the last entry ever added
+    pub last_id: StreamId,
+    /// the count of the entries ever added
+    pub entries_added: u64,
+    /// the greatest ID of the entries deleted by XDEL
+    pub max_deleted_id: StreamId,
+    pub groups: BTreeMap<Bytes, StreamGroup>,
+}
+
+impl RudisStream {
+    pub fn new() -> RudisStream {
+        RudisStream::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The ID of the first entry, 0-0 if the stream is empty
+    pub fn first_id(&self) -> StreamId {
+        self.entries.keys().next().copied().unwrap_or_default()
+    }
+
+    /// The entries from `start` to `end` included
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> impl DoubleEndedIterator<Item = (&StreamId, &Vec<(Bytes, Bytes)>)> {
+        (start <= end)
+            .then(|| self.entries.range(start..=end))
+            .into_iter()
+            .flatten()
+    }
+
+    /// The ID of the next entry added at `now`, whose sequence number is
+    /// generated for the time `ms` if given. None if there is no greater ID
+    pub fn next_id(&self, now: u64, ms: Option<u64>) -> Option<StreamId> {
+        let last = self.last_id;
+        match ms {
+            None if now > last.ms => Some(StreamId::new(now, 0)),
+            None => last.incr(),
+            Some(ms) if ms > last.ms => Some(StreamId::new(ms, 0)),
+            Some(ms) if ms == last.ms => Some(StreamId::new(ms, last.seq.checked_add(1)?)),
+            Some(_) => None,
+        }
+    }
+
+    pub fn insert(&mut self, id: StreamId, fields: Vec<(Bytes, Bytes)>) {
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    /// Delete the entry as XDEL does, true if it existed
+    pub fn remove(&mut self, id: &StreamId) -> bool {
+        if self.entries.remove(id).is_none() {
+            return false;
+        }
+        if *id > self.max_deleted_id {
+            self.max_deleted_id = *id;
+        }
+        true
+    }
+
+    /// Delete the oldest entries so that at most `maxlen` are left, or the
+    /// ones whose ID is smaller than `minid`, at most `limit` of them.
+    /// Returns the number of entries deleted
+    pub fn trim(
+        &mut self,
+        maxlen: Option<u64>,
+        minid: Option<StreamId>,
+        limit: Option<u64>,
+    ) -> u64 {
+        let mut deleted = 0;
+        while limit.is_none_or(|limit| deleted < limit) {
+            let first = match self.entries.first_key_value() {
+                Some((id, _)) => *id,
+                None => break,
+            };
+            let exceeds = maxlen.is_some_and(|maxlen| self.entries.len() as u64 > maxlen)
+                || minid.is_some_and(|minid| first < minid);
+            if !exceeds {
+                break;
+            }
+            self.entries.remove(&first);
+            deleted += 1;
+        }
+        deleted
+    }
+
+    /// Whether entries may have been deleted from `start` onwards, see
+    /// streamRangeHasTombstones in redis
+    pub fn has_tombstones(&self, start: StreamId) -> bool {
+        if self.is_empty() || self.max_deleted_id.is_zero() {
+            return false;
+        }
+        start <= self.max_deleted_id
+    }
+
+    /// The number of entries added up to `id`, None if it can't be known,
+    /// see streamEstimateDistanceFromFirstEverEntry in redis
+    pub fn estimate_entries_read(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if self.is_empty() && id <= self.last_id {
+            return Some(self.entries_added);
+        }
+        match id.cmp(&self.last_id) {
+            Ordering::Equal => return Some(self.entries_added),
+            Ordering::Greater => return None,
+            Ordering::Less => {}
+        }
+        let first = self.first_id();
+        if self.max_deleted_id.is_zero() || self.max_deleted_id < first {
+            // no entry was deleted after the first one
+            match id.cmp(&first) {
+                Ordering::Less => return Some(self.entries_added - self.len() as u64),
+                Ordering::Equal => return Some(self.entries_added - self.len() as u64 + 1),
+                Ordering::Greater => {}
+            }
+        }
+        None
+    }
+
+    /// The number of entries the group has yet to read, None if it can't be
+    /// known
+    pub fn lag(&self, group: &StreamGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let entries_read = match group.entries_read {
+            Some(read) if !self.has_tombstones(group.last_id) => read,
+            _ => self.estimate_entries_read(group.last_id)?,
+        };
+        Some(self.entries_added.saturating_sub(entries_read))
+    }
+
+    /// Move the group to the delivered entry, keeping its count of entries
+    /// read
+    pub fn advance_group(&self, group: &mut StreamGroup, id: StreamId) {
+        if id <= group.last_id {
+            return;
+        }
+        group.entries_read = match group.entries_read {
+            Some(read) if !self.has_tombstones(id) => Some(read + 1),
+            _ => self.estimate_entries_read(id),
+        };
+        group.last_id = id;
     }
 }
 
@@ -149,14 +638,15 @@ pub enum RudisObject {
     Set(RudisSet),
     Hash(RudisHash),
     ZSet(RudisZSet),
+    Stream(RudisStream),
 }
 
 impl RudisObject {
     pub fn new_string() -> RudisObject {
-        RudisObject::String(RudisString::from(BytesMut::new()))
+        RudisObject::String(RudisString::from(Bytes::new()))
     }
 
-    pub fn new_string_from(value: BytesMut) -> RudisObject {
+    pub fn new_string_from(value: Bytes) -> RudisObject {
         RudisObject::String(RudisString::from(value))
     }
 
@@ -171,7 +661,7 @@ impl RudisObject {
         *self = RudisObject::String(value);
     }
 
-    pub fn as_string(&self) -> Option<&BytesMut> {
+    pub fn as_string(&self) -> Option<&Bytes> {
         match self {
             RudisObject::String(value) => Some(value),
             _ => None,
@@ -182,7 +672,7 @@ impl RudisObject {
         RudisObject::List(RudisList::new())
     }
 
-    pub fn new_list_from(value: VecDeque<BytesMut>) -> RudisObject {
+    pub fn new_list_from(value: VecDeque<Bytes>) -> RudisObject {
         RudisObject::List(RudisList { value })
     }
 
@@ -198,7 +688,7 @@ impl RudisObject {
         RudisObject::Hash(RudisHash::new())
     }
 
-    pub fn new_hash_from(value: HashMap<Bytes, BytesMut>) -> RudisObject {
+    pub fn new_hash_from(value: HashMap<Bytes, Bytes>) -> RudisObject {
         RudisObject::Hash(RudisHash { value })
     }
 
@@ -206,8 +696,16 @@ impl RudisObject {
         RudisObject::ZSet(RudisZSet::new())
     }
 
-    pub fn new_zset_from(value: BTreeMap<Bytes, f64>) -> RudisObject {
-        RudisObject::ZSet(RudisZSet { value })
+    pub fn new_zset_from(value: RudisZSet) -> RudisObject {
+        RudisObject::ZSet(value)
+    }
+
+    pub fn new_stream() -> RudisObject {
+        RudisObject::Stream(RudisStream::new())
+    }
+
+    pub fn new_stream_from(value: RudisStream) -> RudisObject {
+        RudisObject::Stream(value)
     }
 
     pub fn get_type(&self) -> &str {
@@ -217,6 +715,7 @@ impl RudisObject {
             RudisObject::Set(_) => "set",
             RudisObject::Hash(_) => "hash",
             RudisObject::ZSet(_) => "zset",
+            RudisObject::Stream(_) => "stream",
         }
     }
 
@@ -266,7 +765,7 @@ impl RudisObject {
 
     pub fn serialize(&self) -> Frame {
         match self {
-            RudisObject::String(value) => Frame::Bulk(<BytesMut as Clone>::clone(&value).freeze()),
+            RudisObject::String(value) => Frame::Bulk(value.value.clone()),
             _ => Frame::Error("not implemented".into()),
         }
     }
@@ -0,0 +1,382 @@
+use crate::client::{Client, ClientHandle, REDIS_PUBSUB};
+use crate::config::OutputBufferLimit;
+use crate::frame::Frame;
+use crate::shared;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// The size of the messages queued for a subscribed client, checked against
+/// client-output-buffer-limit pubsub when a message is published
+#[derive(Debug, Default)]
+pub struct PubSubQueue {
+    bytes: AtomicU64,
+    /// millisecond timestamp since when the soft limit is reached, 0 if it
+    /// isn't
+    soft_limit_reached_time: AtomicU64,
+}
+
+impl PubSubQueue {
+    /// The client wrote out the message
+    pub fn dequeued(&self, message: &Frame) {
+        self.bytes
+            .fetch_sub(message_size(message), Ordering::Relaxed);
+    }
+
+    /// Whether `bytes` queued are past the limits, see
+    /// checkClientOutputBufferLimits in redis
+    fn limit_reached(&self, bytes: u64, limit: &OutputBufferLimit) -> bool {
+        if limit.hard != 0 && bytes >= limit.hard {
+            return true;
+        }
+        if limit.soft == 0 || bytes < limit.soft {
+            self.soft_limit_reached_time.store(0, Ordering::Relaxed);
+            return false;
+        }
+        let now = shared::now_ms();
+        match self.soft_limit_reached_time.compare_exchange(
+            0,
+            now,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            // the first time the soft limit is reached
+            Ok(_) => false,
+            Err(since) => (now - since) / 1000 > limit.soft_seconds,
+        }
+    }
+}
+
+/// The bytes a message takes in the queue, its payloads
+fn message_size(message: &Frame) -> u64 {
+    match message {
+        Frame::Push(parts) => parts.iter().map(message_size).sum(),
+        Frame::Bulk(b) => b.len() as u64,
+        _ => 0,
+    }
+}
+
+/// The queue of the messages for a subscribed client
+#[derive(Debug, Clone)]
+pub struct Subscriber {
+    pub tx: UnboundedSender<Frame>,
+    pub queue: Arc<PubSubQueue>,
+    pub handle: Arc<ClientHandle>,
+}
+
+impl Subscriber {
+    /// Queue the message, false if the client is gone or was disconnected
+    /// for reaching the limit
+    fn send(&self, message: &Frame, limit: &OutputBufferLimit) -> bool {
+        let size = message_size(message);
+        let bytes = self.queue.bytes.fetch_add(size, Ordering::Relaxed) + size;
+        if self.queue.limit_reached(bytes, limit) {
+            self.queue.bytes.fetch_sub(size, Ordering::Relaxed);
+            log::warn!(
+                "client {} scheduled to be closed for overcoming of output buffer limits",
+                self.handle.address
+            );
+            self.handle.close();
+            return false;
+        }
+        if self.tx.send(message.clone()).is_err() {
+            self.queue.bytes.fetch_sub(size, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+}
+
+/// Server-wide registry of the channels and patterns clients are subscribed to
+#[derive(Default, Debug)]
+pub struct PubSubState {
+    pub channels: HashMap<Bytes, HashMap<u64, Subscriber>>,
+    pub patterns: HashMap<Bytes, HashMap<u64, Subscriber>>,
+}
+
+impl PubSubState {
+    pub fn new() -> PubSubState {
+        PubSubState {
+            channels: HashMap::new(),
+            patterns: HashMap::new(),
+        }
+    }
+
+    fn add(
+        table: &mut HashMap<Bytes, HashMap<u64, Subscriber>>,
+        key: Bytes,
+        id: u64,
+        subscriber: Subscriber,
+    ) -> bool {
+        table
+            .entry(key)
+            .or_default()
+            .insert(id, subscriber)
+            .is_none()
+    }
+
+    fn remove(table: &mut HashMap<Bytes, HashMap<u64, Subscriber>>, key: &Bytes, id: u64) -> bool {
+        if let Some(clients) = table.get_mut(key) {
+            let removed = clients.remove(&id).is_some();
+            if clients.is_empty() {
+                table.remove(key);
+            }
+            removed
+        } else {
+            false
+        }
+    }
+
+    pub fn subscribe(&mut self, channel: Bytes, id: u64, subscriber: Subscriber) -> bool {
+        Self::add(&mut self.channels, channel, id, subscriber)
+    }
+
+    pub fn unsubscribe(&mut self, channel: &Bytes, id: u64) -> bool {
+        Self::remove(&mut self.channels, channel, id)
+    }
+
+    pub fn psubscribe(&mut self, pattern: Bytes, id: u64, subscriber: Subscriber) -> bool {
+        Self::add(&mut self.patterns, pattern, id, subscriber)
+    }
+
+    pub fn punsubscribe(&mut self, pattern: &Bytes, id: u64) -> bool {
+        Self::remove(&mut self.patterns, pattern, id)
+    }
+
+    /// Deliver a message to every client subscribed to the channel or to a
+    /// matching pattern, return the number of clients that received it. The
+    /// clients with more than `limit` queued are disconnected instead
+    pub fn publish(&self, channel: &Bytes, message: &Bytes, limit: &OutputBufferLimit) -> usize {
+        let mut receivers = 0;
+
+        if let Some(clients) = self.channels.get(channel) {
+            let frame = Frame::Push(vec![
+                Frame::Bulk(Bytes::from_static(b"message")),
+                Frame::Bulk(channel.clone()),
+                Frame::Bulk(message.clone()),
+            ]);
+            for subscriber in clients.values() {
+                if subscriber.send(&frame, limit) {
+                    receivers += 1;
+                }
+            }
+        }
+
+        for (pattern, clients) in self.patterns.iter() {
+            if !shared::string_match(pattern, channel, false) {
+                continue;
+            }
+            let frame = Frame::Push(vec![
+                Frame::Bulk(Bytes::from_static(b"pmessage")),
+                Frame::Bulk(pattern.clone()),
+                Frame::Bulk(channel.clone()),
+                Frame::Bulk(message.clone()),
+            ]);
+            for subscriber in clients.values() {
+                if subscriber.send(&frame, limit) {
+                    receivers += 1;
+                }
+            }
+        }
+
+        receivers
+    }
+
+    /// Active channels, i.e. channels with at least one subscriber
+    pub fn active_channels(&self, pattern: Option<&Bytes>) -> Vec<Bytes> {
+        self.channels
+            .keys()
+            .filter(|channel| match pattern {
+                Some(pattern) => shared::string_match(pattern, channel, false),
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn num_subscribers(&self, channel: &Bytes) -> usize {
+        self.channels
+            .get(channel)
+            .map_or(0, |clients| clients.len())
+    }
+
+    pub fn num_patterns(&self) -> usize {
+        self.patterns.len()
+    }
+}
+
+/// The messages and subscription replies are push frames, i.e. arrays for
+/// the RESP2 clients
+fn subscription_reply(kind: &'static [u8], name: Option<Bytes>, count: usize) -> Frame {
+    Frame::Push(vec![
+        Frame::Bulk(Bytes::from_static(kind)),
+        match name {
+            Some(name) => Frame::Bulk(name),
+            None => Frame::Null,
+        },
+        Frame::Integer(count as i64),
+    ])
+}
+
+impl Client {
+    fn pubsub_subscriber(&self) -> Subscriber {
+        Subscriber {
+            tx: self.pubsub_tx.clone(),
+            queue: self.pubsub_queue.clone(),
+            handle: self.handle.clone(),
+        }
+    }
+
+    /// Number of channels and patterns the client is subscribed to
+    pub fn pubsub_count(&self) -> usize {
+        self.pubsub_channels.len() + self.pubsub_patterns.len()
+    }
+
+    pub async fn pubsub_subscribe_channel(&mut self, channel: Bytes) -> std::io::Result<()> {
+        if self.pubsub_channels.insert(channel.clone()) {
+            self.server.pubsub.write().await.subscribe(
+                channel.clone(),
+                self.id,
+                self.pubsub_subscriber(),
+            );
+            self.add_flags(REDIS_PUBSUB).await;
+        }
+        let reply = subscription_reply(b"subscribe", Some(channel), self.pubsub_count());
+        self.write_frame(&reply).await?;
+        Ok(())
+    }
+
+    pub async fn pubsub_unsubscribe_channel(
+        &mut self,
+        channel: Bytes,
+        notify: bool,
+    ) -> std::io::Result<()> {
+        if self.pubsub_channels.remove(&channel) {
+            self.server
+                .pubsub
+                .write()
+                .await
+                .unsubscribe(&channel, self.id);
+            if self.pubsub_count() == 0 {
+                self.remove_flags(REDIS_PUBSUB).await;
+            }
+        }
+        if notify {
+            let reply = subscription_reply(b"unsubscribe", Some(channel), self.pubsub_count());
+            self.write_frame(&reply).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn pubsub_unsubscribe_all_channels(&mut self, notify: bool) -> std::io::Result<()> {
+        let channels: Vec<Bytes> = self.pubsub_channels.iter().cloned().collect();
+        if channels.is_empty() && notify {
+            let reply = subscription_reply(b"unsubscribe", None, self.pubsub_count());
+            self.write_frame(&reply).await?;
+        }
+        for channel in channels {
+            self.pubsub_unsubscribe_channel(channel, notify).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn pubsub_subscribe_pattern(&mut self, pattern: Bytes) -> std::io::Result<()> {
+        if self.pubsub_patterns.insert(pattern.clone()) {
+            self.server.pubsub.write().await.psubscribe(
+                pattern.clone(),
+                self.id,
+                self.pubsub_subscriber(),
+            );
+            self.add_flags(REDIS_PUBSUB).await;
+        }
+        let reply = subscription_reply(b"psubscribe", Some(pattern), self.pubsub_count());
+        self.write_frame(&reply).await?;
+        Ok(())
+    }
+
+    pub async fn pubsub_unsubscribe_pattern(
+        &mut self,
+        pattern: Bytes,
+        notify: bool,
+    ) -> std::io::Result<()> {
+        if self.pubsub_patterns.remove(&pattern) {
+            self.server
+                .pubsub
+                .write()
+                .await
+                .punsubscribe(&pattern, self.id);
+            if self.pubsub_count() == 0 {
+                self.remove_flags(REDIS_PUBSUB).await;
+            }
+        }
+        if notify {
+            let reply = subscription_reply(b"punsubscribe", Some(pattern), self.pubsub_count());
+            self.write_frame(&reply).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn pubsub_unsubscribe_all_patterns(&mut self, notify: bool) -> std::io::Result<()> {
+        let patterns: Vec<Bytes> = self.pubsub_patterns.iter().cloned().collect();
+        if patterns.is_empty() && notify {
+            let reply = subscription_reply(b"punsubscribe", None, self.pubsub_count());
+            self.write_frame(&reply).await?;
+        }
+        for pattern in patterns {
+            self.pubsub_unsubscribe_pattern(pattern, notify).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_buffer_limits() {
+        let limit = OutputBufferLimit {
+            hard: 100,
+            soft: 10,
+            soft_seconds: 1,
+        };
+        let queue = PubSubQueue::default();
+        assert!(!queue.limit_reached(9, &limit));
+        assert!(queue.limit_reached(100, &limit));
+
+        // past the soft limit for longer than soft_seconds
+        assert!(!queue.limit_reached(10, &limit));
+        assert!(!queue.limit_reached(50, &limit));
+        let since = queue.soft_limit_reached_time.load(Ordering::Relaxed);
+        queue
+            .soft_limit_reached_time
+            .store(since - 2000, Ordering::Relaxed);
+        assert!(queue.limit_reached(50, &limit));
+        // back under the soft limit
+        assert!(!queue.limit_reached(9, &limit));
+        assert!(!queue.limit_reached(50, &limit));
+
+        let unlimited = OutputBufferLimit {
+            hard: 0,
+            soft: 0,
+            soft_seconds: 0,
+        };
+        assert!(!queue.limit_reached(u64::MAX, &unlimited));
+    }
+
+    #[test]
+    fn parse_output_buffer_limit() {
+        assert_eq!(
+            OutputBufferLimit::parse("32mb 8mb 60"),
+            Some(OutputBufferLimit::default())
+        );
+        assert_eq!(
+            OutputBufferLimit::parse("0 0 0").map(|limit| limit.to_string()),
+            Some("0 0 0".to_owned())
+        );
+        assert_eq!(OutputBufferLimit::parse("32mb 8mb"), None);
+        assert_eq!(OutputBufferLimit::parse("32mb 8mb 60 1"), None);
+    }
+}
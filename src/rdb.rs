@@ -1,9 +1,11 @@
-use crate::object::RudisObject;
+use crate::object::{
+    RudisObject, RudisStream, RudisZSet, StreamConsumer, StreamGroup, StreamId, StreamPending,
+};
 use crate::server::Server;
 use crate::shared;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use libc::pid_t;
-use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Display;
 use std::io::{Error, ErrorKind, Result, Write};
 use std::ops::{Deref, DerefMut};
@@ -23,6 +25,10 @@ const REDIS_RDB_TYPE_LIST_ZIPLIST: u8 = 10;
 const REDIS_RDB_TYPE_SET_INTSET: u8 = 11;
 const REDIS_RDB_TYPE_ZSET_ZIPLIST: u8 = 12;
 const REDIS_RDB_TYPE_HASH_ZIPLIST: u8 = 13;
+const REDIS_RDB_TYPE_STREAM: u8 = 15;
+
+// an unknown count or time, e.g. the entries read by a consumer group
+const REDIS_RDB_UNKNOWN_COUNT: u64 = u64::MAX;
 
 // 以 MS 计算的过期时间
 const REDIS_RDB_OPCODE_EXPIRETIME_MS: u8 = 252;
@@ -110,6 +116,7 @@ impl Rdb {
             RudisObject::Set(_) => self.put_u8(REDIS_RDB_TYPE_SET),
             RudisObject::Hash(_) => self.put_u8(REDIS_RDB_TYPE_HASH),
             RudisObject::ZSet(_) => self.put_u8(REDIS_RDB_TYPE_ZSET),
+            RudisObject::Stream(_) => self.put_u8(REDIS_RDB_TYPE_STREAM),
         }
     }
 
@@ -123,10 +130,119 @@ impl Rdb {
         }
     }
 
-    fn load_string_object(&mut self) -> Result<BytesMut> {
+    fn load_string_object(&mut self) -> Result<Bytes> {
+        let len = self.get_u32() as usize;
+        Ok(self.split_to(len).freeze())
+    }
+
+    fn save_stream_id(&mut self, id: &StreamId) {
+        self.put_u64(id.ms);
+        self.put_u64(id.seq);
+    }
+
+    fn load_stream_id(&mut self) -> StreamId {
+        let ms = self.get_u64();
+        StreamId::new(ms, self.get_u64())
+    }
+
+    /// The entries, the ID counters, then the consumer groups with their
+    /// pending entries and consumers
+    fn save_stream(&mut self, s: &RudisStream) {
+        self.put_u32(s.len() as u32);
+        for (id, fields) in s.entries.iter() {
+            self.save_stream_id(id);
+            self.put_u32(fields.len() as u32);
+            for (k, v) in fields {
+                self.put_u32(k.len() as u32);
+                self.put_slice(k);
+                self.put_u32(v.len() as u32);
+                self.put_slice(v);
+            }
+        }
+        self.save_stream_id(&s.last_id);
+        self.put_u64(s.entries_added);
+        self.save_stream_id(&s.max_deleted_id);
+
+        self.put_u32(s.groups.len() as u32);
+        for (name, group) in s.groups.iter() {
+            self.put_u32(name.len() as u32);
+            self.put_slice(name);
+            self.save_stream_id(&group.last_id);
+            self.put_u64(group.entries_read.unwrap_or(REDIS_RDB_UNKNOWN_COUNT));
+            self.put_u32(group.pel.len() as u32);
+            for (id, pending) in group.pel.iter() {
+                self.save_stream_id(id);
+                self.put_u64(pending.delivery_time);
+                self.put_u64(pending.delivery_count);
+                self.put_u32(pending.consumer.len() as u32);
+                self.put_slice(&pending.consumer);
+            }
+            self.put_u32(group.consumers.len() as u32);
+            for (name, consumer) in group.consumers.iter() {
+                self.put_u32(name.len() as u32);
+                self.put_slice(name);
+                self.put_u64(consumer.seen_time);
+                self.put_u64(consumer.active_time.unwrap_or(REDIS_RDB_UNKNOWN_COUNT));
+            }
+        }
+    }
+
+    fn load_stream(&mut self) -> Result<RudisStream> {
+        let mut s = RudisStream::new();
         let len = self.get_u32() as usize;
-        let mut buf = self.split_to(len);
-        Ok(buf)
+        for _ in 0..len {
+            let id = self.load_stream_id();
+            let n = self.get_u32() as usize;
+            let mut fields = Vec::with_capacity(n);
+            for _ in 0..n {
+                let k = self.load_string_object()?;
+                let v = self.load_string_object()?;
+                fields.push((k, v));
+            }
+            s.entries.insert(id, fields);
+        }
+        s.last_id = self.load_stream_id();
+        s.entries_added = self.get_u64();
+        s.max_deleted_id = self.load_stream_id();
+
+        let groups = self.get_u32() as usize;
+        for _ in 0..groups {
+            let name = self.load_string_object()?;
+            let last_id = self.load_stream_id();
+            let entries_read = Some(self.get_u64()).filter(|n| *n != REDIS_RDB_UNKNOWN_COUNT);
+            let mut group = StreamGroup::new(last_id, entries_read);
+            let pel = self.get_u32() as usize;
+            for _ in 0..pel {
+                let id = self.load_stream_id();
+                let delivery_time = self.get_u64();
+                let delivery_count = self.get_u64();
+                let consumer = self.load_string_object()?;
+                group
+                    .consumers
+                    .entry(consumer.clone())
+                    .or_default()
+                    .pending
+                    .insert(id);
+                group.pel.insert(
+                    id,
+                    StreamPending {
+                        consumer,
+                        delivery_time,
+                        delivery_count,
+                    },
+                );
+            }
+            let consumers = self.get_u32() as usize;
+            for _ in 0..consumers {
+                let name = self.load_string_object()?;
+                let consumer: &mut StreamConsumer = group.consumers.entry(name).or_default();
+                consumer.seen_time = self.get_u64();
+                consumer.active_time =
+                    Some(self.get_u64()).filter(|t| *t != REDIS_RDB_UNKNOWN_COUNT);
+            }
+            s.groups.insert(name, group);
+        }
+        Ok(s)
     }
 
     fn save_object(&mut self, obj: &RudisObject) {
@@ -153,8 +269,8 @@ impl Rdb {
                 self.put_u32(z.len() as u32);
                 for (k, v) in z.iter() {
                     self.put_u32(k.len() as u32);
-                    self.put_slice(&k);
-                    self.put_f64(*v);
+                    self.put_slice(k);
+                    self.put_f64(v);
                 }
             }
             RudisObject::Hash(h) => {
@@ -166,6 +282,7 @@ impl Rdb {
                     self.put_slice(&v);
                 }
             }
+            RudisObject::Stream(s) => self.save_stream(s),
         }
     }
 
@@ -189,15 +306,15 @@ impl Rdb {
                 let mut s = HashSet::with_capacity(len);
                 for _ in 0..len {
                     let st = self.load_string_object()?;
-                    s.insert(st.freeze());
+                    s.insert(st);
                 }
                 Ok(RudisObject::new_set_from(s))
             }
             REDIS_RDB_TYPE_ZSET => {
                 let len = self.get_u32() as usize;
-                let mut z = BTreeMap::new();
+                let mut z = RudisZSet::new();
                 for _ in 0..len {
-                    let k = self.load_string_object()?.freeze();
+                    let k = self.load_string_object()?;
                     let v = self.get_f64();
                     z.insert(k, v);
                 }
@@ -207,12 +324,16 @@ impl Rdb {
                 let len = self.get_u32() as usize;
                 let mut h = HashMap::with_capacity(len);
                 for _ in 0..len {
-                    let k = self.load_string_object()?.freeze();
+                    let k = self.load_string_object()?;
                     let v = self.load_string_object()?;
                     h.insert(k, v);
                 }
                 Ok(RudisObject::new_hash_from(h))
             }
+            REDIS_RDB_TYPE_STREAM => {
+                let s = self.load_stream()?;
+                Ok(RudisObject::new_stream_from(s))
+            }
             _ => panic!(),
         }
     }
@@ -251,19 +372,22 @@ impl Server {
         let magic = b"REDIS0006";
         rdb.put_slice(magic);
 
-        // for db in self.iter() {
-        let db = self.get(0);
-        // write SELECTDB index
-        rdb.put_u8(REDIS_RDB_OPCODE_SELECTDB);
-        rdb.put_u32(db.index);
-
-        for it in db.iter() {
-            if it.is_expired() {
+        for db in self.databases() {
+            if db.is_empty() {
                 continue;
             }
-            rdb.save_key_value_pair(it.key(), &it.value, it.expire_at, now);
+
+            // write SELECTDB index
+            rdb.put_u8(REDIS_RDB_OPCODE_SELECTDB);
+            rdb.put_u32(db.index);
+
+            for it in db.iter() {
+                if it.is_expired() {
+                    continue;
+                }
+                rdb.save_key_value_pair(it.key(), &it.value, it.expire_at, now);
+            }
         }
-        // }
 
         // write EOF
         rdb.put_u8(REDIS_RDB_OPCODE_EOF);
@@ -326,6 +450,7 @@ impl Server {
         if &rdb[0..9] != magic {
             return Err(Error::new(ErrorKind::InvalidData, "Invalid RDB file magic"));
         }
+        rdb.advance(magic.len());
 
         let mut db = None;
 
@@ -339,7 +464,7 @@ impl Server {
                 }
                 REDIS_RDB_OPCODE_SELECTDB => {
                     let db_index = rdb.get_u32() as usize;
-                    if db_index >= 1 {
+                    if db_index >= self.db_num() {
                         return Err(Error::new(ErrorKind::InvalidData, "Invalid DB index"));
                     }
                     db = Some(self.get(db_index));
@@ -357,7 +482,7 @@ impl Server {
             }
 
             // now opcode is key type
-            let key = rdb.load_string_object()?.freeze();
+            let key = rdb.load_string_object()?;
             let value = rdb.load_object(opcode)?;
 
             // check expire time
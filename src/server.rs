@@ -1,15 +1,21 @@
+use crate::acl::Acl;
 use crate::aof::{AofOption, AofState};
-use crate::client::{Client, ClientInner};
+use crate::client::{Client, ClientHandle, REDIS_BLOCKED, REDIS_PUBSUB};
+use crate::command::Command;
 use crate::config::ConfigRef;
 use crate::connection::Connection;
 use crate::dbms::DatabaseRef;
+use crate::frame::Frame;
+use crate::pubsub::PubSubState;
 use crate::rdb::{Rdb, RdbState};
 use crate::shared;
+use bytes::Bytes;
 use log;
+use std::collections::HashMap;
 use std::io::{Error, ErrorKind, Result};
 use std::net::{Ipv4Addr, SocketAddr};
 use std::os::fd::AsRawFd;
-use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicI32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::fs::{File, OpenOptions};
@@ -22,26 +28,74 @@ pub struct RudisServerInner {
     pub runid: String,
 }
 
+/// Counters reported by INFO and reset by CONFIG RESETSTAT
+#[derive(Default)]
+pub struct Stats {
+    pub evicted_keys: AtomicU64,
+    /// active expire cycles stopped early because of the time limit
+    pub expired_time_cap_reached_count: AtomicU64,
+    /// estimated ratio of the expired keys not yet deleted, stored as f64
+    /// bits
+    expired_stale_perc: AtomicU64,
+}
+
+impl Stats {
+    pub fn expired_stale_perc(&self) -> f64 {
+        f64::from_bits(self.expired_stale_perc.load(Ordering::Relaxed))
+    }
+
+    pub fn set_expired_stale_perc(&self, perc: f64) {
+        self.expired_stale_perc
+            .store(perc.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.evicted_keys.store(0, Ordering::Relaxed);
+        self.expired_time_cap_reached_count
+            .store(0, Ordering::Relaxed);
+        self.set_expired_stale_perc(0.0);
+    }
+}
+
 pub struct Server {
     pub config: ConfigRef,
-    pub dbs: DatabaseRef, // only one database for now
+    pub dbs: parking_lot::RwLock<Vec<DatabaseRef>>,
     pub clock_ms: AtomicU64,
+    pub start_time_ms: u64,
+    pub stats: Stats,
+    /// the database the next active expire cycle starts from
+    pub expire_current_db: AtomicUsize,
     pub rdb_state: RwLock<RdbState>,
     pub aof_state: RwLock<AofState>,
     pub inner: RwLock<RudisServerInner>,
     pub listener_fd: AtomicI32,
     pub quit_ch: broadcast::Sender<()>,
+    pub pubsub: RwLock<PubSubState>,
+    pub acl: RwLock<Acl>,
+    pub next_client_id: AtomicU64,
+    /// the connected clients, by id
+    pub clients: parking_lot::RwLock<HashMap<u64, Arc<ClientHandle>>>,
+    /// Every command runs holding the read side, commands that must appear
+    /// atomic to the other clients (EXEC) hold the write side
+    pub keyspace_lock: RwLock<()>,
 }
 
 impl Server {
     pub async fn from_config(config: ConfigRef) -> Arc<Server> {
+        let db_num = config.read().await.db_num;
         let server = Arc::new(Server {
             config,
 
-            dbs: DatabaseRef::new(),
+            dbs: parking_lot::RwLock::new(
+                (0..db_num).map(|i| DatabaseRef::new(i as u32)).collect(),
+            ),
 
             clock_ms: AtomicU64::new(shared::now_ms()),
 
+            start_time_ms: shared::now_ms(),
+            stats: Stats::default(),
+            expire_current_db: AtomicUsize::new(0),
+
             rdb_state: RwLock::new(RdbState::new()),
 
             aof_state: RwLock::new(AofState::new()),
@@ -51,6 +105,11 @@ impl Server {
             }),
             listener_fd: AtomicI32::new(-1),
             quit_ch: broadcast::channel(1).0,
+            pubsub: RwLock::new(PubSubState::new()),
+            acl: RwLock::new(Acl::new()),
+            next_client_id: AtomicU64::new(1),
+            clients: parking_lot::RwLock::new(HashMap::new()),
+            keyspace_lock: RwLock::new(()),
         });
 
         server.init().await.unwrap();
@@ -61,6 +120,19 @@ impl Server {
     }
 
     async fn init(self: &Arc<Self>) -> Result<()> {
+        // handle acl
+        {
+            let config = self.config.read().await;
+            let mut acl = self.acl.write().await;
+            acl.set_requirepass(config.requirepass.as_deref());
+            if let Some(aclfile) = &config.aclfile {
+                if let Err(e) = acl.load_file(aclfile) {
+                    log::error!("Error loading the ACL file {}: {}", aclfile, e);
+                    return Err(e);
+                }
+            }
+        }
+
         // handle aof
         if self.config.read().await.aof_state == AofOption::On {
             let mut aof_state = self.aof_state.write().await;
@@ -77,9 +149,43 @@ impl Server {
         Ok(())
     }
 
-    pub fn get(&self, _index: usize) -> DatabaseRef {
-        // self.inner[index].clone()
-        self.dbs.clone()
+    pub fn get(&self, index: usize) -> DatabaseRef {
+        self.dbs.read()[index].clone()
+    }
+
+    pub fn db_num(&self) -> usize {
+        self.dbs.read().len()
+    }
+
+    /// Snapshot of all databases, in index order
+    pub fn databases(&self) -> Vec<DatabaseRef> {
+        self.dbs.read().clone()
+    }
+
+    /// Swap the contents of two databases, clients connected to one of them
+    /// will see the data of the other one on their next command
+    pub fn swap_db(&self, index1: usize, index2: usize) {
+        let mut dbs = self.dbs.write();
+        dbs[index1].touch_all_watched_keys();
+        dbs[index2].touch_all_watched_keys();
+        dbs.swap(index1, index2);
+        dbs[index1].index = index1 as u32;
+        dbs[index2].index = index2 as u32;
+    }
+
+    /// Propagate the deletion of a key by the server itself, e.g. when it is
+    /// evicted or expired, as a DEL to the AOF
+    pub async fn propagate_deletion(&self, db_index: u32, key: Bytes) {
+        if self.config.read().await.aof_state == AofOption::Off {
+            return;
+        }
+        let frame = Frame::Array(vec![
+            Frame::Bulk(Bytes::from_static(b"DEL")),
+            Frame::Bulk(key),
+        ]);
+        if let Ok(cmd) = Command::from(frame) {
+            let _ = self.feed_append_only_file(cmd, db_index).await;
+        }
     }
 
     pub async fn start(self: &Arc<Self>) -> Result<()> {
@@ -149,19 +255,8 @@ impl Server {
                 conn = listener.accept() => match conn {
                     Ok((connection, address)) => {
                         log::info!("Accepted connection from {}", address);
-                        let mut c = Client {
-                            config: self.config.clone(),
-                            server: self.clone(),
-                            db: self.get(0),
-                            connection: Some(Connection::from(connection)),
-                            address,
-                            inner: RwLock::new(ClientInner {
-                                name: String::new(),
-                                last_interaction: 0,
-                                flags: Default::default(),
-                            }),
-                            quit_ch: self.quit_ch.subscribe(),
-                        };
+                        let mut c =
+                            Client::new(self.clone(), Some(Connection::from(connection)), address);
                         tokio::spawn(async move {
                             c.serve().await;
                         });
@@ -208,24 +303,23 @@ impl Server {
 
         // 1000 ms: print stats info
         if 1000 <= period_ms || cronloops % (1000 / period_ms) == 0 {
-            // for db in self.dbs.iter() {
-            let db = self.get(0);
-            let index = db.index;
-            let size = db.dict.capacity();
-            let used = db.dict.len();
-            let vkeys = db.iter().filter(|it| it.is_volatile()).count();
-            if used > 0 || vkeys > 0 {
-                log::debug!(
-                    "DB {}: {} keys ({} volatile) in {} slots RDB child pid: {} AOF child pid: {}",
-                    index,
-                    used,
-                    vkeys,
-                    size,
-                    self.rdb_state.read().await.rdb_child_pid.unwrap_or(-1),
-                    self.aof_state.read().await.aof_child_pid.unwrap_or(-1)
-                );
+            for db in self.databases() {
+                let index = db.index;
+                let size = db.capacity();
+                let used = db.len();
+                let vkeys = db.iter().filter(|it| it.is_volatile()).count();
+                if used > 0 || vkeys > 0 {
+                    log::debug!(
+                        "DB {}: {} keys ({} volatile) in {} slots RDB child pid: {} AOF child pid: {}",
+                        index,
+                        used,
+                        vkeys,
+                        size,
+                        self.rdb_state.read().await.rdb_child_pid.unwrap_or(-1),
+                        self.aof_state.read().await.aof_child_pid.unwrap_or(-1)
+                    );
+                }
             }
-            // }
         }
 
         self.clients_cron(cronloops).await;
@@ -303,9 +397,36 @@ impl Server {
         Some(period_ms)
     }
 
-    async fn clients_cron(&self, _cronloops: u64) {}
+    pub fn link_client(&self, client: Arc<ClientHandle>) {
+        self.clients.write().insert(client.id, client);
+    }
 
-    async fn databases_cron(&self, _cronloops: u64) {}
+    pub fn unlink_client(&self, id: u64) {
+        self.clients.write().remove(&id);
+    }
+
+    async fn clients_cron(&self, _cronloops: u64) {
+        // close the clients idle for too long, the subscribed and the blocked
+        // clients are only waiting for messages or elements
+        let timeout = self.config.read().await.timeout;
+        if timeout == 0 {
+            return;
+        }
+        for client in self.clients.read().values() {
+            if client.flags.load(Ordering::Relaxed) & (REDIS_PUBSUB | REDIS_BLOCKED) != 0 {
+                continue;
+            }
+            if client.idle_time() > timeout {
+                log::debug!("Closing idle client {}", client.address);
+                client.close();
+            }
+        }
+    }
+
+    async fn databases_cron(&self, _cronloops: u64) {
+        // delete some of the expired keys nobody accesses
+        self.active_expire_cycle().await;
+    }
 
     fn would_block(err: &Error) -> bool {
         err.kind() == ErrorKind::WouldBlock
@@ -13,9 +13,15 @@ pub const null_bulk: Frame = Frame::Null;
 pub const wrong_type_err: Frame = Frame::Error(Bytes::from_static(
     b"WRONGTYPE Operation against a key holding the wrong kind of value",
 ));
+pub const invalid_hll_err: Frame = Frame::Error(Bytes::from_static(
+    b"WRONGTYPE Key is not a valid HyperLogLog string value.",
+));
 pub const no_key_err: Frame = Frame::Error(Bytes::from_static(b"ERR no such key"));
 pub const protocol_err: Frame = Frame::Error(Bytes::from_static(b"ERR Protocol error"));
 pub const syntax_err: Frame = Frame::Error(Bytes::from_static(b"ERR syntax error"));
+pub const oom_err: Frame = Frame::Error(Bytes::from_static(
+    b"OOM command not allowed when used memory > 'maxmemory'.",
+));
 
 pub fn now_ms() -> u64 {
     SystemTime::now()
@@ -53,6 +59,374 @@ pub fn extend_bulk_string<'a>(out: &mut BytesMut, s: impl Into<&'a [u8]>) {
     out.extend_from_slice(b"\r\n");
 }
 
+/// The AOF representation of a command called with `args`
+pub fn rewrite_args<'a>(args: impl IntoIterator<Item = &'a [u8]>) -> BytesMut {
+    let args: Vec<&[u8]> = args.into_iter().collect();
+    let mut out = BytesMut::new();
+    extend_array(&mut out, args.len());
+    for arg in args {
+        extend_bulk_string(&mut out, arg);
+    }
+    out
+}
+
+/// Parse a memory amount like "100", "1k" or "10mb", see `memtoll` in redis
+/// util.c
+pub fn parse_memory(s: &str) -> Option<u64> {
+    let s = s.trim().to_ascii_lowercase();
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let mul = match &s[digits..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    s[..digits].parse::<u64>().ok()?.checked_mul(mul)
+}
+
+/// Resolve the `start` and `stop` indexes of a list or sorted set of `len`
+/// elements, the negative ones counting from the end, into an inclusive
+/// range, None if it is empty
+pub fn index_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        None
+    } else {
+        Some((start as usize, stop as usize))
+    }
+}
+
+/// MurmurHash2, 64 bit version, by Austin Appleby, as used by redis
+pub fn murmur_hash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+const SCAN_HASH_SEED: u64 = 0x5bd1e995;
+
+/// Hash of an element giving the order of the SCAN family, it is the same
+/// for every process so the cursors stay valid across restarts
+pub fn scan_hash(element: &[u8]) -> u64 {
+    murmur_hash64a(element, SCAN_HASH_SEED)
+}
+
+/// Take the `count` elements with the smallest hashes from `cursor` on, and
+/// return the cursor of the next page, None once the elements are all
+/// taken. The elements of a hash are taken together, so every element
+/// present for the whole scan is taken exactly once whatever is added or
+/// removed between the pages
+pub fn scan_page<T>(
+    elements: impl Iterator<Item = (u64, T)>,
+    cursor: u64,
+    count: usize,
+) -> (Vec<T>, Option<u64>) {
+    let mut elements: Vec<(u64, T)> = elements.filter(|(hash, _)| *hash >= cursor).collect();
+    let count = count.max(1);
+    if elements.len() <= count {
+        return (elements.into_iter().map(|(_, e)| e).collect(), None);
+    }
+    elements.select_nth_unstable_by_key(count - 1, |(hash, _)| *hash);
+    let last = elements[count - 1].0;
+    let more = elements[count..].iter().any(|(hash, _)| *hash > last);
+    let page = elements
+        .into_iter()
+        .filter(|(hash, _)| *hash <= last)
+        .map(|(_, e)| e)
+        .collect();
+    (page, more.then(|| last + 1))
+}
+
 pub fn get_pid() -> u32 {
     unsafe { libc::getpid() as u32 }
 }
+
+/// Glob-style pattern matching, see `stringmatchlen` in redis util.c
+pub fn string_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+
+    let (mut p, mut s) = (0, 0);
+    while p < pattern.len() && s < string.len() {
+        match pattern[p] {
+            b'*' => {
+                // collapse consecutive stars
+                while p + 1 < pattern.len() && pattern[p + 1] == b'*' {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                return (s..string.len())
+                    .any(|i| string_match(&pattern[p + 1..], &string[i..], nocase));
+            }
+            b'?' => {
+                s += 1;
+            }
+            b'[' => {
+                p += 1;
+                let not = p < pattern.len() && pattern[p] == b'^';
+                if not {
+                    p += 1;
+                }
+                let mut matched = false;
+                loop {
+                    if p >= pattern.len() {
+                        // unterminated class, the last char closes it
+                        p -= 1;
+                        break;
+                    }
+                    if pattern[p] == b'\\' && p + 1 < pattern.len() {
+                        p += 1;
+                        if pattern[p] == string[s] {
+                            matched = true;
+                        }
+                    } else if pattern[p] == b']' {
+                        break;
+                    } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' {
+                        let (mut start, mut end) = (pattern[p], pattern[p + 2]);
+                        let mut c = string[s];
+                        if start > end {
+                            std::mem::swap(&mut start, &mut end);
+                        }
+                        if nocase {
+                            start = start.to_ascii_lowercase();
+                            end = end.to_ascii_lowercase();
+                            c = c.to_ascii_lowercase();
+                        }
+                        p += 2;
+                        if c >= start && c <= end {
+                            matched = true;
+                        }
+                    } else if eq(pattern[p], string[s]) {
+                        matched = true;
+                    }
+                    p += 1;
+                }
+                if not {
+                    matched = !matched;
+                }
+                if !matched {
+                    return false;
+                }
+                s += 1;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+                if !eq(pattern[p], string[s]) {
+                    return false;
+                }
+                s += 1;
+            }
+            c => {
+                if !eq(c, string[s]) {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+        p += 1;
+    }
+
+    // the string is consumed, only trailing stars may remain
+    while p < pattern.len() && pattern[p] == b'*' {
+        p += 1;
+    }
+    p == pattern.len() && s == string.len()
+}
+
+/// Split a line into arguments separated by whitespace, supporting "double
+/// quoted" strings with escapes such as \n or \x41 and 'single quoted'
+/// strings. None if the quotes are unbalanced, see `sdssplitargs` in redis
+/// sds.c
+pub fn split_args(line: &[u8]) -> Option<Vec<Bytes>> {
+    let hex = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+
+    let mut args = Vec::new();
+    let mut p = 0;
+    loop {
+        // skip blanks
+        while p < line.len() && line[p].is_ascii_whitespace() {
+            p += 1;
+        }
+        if p == line.len() {
+            return Some(args);
+        }
+
+        let mut current = Vec::new();
+        let mut in_dq = false; // inside "double quotes"
+        let mut in_sq = false; // inside 'single quotes'
+        loop {
+            if in_dq {
+                if p == line.len() {
+                    // unterminated quotes
+                    return None;
+                }
+                if line[p] == b'\\'
+                    && p + 3 < line.len()
+                    && line[p + 1] == b'x'
+                    && hex(line[p + 2]).is_some()
+                    && hex(line[p + 3]).is_some()
+                {
+                    current.push(hex(line[p + 2]).unwrap() * 16 + hex(line[p + 3]).unwrap());
+                    p += 3;
+                } else if line[p] == b'\\' && p + 1 < line.len() {
+                    p += 1;
+                    current.push(match line[p] {
+                        b'n' => b'\n',
+                        b'r' => b'\r',
+                        b't' => b'\t',
+                        b'b' => 8,
+                        b'a' => 7,
+                        c => c,
+                    });
+                } else if line[p] == b'"' {
+                    // closing quote must be followed by a space or nothing
+                    if p + 1 < line.len() && !line[p + 1].is_ascii_whitespace() {
+                        return None;
+                    }
+                    p += 1;
+                    break;
+                } else {
+                    current.push(line[p]);
+                }
+            } else if in_sq {
+                if p == line.len() {
+                    // unterminated quotes
+                    return None;
+                }
+                if line[p] == b'\\' && p + 1 < line.len() && line[p + 1] == b'\'' {
+                    p += 1;
+                    current.push(b'\'');
+                } else if line[p] == b'\'' {
+                    // closing quote must be followed by a space or nothing
+                    if p + 1 < line.len() && !line[p + 1].is_ascii_whitespace() {
+                        return None;
+                    }
+                    p += 1;
+                    break;
+                } else {
+                    current.push(line[p]);
+                }
+            } else {
+                if p == line.len() {
+                    break;
+                }
+                match line[p] {
+                    b' ' | b'\n' | b'\r' | b'\t' | b'\0' => break,
+                    b'"' => in_dq = true,
+                    b'\'' => in_sq = true,
+                    c => current.push(c),
+                }
+            }
+            p += 1;
+        }
+        args.push(Bytes::from(current));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(line: &str) -> Option<Vec<String>> {
+        split_args(line.as_bytes()).map(|args| {
+            args.iter()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn split_args_as_redis() {
+        assert_eq!(split("").unwrap(), Vec::<String>::new());
+        assert_eq!(split("  \t ").unwrap(), Vec::<String>::new());
+        assert_eq!(split(" SET  key\tvalue ").unwrap(), ["SET", "key", "value"]);
+        assert_eq!(split(r#"SET "a b" 'c d'"#).unwrap(), ["SET", "a b", "c d"]);
+        assert_eq!(split(r#""\x41\x4a\n\t\"""#).unwrap(), ["AJ\n\t\""]);
+        assert_eq!(split(r#""\x4""#).unwrap(), ["x4"]);
+        assert_eq!(split(r"'it\'s' '\n'").unwrap(), ["it's", "\\n"]);
+        assert_eq!(split(r#"a"b""#).unwrap(), ["ab"]);
+        assert_eq!(split(r#""""#).unwrap(), [""]);
+    }
+
+    #[test]
+    fn split_args_unbalanced_quotes() {
+        assert_eq!(split(r#"SET "a"#), None);
+        assert_eq!(split("SET 'a"), None);
+        assert_eq!(split(r#""a"b"#), None);
+        assert_eq!(split("'a'b"), None);
+    }
+
+    #[test]
+    fn scan_hash_is_stable() {
+        // the cursors handed to the clients depend on it
+        assert_eq!(scan_hash(b""), 0xab4c3bbe286bc621);
+        assert_eq!(scan_hash(b"key:1"), 0x542e2c830b1a0974);
+        assert_ne!(scan_hash(b"key:1"), scan_hash(b"key:2"));
+    }
+
+    #[test]
+    fn scan_page_takes_equal_hashes_together() {
+        let elements = [(5, 'a'), (1, 'b'), (9, 'c'), (5, 'd'), (3, 'e'), (7, 'f')];
+        let page = |cursor, count| {
+            let (mut page, next) = scan_page(elements.iter().copied(), cursor, count);
+            page.sort();
+            (page, next)
+        };
+        assert_eq!(page(0, 2), (vec!['b', 'e'], Some(4)));
+        // both elements of hash 5 though the count is 1
+        assert_eq!(page(4, 1), (vec!['a', 'd'], Some(6)));
+        assert_eq!(page(6, 1), (vec!['f'], Some(8)));
+        assert_eq!(page(8, 1), (vec!['c'], None));
+        assert_eq!(page(0, 0), (vec!['b'], Some(2)));
+        assert_eq!(page(0, 10).1, None);
+        assert_eq!(page(10, 1), (vec![], None));
+
+        let max = [(u64::MAX, 'a'), (u64::MAX, 'b')];
+        let (page, next) = scan_page(max.iter().copied(), u64::MAX, 1);
+        assert_eq!((page.len(), next), (2, None));
+    }
+}
@@ -0,0 +1,567 @@
+use crate::object::{ZLexRange, ZScore, ZScoreRange};
+use bytes::Bytes;
+use rand::Rng;
+
+/* The members of a sorted set ordered by score, then lexicographically, in
+ * a skiplist as in redis t_zset.c. Every link records the number of nodes
+ * it skips, its span, so that the rank of a member is summed on the way to
+ * it and a member is found by its rank in O(log(N)). The nodes are kept in
+ * a vector and linked by their index. */
+
+const ZSKIPLIST_MAXLEVEL: usize = 32; /* Should be enough for 2^64 elements */
+const ZSKIPLIST_P: f64 = 0.25; /* Skiplist P = 1/4 */
+/// The index of no node, after the last one
+const NIL: usize = usize::MAX;
+/// The index of the header, before the first node
+const HEADER: usize = 0;
+
+#[derive(Debug, Clone, Copy)]
+struct ZSkipListLevel {
+    forward: usize,
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct ZSkipListNode {
+    member: Bytes,
+    score: f64,
+    backward: usize,
+    level: Vec<ZSkipListLevel>,
+}
+
+impl ZSkipListNode {
+    fn new(member: Bytes, score: f64, level: usize) -> ZSkipListNode {
+        ZSkipListNode {
+            member,
+            score,
+            backward: NIL,
+            level: vec![
+                ZSkipListLevel {
+                    forward: NIL,
+                    span: 0,
+                };
+                level
+            ],
+        }
+    }
+
+    /// Whether the node comes before `(score, member)`
+    fn before(&self, score: f64, member: &[u8]) -> bool {
+        (ZScore(self.score), &self.member[..]) < (ZScore(score), member)
+    }
+
+    fn is(&self, score: f64, member: &[u8]) -> bool {
+        self.score == score && self.member == member
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZSkipList {
+    /// the header then the nodes, the slots of the deleted nodes are in
+    /// `free` until reused
+    nodes: Vec<ZSkipListNode>,
+    free: Vec<usize>,
+    tail: usize,
+    length: usize,
+    /// the number of levels in use
+    level: usize,
+}
+
+impl Default for ZSkipList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ZSkipList {
+    pub fn new() -> ZSkipList {
+        ZSkipList {
+            nodes: vec![ZSkipListNode::new(Bytes::new(), 0.0, ZSKIPLIST_MAXLEVEL)],
+            free: Vec::new(),
+            tail: NIL,
+            length: 0,
+            level: 1,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// A level between 1 and ZSKIPLIST_MAXLEVEL, the higher the less likely
+    fn random_level() -> usize {
+        let mut rng = rand::thread_rng();
+        let mut level = 1;
+        while level < ZSKIPLIST_MAXLEVEL && rng.gen::<f64>() < ZSKIPLIST_P {
+            level += 1;
+        }
+        level
+    }
+
+    fn forward(&self, x: usize, i: usize) -> usize {
+        self.nodes[x].level[i].forward
+    }
+
+    /// Insert a member which isn't in the list yet
+    pub fn insert(&mut self, score: f64, member: Bytes) {
+        // the last node before the new one at every level, and its rank
+        let mut update = [HEADER; ZSKIPLIST_MAXLEVEL];
+        let mut rank = [0; ZSKIPLIST_MAXLEVEL];
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || !self.nodes[next].before(score, &member) {
+                    break;
+                }
+                rank[i] += self.nodes[x].level[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = Self::random_level();
+        if level > self.level {
+            for i in self.level..level {
+                self.nodes[HEADER].level[i].span = self.length;
+            }
+            self.level = level;
+        }
+
+        let mut node = ZSkipListNode::new(member, score, level);
+        if update[0] != HEADER {
+            node.backward = update[0];
+        }
+        let x = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        for i in 0..level {
+            let prev = update[i];
+            let skipped = rank[0] - rank[i];
+            self.nodes[x].level[i] = ZSkipListLevel {
+                forward: self.forward(prev, i),
+                span: self.nodes[prev].level[i].span - skipped,
+            };
+            self.nodes[prev].level[i] = ZSkipListLevel {
+                forward: x,
+                span: skipped + 1,
+            };
+        }
+        // the higher links now skip one more node
+        for (i, prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*prev].level[i].span += 1;
+        }
+
+        match self.forward(x, 0) {
+            NIL => self.tail = x,
+            next => self.nodes[next].backward = x,
+        }
+        self.length += 1;
+    }
+
+    /// Remove the member, false if it isn't in the list
+    pub fn delete(&mut self, score: f64, member: &[u8]) -> bool {
+        let mut update = [HEADER; ZSKIPLIST_MAXLEVEL];
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || !self.nodes[next].before(score, member) {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+        let x = self.forward(x, 0);
+        if x == NIL || !self.nodes[x].is(score, member) {
+            return false;
+        }
+
+        for (i, prev) in update.iter().enumerate().take(self.level) {
+            let link = &self.nodes[*prev].level[i];
+            self.nodes[*prev].level[i] = if link.forward == x {
+                ZSkipListLevel {
+                    forward: self.nodes[x].level[i].forward,
+                    span: link.span + self.nodes[x].level[i].span - 1,
+                }
+            } else {
+                ZSkipListLevel {
+                    forward: link.forward,
+                    span: link.span - 1,
+                }
+            };
+        }
+        let backward = self.nodes[x].backward;
+        match self.forward(x, 0) {
+            NIL => self.tail = backward,
+            next => self.nodes[next].backward = backward,
+        }
+        while self.level > 1 && self.forward(HEADER, self.level - 1) == NIL {
+            self.level -= 1;
+        }
+        self.length -= 1;
+
+        if self.length == 0 {
+            *self = ZSkipList::new();
+        } else {
+            self.nodes[x] = ZSkipListNode::new(Bytes::new(), 0.0, 0);
+            self.free.push(x);
+        }
+        true
+    }
+
+    /// The 0-based rank of the member
+    pub fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let mut x = HEADER;
+        let mut rank = 0;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL
+                    || !(self.nodes[next].before(score, member)
+                        || self.nodes[next].is(score, member))
+                {
+                    break;
+                }
+                rank += self.nodes[x].level[i].span;
+                x = next;
+            }
+            if x != HEADER && self.nodes[x].is(score, member) {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// The node of the 1-based rank
+    fn node_by_rank(&self, rank: usize) -> usize {
+        let mut x = HEADER;
+        let mut traversed = 0;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || traversed + self.nodes[x].level[i].span > rank {
+                    break;
+                }
+                traversed += self.nodes[x].level[i].span;
+                x = next;
+            }
+            if traversed == rank {
+                return x;
+            }
+        }
+        NIL
+    }
+
+    /// The first node which is not `before`, and its 1-based rank. `before`
+    /// must hold for a prefix of the list
+    fn first_after(&self, before: impl Fn(&ZSkipListNode) -> bool) -> (usize, usize) {
+        let mut x = HEADER;
+        let mut rank = 0;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || !before(&self.nodes[next]) {
+                    break;
+                }
+                rank += self.nodes[x].level[i].span;
+                x = next;
+            }
+        }
+        (self.forward(x, 0), rank + 1)
+    }
+
+    /// The last node `within`, and its 1-based rank. `within` must hold for
+    /// a prefix of the list
+    fn last_within(&self, within: impl Fn(&ZSkipListNode) -> bool) -> (usize, usize) {
+        let mut x = HEADER;
+        let mut rank = 0;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || !within(&self.nodes[next]) {
+                    break;
+                }
+                rank += self.nodes[x].level[i].span;
+                x = next;
+            }
+        }
+        if x == HEADER {
+            (NIL, 0)
+        } else {
+            (x, rank)
+        }
+    }
+
+    /// The nodes from `first` to `last`, given with their ranks
+    fn between(&self, first: (usize, usize), last: (usize, usize)) -> ZSkipListIter<'_> {
+        if first.0 == NIL || last.0 == NIL || first.1 > last.1 {
+            return ZSkipListIter {
+                list: self,
+                front: NIL,
+                back: NIL,
+                len: 0,
+            };
+        }
+        ZSkipListIter {
+            list: self,
+            front: first.0,
+            back: last.0,
+            len: last.1 - first.1 + 1,
+        }
+    }
+
+    /// All the members in order
+    pub fn iter(&self) -> ZSkipListIter<'_> {
+        ZSkipListIter {
+            list: self,
+            front: self.forward(HEADER, 0),
+            back: self.tail,
+            len: self.length,
+        }
+    }
+
+    /// The members from the 0-based rank `start` to `stop` included, which
+    /// must be in the list
+    pub fn range_by_rank(&self, start: usize, stop: usize) -> ZSkipListIter<'_> {
+        self.between(
+            (self.node_by_rank(start + 1), start + 1),
+            (self.node_by_rank(stop + 1), stop + 1),
+        )
+    }
+
+    pub fn range_by_score(&self, range: &ZScoreRange) -> ZSkipListIter<'_> {
+        self.between(
+            self.first_after(|node| !range.above_min(node.score)),
+            self.last_within(|node| range.below_max(node.score)),
+        )
+    }
+
+    /// The members in the lexicographic range, only meaningful when all the
+    /// members have the same score
+    pub fn range_by_lex(&self, range: &ZLexRange) -> ZSkipListIter<'_> {
+        self.between(
+            self.first_after(|node| !range.above_min(&node.member)),
+            self.last_within(|node| range.below_max(&node.member)),
+        )
+    }
+}
+
+/// The members of a range of the list, in order from both ends
+pub struct ZSkipListIter<'a> {
+    list: &'a ZSkipList,
+    front: usize,
+    back: usize,
+    len: usize,
+}
+
+impl<'a> Iterator for ZSkipListIter<'a> {
+    type Item = (&'a Bytes, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.front];
+        self.front = node.level[0].forward;
+        self.len -= 1;
+        Some((&node.member, node.score))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl DoubleEndedIterator for ZSkipListIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.back];
+        self.back = node.backward;
+        self.len -= 1;
+        Some((&node.member, node.score))
+    }
+}
+
+impl ExactSizeIterator for ZSkipListIter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::ZLexBound;
+
+    /// Check every link against the order of the nodes at level 0
+    fn check_links(list: &ZSkipList) -> Vec<(f64, Bytes)> {
+        let mut order = vec![HEADER];
+        let mut x = list.forward(HEADER, 0);
+        while x != NIL {
+            // the first node has no backward link
+            let backward = if order.len() == 1 {
+                NIL
+            } else {
+                order[order.len() - 1]
+            };
+            assert_eq!(list.nodes[x].backward, backward);
+            order.push(x);
+            x = list.forward(x, 0);
+        }
+        assert_eq!(order.len() - 1, list.len());
+        assert_eq!(
+            list.tail,
+            if list.is_empty() {
+                NIL
+            } else {
+                order[list.len()]
+            }
+        );
+
+        for i in 0..list.level {
+            let (mut x, mut rank) = (HEADER, 0);
+            loop {
+                let link = list.nodes[x].level[i];
+                if link.forward == NIL {
+                    // the last link spans to the end of the list
+                    assert_eq!(rank + link.span, list.len(), "level {}", i);
+                    break;
+                }
+                rank += link.span;
+                assert_eq!(order[rank], link.forward, "level {} rank {}", i, rank);
+                x = link.forward;
+            }
+        }
+        order[1..]
+            .iter()
+            .map(|&x| (list.nodes[x].score, list.nodes[x].member.clone()))
+            .collect()
+    }
+
+    fn members<'a>(iter: impl Iterator<Item = (&'a Bytes, f64)>) -> Vec<String> {
+        iter.map(|(member, _)| String::from_utf8(member.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn insert_delete_rank() {
+        let mut list = ZSkipList::new();
+        let mut model: Vec<(f64, Bytes)> = Vec::new();
+        let mut rng = rand::thread_rng();
+        for round in 0..2000 {
+            let score = rng.gen_range(0..50) as f64;
+            let member = Bytes::from(format!("m{}", rng.gen_range(0..300)));
+            let pos = model.iter().position(|(_, m)| *m == member);
+            match pos {
+                Some(pos) => {
+                    let (score, member) = model.remove(pos);
+                    assert!(list.delete(score, &member));
+                    assert!(!list.delete(score, &member));
+                    assert_eq!(list.rank(score, &member), None);
+                }
+                None => {
+                    assert!(!list.delete(score, &member));
+                    list.insert(score, member.clone());
+                    model.push((score, member));
+                    model.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                }
+            }
+            if round % 100 == 0 || list.len() < 10 {
+                assert_eq!(check_links(&list), model);
+                for (rank, (score, member)) in model.iter().enumerate() {
+                    assert_eq!(list.rank(*score, member), Some(rank));
+                }
+            }
+        }
+        assert_eq!(check_links(&list), model);
+        // the slots of the deleted nodes are reused
+        assert_eq!(list.nodes.len(), 1 + list.len() + list.free.len());
+        assert!(list.nodes.len() <= 301);
+
+        for (score, member) in model {
+            assert!(list.delete(score, &member));
+        }
+        assert!(list.is_empty());
+        assert_eq!((list.nodes.len(), list.level, list.tail), (1, 1, NIL));
+    }
+
+    #[test]
+    fn ranges() {
+        let mut list = ZSkipList::new();
+        for (i, member) in ["a", "b", "c", "d", "e", "f"].iter().enumerate() {
+            list.insert((i / 2) as f64, Bytes::from(*member));
+        }
+        check_links(&list);
+        assert_eq!(members(list.iter()), ["a", "b", "c", "d", "e", "f"]);
+        assert_eq!(members(list.iter().rev()), ["f", "e", "d", "c", "b", "a"]);
+        assert_eq!(list.rank(1.0, b"d"), Some(3));
+        assert_eq!(list.rank(2.0, b"d"), None);
+
+        assert_eq!(members(list.range_by_rank(1, 3)), ["b", "c", "d"]);
+        assert_eq!(members(list.range_by_rank(5, 5)), ["f"]);
+        assert_eq!(list.range_by_rank(2, 4).len(), 3);
+
+        let score = |min, minex, max, maxex| ZScoreRange {
+            min,
+            max,
+            minex,
+            maxex,
+        };
+        assert_eq!(
+            members(list.range_by_score(&score(1.0, false, 2.0, false))),
+            ["c", "d", "e", "f"]
+        );
+        assert_eq!(
+            members(list.range_by_score(&score(0.0, true, 2.0, true))),
+            ["c", "d"]
+        );
+        assert_eq!(
+            members(
+                list.range_by_score(&score(f64::NEG_INFINITY, false, 0.5, false))
+                    .rev()
+            ),
+            ["b", "a"]
+        );
+        assert_eq!(list.range_by_score(&score(3.0, false, 9.0, false)).len(), 0);
+        assert_eq!(list.range_by_score(&score(1.0, true, 1.0, false)).len(), 0);
+
+        let mut list = ZSkipList::new();
+        for member in ["apple", "banana", "cherry", "date"] {
+            list.insert(0.0, Bytes::from(member));
+        }
+        let lex = |min, max| ZLexRange { min, max };
+        let b = |s: &str| Bytes::from(s.to_owned());
+        assert_eq!(
+            members(list.range_by_lex(&lex(ZLexBound::Min, ZLexBound::Excluded(b("cherry"))))),
+            ["apple", "banana"]
+        );
+        assert_eq!(
+            members(list.range_by_lex(&lex(ZLexBound::Included(b("b")), ZLexBound::Max))),
+            ["banana", "cherry", "date"]
+        );
+        assert_eq!(
+            members(list.range_by_lex(&lex(
+                ZLexBound::Excluded(b("banana")),
+                ZLexBound::Included(b("cherry"))
+            ))),
+            ["cherry"]
+        );
+        assert_eq!(
+            list.range_by_lex(&lex(ZLexBound::Max, ZLexBound::Min))
+                .len(),
+            0
+        );
+    }
+}